@@ -15,13 +15,13 @@ extern "C" {
  * @return 0 on success, negative on error:
  *         -1: already running
  *         -2: invalid data_dir
- *         -3: runtime initialization failed
+ *         -3: runtime initialization failed or task could not start
  *         -4: bootstrap failed
  */
 int32_t arti_start(const char *data_dir, uint16_t socks_port);
 
 /**
- * Stop Arti gracefully.
+ * Stop Arti gracefully. Aborts an in-progress bootstrap.
  *
  * @return 0 on success, -1 if not running
  */
@@ -44,26 +44,1413 @@ int32_t arti_bootstrap_progress(void);
 /**
  * Get the current bootstrap summary string.
  *
+ * The buffer is filled even when not running (with the last summary, or
+ * an empty string if never started), but -2 is returned in that case.
+ *
  * @param buf Buffer to write the summary into
  * @param len Length of the buffer
- * @return Number of bytes written, -1 on error
+ * @return Number of bytes written, -1 on error, -2 if not running
  */
 int32_t arti_bootstrap_summary(char *buf, int32_t len);
 
 /**
  * Signal Arti to go dormant (reduce resource usage).
  *
- * @return 0 on success, -1 if not running
+ * With keep_listener non-zero the SOCKS port keeps accepting and the first
+ * connection wakes Arti (that connection pays the wake-up latency). With 0
+ * the port is closed, so connections are refused instead of waiting, and
+ * arti_wake reopens it on the same port. Extra ports keep accepting either
+ * way; an adopted listener can't be closed.
+ *
+ * @param keep_listener Non-zero to keep the SOCKS port open
+ * @return 0 on success, -1 if not running, -2 if keep_listener is 0 but the
+ *         listener was adopted with arti_start_on_fd
  */
-int32_t arti_go_dormant(void);
+int32_t arti_go_dormant(int32_t keep_listener);
 
 /**
  * Signal Arti to wake from dormant mode.
  *
- * @return 0 on success, -1 if not running
+ * Reopens the SOCKS port if arti_go_dormant closed it.
+ *
+ * @return 0 on success, -1 if not running, -2 if the SOCKS port could not
+ *         be reopened (Arti stays dormant)
  */
 int32_t arti_wake(void);
 
+/**
+ * Enable or disable dual-stack SOCKS listening (applies at next start).
+ *
+ * When enabled, the SOCKS port is bound on both 127.0.0.1 and [::1].
+ *
+ * @param enabled Non-zero to bind both loopback addresses
+ * @return 0 on success
+ */
+int32_t arti_set_dual_stack(int32_t enabled);
+
+/**
+ * Set the number of primary entry guards (applies at next start).
+ *
+ * Changing the default affects anonymity; see the Rust docs for details.
+ *
+ * @param n Number of guards, clamped to 1..10; 0 or negative restores default
+ * @return The effective value (0 means consensus default)
+ */
+int32_t arti_set_num_guards(int32_t n);
+
+/**
+ * Configure an upstream HTTP or SOCKS5 proxy for reaching the Tor network.
+ *
+ * @param url Proxy URL ("http://host:port" or "socks5://host:port"), or NULL to clear
+ * @return 0 if cleared, -1 if unsupported by this build, -2 if the URL is invalid
+ */
+int32_t arti_set_upstream_proxy(const char *url);
+
+/** Network error categories passed to ArtiNetworkErrorCallback */
+#define ARTI_NETWORK_ERROR_OTHER 0
+#define ARTI_NETWORK_ERROR_OFFLINE 1
+#define ARTI_NETWORK_ERROR_FILTERED 2
+#define ARTI_NETWORK_ERROR_GUARD_UNREACHABLE 3
+#define ARTI_NETWORK_ERROR_CLOCK_SKEW 4
+#define ARTI_NETWORK_ERROR_DIRECTORY 5
+#define ARTI_NETWORK_ERROR_LISTENER 6
+
+/**
+ * Callback for failures reaching the Tor network.
+ *
+ * @param category One of the ARTI_NETWORK_ERROR_* values
+ * @param message Human-readable message, valid only during the call
+ * @param ctx Context pointer supplied at registration
+ */
+typedef void (*ArtiNetworkErrorCallback)(int32_t category, const char *message, void *ctx);
+
+/**
+ * Register a callback for failures reaching the Tor network itself
+ * (not per-stream failures), and for SOCKS listeners that break and cannot
+ * be rebound (ARTI_NETWORK_ERROR_LISTENER). May fire on any thread.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_network_error_callback(ArtiNetworkErrorCallback cb, void *ctx);
+
+/**
+ * Bind an additional SOCKS port whose connections all share one isolation
+ * token (and never share circuits with other ids). Requires a running,
+ * bootstrapped client; closed by arti_stop.
+ *
+ * @param port Loopback port to listen on
+ * @param isolation_id Isolation group for all connections on this port
+ * @return 0 on success, -1 if not running/ready, -2 on bind failure,
+ *         -3 if the port is already an additional SOCKS port
+ */
+int32_t arti_add_socks_port(uint16_t port, uint64_t isolation_id);
+
+/**
+ * Get pluggable transport statistics as JSON
+ * (e.g. {"transport":"obfs4","connections":2}; non-name fields optional).
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Number of bytes written, -1 if no pluggable transport is active
+ */
+int32_t arti_transport_stats(char *out_buf, int32_t out_len);
+
+/**
+ * Set resource limits.
+ *
+ * max_memory_mb sets arti's memory quota (applies at next start).
+ * max_circuits caps concurrently relayed SOCKS connections (immediate);
+ * requests over the cap receive a SOCKS general-failure reply.
+ *
+ * @param max_circuits Maximum concurrent connections, <= 0 for no limit
+ * @param max_memory_mb Memory quota in MiB, <= 0 for arti's default
+ * @return 0 on success
+ */
+int32_t arti_set_resource_limits(int32_t max_circuits, int32_t max_memory_mb);
+
+/**
+ * Pin the first hop of every circuit to a specific relay.
+ *
+ * If set while running it is applied immediately. If set before start, a
+ * cached consensus naming the relay makes it the first hop from the start,
+ * bootstrap included; without one, bootstrap uses the usual guards and the
+ * pin is applied once it completes, retiring the circuits built until then.
+ *
+ * @param fingerprint Relay RSA fingerprint (40 hex digits, optional '$'), or NULL to clear
+ * @return 0 on success, -1 if running and the relay isn't in the consensus,
+ *         -2 if malformed, -3 if it could not be applied (the previous pin
+ *         stays in place; reason in arti_last_error)
+ */
+int32_t arti_set_entry_guard(const char *fingerprint);
+
+/**
+ * Get the port the main SOCKS listener is bound to.
+ *
+ * @return Port number, -1 if the listener is not up
+ */
+int32_t arti_socks_port(void);
+
+/**
+ * Move the main SOCKS listener to a different port without re-bootstrapping.
+ * The new port is bound before the old one is closed.
+ *
+ * @param new_port Port to listen on (0 picks a free port)
+ * @return The port now bound, -1 if not running/bootstrapped or on bind failure
+ */
+int32_t arti_rebind_socks(uint16_t new_port);
+
+/**
+ * Callback receiving a bootstrap status update as JSON.
+ *
+ * @param json Null-terminated UTF-8 JSON object, valid only during the call
+ * @param ctx Context pointer supplied at registration
+ */
+typedef void (*ArtiBootstrapJsonCallback)(const char *json, void *ctx);
+
+/**
+ * Register a callback receiving every bootstrap status update as JSON, e.g.
+ * {"percentage":40,"phase":"blocked","ready":false,"blockage":"...",
+ *  "blockage_category":1,"detail":"..."}.
+ * phase is one of "starting", "bootstrapping", "blocked", "ready";
+ * blockage and blockage_category are null unless blocked. May fire on any thread.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_bootstrap_json_callback(ArtiBootstrapJsonCallback cb, void *ctx);
+
+/**
+ * Start in offline mode: load the cached consensus under data_dir read-only
+ * without connecting to the network. Status queries keep working; the SOCKS
+ * listener (on a free port, see arti_socks_port) answers every request with
+ * "network unreachable". Leave with arti_stop.
+ *
+ * @param data_dir Path to data directory for Tor state
+ * @return 0 on success, -1 if already running, -2 if data_dir is invalid,
+ *         -3 if runtime init or listener bind failed,
+ *         -5 if no usable cached directory exists
+ */
+int32_t arti_start_offline(const char *data_dir);
+
+/**
+ * Callback for connectivity transitions.
+ *
+ * @param online 1 when Arti is ready for traffic, 0 otherwise
+ * @param ctx Context pointer supplied at registration
+ */
+typedef void (*ArtiConnectivityCallback)(int32_t online, void *ctx);
+
+/**
+ * Register a callback fired whenever overall connectivity flips between
+ * online (ready for traffic) and offline, including when Arti stops. The
+ * current state is reported once, synchronously, on registration. May fire
+ * on any thread.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_connectivity_callback(ArtiConnectivityCallback cb, void *ctx);
+
+/**
+ * Start Arti with separate state and cache directories, e.g. state in
+ * Application Support and cache under Caches/ (which iOS may purge).
+ * Each directory is created if missing.
+ *
+ * @param state_dir Path for persistent Tor state
+ * @param cache_dir Path for the directory cache
+ * @param socks_port Port for SOCKS5 proxy
+ * @return 0 on success, -1 if already running, -2 if state_dir is invalid,
+ *         -3 if runtime init failed, -5 if cache_dir is invalid
+ */
+int32_t arti_start_with_dirs(const char *state_dir, const char *cache_dir, uint16_t socks_port);
+
+/**
+ * Get a coarse estimate of the memory held by the Arti client, derived from
+ * the directory size and the number of relayed connections (arti's own
+ * memory tracker is not exposed). Use as an order of magnitude only.
+ *
+ * @return Estimated bytes in use, 0 if not running or not bootstrapped
+ */
+uint64_t arti_memory_usage_bytes(void);
+
+/**
+ * Start Arti without keeping state between runs. Runs against a fresh
+ * scratch directory in the system temp dir that is deleted when Arti stops
+ * (leftovers from a crash are deleted on the next ephemeral start).
+ * Tradeoff: every launch bootstraps from scratch and picks new entry guards.
+ *
+ * @param socks_port Port for SOCKS5 proxy
+ * @return 0 on success, -1 if already running, -2 if the scratch directory
+ *         could not be created, -3 if runtime init failed
+ */
+int32_t arti_start_ephemeral(uint16_t socks_port);
+
+/**
+ * Require an HAProxy PROXY protocol header (v1 or v2) at the start of every
+ * SOCKS connection, as sent by a local forwarder. Connections without a
+ * valid header are dropped. Applies to connections accepted afterwards.
+ *
+ * @param enabled Non-zero to require the header
+ * @return 0 on success
+ */
+int32_t arti_set_proxy_protocol(int32_t enabled);
+
+/**
+ * Set the isolation id for connections on the main SOCKS port. Changing it
+ * moves future connections onto fresh circuits (a per-port new identity);
+ * open streams are unaffected. Ids share the arti_add_socks_port namespace.
+ *
+ * @param id Isolation group, or 0 for arti's default isolation
+ * @return 0 on success
+ */
+int32_t arti_set_default_isolation(uint64_t id);
+
+/**
+ * Get the isolation id used for connections on the main SOCKS port.
+ *
+ * @return The id last set, or 0 if none
+ */
+uint64_t arti_default_isolation(void);
+
+/**
+ * Configure channel padding. Normal padding costs a few hundred bytes per
+ * second per open channel on mobile data; reduced padding (as in Tor
+ * Browser for Android) sends far less; disabled removes the defense.
+ * Applies at the next start, or immediately if running.
+ *
+ * @param enabled Zero to disable padding
+ * @param reduced Non-zero (with enabled) for reduced padding
+ * @return 0 on success, -1 if it could not be applied to the running client
+ */
+int32_t arti_set_padding(int32_t enabled, int32_t reduced);
+
+/**
+ * List the open SOCKS listeners as a JSON array, one object per bound
+ * address: {"type":"socks","address":"127.0.0.1:39050","isolation_id":null}.
+ * The main port reports the default isolation id (null when unset); extra
+ * ports report their own. Port forwards are listed as
+ * {"type":"forward","address":"127.0.0.1:8080","target":"example.com:80"}.
+ * Closed listeners are not listed.
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written (excluding null terminator), -1 if the buffer is null
+ *         or too small for the whole list
+ */
+int32_t arti_list_listeners(char *out_buf, int32_t out_len);
+
+/**
+ * Set the bridges for the next start from newline-separated bridge lines.
+ * Blank lines and '#' comments are ignored; a "Bridge" prefix is accepted.
+ * Only plain "IP:ORPort FINGERPRINT" lines are supported (no pluggable
+ * transports in this build). On a parse error nothing is stored and the
+ * error, naming the line, is available from arti_last_error.
+ *
+ * @param lines Bridge lines, or NULL/empty to clear
+ * @return Number of bridges stored, -1 on a parse error or invalid UTF-8
+ */
+int32_t arti_set_bridges_blob(const char *lines);
+
+/**
+ * Get a description of the most recent error reported by a call that
+ * documents it (e.g. arti_set_bridges_blob).
+ *
+ * @param buf Buffer to write the message into
+ * @param len Length of the buffer
+ * @return Bytes written (excluding null terminator), 0 if none,
+ *         -1 if buffer is null or too small
+ */
+int32_t arti_last_error(char *buf, int32_t len);
+
+/**
+ * Normalize a bridge from a QR code or link into canonical bridge lines
+ * (newline-separated, ready for arti_set_bridges_blob). Accepts bridge://
+ * URIs (percent-encoded line), the ['line', ...] list in
+ * bridges.torproject.org QR codes, and plain bridge lines. Stores nothing.
+ *
+ * @param uri The scanned or pasted text
+ * @param out_line_buf Buffer to write the bridge lines into
+ * @param out_len Length of the buffer
+ * @return Bytes written (excluding null terminator), -1 if unrecognized,
+ *         -2 if not usable in this build (see arti_last_error),
+ *         -3 if the buffer is null or too small
+ */
+int32_t arti_parse_bridge_uri(const char *uri, char *out_line_buf, int32_t out_len);
+
+/**
+ * Callback for consensus updates.
+ *
+ * @param valid_after Consensus valid-after time, Unix seconds
+ * @param ctx Context pointer supplied at registration
+ */
+typedef void (*ArtiConsensusCallback)(int64_t valid_after, void *ctx);
+
+/**
+ * Register a callback fired when arti installs a new consensus: once for
+ * the consensus in use when bootstrap completes, then for each replacement.
+ * May fire on any thread.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_consensus_callback(ArtiConsensusCallback cb, void *ctx);
+
+/**
+ * Restrict path selection to relays advertising certain subprotocols
+ * (consensus "proto" syntax, e.g. "Relay=4 Link=5"). arti exposes no
+ * predicate for this, so a well-formed constraint is rejected with -1.
+ *
+ * @param proto Required subprotocol versions, or NULL to clear
+ * @return 0 if cleared, -1 if unsupported by this build, -2 if malformed
+ */
+int32_t arti_set_min_relay_protocol(const char *proto);
+
+/**
+ * Tag the next accepted SOCKS connection with a label for logging.
+ *
+ * Best-effort and racy: the label attaches to whichever connection is
+ * accepted next on any listener. Set it immediately before opening the
+ * connection and only rely on it when requests are issued one at a time.
+ *
+ * @param label Label text, or NULL to clear
+ * @return 0 on success, -1 if the label is not valid UTF-8
+ */
+int32_t arti_set_next_connection_label(const char *label);
+
+#define ARTI_ISOLATE_DEST_ADDR 1
+#define ARTI_ISOLATE_DEST_PORT 2
+#define ARTI_ISOLATE_CREDENTIALS 4
+
+/**
+ * Set extra isolation for SOCKS connections, on every listener.
+ *
+ * Each set bit stops streams that differ in that respect (destination
+ * host, destination port, SOCKS username/password) from sharing a circuit.
+ * Applies to connections accepted afterwards.
+ *
+ * @param flags Bitwise OR of ARTI_ISOLATE_* flags, or 0 for none
+ * @return 0 on success, -1 if flags contains unknown bits
+ */
+int32_t arti_set_isolation_flags(int32_t flags);
+
+/**
+ * Get the circuit path of the most recent connection, as JSON.
+ *
+ * Writes {"hops":[{"role","fingerprint","nickname","address","country"}, ...]},
+ * guard first. Only the latest connection is remembered. nickname is null
+ * for relays missing from the consensus; country is always null (no GeoIP).
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written, 0 if no connection has been made, -1 if the buffer
+ *         is null or too small
+ */
+int32_t arti_last_circuit_path(char *out_buf, int32_t out_len);
+
+/**
+ * Load bridges from a passphrase-encrypted config bundle.
+ *
+ * The bundle holds bridge lines encrypted with AES-256-CTR and
+ * authenticated with HMAC-SHA256 under PBKDF2-derived keys (layout in
+ * bundle.rs). Bridges apply at the next arti_start. Failures are described
+ * by arti_last_error.
+ *
+ * @param path Path to the bundle file
+ * @param passphrase Passphrase
+ * @return Number of bridges loaded, -1 bad argument or unreadable file,
+ *         -2 not a valid bundle, -3 wrong passphrase, -4 bad bridge lines
+ */
+int32_t arti_load_config_bundle(const char *path, const char *passphrase);
+
+/**
+ * Recover after the OS has torn down the network, without a stop/start.
+ *
+ * Retires the circuits recent SOCKS streams used so new streams build fresh
+ * ones through the same guards. Config and guards are unchanged; this is
+ * not a new identity. Safe to call while healthy.
+ *
+ * @return Number of circuits retired, -1 if not running or not bootstrapped
+ */
+int32_t arti_reconnect(void);
+
+/**
+ * Start Arti serving SOCKS on a listening socket created by the app.
+ *
+ * listener_fd must be a bound, listening TCP socket. Arti takes ownership
+ * and closes it on arti_stop; it is not rebound if it fails.
+ *
+ * @param data_dir Path to data directory for Tor state
+ * @param listener_fd Bound, listening TCP socket
+ * @return 0 on success, -1 if already running or the fd is not a listening
+ *         TCP socket (caller keeps the fd), -2 if data_dir is invalid
+ *         (caller keeps the fd), -3 if the runtime or task failed
+ */
+int32_t arti_start_on_fd(const char *data_dir, int32_t listener_fd);
+
+/**
+ * Callback fired when a background budget is nearly used up.
+ *
+ * @param remaining_secs Time left in the budget
+ * @param ctx Opaque pointer given at registration
+ */
+typedef void (*ArtiBudgetExpiringCallback)(int32_t remaining_secs, void *ctx);
+
+/**
+ * Tell Arti how long the app may keep running in the background (advisory).
+ *
+ * A few seconds before the budget runs out Arti goes dormant and fires the
+ * budget-expiring callback. A new budget replaces the old; arti_wake
+ * cancels it.
+ *
+ * @param secs Remaining background time in seconds, or 0 to cancel
+ * @return 0 on success, -1 if not running
+ */
+int32_t arti_set_background_budget(int32_t secs);
+
+/**
+ * Register a callback fired when a background budget is nearly used up.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_budget_expiring_callback(ArtiBudgetExpiringCallback cb, void *ctx);
+
+#define ARTI_CONFIG_PADDING 1
+#define ARTI_CONFIG_BRIDGES 2
+#define ARTI_CONFIG_GUARDS 4
+#define ARTI_CONFIG_MEMORY 8
+
+/**
+ * Apply a TOML settings document, live where the running client allows it.
+ *
+ * Sections: [channel] padding, [bridges] bridges, [guards] num_primary,
+ * [system.memory] max_mb. Only sections present are changed. When not
+ * running, everything is stored for the next start. Categories arti can't
+ * change live are left unchanged and reported as needing a restart.
+ *
+ * @param config_toml Settings document
+ * @return Applied ARTI_CONFIG_* bits in bits 0-7 and needs-restart bits in
+ *         bits 8-15; -1 if the document is invalid (see arti_last_error);
+ *         -2 if the client rejected the change
+ */
+int32_t arti_apply_config(const char *config_toml);
+
+/**
+ * Report clock skew between this device and the Tor network.
+ *
+ * When skew is significant, bootstrap failures are reported with
+ * ARTI_NETWORK_ERROR_CLOCK_SKEW. The estimate survives a failed bootstrap
+ * and is reset by the next start.
+ *
+ * @param out_skew Receives the skew in seconds (positive = device clock
+ *                 fast, negative = slow, 0 = no estimate)
+ * @return 1 if significant skew was detected, 0 if not, -1 if out_skew is NULL
+ */
+int32_t arti_clock_skew_secs(int32_t *out_skew);
+
+/**
+ * Restrict which destination ports SOCKS CONNECT requests may reach.
+ *
+ * Requests to other ports are refused with SOCKS reply 0x02 ("connection
+ * not allowed by ruleset") before anything is sent over Tor. Applies to
+ * connections accepted afterwards.
+ *
+ * @param ports Allowed ports (may be NULL when count is 0)
+ * @param count Number of ports; 0 allows every port
+ * @return 0 on success, -1 if ports is NULL with a non-zero count or count
+ *         is negative
+ */
+int32_t arti_set_allowed_ports(const uint16_t *ports, int32_t count);
+
+/**
+ * Callback suggesting that the app offer bridges.
+ *
+ * @param category ARTI_NETWORK_ERROR_* value of the blockage
+ * @param stalled_secs How long bootstrap has gone without progress
+ * @param ctx Context pointer passed at registration
+ */
+typedef void (*ArtiCensorshipCallback)(int32_t category, int32_t stalled_secs, void *ctx);
+
+/**
+ * Register a callback suggesting that the app offer bridges.
+ *
+ * Fires when bootstrap has been blocked on reaching the Tor network
+ * (connections filtered, or guards unreachable) with no progress for the
+ * threshold set with arti_set_censorship_threshold (30 seconds by default).
+ * Fires once per stall. Being offline or having a wrong clock does not count.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_censorship_callback(ArtiCensorshipCallback cb, void *ctx);
+
+/**
+ * Set how long bootstrap must stall before the censorship callback fires.
+ *
+ * @param secs Seconds without progress; 30 by default
+ * @return 0 on success, -1 if secs is not positive
+ */
+int32_t arti_set_censorship_threshold(int32_t secs);
+
+/**
+ * Use custom directory authorities and fallback caches, e.g. to join a
+ * private or chutney test network instead of the public Tor network.
+ *
+ * Takes the [tor_network] section of an arti configuration file
+ * ([tor_network.authorities] and [[tor_network.fallback_caches]]).
+ * Overriding the authorities requires overriding the fallback caches too.
+ * Applies at the next arti_start.
+ *
+ * @param config_toml TOML document, or NULL to restore the public network
+ * @return 0 on success, -1 if the document is invalid (see arti_last_error)
+ */
+int32_t arti_set_directory_authorities(const char *config_toml);
+
+/**
+ * Callback receiving SOCKS stream events as JSON.
+ *
+ * @param json Null-terminated UTF-8 JSON object, valid only during the call
+ * @param ctx Context pointer passed at registration
+ */
+typedef void (*ArtiStreamEventCallback)(const char *json, void *ctx);
+
+/**
+ * Register a callback receiving SOCKS stream events as JSON.
+ *
+ * An event fires when a relayed CONNECT stream closes, e.g.
+ * {"event":"closed","target":"example.com:443","bytes_read":5120,
+ * "bytes_written":310,"duration_ms":840,"success":true}. bytes_read is what
+ * came back over Tor and bytes_written what was sent; both are exact even
+ * when one direction closes early or the relay fails.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_stream_event_callback(ArtiStreamEventCallback cb, void *ctx);
+
+/**
+ * Tear everything down and return the library to its freshly loaded state.
+ *
+ * The counterpart to the implicit first-time initialization done by the
+ * first arti_start: stops Arti if running, shuts the async runtime down
+ * (waiting up to 2 seconds for its tasks) and replaces it, unregisters every
+ * callback, and resets every setting made through this API to its default.
+ * Blocks until done. Must not be called from one of this library's callbacks.
+ *
+ * @return 0 on success, -1 if a new runtime could not be created (everything
+ *         else is still reset)
+ */
+int32_t arti_cleanup(void);
+
+/**
+ * Limit how long a SOCKS CONNECT to a clearnet target may take.
+ *
+ * A CONNECT that hasn't completed in time gets SOCKS reply 0x06 ("TTL
+ * expired"). .onion targets use arti_set_onion_connect_timeout instead.
+ *
+ * @param timeout_ms Milliseconds; 0 (the default) leaves only arti's own timeouts
+ * @return 0 on success, -1 if timeout_ms is negative
+ */
+int32_t arti_set_connect_timeout(int32_t timeout_ms);
+
+/**
+ * Limit how long a SOCKS CONNECT to a .onion target may take.
+ *
+ * Onion services legitimately take much longer to reach than clearnet
+ * hosts; keeping this separate lets the clearnet timeout stay tight.
+ *
+ * @param timeout_ms Milliseconds; 0 (the default) leaves only arti's own timeouts
+ * @return 0 on success, -1 if timeout_ms is negative
+ */
+int32_t arti_set_onion_connect_timeout(int32_t timeout_ms);
+
+/**
+ * Check whether an onion service's descriptor is currently published.
+ *
+ * Runs arti's onion service connector as far as a rendezvous circuit,
+ * without opening a stream. A service whose descriptor is found but which
+ * doesn't answer still counts as found. Blocks for at most timeout_ms.
+ *
+ * @param onion_addr Onion address, e.g. "<56 chars>.onion" (no port)
+ * @param timeout_ms Upper bound on the check, in milliseconds
+ * @return 1 if found, 0 if not found within the timeout, -1 on a bad
+ *         argument or if Arti is not bootstrapped (see arti_last_error)
+ */
+int32_t arti_onion_reachable(const char *onion_addr, int32_t timeout_ms);
+
+/**
+ * Set how many times a failed bootstrap is retried (applies at next start).
+ *
+ * Retries wait 1s, 2s, 4s, ... (capped at 30s) with random jitter, and the
+ * bootstrap summary shows the attempt, e.g. "Bootstrapping (attempt 2 of
+ * 3)...". Failures caused by clock skew are not retried.
+ *
+ * @param count Retries after the first attempt, 0 to 10; 2 by default
+ * @return 0 on success, -1 if count is out of range
+ */
+int32_t arti_set_bootstrap_retries(int32_t count);
+
+/**
+ * Check whether traffic is flowing, for a live activity indicator.
+ *
+ * @return 1 if bytes moved on any SOCKS stream within the activity window
+ *         (see arti_set_activity_window), 0 otherwise
+ */
+int32_t arti_is_active(void);
+
+/**
+ * Set how recent traffic must be for arti_is_active to report it.
+ *
+ * @param ms Window in milliseconds; 1000 by default
+ * @return 0 on success, -1 if ms is negative
+ */
+int32_t arti_set_activity_window(int32_t ms);
+
+/**
+ * Load bridges from a Tor-style bridges file.
+ *
+ * Lines that fail to parse (other torrc options, or transports this build
+ * can't use) are skipped with a warning in the log. The bridges that parsed
+ * replace the current set and apply at the next arti_start; if none did,
+ * the current set is left alone.
+ *
+ * @param path Path to the file
+ * @return Number of bridges loaded, -1 if path is NULL or the file can't be
+ *         read
+ */
+int32_t arti_load_bridges_file(const char *path);
+
+/**
+ * Seed circuit path selection for reproducible tests. Testing only:
+ * predictable paths reduce anonymity.
+ *
+ * The compiled arti cannot seed path selection, so this always fails; the
+ * reason is available from arti_last_error.
+ *
+ * @param seed RNG seed
+ * @return -1 (not supported by the compiled arti)
+ */
+int32_t arti_set_deterministic_paths(uint64_t seed);
+
+/**
+ * List the SOCKS sessions currently being relayed, as JSON.
+ *
+ * Writes an array, oldest first, e.g. [{"index":3,"target":
+ * "example.com:443","label":"feed","age_ms":5120}]. label is the one set by
+ * arti_set_next_connection_label (null if none).
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Number of bytes written, -1 if the buffer is null or too small
+ */
+int32_t arti_connection_info(char *out_buf, int32_t out_len);
+
+/**
+ * Close one SOCKS session without disturbing the circuit or other streams
+ * sharing it.
+ *
+ * @param index Session index from arti_connection_info
+ * @return 0 on success, -1 if Arti is not running or the index is unknown
+ */
+int32_t arti_close_connection(int32_t index);
+
+/**
+ * Write diagnostic events to a newline-delimited JSON file.
+ *
+ * Each line is {"ts_ms":…,"type":…,"data":{…}} with type "bootstrap",
+ * "stream" or "error"; data is what the matching callback receives (plus
+ * "opened" stream events). Existing files are appended to. Past 1 MiB the
+ * file is renamed to <path>.1 and a new one begun.
+ *
+ * @param path Log file path; NULL stops logging
+ * @return 0 on success, -1 if the file can't be opened
+ */
+int32_t arti_set_event_log_file(const char *path);
+
+/**
+ * Check that a directory will do as a data directory, without starting.
+ *
+ * The path must be absolute and either an existing directory or creatable
+ * under one, writable, and with at least 32 MiB free. Nothing is created.
+ *
+ * @param path Proposed data directory
+ * @return 0 if usable, -1 if the path is invalid, -2 if not writable, -3 if
+ *         there is not enough free space
+ */
+int32_t arti_validate_data_dir(const char *path);
+
+/**
+ * Set the per-direction buffer used to relay each SOCKS connection
+ * (applies to connections accepted afterwards).
+ *
+ * Memory use is twice this per connection. Larger buffers cost less CPU per
+ * byte on bulk downloads; over loopback, 256 KiB buffers relayed about
+ * 25% faster than the default.
+ *
+ * @param bytes Buffer size, 4096 to 1048576; 16384 by default
+ * @return 0 on success, -1 if bytes is out of range
+ */
+int32_t arti_set_relay_buffer_size(int32_t bytes);
+
+/**
+ * Get the configuration Arti is running with, as an arti TOML document.
+ *
+ * Rebuilt from the current settings, so it shows every override in effect;
+ * options left at arti's defaults are omitted. Settings that only apply at
+ * the next start are shown already.
+ *
+ * @param out_buf Buffer to write the TOML into
+ * @param out_len Length of the buffer
+ * @return Number of bytes written, -1 if not running or the buffer is null
+ *         or too small
+ */
+int32_t arti_effective_config(char *out_buf, int32_t out_len);
+
+/**
+ * Callback asked before each SOCKS CONNECT is opened over Tor.
+ *
+ * @param host Null-terminated UTF-8 host, valid only during the call
+ * @param port Destination port
+ * @param ctx Context pointer passed at registration
+ * @return Nonzero to refuse the connection
+ */
+typedef int32_t (*ArtiConnectionFilterCallback)(const char *host, uint16_t port, void *ctx);
+
+/**
+ * Register a callback that can veto SOCKS connections.
+ *
+ * Refused connections get SOCKS reply 0x02 before anything is sent over
+ * Tor. The callback runs on a blocking-work thread, possibly concurrently
+ * for several connections, and each connection waits for it: keep it quick.
+ *
+ * @param cb Filter callback, or NULL to allow every connection
+ * @param ctx Context pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_connection_filter_callback(ArtiConnectionFilterCallback cb, void *ctx);
+
+/**
+ * Export the guard selection (arti's guard state JSON) for a new device.
+ *
+ * Privacy: the blob reveals which relays this user enters Tor through, and
+ * devices sharing it look alike to their guards. Move it only between the
+ * same user's devices and don't keep copies.
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Number of bytes written, -1 if not bootstrapped, unreadable, or
+ *         the buffer is null or too small
+ */
+int32_t arti_export_guards(char *out_buf, int32_t out_len);
+
+/**
+ * Import guard state from arti_export_guards; applies at the next start.
+ *
+ * @param blob Exported guard state, or NULL to cancel a pending import
+ * @return 0 on success, -1 if running or the blob is not a JSON object
+ */
+int32_t arti_import_guards(const char *blob);
+
+/**
+ * Callback fired when the device itself appears to go offline or back.
+ *
+ * @param offline 1 when the device looks offline, 0 once it no longer does
+ * @param ctx Context pointer passed at registration
+ */
+typedef void (*ArtiOfflineCallback)(int32_t offline, void *ctx);
+
+/**
+ * Register a callback for the device itself going offline and back.
+ *
+ * Fires only when dialing guards fails locally (no route, network
+ * unreachable), so the app can show "no internet" rather than "Tor is
+ * blocked"; 0 follows once that clears or Arti stops. On recovery Arti
+ * rebuilds recent circuits, as arti_reconnect does.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Context pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_offline_callback(ArtiOfflineCallback cb, void *ctx);
+
+/**
+ * Retire the circuits one isolation group has been using, leaving other
+ * groups' circuits alone. Open streams carry on; the group's next streams
+ * build fresh circuits.
+ *
+ * @param id Isolation id, as used with arti_add_socks_port or
+ *           arti_set_default_isolation
+ * @return Number of circuits retired, -1 if not running or not bootstrapped
+ */
+int32_t arti_drop_isolation(uint64_t id);
+
+/**
+ * Choose what the SOCKS port does before bootstrap finishes (applies at
+ * next start).
+ *
+ * By default the port only opens once Tor is ready. Otherwise it opens as
+ * soon as Tor starts, and requests made during bootstrap either wait for it
+ * (queue, bounded by the connect timeout if set), get a SOCKS general
+ * failure (reject), or get "network unreachable" (fail fast).
+ *
+ * @param mode 0 = closed until ready, 1 = queue, 2 = reject, 3 = fail fast
+ * @return 0 on success, -1 if mode is unknown
+ */
+int32_t arti_set_prebootstrap_behavior(int32_t mode);
+
+/**
+ * Forward a loopback port to a fixed Tor target, clearnet or onion, with no
+ * SOCKS handshake. Every connection to 127.0.0.1:local_port is tunnelled to
+ * target_host:target_port using the default isolation, subject to the same
+ * policy as SOCKS connections (a refused connection is closed). Requires a
+ * running, bootstrapped client; removed by arti_forward_stop and arti_stop.
+ *
+ * @param local_port Loopback port to listen on
+ * @param target_host Hostname, IP address or onion address
+ * @param target_port Port to reach on the target
+ * @return 0 on success, -1 if not running or not bootstrapped, -2 if the port
+ *         could not be bound, -3 if the port is already forwarded, -4 if the
+ *         target is not a valid address
+ */
+int32_t arti_forward_port(uint16_t local_port, const char *target_host, uint16_t target_port);
+
+/**
+ * Remove a port forward. Connections already being forwarded carry on.
+ *
+ * @param local_port The forwarded loopback port
+ * @return 0 on success, -1 if no forward uses that port
+ */
+int32_t arti_forward_stop(uint16_t local_port);
+
+/**
+ * Get the circuit build timeout currently in use, as learned from observed
+ * build times (or as fixed by arti_set_circuit_build_timeout).
+ *
+ * @return Timeout for a 3-hop circuit in milliseconds, -1 if not running and
+ *         bootstrapped
+ */
+int32_t arti_circuit_build_timeout_ms(void);
+
+/**
+ * Fix the circuit build timeout, e.g. a generous one for high-latency mobile
+ * links. Overriding disables adaptive learning until cleared with 0. Applies
+ * immediately and to later starts.
+ *
+ * @param ms Timeout in milliseconds, at least 10; 0 restores adaptive learning
+ * @return 0 on success, -1 if not running and bootstrapped or ms is out of
+ *         range, -2 if the setting could not be applied
+ */
+int32_t arti_set_circuit_build_timeout(int32_t ms);
+
+/**
+ * Serve a small status page on loopback (http://127.0.0.1:<port>/), showing
+ * bootstrap state, traffic and circuits; /status.json serves the raw JSON.
+ * Only requests addressed to a loopback host are answered. Closed by
+ * arti_stop.
+ *
+ * @param port Loopback port to listen on; 0 for any free port
+ * @return The port served on, -1 if not running, -2 if the port could not be
+ *         bound, -3 if the page is already being served
+ */
+int32_t arti_start_status_server(uint16_t port);
+
+/**
+ * Choose how many hops exit circuits use. Experimental: any length other
+ * than the standard 3 makes circuits stand out and weakens anonymity. The
+ * compiled arti only builds 3-hop exit circuits, so other values are refused
+ * (see arti_last_error).
+ *
+ * @param hops Hops per circuit; 3 by default
+ * @return 0 if hops is 3, -1 otherwise (not supported)
+ */
+int32_t arti_set_circuit_length(int32_t hops);
+
+/**
+ * Get arti's description of why bootstrap is blocked, e.g. "We seem to be
+ * offline (...)". Empty while bootstrap progresses normally, once it is
+ * complete, and when not running. Truncated if the buffer is too small.
+ *
+ * @param out_buf Buffer to write the reason into
+ * @param out_len Length of the buffer
+ * @return Bytes written (excluding null terminator; 0 if not blocked), -1 if
+ *         the buffer is null or empty
+ */
+int32_t arti_bootstrap_blockage(char *out_buf, int32_t out_len);
+
+/**
+ * Callback asked for fresh bridges when bootstrap appears blocked.
+ *
+ * The returned string is owned by the app and never freed by the library,
+ * which copies it as soon as the callback returns. Return a static string,
+ * or keep the last one alive and free it on the next call.
+ *
+ * @param ctx Context pointer passed at registration
+ * @return Newline-separated bridge lines, or NULL to offer none
+ */
+typedef const char *(*ArtiBridgeProviderCallback)(void *ctx);
+
+/**
+ * Register a callback that fetches bridges when bootstrap appears blocked.
+ *
+ * Called right after the censorship callback fires, on a background thread
+ * where it may block while fetching. Bridges that parse replace the
+ * configured set and the bootstrapping client retries with them at once.
+ *
+ * @param cb Bridge provider callback, or NULL to clear
+ * @param ctx Context pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_bridge_provider_callback(ArtiBridgeProviderCallback cb, void *ctx);
+
+/**
+ * Exclude relays in the given countries from every circuit position.
+ *
+ * Needs a GeoIP database, which this build leaves out, so any non-empty
+ * list is refused with the reason in arti_last_error.
+ *
+ * @param exclude_countries Array of two-letter country codes (may be NULL
+ *        when count is 0)
+ * @param count Number of codes; 0 excludes no countries
+ * @return 0 if count is 0, -1 otherwise (GeoIP unavailable)
+ */
+int32_t arti_set_country_constraints(const char *const *exclude_countries, int32_t count);
+
+/**
+ * Get how long the latest arti_start took to bootstrap.
+ *
+ * Measured from the arti_start call until the client was ready for
+ * traffic. Still available after arti_stop, until the next start.
+ *
+ * @return Duration in milliseconds, or -1 if no start has finished
+ *         bootstrapping yet
+ */
+int32_t arti_last_bootstrap_duration_ms(void);
+
+/**
+ * Open a stream over Tor as two pipe fds, one per direction.
+ *
+ * Blocks until the stream is open. Read the destination's data from
+ * *out_read_fd and send it data through *out_write_fd. Closing the write
+ * fd half-closes the stream; the read fd reports end-of-file once the
+ * destination has finished.
+ *
+ * Both fds belong to the caller, who must close each of them; the stream
+ * ends once both are closed, or early via arti_close_connection (it is
+ * listed with the label "pipes"). The fds are blocking and close-on-exec.
+ * The stream gets the same checks as a SOCKS CONNECT (Tor-only domains,
+ * allowed ports, connection filter, fail closed, connection limit).
+ *
+ * @param host Hostname, IP address or onion address
+ * @param port Destination port
+ * @param out_read_fd Receives the fd to read from
+ * @param out_write_fd Receives the fd to write to
+ * @return 0 on success, -1 on failure (see arti_last_error)
+ */
+int32_t arti_open_stream_split(const char *host,
+                               uint16_t port,
+                               int32_t *out_read_fd,
+                               int32_t *out_write_fd);
+
+/**
+ * Save a settings document as a named profile.
+ *
+ * The document is the one arti_apply_config takes and is validated the
+ * same way before being saved as <data_dir>/profiles/<name>.toml.
+ *
+ * @param data_dir Data directory the profile belongs to
+ * @param name 1-64 ASCII letters, digits, '-' or '_'
+ * @param config_toml Settings document
+ * @return 0 on success, -1 if data_dir or name is invalid, -2 if the
+ *         document is invalid (see arti_last_error), -3 if it could not
+ *         be written
+ */
+int32_t arti_save_profile(const char *data_dir, const char *name, const char *config_toml);
+
+/**
+ * Start Arti with the settings of a saved profile.
+ *
+ * Settings a profile can hold are reset to their defaults and then set
+ * from the profile, and put back as they were if the start fails.
+ * Otherwise like arti_start.
+ *
+ * @param data_dir Data directory holding the profile
+ * @param name Profile saved with arti_save_profile
+ * @param socks_port Port for SOCKS5 proxy
+ * @return As arti_start, plus -5 if the profile is missing or unreadable
+ */
+int32_t arti_start_profile(const char *data_dir, const char *name, uint16_t socks_port);
+
+/**
+ * List saved profiles as a sorted JSON array of names, e.g. ["home","work"].
+ *
+ * @param data_dir Data directory
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written, or -1 on error or if the buffer is too small
+ */
+int32_t arti_list_profiles(const char *data_dir, char *out_buf, int32_t out_len);
+
+/**
+ * Callback fired when the async runtime appears stuck.
+ *
+ * @param stalled_ms How long a trivial probe task went unrun
+ * @param ctx Context pointer passed at registration
+ */
+typedef void (*ArtiRuntimeStallCallback)(int32_t stalled_ms, void *ctx);
+
+/**
+ * Register a callback fired when the async runtime appears stuck.
+ *
+ * While running, a watchdog thread checks every 10 seconds that the runtime
+ * runs a trivial task within 5 seconds, and fires the callback (from that
+ * thread, once per stall) when it does not. Skipped while no callback is
+ * registered.
+ *
+ * @param cb Stall callback, or NULL to clear
+ * @param ctx Context pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_runtime_stall_callback(ArtiRuntimeStallCallback cb, void *ctx);
+
+/**
+ * Prefer (require = 0) or require a particular exit relay.
+ *
+ * The relay must be a fingerprint or nickname and, while running, an exit
+ * in the current consensus. This arti build chooses exits itself and
+ * cannot be steered to one relay: a valid preference is accepted but does
+ * not change the exit used, and requiring a relay is refused. Failures
+ * leave the reason in arti_last_error.
+ *
+ * @param fingerprint_or_nickname Relay fingerprint or nickname; NULL or
+ *        empty for no preference
+ * @param require Nonzero to require the relay, 0 to prefer it
+ * @return 0 if no relay is given or a valid one is preferred, -1 if it is
+ *         malformed, not a listed exit, or required (unsupported)
+ */
+int32_t arti_set_exit_relay(const char *fingerprint_or_nickname, int32_t require);
+
+/**
+ * Get the running onion service's introduction/rendezvous counts as JSON.
+ *
+ * This build only includes the onion service client and never hosts a
+ * service, so there is nothing to report.
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written, or -1 if no onion service is running
+ */
+int32_t arti_onion_stats(char *out_buf, int32_t out_len);
+
+/**
+ * Allow or forbid IPv6 addresses when connecting to relays.
+ *
+ * This arti build has no such setting, though it already races a relay's
+ * addresses so unreachable IPv6 doesn't stall bootstrap. Only enabling
+ * (the default) succeeds.
+ *
+ * @param enabled Nonzero to allow IPv6 relay addresses
+ * @return 0 if enabled is nonzero, -1 otherwise (unsupported)
+ */
+int32_t arti_set_relay_ipv6(int32_t enabled);
+
+/**
+ * Build the circuits to an onion service ahead of the first stream.
+ *
+ * Fetches the descriptor and builds the rendezvous circuit by opening a
+ * stream to the given port and closing it at once; arti keeps the circuit
+ * for the next stream there while it is fresh. Isolated like a SOCKS
+ * stream on the main port without credentials. Blocks until done.
+ *
+ * @param onion_addr Onion service and port, e.g. "<56 chars>.onion:80"
+ * @return 0 once the circuit is ready, -1 on failure (see arti_last_error)
+ */
+int32_t arti_onion_prewarm(const char *onion_addr);
+
+/**
+ * Time since traffic last flowed, for telling a quiet connection from a
+ * dead one.
+ *
+ * Counts from the last byte relayed on any SOCKS stream, the moment
+ * arti_is_active measures from. Circuit builds with no traffic on them
+ * do not count.
+ *
+ * @return Whole seconds since bytes last moved, or -1 if none have moved
+ *         since the library was loaded or reset
+ */
+int32_t arti_seconds_since_activity(void);
+
+/**
+ * Keep idle SOCKS streams' circuits in use, so that NAT and firewall
+ * mappings along the way don't expire under a long-lived, mostly silent
+ * connection (applies to connections accepted afterwards).
+ *
+ * Whenever a stream has been idle for secs, a DNS lookup of its
+ * destination is sent with the stream's isolation, normally over its own
+ * circuit. Costs a few cells per interval per idle stream, trading some
+ * bandwidth for connection longevity. Onion service streams are left
+ * alone.
+ *
+ * @param secs Idle seconds between keepalives; 0 (the default) turns them off
+ * @return 0 on success, -1 if secs is negative
+ */
+int32_t arti_set_stream_keepalive(int32_t secs);
+
+/**
+ * Report the hit, miss and entry counts of the DNS cache.
+ *
+ * There is no DNS cache to report on: every SOCKS RESOLVE and RESOLVE_PTR
+ * goes to an exit relay and the answers are not kept. The outputs are
+ * left untouched and the reason is in arti_last_error.
+ *
+ * @param out_hits Receives the number of lookups answered from the cache
+ * @param out_misses Receives the number of lookups sent to an exit
+ * @param out_entries Receives the number of names cached
+ * @return -1 always (no DNS cache in this build)
+ */
+int32_t arti_dns_cache_stats(uint64_t *out_hits, uint64_t *out_misses, uint64_t *out_entries);
+
+/**
+ * Drop every cached DNS answer, e.g. after a network change.
+ *
+ * Nothing is cached, so there is nothing to drop.
+ *
+ * @return 0 always
+ */
+int32_t arti_dns_cache_clear(void);
+
+/**
+ * Send only the listed domains through Tor, for apps that use Tor for
+ * some endpoints and connect to the rest directly.
+ *
+ * A SOCKS CONNECT or RESOLVE naming any other host, including an address
+ * literal, is refused with SOCKS reply 0x02 before anything is sent over
+ * Tor. Each domain also covers its subdomains; matching ignores case and
+ * a trailing dot. Applies to connections accepted afterwards.
+ *
+ * @param domains Array of domain names (may be NULL when count is 0)
+ * @param count Number of domains; 0 sends everything through Tor
+ * @return 0 on success, -1 if an entry is NULL, empty or not UTF-8, or
+ *         count is negative
+ */
+int32_t arti_set_tor_only_domains(const char *const *domains, int32_t count);
+
+/**
+ * Refuse SOCKS CONNECTs outright whenever Tor is not ready for traffic,
+ * so an app never mistakes a proxy that is still bootstrapping (or has
+ * lost the network) for a working one.
+ *
+ * While Tor is not ready, every CONNECT gets SOCKS reply 0x03 ("network
+ * unreachable") at once, even with the pre-bootstrap behavior set to
+ * queue, and nothing is sent over Tor. Applies to connections accepted
+ * afterwards.
+ *
+ * @param enabled Nonzero to fail closed; off by default
+ * @return 0 on success
+ */
+int32_t arti_set_fail_closed(int32_t enabled);
+
+/**
+ * Report congestion-control and flow-control state, e.g. each circuit's
+ * congestion window.
+ *
+ * Arti 0.38 offers no way to read this state, so there is nothing to
+ * report; the reason is in arti_last_error.
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written, or -1 if Arti is not running or the stats are
+ *         unavailable
+ */
+int32_t arti_congestion_stats(char *out_buf, int32_t out_len);
+
+/**
+ * Wait until the main SOCKS listener is bound and accepting connections.
+ *
+ * Confirms it by connecting to the port over loopback; the listener
+ * recognises the probe and closes it without treating it as a client.
+ * This only says the port is live, not that Tor is bootstrapped. Must not
+ * be called from one of this library's callbacks.
+ *
+ * @param timeout_ms Longest to wait, in milliseconds; 0 checks once
+ * @return 0 once the port accepts connections, -1 if Arti is not running
+ *         or timeout_ms is negative, -2 on timeout
+ */
+int32_t arti_wait_socks_ready(int32_t timeout_ms);
+
+/**
+ * Turn the per-connection debug logging of the SOCKS listeners on or off.
+ *
+ * With thousands of short-lived connections and debug logging enabled,
+ * formatting each connection's messages adds up. Turning it off skips the
+ * work entirely, whatever the log filter says; other logging is
+ * unaffected.
+ *
+ * @param enabled Nonzero to log each connection (the default)
+ * @return 0 on success
+ */
+int32_t arti_set_connection_logging(int32_t enabled);
+
+/**
+ * Name the async runtime's threads and optionally lower their priority.
+ *
+ * Applies when the runtime is built: by the first arti_start, and again
+ * by arti_cleanup (which then resets this setting). Threads are named
+ * "<prefix>-<n>". Low priority means the utility QoS class on Apple
+ * platforms and a nice value of 10 on Linux and Android; other platforms
+ * ignore it.
+ *
+ * @param name_prefix Thread name prefix, or NULL for the default names
+ * @param low_priority Nonzero to run the threads at background priority
+ * @return 0 on success, -1 if name_prefix is empty, longer than 32 bytes
+ *         or not UTF-8
+ */
+int32_t arti_set_thread_config(const char *name_prefix, int32_t low_priority);
+
+/**
+ * List the entry guards in arti's saved guard state.
+ *
+ * Writes {"guards":[{"fingerprint":"ABCD...","sample":"default",
+ * "added_at":"...","confirmed_at":"...","in_use":true}, ...]}. sample is
+ * "default", "restricted" or "bridges"; confirmed_at is when the guard
+ * was first used successfully (null if never); in_use marks the guard of
+ * the latest circuit a stream used. Disabled and unlisted guards are left
+ * out. This is not a reachability report: the compiled arti keeps whether
+ * a guard is reachable, and when it was last contacted, in memory only
+ * and does not expose them, so a guard that just failed is still listed.
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written, or -1 if Arti is not running, the state can't be
+ *         read, or the buffer is too small
+ */
+int32_t arti_saved_guards(char *out_buf, int32_t out_len);
+
+/**
+ * List the onion services this library is hosting, as JSON:
+ * [{"nickname":"alice","address":"<56 chars>.onion"}, ...].
+ *
+ * This build includes only arti's onion service client, so the list is
+ * always empty.
+ *
+ * @param out_buf Buffer to write the JSON into
+ * @param out_len Length of the buffer
+ * @return Bytes written, or -1 if the buffer is null or too small
+ */
+int32_t arti_onion_list(char *out_buf, int32_t out_len);
+
+/**
+ * Shut down the hosted onion service called nickname, leaving any others
+ * running.
+ *
+ * This build hosts no onion services, so there is never one to shut down.
+ *
+ * @param nickname Service nickname
+ * @return 0 once the service is down, -1 if there is no such service
+ */
+int32_t arti_onion_destroy_by_name(const char *nickname);
+
+/**
+ * Estimate the exit's bandwidth by downloading a small test file (at most
+ * 256 KiB over plain HTTP) through Tor. Only the body's arrival is timed.
+ * The stream gets the same policy and isolation as a SOCKS stream.
+ *
+ * NOTE: unless arti_set_bandwidth_probe_url names another endpoint, the
+ * file is fetched from a third party, http://speed.cloudflare.com, which
+ * sees every probe request (arriving from the exit, not from the device).
+ *
+ * @param timeout_ms Longest the whole measurement may take, in milliseconds
+ * @return Estimated kilobytes (1000 bytes) per second; -1 if not running,
+ *         timeout_ms is invalid, the policy refuses the endpoint, or the
+ *         download failed
+ */
+int32_t arti_measure_exit_bandwidth(int32_t timeout_ms);
+
+/**
+ * Choose where arti_measure_exit_bandwidth downloads its test file, e.g.
+ * a file served by the app's own infrastructure. Reset by arti_cleanup.
+ *
+ * @param url http://host[:port][/path]; NULL for the default
+ *        (speed.cloudflare.com)
+ * @return 0 on success, -1 if url is not an http:// URL (see
+ *         arti_last_error)
+ */
+int32_t arti_set_bandwidth_probe_url(const char *url);
+
+/**
+ * Set how many times the whole bootstrap is attempted, each attempt with a
+ * new client and its own retries (applies at next start). The summary shows
+ * e.g. "Retrying bootstrap 2/3..." between attempts.
+ *
+ * @param count Attempts in all, 1 to 10; 1 by default
+ * @return 0 on success, -1 if count is out of range
+ */
+int32_t arti_set_bootstrap_attempts(int32_t count);
+
+/**
+ * Start every bootstrap summary set from now on with prefix and ": ",
+ * e.g. "MyApp: Bootstrapping...". Empty summaries stay empty.
+ *
+ * @param prefix Prefix, at most 32 bytes; NULL or "" for none
+ * @return 0 on success, -1 if not valid UTF-8 or too long
+ */
+int32_t arti_set_summary_prefix(const char *prefix);
+
+/**
+ * Get the prefix set with arti_set_summary_prefix.
+ *
+ * @param buf Buffer to write the prefix into
+ * @param len Length of the buffer
+ * @return Bytes written (0 if there is no prefix; truncated to fit), or -1 if buf
+ *         is NULL or empty
+ */
+int32_t arti_summary_prefix(char *buf, int32_t len);
+
+/**
+ * Callback for bootstrap milestones.
+ *
+ * @param percent 25, 50, 75 or 100
+ * @param ctx Context pointer supplied at registration
+ */
+typedef void (*ArtiMilestoneCallback)(int32_t percent, void *ctx);
+
+/**
+ * Register a callback fired as bootstrap first reaches 25, 50, 75 and 100
+ * percent: at most four times per start, each milestone once and in order.
+ * May fire on any thread.
+ *
+ * @param cb Callback, or NULL to clear
+ * @param ctx Opaque pointer passed back to cb
+ * @return 0 on success
+ */
+int32_t arti_set_milestone_callback(ArtiMilestoneCallback cb, void *ctx);
+
+/**
+ * Set whether listeners are bound with SO_REUSEADDR (applies to ports bound
+ * afterwards), so a SOCKS port can be rebound straight after a stop while
+ * old connections linger in TIME_WAIT.
+ *
+ * @param enabled Nonzero to set SO_REUSEADDR; on by default except on Windows
+ * @return 0 on success
+ */
+int32_t arti_set_reuse_addr(int32_t enabled);
+
+/**
+ * Get the number of the bootstrap attempt in progress, counting every try
+ * (retries and new clients alike) since the latest start; it stays at the
+ * attempt that succeeded until the next start.
+ *
+ * @return The attempt number (1 or more), or 0 if none since the latest start
+ */
+int32_t arti_bootstrap_attempt(void);
+
 #ifdef __cplusplus
 }
 #endif
@@ -31,7 +31,7 @@ private func arti_bootstrap_progress() -> Int32
 private func arti_bootstrap_summary(_ buf: UnsafeMutablePointer<CChar>, _ len: Int32) -> Int32
 
 @_silgen_name("arti_go_dormant")
-private func arti_go_dormant() -> Int32
+private func arti_go_dormant(_ keepListener: Int32) -> Int32
 
 @_silgen_name("arti_wake")
 private func arti_wake() -> Int32
@@ -0,0 +1,211 @@
+//! Exit bandwidth probe
+//!
+//! Downloads a small amount over plain HTTP through a Tor stream and
+//! reports how fast the body arrived. Only the body is timed: circuit
+//! setup and the request round trip say more about latency than about
+//! how much the exit can carry. The probe is kept to a few hundred
+//! kilobytes so running it now and then costs little.
+
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+/// Endpoint used until the app sets one: a third party, so the app should
+/// say so to its users or configure its own
+pub const DEFAULT_URL: &str = "http://speed.cloudflare.com/__down?bytes=262144";
+/// Most body bytes the probe reads
+pub const PROBE_BYTES: usize = 256 * 1024;
+/// Body bytes needed before a download cut short still gives an estimate
+pub const MIN_SAMPLE_BYTES: usize = 16 * 1024;
+
+/// Longest response header accepted
+const MAX_HEADER_LEN: usize = 16 * 1024;
+
+/// Where the probe downloads from: a plain `http://` URL, as the body is
+/// throwaway and timing it through TLS would add nothing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    /// Path and query, starting with `/`
+    pub path: String,
+}
+
+/// Endpoint set with `arti_set_bandwidth_probe_url` (`None` = `DEFAULT_URL`)
+static PROBE_ENDPOINT: Mutex<Option<Endpoint>> = Mutex::new(None);
+
+/// Use `endpoint` for later probes; `None` goes back to `DEFAULT_URL`.
+pub fn set_endpoint(endpoint: Option<Endpoint>) {
+    if let Ok(mut current) = PROBE_ENDPOINT.lock() {
+        *current = endpoint;
+    }
+}
+
+/// The endpoint the next probe downloads from.
+pub fn endpoint() -> Endpoint {
+    PROBE_ENDPOINT
+        .lock()
+        .ok()
+        .and_then(|current| current.clone())
+        .unwrap_or_else(|| Endpoint::parse(DEFAULT_URL).expect("the default URL parses"))
+}
+
+/// Go back to the default endpoint.
+pub fn reset() {
+    set_endpoint(None);
+}
+
+impl Endpoint {
+    /// Parse `http://host[:port][/path]`; IPv6 hosts go in brackets.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("{} is not an http:// URL", url))?;
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('/') => (&rest[..i], rest[i..].to_owned()),
+            Some(i) => (&rest[..i], format!("/{}", &rest[i..])),
+            None => (rest, "/".to_owned()),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) if !authority[i..].contains(']') => {
+                let port = authority[i + 1..]
+                    .parse::<u16>()
+                    .map_err(|_| format!("bad port in {}", url))?;
+                (&authority[..i], port)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || port == 0 {
+            return Err(format!("no host or port in {}", url));
+        }
+        Ok(Endpoint { host: host.to_owned(), port, path })
+    }
+}
+
+/// Request `endpoint` over `stream` and return the body's rate in
+/// kilobytes (1000 bytes) per second, reading at most `PROBE_BYTES`. If
+/// `deadline` passes first, the rate so far is returned when at least
+/// `MIN_SAMPLE_BYTES` arrived.
+pub async fn measure<S>(mut stream: S, endpoint: &Endpoint, deadline: Instant) -> io::Result<u64>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        endpoint.path, endpoint.host
+    );
+    let timed_out = || io::Error::new(io::ErrorKind::TimedOut, "bandwidth probe timed out");
+    tokio::time::timeout_at(deadline, stream.write_all(request.as_bytes()))
+        .await
+        .map_err(|_| timed_out())??;
+
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut header = Vec::new();
+    let mut received = loop {
+        let n = tokio::time::timeout_at(deadline, stream.read(&mut buf))
+            .await
+            .map_err(|_| timed_out())??;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no response"));
+        }
+        header.extend_from_slice(&buf[..n]);
+        if let Some(end) = header.windows(4).position(|w| w == b"\r\n\r\n") {
+            check_status(&header[..end])?;
+            break header.len() - (end + 4);
+        }
+        if header.len() > MAX_HEADER_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response header too long"));
+        }
+    };
+
+    let started = Instant::now();
+    while received < PROBE_BYTES {
+        match tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => received += n,
+            Ok(Err(e)) => return Err(e),
+            Err(_) if received >= MIN_SAMPLE_BYTES => break,
+            Err(_) => return Err(timed_out()),
+        }
+    }
+    if received == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "empty response body"));
+    }
+    Ok(rate(received, started.elapsed()))
+}
+
+/// Fail unless `header` starts with a 2xx status line.
+fn check_status(header: &[u8]) -> io::Result<()> {
+    let line = header.split(|&b| b == b'\r').next().unwrap_or_default();
+    let code = std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse::<u16>().ok());
+    match code {
+        Some(200..=299) => Ok(()),
+        Some(code) => Err(io::Error::other(format!("probe endpoint answered {}", code))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "not an HTTP response")),
+    }
+}
+
+/// Kilobytes per second, never 0 for a transfer that happened at all.
+fn rate(bytes: usize, elapsed: Duration) -> u64 {
+    let millis = elapsed.as_millis().max(1) as u64;
+    (bytes as u64 / millis).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_http_urls() {
+        let default = Endpoint::parse(DEFAULT_URL).unwrap();
+        assert_eq!((default.host.as_str(), default.port), ("speed.cloudflare.com", 80));
+        assert_eq!(default.path, "/__down?bytes=262144");
+        let v6 = Endpoint::parse("http://[2001:db8::1]:8080").unwrap();
+        assert_eq!((v6.host.as_str(), v6.port, v6.path.as_str()), ("2001:db8::1", 8080, "/"));
+        assert_eq!(Endpoint::parse("http://example.com?x=1").unwrap().path, "/?x=1");
+        for bad in ["https://example.com/", "http://", "http://example.com:0/", "example.com"] {
+            assert!(Endpoint::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn set_endpoint_replaces_the_default_until_reset() {
+        let own = Endpoint::parse("http://probe.example:8080/file").unwrap();
+        set_endpoint(Some(own.clone()));
+        assert_eq!(endpoint(), own);
+        reset();
+        assert_eq!(endpoint(), Endpoint::parse(DEFAULT_URL).unwrap());
+    }
+
+    #[tokio::test]
+    async fn times_the_body_and_rejects_errors() {
+        let endpoint = Endpoint::parse(DEFAULT_URL).unwrap();
+        let (near, mut far) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(async move {
+            let mut request = [0u8; 512];
+            let n = far.read(&mut request).await.unwrap();
+            assert!(request[..n].starts_with(b"GET /__down?bytes="));
+            far.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n")
+                .await
+                .unwrap();
+            far.write_all(&vec![0u8; PROBE_BYTES]).await.unwrap();
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert!(measure(near, &endpoint, deadline).await.unwrap() >= 1);
+        server.await.unwrap();
+
+        let (near, mut far) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let _ = far.read(&mut [0u8; 512]).await;
+            let _ = far.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await;
+        });
+        assert!(measure(near, &endpoint, deadline).await.is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Bridge lines supplied by the app
+//!
+//! Bridges are parsed when they are set, so mistakes are reported to the
+//! caller immediately, and folded into each configuration built afterwards.
+
+use std::sync::Mutex;
+
+use arti_client::config::BridgeConfigBuilder;
+
+/// Bridges to use from the next start
+static BRIDGES: Mutex<Vec<BridgeConfigBuilder>> = Mutex::new(Vec::new());
+
+/// Parse newline-separated bridge lines.
+///
+/// Blank lines and lines starting with `#` are skipped, and a torrc-style
+/// `Bridge` prefix is accepted. On failure the error names the 1-based
+/// line number.
+pub fn parse_lines(blob: &str) -> Result<Vec<BridgeConfigBuilder>, String> {
+    parsed_lines(blob).collect()
+}
+
+/// Parse bridge lines like [`parse_lines`], but skip lines that fail
+/// instead of rejecting the lot, returning the bridges and an error per
+/// skipped line.
+///
+/// Meant for existing Tor `bridges` files, which may hold transports this
+/// build can't use or other torrc options alongside the bridges.
+pub fn parse_usable_lines(blob: &str) -> (Vec<BridgeConfigBuilder>, Vec<String>) {
+    let mut bridges = Vec::new();
+    let mut skipped = Vec::new();
+    for result in parsed_lines(blob) {
+        match result {
+            Ok(bridge) => bridges.push(bridge),
+            Err(e) => skipped.push(e),
+        }
+    }
+    (bridges, skipped)
+}
+
+fn parsed_lines(blob: &str) -> impl Iterator<Item = Result<BridgeConfigBuilder, String>> + '_ {
+    blob.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        Some(line.parse().map_err(|e| format!("line {}: {}", i + 1, e)))
+    })
+}
+
+/// Normalize a bridge as found in a QR code or link into canonical bridge
+/// lines (single-spaced, no `Bridge` prefix).
+///
+/// Recognized encodings:
+/// * `bridge://` followed by a percent-encoded bridge line, as shared by
+///   Tor Browser for Android and Orbot
+/// * The list that bridges.torproject.org QR codes carry, e.g.
+///   `['obfs4 192.0.2.1:443 ... ', 'obfs4 ...']`
+/// * A plain bridge line
+///
+/// Returns `None` for anything else. The lines are not checked for being
+/// usable bridges; see [`parse_lines`].
+pub fn normalize_uri(uri: &str) -> Option<Vec<String>> {
+    let uri = uri.trim();
+    let raw: Vec<String> = if let Some(rest) = strip_prefix_ignore_case(uri, "bridge://") {
+        vec![percent_decode(rest)?]
+    } else if uri.starts_with('[') && uri.ends_with(']') {
+        uri[1..uri.len() - 1]
+            .split(',')
+            .map(|item| item.trim().trim_matches(|c| c == '\'' || c == '"').to_owned())
+            .filter(|item| !item.is_empty())
+            .collect()
+    } else if !uri.contains("://") {
+        vec![uri.to_owned()]
+    } else {
+        return None;
+    };
+
+    let lines: Vec<String> = raw.iter().filter_map(|line| canonical_line(line)).collect();
+    (!lines.is_empty() && lines.len() == raw.len()).then_some(lines)
+}
+
+/// Collapse whitespace and drop a `Bridge` prefix; `None` unless the line
+/// looks like `[transport] host:port ...`.
+fn canonical_line(line: &str) -> Option<String> {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    if words.first().is_some_and(|w| w.eq_ignore_ascii_case("bridge")) {
+        words.remove(0);
+    }
+    let addr_word = match words.first() {
+        Some(w) if w.contains(':') => w,
+        Some(transport) if transport.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+            words.get(1)?
+        }
+        _ => return None,
+    };
+    if !addr_word.contains(':') {
+        return None;
+    }
+    Some(words.join(" "))
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &s[prefix.len()..])
+}
+
+/// Decode `%XX` escapes (and `+` as space); `None` on a bad escape or
+/// non-UTF-8 result.
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Replace the configured bridges.
+pub fn set(bridges: Vec<BridgeConfigBuilder>) {
+    if let Ok(mut current) = BRIDGES.lock() {
+        *current = bridges;
+    }
+}
+
+/// The configured bridges.
+pub fn configured() -> Vec<BridgeConfigBuilder> {
+    BRIDGES.lock().map(|b| b.clone()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FP: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    #[test]
+    fn parses_lines_skipping_blanks_and_comments() {
+        let blob = format!(
+            "# from bridges.torproject.org\n\n\
+             192.0.2.1:443 {FP}\r\n\
+             Bridge [2001:db8::1]:9001 {FP}\n"
+        );
+        assert_eq!(parse_lines(&blob).unwrap().len(), 2);
+        assert!(parse_lines("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn normalizes_bridge_scheme_uri() {
+        let uri = format!("bridge://obfs4%20192.0.2.1%3A443%20{FP}%20cert%3DAbC%2B%20iat-mode%3D0");
+        assert_eq!(
+            normalize_uri(&uri).unwrap(),
+            vec![format!("obfs4 192.0.2.1:443 {FP} cert=AbC+ iat-mode=0")]
+        );
+
+        let plain = format!("BRIDGE://192.0.2.1%3A443+{FP}");
+        assert_eq!(normalize_uri(&plain).unwrap(), vec![format!("192.0.2.1:443 {FP}")]);
+    }
+
+    #[test]
+    fn normalizes_bridgedb_qr_list() {
+        let qr = format!(
+            "['obfs4 192.0.2.1:443 {FP} cert=x iat-mode=0', \
+             'obfs4 192.0.2.2:80 {FP} cert=y iat-mode=0']"
+        );
+        let lines = normalize_uri(&qr).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], format!("obfs4 192.0.2.2:80 {FP} cert=y iat-mode=0"));
+    }
+
+    #[test]
+    fn normalizes_plain_lines_and_rejects_unknown_formats() {
+        let line = format!("  Bridge   192.0.2.1:443   {FP} ");
+        assert_eq!(normalize_uri(&line).unwrap(), vec![format!("192.0.2.1:443 {FP}")]);
+
+        assert_eq!(normalize_uri("https://bridges.torproject.org/"), None);
+        assert_eq!(normalize_uri("bridge://%zz"), None);
+        assert_eq!(normalize_uri("hello world"), None);
+        assert_eq!(normalize_uri(""), None);
+    }
+
+    #[test]
+    fn reports_line_number_of_first_error() {
+        let blob = format!("192.0.2.1:443 {FP}\nnot-a-bridge\n192.0.2.2:443 nonsense");
+        let err = parse_lines(&blob).unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+    }
+
+    #[test]
+    fn usable_lines_skip_what_fails() {
+        let file = format!("UseBridges 1\n# mine\nBridge 192.0.2.1:443 {FP}\n\nnot-a-bridge\n");
+        let (bridges, skipped) = parse_usable_lines(&file);
+        assert_eq!(bridges.len(), 1);
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped[0].starts_with("line 1:"), "{}", skipped[0]);
+        assert!(skipped[1].starts_with("line 5:"), "{}", skipped[1]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Passphrase-encrypted config bundles
+//!
+//! A bundle carries bridge lines (the same text `arti_set_bridges_blob`
+//! accepts) so they need not sit on disk in plaintext. The format uses only
+//! primitives already linked in for Tor, so it can be produced with any
+//! common crypto library:
+//!
+//! ```text
+//! magic      4 bytes   "ARB1"
+//! iterations 4 bytes   PBKDF2 rounds, big-endian
+//! salt      16 bytes
+//! iv        16 bytes   initial AES-CTR counter block
+//! ciphertext N bytes   AES-256-CTR of the UTF-8 bridge lines
+//! tag       32 bytes   HMAC-SHA256 over everything before it
+//! ```
+//!
+//! The 64 bytes of PBKDF2-HMAC-SHA256(passphrase, salt, iterations) are the
+//! AES key followed by the HMAC key. The tag is checked before anything is
+//! decrypted, so a wrong passphrase and a corrupted body look the same;
+//! both are reported as a wrong passphrase.
+
+use cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use tor_llcrypto::cipher::aes::Aes256Ctr;
+use tor_llcrypto::d::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MAGIC: &[u8; 4] = b"ARB1";
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 4 + SALT_LEN + IV_LEN;
+
+/// Iteration bounds: enough to slow guessing, without letting a hostile
+/// bundle stall the caller
+const MIN_ITERATIONS: u32 = 10_000;
+const MAX_ITERATIONS: u32 = 10_000_000;
+
+/// Why a bundle could not be opened.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BundleError {
+    /// Not a bundle, or a header this version doesn't accept
+    Malformed(&'static str),
+    /// The tag didn't verify: wrong passphrase or a corrupted body
+    WrongPassphrase,
+}
+
+/// Decrypt `data` with `passphrase`, returning the bundled bridge lines.
+pub fn open(data: &[u8], passphrase: &[u8]) -> Result<String, BundleError> {
+    if data.len() < HEADER_LEN + TAG_LEN || !data.starts_with(MAGIC) {
+        return Err(BundleError::Malformed("not a config bundle"));
+    }
+    let iterations = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iterations) {
+        return Err(BundleError::Malformed("unsupported iteration count"));
+    }
+    let salt = &data[8..8 + SALT_LEN];
+    let iv = &data[8 + SALT_LEN..HEADER_LEN];
+    let (body, tag) = data.split_at(data.len() - TAG_LEN);
+
+    let keys = derive_keys(passphrase, salt, iterations);
+    let mut mac = HmacSha256::new_from_slice(&keys[32..]).expect("HMAC takes any key length");
+    mac.update(body);
+    mac.verify_slice(tag).map_err(|_| BundleError::WrongPassphrase)?;
+
+    let mut plaintext = body[HEADER_LEN..].to_vec();
+    Aes256Ctr::new(keys[..32].into(), iv.into()).apply_keystream(&mut plaintext);
+    String::from_utf8(plaintext).map_err(|_| BundleError::Malformed("contents are not UTF-8"))
+}
+
+/// PBKDF2-HMAC-SHA256 with a 64-byte output (two blocks).
+fn derive_keys(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 64] {
+    let prf = HmacSha256::new_from_slice(passphrase).expect("HMAC takes any key length");
+    let mut out = [0u8; 64];
+    for (block, chunk) in out.chunks_mut(32).enumerate() {
+        let mut mac = prf.clone();
+        mac.update(salt);
+        mac.update(&(block as u32 + 1).to_be_bytes());
+        let mut u = mac.finalize().into_bytes();
+        chunk.copy_from_slice(&u);
+        for _ in 1..iterations {
+            let mut mac = prf.clone();
+            mac.update(&u);
+            u = mac.finalize().into_bytes();
+            chunk.iter_mut().zip(u.iter()).for_each(|(c, b)| *c ^= b);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a bundle the way an app-side tool would.
+    fn seal(plaintext: &str, passphrase: &[u8], iterations: u32) -> Vec<u8> {
+        let salt = [7u8; SALT_LEN];
+        let iv = [9u8; IV_LEN];
+        let keys = derive_keys(passphrase, &salt, iterations);
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&iterations.to_be_bytes());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&iv);
+        let mut ciphertext = plaintext.as_bytes().to_vec();
+        Aes256Ctr::new(keys[..32].into(), (&iv).into()).apply_keystream(&mut ciphertext);
+        data.extend_from_slice(&ciphertext);
+
+        let mut mac = HmacSha256::new_from_slice(&keys[32..]).unwrap();
+        mac.update(&data);
+        data.extend_from_slice(&mac.finalize().into_bytes());
+        data
+    }
+
+    #[test]
+    fn pbkdf2_matches_rfc7914_vector() {
+        // RFC 7914 section 11: PBKDF2-HMAC-SHA256("passwd", "salt", 1, 64)
+        let out = derive_keys(b"passwd", b"salt", 1);
+        assert_eq!(out[..8], [0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f]);
+        assert_eq!(out[56..], [0x09, 0x11, 0x20, 0x41, 0xd3, 0xa1, 0x97, 0x83]);
+    }
+
+    #[test]
+    fn round_trips_bridge_lines() {
+        let lines = "192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567\n";
+        let bundle = seal(lines, b"correct horse", MIN_ITERATIONS);
+        assert_eq!(open(&bundle, b"correct horse").unwrap(), lines);
+    }
+
+    #[test]
+    fn distinguishes_wrong_passphrase_from_malformed() {
+        let mut bundle = seal("lines", b"secret", MIN_ITERATIONS);
+        assert_eq!(open(&bundle, b"guess"), Err(BundleError::WrongPassphrase));
+
+        assert!(matches!(open(b"ARB1", b"secret"), Err(BundleError::Malformed(_))));
+        assert!(matches!(open(&[0u8; 100], b"secret"), Err(BundleError::Malformed(_))));
+        bundle[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(open(&bundle, b"secret"), Err(BundleError::Malformed(_))));
+    }
+}
@@ -0,0 +1,398 @@
+//! C callback registration
+//!
+//! Callbacks are registered from Swift together with an opaque context
+//! pointer, which is handed back untouched on every invocation. Callbacks may
+//! fire on any Arti worker thread.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Mutex;
+
+use arti_client::status::BlockageKind;
+
+use crate::{eventlog, json};
+
+/// Callback fired as bootstrap first reaches each milestone; `percent` is
+/// 25, 50, 75 or 100.
+pub type ArtiMilestoneCallback = extern "C" fn(percent: c_int, ctx: *mut c_void);
+
+/// Callback fired when connections to the Tor network itself fail.
+///
+/// `category` is one of the `NETWORK_ERROR_*` constants and `message` is a
+/// human-readable, null-terminated UTF-8 string valid only for the duration
+/// of the call.
+pub type ArtiNetworkErrorCallback =
+    extern "C" fn(category: c_int, message: *const c_char, ctx: *mut c_void);
+
+/// Callback receiving each bootstrap status update as a JSON object.
+///
+/// `json` is a null-terminated UTF-8 string valid only for the duration of
+/// the call.
+pub type ArtiBootstrapJsonCallback = extern "C" fn(json: *const c_char, ctx: *mut c_void);
+
+/// Callback receiving SOCKS stream events as JSON objects.
+///
+/// `json` is a null-terminated UTF-8 string valid only for the duration of
+/// the call.
+pub type ArtiStreamEventCallback = extern "C" fn(json: *const c_char, ctx: *mut c_void);
+
+/// Callback fired when overall connectivity flips; `online` is 1 when arti
+/// is ready for traffic and 0 otherwise.
+pub type ArtiConnectivityCallback = extern "C" fn(online: c_int, ctx: *mut c_void);
+
+/// Callback fired when the device itself appears to lose or regain its
+/// network connection; `offline` is 1 when it looks offline and 0 once it
+/// no longer does.
+pub type ArtiOfflineCallback = extern "C" fn(offline: c_int, ctx: *mut c_void);
+
+/// Callback fired when a new consensus is installed; `valid_after` is its
+/// valid-after time in seconds since the Unix epoch.
+pub type ArtiConsensusCallback = extern "C" fn(valid_after: i64, ctx: *mut c_void);
+
+/// Callback fired when a background budget is nearly used up;
+/// `remaining_secs` is the time left in the budget.
+pub type ArtiBudgetExpiringCallback = extern "C" fn(remaining_secs: c_int, ctx: *mut c_void);
+
+/// Callback fired when bootstrap has been blocked from reaching the Tor
+/// network long enough that bridges are worth suggesting. `category` is the
+/// `NETWORK_ERROR_*` value of the blockage and `stalled_secs` how long
+/// bootstrap has gone without progress.
+pub type ArtiCensorshipCallback =
+    extern "C" fn(category: c_int, stalled_secs: c_int, ctx: *mut c_void);
+
+/// Callback asked before each SOCKS CONNECT is opened over Tor; return
+/// nonzero to refuse the connection.
+///
+/// `host` is the requested hostname or address as a null-terminated UTF-8
+/// string valid only for the duration of the call, and `port` the
+/// destination port.
+pub type ArtiConnectionFilterCallback =
+    extern "C" fn(host: *const c_char, port: u16, ctx: *mut c_void) -> c_int;
+
+/// Callback fired when the async runtime has failed to run a trivial task
+/// within `stalled_ms` milliseconds, suggesting its threads are stuck.
+pub type ArtiRuntimeStallCallback = extern "C" fn(stalled_ms: c_int, ctx: *mut c_void);
+
+/// Callback asked for fresh bridges when bootstrap looks blocked; returns
+/// newline-separated bridge lines, or NULL to offer none.
+///
+/// The returned string stays owned by the app and is never freed by the
+/// library, which copies it as soon as the callback returns. It must stay
+/// valid until then: return a static string, or keep the last one alive
+/// and free it on the next call.
+pub type ArtiBridgeProviderCallback = extern "C" fn(ctx: *mut c_void) -> *const c_char;
+
+/// Unclassified failure
+pub const NETWORK_ERROR_OTHER: c_int = 0;
+/// The device does not appear to have a working internet connection
+pub const NETWORK_ERROR_OFFLINE: c_int = 1;
+/// TCP connections succeed but Tor handshakes do not (likely censorship)
+pub const NETWORK_ERROR_FILTERED: c_int = 2;
+/// Guards or other relays could not be reached
+pub const NETWORK_ERROR_GUARD_UNREACHABLE: c_int = 3;
+/// The local clock appears to be wrong
+pub const NETWORK_ERROR_CLOCK_SKEW: c_int = 4;
+/// Directory information (consensus, descriptors) could not be fetched
+pub const NETWORK_ERROR_DIRECTORY: c_int = 5;
+/// A local SOCKS listener failed and could not be rebound
+pub const NETWORK_ERROR_LISTENER: c_int = 6;
+
+/// A registered callback and its context pointer.
+pub(crate) struct CallbackSlot<F: Copy> {
+    inner: Mutex<Option<(F, usize)>>,
+}
+
+impl<F: Copy> CallbackSlot<F> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            inner: Mutex::new(None),
+        }
+    }
+
+    /// Register `cb` (or clear the slot when `None`).
+    pub(crate) fn set(&self, cb: Option<F>, ctx: *mut c_void) {
+        if let Ok(mut guard) = self.inner.lock() {
+            *guard = cb.map(|cb| (cb, ctx as usize));
+        }
+    }
+
+    /// Return the registered callback, if any.
+    pub(crate) fn get(&self) -> Option<(F, *mut c_void)> {
+        let guard = self.inner.lock().ok()?;
+        guard.map(|(cb, ctx)| (cb, ctx as *mut c_void))
+    }
+}
+
+pub(crate) static NETWORK_ERROR: CallbackSlot<ArtiNetworkErrorCallback> = CallbackSlot::new();
+pub(crate) static BOOTSTRAP_JSON: CallbackSlot<ArtiBootstrapJsonCallback> = CallbackSlot::new();
+pub(crate) static CONNECTIVITY: CallbackSlot<ArtiConnectivityCallback> = CallbackSlot::new();
+pub(crate) static CONSENSUS: CallbackSlot<ArtiConsensusCallback> = CallbackSlot::new();
+pub(crate) static BUDGET_EXPIRING: CallbackSlot<ArtiBudgetExpiringCallback> = CallbackSlot::new();
+pub(crate) static CENSORSHIP: CallbackSlot<ArtiCensorshipCallback> = CallbackSlot::new();
+pub(crate) static OFFLINE: CallbackSlot<ArtiOfflineCallback> = CallbackSlot::new();
+pub(crate) static CONNECTION_FILTER: CallbackSlot<ArtiConnectionFilterCallback> =
+    CallbackSlot::new();
+pub(crate) static STREAM_EVENT: CallbackSlot<ArtiStreamEventCallback> = CallbackSlot::new();
+pub(crate) static RUNTIME_STALL: CallbackSlot<ArtiRuntimeStallCallback> = CallbackSlot::new();
+pub(crate) static BRIDGE_PROVIDER: CallbackSlot<ArtiBridgeProviderCallback> =
+    CallbackSlot::new();
+pub(crate) static MILESTONE: CallbackSlot<ArtiMilestoneCallback> = CallbackSlot::new();
+
+/// Bootstrap percentages `MILESTONE` reports, evenly spaced up to 100
+const MILESTONE_STEP: c_int = 25;
+/// Highest milestone reported since the last start
+static LAST_MILESTONE: AtomicI32 = AtomicI32::new(0);
+
+/// Last connectivity state reported to `CONNECTIVITY`
+static ONLINE: AtomicBool = AtomicBool::new(false);
+/// Last device state reported to `OFFLINE`
+static DEVICE_OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Map an arti blockage to a network error category.
+pub(crate) fn blockage_category(kind: &BlockageKind) -> c_int {
+    match kind {
+        BlockageKind::Offline => NETWORK_ERROR_OFFLINE,
+        BlockageKind::Filtering => NETWORK_ERROR_FILTERED,
+        BlockageKind::CantReachTor => NETWORK_ERROR_GUARD_UNREACHABLE,
+        BlockageKind::ClockSkewed => NETWORK_ERROR_CLOCK_SKEW,
+        BlockageKind::CantBootstrap => NETWORK_ERROR_DIRECTORY,
+        _ => NETWORK_ERROR_OTHER,
+    }
+}
+
+/// Convert `s` for handing to C, dropping any interior NULs.
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+/// Invoke the network error callback, if registered, and log the error.
+pub(crate) fn notify_network_error(category: c_int, message: &str) {
+    eventlog::record(
+        "error",
+        &json::Object::new().num("category", category).str("message", message).finish(),
+    );
+    if let Some((cb, ctx)) = NETWORK_ERROR.get() {
+        let message = c_string(message);
+        cb(category, message.as_ptr(), ctx);
+    }
+}
+
+/// Invoke the bootstrap JSON callback, if registered, and log the update.
+pub(crate) fn notify_bootstrap_json(json: &str) {
+    eventlog::record("bootstrap", json);
+    if let Some((cb, ctx)) = BOOTSTRAP_JSON.get() {
+        let json = c_string(json);
+        cb(json.as_ptr(), ctx);
+    }
+}
+
+/// Invoke the stream event callback, if registered, and log the event.
+pub(crate) fn notify_stream_event(json: &str) {
+    eventlog::record("stream", json);
+    if let Some((cb, ctx)) = STREAM_EVENT.get() {
+        let json = c_string(json);
+        cb(json.as_ptr(), ctx);
+    }
+}
+
+/// Invoke the consensus callback, if registered.
+pub(crate) fn notify_consensus(valid_after: i64) {
+    if let Some((cb, ctx)) = CONSENSUS.get() {
+        cb(valid_after, ctx);
+    }
+}
+
+/// Invoke the budget-expiring callback, if registered.
+pub(crate) fn notify_budget_expiring(remaining_secs: c_int) {
+    if let Some((cb, ctx)) = BUDGET_EXPIRING.get() {
+        cb(remaining_secs, ctx);
+    }
+}
+
+/// Invoke the runtime stall callback, if registered.
+pub(crate) fn notify_runtime_stall(stalled_ms: c_int) {
+    if let Some((cb, ctx)) = RUNTIME_STALL.get() {
+        cb(stalled_ms, ctx);
+    }
+}
+
+/// Invoke the censorship callback, if registered.
+pub(crate) fn notify_censorship(category: c_int, stalled_secs: c_int) {
+    if let Some((cb, ctx)) = CENSORSHIP.get() {
+        cb(category, stalled_secs, ctx);
+    }
+}
+
+/// Note bootstrap progress, invoking the milestone callback, if
+/// registered, for each milestone `percent` passes for the first time.
+/// Progress that jumps past several reports each of them in order.
+pub(crate) fn report_progress(percent: c_int) {
+    let reached = percent.clamp(0, 100) / MILESTONE_STEP * MILESTONE_STEP;
+    let last = LAST_MILESTONE.fetch_max(reached, Ordering::SeqCst);
+    if reached <= last {
+        return;
+    }
+    if let Some((cb, ctx)) = MILESTONE.get() {
+        for milestone in (last + MILESTONE_STEP..=reached).step_by(MILESTONE_STEP as usize) {
+            cb(milestone, ctx);
+        }
+    }
+}
+
+/// Start counting milestones from 0 again, for a new start.
+pub(crate) fn reset_milestones() {
+    LAST_MILESTONE.store(0, Ordering::SeqCst);
+}
+
+/// Ask the connection filter callback, if registered, whether to refuse a
+/// connection to `host`:`port`.
+///
+/// The callback runs on a blocking-task thread, so a slow policy lookup
+/// holds up only this connection. Refuses if the callback panics.
+pub(crate) async fn connection_blocked(host: &str, port: u16) -> bool {
+    let Some((cb, ctx)) = CONNECTION_FILTER.get() else {
+        return false;
+    };
+    let host = c_string(host);
+    let ctx = ctx as usize;
+    tokio::task::spawn_blocking(move || cb(host.as_ptr(), port, ctx as *mut c_void) != 0)
+        .await
+        .unwrap_or(true)
+}
+
+/// Ask the bridge provider callback, if registered, for bridge lines.
+///
+/// The callback runs on a blocking-task thread, since fetching bridges may
+/// take a network round trip. Returns `None` if there is no callback, it
+/// returns NULL or invalid UTF-8, or it panics.
+pub(crate) async fn provide_bridges() -> Option<String> {
+    let (cb, ctx) = BRIDGE_PROVIDER.get()?;
+    let ctx = ctx as usize;
+    tokio::task::spawn_blocking(move || {
+        let lines = cb(ctx as *mut c_void);
+        if lines.is_null() {
+            return None;
+        }
+        unsafe { CStr::from_ptr(lines) }.to_str().ok().map(str::to_owned)
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Unregister every callback and forget the last connectivity state.
+pub(crate) fn clear_all() {
+    NETWORK_ERROR.set(None, std::ptr::null_mut());
+    BOOTSTRAP_JSON.set(None, std::ptr::null_mut());
+    CONNECTIVITY.set(None, std::ptr::null_mut());
+    CONSENSUS.set(None, std::ptr::null_mut());
+    BUDGET_EXPIRING.set(None, std::ptr::null_mut());
+    CENSORSHIP.set(None, std::ptr::null_mut());
+    STREAM_EVENT.set(None, std::ptr::null_mut());
+    CONNECTION_FILTER.set(None, std::ptr::null_mut());
+    OFFLINE.set(None, std::ptr::null_mut());
+    BRIDGE_PROVIDER.set(None, std::ptr::null_mut());
+    RUNTIME_STALL.set(None, std::ptr::null_mut());
+    MILESTONE.set(None, std::ptr::null_mut());
+    reset_milestones();
+    ONLINE.store(false, Ordering::SeqCst);
+    DEVICE_OFFLINE.store(false, Ordering::SeqCst);
+}
+
+/// Record the current connectivity state, notifying the callback if it
+/// changed.
+pub(crate) fn set_online(online: bool) {
+    if ONLINE.swap(online, Ordering::SeqCst) != online {
+        if let Some((cb, ctx)) = CONNECTIVITY.get() {
+            cb(online as c_int, ctx);
+        }
+    }
+}
+
+/// Record whether the device looks offline, notifying the callback if that
+/// changed. Returns true when the device has just come back.
+pub(crate) fn set_device_offline(offline: bool) -> bool {
+    let was_offline = DEVICE_OFFLINE.swap(offline, Ordering::SeqCst);
+    if was_offline == offline {
+        return false;
+    }
+    if let Some((cb, ctx)) = OFFLINE.get() {
+        cb(offline as c_int, ctx);
+    }
+    was_offline
+}
+
+/// Register the connectivity callback and immediately report the current
+/// state to it.
+pub(crate) fn set_connectivity_callback(cb: Option<ArtiConnectivityCallback>, ctx: *mut c_void) {
+    CONNECTIVITY.set(cb, ctx);
+    if let Some(cb) = cb {
+        cb(ONLINE.load(Ordering::SeqCst) as c_int, ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LAST_OFFLINE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn record_offline(offline: c_int, _ctx: *mut c_void) {
+        LAST_OFFLINE.store(offline, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn offline_callback_fires_on_changes_only() {
+        OFFLINE.set(Some(record_offline), std::ptr::null_mut());
+        assert!(!set_device_offline(true));
+        assert_eq!(LAST_OFFLINE.swap(-1, Ordering::SeqCst), 1);
+        assert!(!set_device_offline(true));
+        assert_eq!(LAST_OFFLINE.load(Ordering::SeqCst), -1);
+
+        assert!(set_device_offline(false));
+        assert_eq!(LAST_OFFLINE.load(Ordering::SeqCst), 0);
+        assert!(!set_device_offline(false));
+        OFFLINE.set(None, std::ptr::null_mut());
+    }
+
+    static MILESTONES: Mutex<Vec<c_int>> = Mutex::new(Vec::new());
+
+    extern "C" fn record_milestone(percent: c_int, _ctx: *mut c_void) {
+        MILESTONES.lock().unwrap().push(percent);
+    }
+
+    #[test]
+    fn milestones_fire_once_each_in_order() {
+        reset_milestones();
+        MILESTONE.set(Some(record_milestone), std::ptr::null_mut());
+        for percent in [5, 30, 30, 20, 90, 99, 100, 100] {
+            report_progress(percent);
+        }
+        assert_eq!(*MILESTONES.lock().unwrap(), [25, 50, 75, 100]);
+
+        reset_milestones();
+        report_progress(60);
+        assert_eq!(MILESTONES.lock().unwrap()[4..], [25, 50]);
+        MILESTONE.set(None, std::ptr::null_mut());
+    }
+
+    extern "C" fn provide_two_bridges(_ctx: *mut c_void) -> *const c_char {
+        c"192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567\n# spare\n".as_ptr()
+    }
+
+    extern "C" fn provide_nothing(_ctx: *mut c_void) -> *const c_char {
+        std::ptr::null()
+    }
+
+    #[tokio::test]
+    async fn bridge_provider_lines_are_copied_out() {
+        BRIDGE_PROVIDER.set(Some(provide_two_bridges), std::ptr::null_mut());
+        let lines = provide_bridges().await.unwrap();
+        assert!(lines.starts_with("192.0.2.1:443 ") && lines.ends_with("# spare\n"));
+
+        BRIDGE_PROVIDER.set(Some(provide_nothing), std::ptr::null_mut());
+        assert_eq!(provide_bridges().await, None);
+        BRIDGE_PROVIDER.set(None, std::ptr::null_mut());
+        assert_eq!(provide_bridges().await, None);
+    }
+}
@@ -0,0 +1,136 @@
+//! "Tor looks blocked" detection
+//!
+//! Arti reports a blockage once guard connections keep failing, but a
+//! single report isn't enough to tell censorship from a flaky network. The
+//! detector waits until bootstrap has been blocked on reaching the Tor
+//! network, with no progress at all, for a configurable number of seconds,
+//! then suggests bridges once. Any progress starts the wait over.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use arti_client::status::BlockageKind;
+
+/// Default seconds without progress before suggesting bridges
+pub const DEFAULT_THRESHOLD_SECS: u32 = 30;
+
+static THRESHOLD_SECS: AtomicU32 = AtomicU32::new(DEFAULT_THRESHOLD_SECS);
+
+/// The configured stall threshold.
+pub fn threshold() -> Duration {
+    Duration::from_secs(THRESHOLD_SECS.load(Ordering::SeqCst).into())
+}
+
+/// Set the stall threshold; applies from the next status update.
+pub fn set_threshold_secs(secs: u32) {
+    THRESHOLD_SECS.store(secs, Ordering::SeqCst);
+}
+
+/// Restore the default stall threshold.
+pub fn reset() {
+    set_threshold_secs(DEFAULT_THRESHOLD_SECS);
+}
+
+/// Whether a blockage points at the Tor network being unreachable from
+/// here, as opposed to being offline or having a wrong clock.
+pub fn suggests_censorship(kind: &BlockageKind) -> bool {
+    matches!(kind, BlockageKind::Filtering | BlockageKind::CantReachTor)
+}
+
+/// Tracks one bootstrap attempt.
+#[derive(Debug, Default)]
+pub struct Detector {
+    /// Highest progress seen so far
+    progress: f32,
+    /// When the current censorship-like stall began
+    stalled_since: Option<Instant>,
+    /// Whether this stall has already been reported
+    reported: bool,
+}
+
+impl Detector {
+    /// Feed a status update: `progress` is the bootstrap fraction and
+    /// `censored` whether arti currently reports a censorship-like
+    /// blockage.
+    pub fn observe(&mut self, progress: f32, censored: bool, now: Instant) {
+        if progress > self.progress {
+            self.progress = progress;
+            self.stalled_since = None;
+            self.reported = false;
+        }
+        if !censored {
+            self.stalled_since = None;
+        } else if self.stalled_since.is_none() {
+            self.stalled_since = Some(now);
+        }
+    }
+
+    /// When the current stall crosses `threshold`, if one is pending.
+    pub fn deadline(&self, threshold: Duration) -> Option<Instant> {
+        match self.stalled_since {
+            Some(since) if !self.reported => Some(since + threshold),
+            _ => None,
+        }
+    }
+
+    /// If the stall has lasted `threshold` and not been reported yet,
+    /// mark it reported and return how long it has lasted.
+    pub fn check(&mut self, threshold: Duration, now: Instant) -> Option<Duration> {
+        let since = self.stalled_since.filter(|_| !self.reported)?;
+        let stalled = now.saturating_duration_since(since);
+        if stalled < threshold {
+            return None;
+        }
+        self.reported = true;
+        Some(stalled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn reports_a_long_stall_once() {
+        let start = Instant::now();
+        let mut detector = Detector::default();
+        detector.observe(0.1, true, start);
+        assert_eq!(detector.deadline(THRESHOLD), Some(start + THRESHOLD));
+        assert_eq!(detector.check(THRESHOLD, start + Duration::from_secs(29)), None);
+
+        let late = start + Duration::from_secs(31);
+        assert_eq!(detector.check(THRESHOLD, late), Some(Duration::from_secs(31)));
+        assert_eq!(detector.check(THRESHOLD, late), None);
+        assert_eq!(detector.deadline(THRESHOLD), None);
+
+        // Still blocked, still no progress: not reported again
+        detector.observe(0.1, true, late);
+        assert_eq!(detector.check(THRESHOLD, late + THRESHOLD), None);
+    }
+
+    #[test]
+    fn progress_or_clearing_restarts_the_wait() {
+        let start = Instant::now();
+        let mut detector = Detector::default();
+        detector.observe(0.1, true, start);
+
+        let later = start + Duration::from_secs(20);
+        detector.observe(0.2, true, later);
+        assert_eq!(detector.check(THRESHOLD, start + THRESHOLD), None);
+        assert_eq!(detector.deadline(THRESHOLD), Some(later + THRESHOLD));
+
+        detector.observe(0.2, false, later);
+        assert_eq!(detector.deadline(THRESHOLD), None);
+        assert_eq!(detector.check(THRESHOLD, later + THRESHOLD), None);
+    }
+
+    #[test]
+    fn only_reachability_blockages_count() {
+        assert!(suggests_censorship(&BlockageKind::Filtering));
+        assert!(suggests_censorship(&BlockageKind::CantReachTor));
+        assert!(!suggests_censorship(&BlockageKind::Offline));
+        assert!(!suggests_censorship(&BlockageKind::ClockSkewed));
+    }
+}
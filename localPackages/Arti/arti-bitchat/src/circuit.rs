@@ -0,0 +1,205 @@
+//! Circuit path of the most recent connection
+//!
+//! Each CONNECT through Tor records the relays of the circuit it was
+//! attached to, replacing the previous record, so only the latest path is
+//! ever kept. Nicknames come from the consensus and are missing for bridges.
+//! Country codes need arti's GeoIP database, which this build leaves out,
+//! so they are always reported as unknown.
+//!
+//! The circuits that recent streams used are remembered too, so that
+//! `arti_reconnect` can retire them after the OS drops every socket, and
+//! so are those of streams opened under an isolation token, so that
+//! `arti_drop_isolation` can retire one group's circuits. The SOCKS layer
+//! passes the token down in [`STREAM_ISOLATION`] while it connects.
+
+use std::sync::Mutex;
+
+use arti_client::{DataStream, IsolationToken, TorClient};
+use tor_linkspec::{HasAddrs, HasRelayIds};
+use tor_proto::circuit::UniqId;
+use tor_proto::client::stream::ClientStreamCtrl;
+use tor_rtcompat::PreferredRuntime;
+
+use crate::json;
+
+/// One relay on a circuit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hop {
+    /// RSA identity, as uppercase hex
+    pub fingerprint: Option<String>,
+    /// Consensus nickname, if the relay is listed
+    pub nickname: Option<String>,
+    /// First advertised OR address
+    pub address: Option<String>,
+}
+
+static LAST_PATH: Mutex<Option<Vec<Hop>>> = Mutex::new(None);
+
+/// Circuits that recent streams were attached to, oldest first
+static USED: Mutex<Vec<UniqId>> = Mutex::new(Vec::new());
+/// How many circuits `USED` remembers
+const USED_CAP: usize = 64;
+
+tokio::task_local! {
+    /// Isolation token of the SOCKS connection whose stream is being opened
+    pub static STREAM_ISOLATION: Option<IsolationToken>;
+}
+
+/// Circuits that streams with an isolation token used, oldest first
+static ISOLATED: Mutex<Vec<(IsolationToken, UniqId)>> = Mutex::new(Vec::new());
+/// How many token/circuit pairs `ISOLATED` remembers
+const ISOLATED_CAP: usize = 256;
+
+/// Record the circuit that `stream` was attached to as the latest path.
+pub fn record(client: &TorClient<PreferredRuntime>, stream: &DataStream) {
+    let Some(tunnel) = stream.client_stream_ctrl().and_then(|ctrl| ctrl.tunnel()) else {
+        return;
+    };
+    if let Ok(mut used) = USED.lock() {
+        push_recent(&mut used, tunnel.unique_id(), USED_CAP);
+    }
+    if let Ok(Some(token)) = STREAM_ISOLATION.try_with(|token| *token) {
+        if let Ok(mut isolated) = ISOLATED.lock() {
+            push_recent(&mut isolated, (token, tunnel.unique_id()), ISOLATED_CAP);
+        }
+    }
+    let Some(path) = tunnel.all_paths().into_iter().next() else {
+        return;
+    };
+    let netdir = client.dirmgr().timely_netdir().ok();
+
+    let hops = path
+        .iter()
+        .filter_map(|entry| entry.as_chan_target())
+        .map(|target| Hop {
+            fingerprint: target.rsa_identity().map(|id| id.to_string().to_uppercase()),
+            nickname: netdir
+                .as_ref()
+                .and_then(|dir| dir.by_ids(target))
+                .map(|relay| relay.rs().nickname().to_owned()),
+            address: target.addrs().next().map(|addr| addr.to_string()),
+        })
+        .collect();
+    if let Ok(mut last) = LAST_PATH.lock() {
+        *last = Some(hops);
+    }
+}
+
+/// Forget the recorded path and circuits.
+pub fn clear() {
+    if let Ok(mut last) = LAST_PATH.lock() {
+        *last = None;
+    }
+    take_used();
+    if let Ok(mut isolated) = ISOLATED.lock() {
+        isolated.clear();
+    }
+}
+
+/// Take the circuits recent streams used, forgetting them.
+pub fn take_used() -> Vec<UniqId> {
+    USED.lock().map(|mut used| std::mem::take(&mut *used)).unwrap_or_default()
+}
+
+/// Take the circuits streams isolated with `token` used, forgetting them.
+pub fn take_isolated(token: IsolationToken) -> Vec<UniqId> {
+    ISOLATED
+        .lock()
+        .map(|mut isolated| take_matching(&mut isolated, &token))
+        .unwrap_or_default()
+}
+
+/// Remove the entries keyed `key`, returning their values.
+fn take_matching<K: PartialEq, V>(list: &mut Vec<(K, V)>, key: &K) -> Vec<V> {
+    let (taken, kept) = std::mem::take(list).into_iter().partition(|(k, _)| k == key);
+    *list = kept;
+    taken.into_iter().map(|(_, v)| v).collect()
+}
+
+/// Append `item` unless already present, dropping the oldest beyond `cap`.
+fn push_recent<T: PartialEq>(list: &mut Vec<T>, item: T, cap: usize) {
+    if list.contains(&item) {
+        return;
+    }
+    if list.len() >= cap {
+        list.remove(0);
+    }
+    list.push(item);
+}
+
+/// Fingerprint of the latest path's guard, if known.
+pub fn last_guard() -> Option<String> {
+    LAST_PATH.lock().ok()?.as_ref()?.first()?.fingerprint.clone()
+}
+
+/// The latest path as JSON, or `None` if no connection has been made.
+pub fn last_json() -> Option<String> {
+    let hops = LAST_PATH.lock().ok()?.clone()?;
+    Some(hops_json(&hops))
+}
+
+/// Encode `hops` as `{"hops":[{"role":..., ...}, ...]}`.
+fn hops_json(hops: &[Hop]) -> String {
+    let items = hops.iter().enumerate().map(|(i, hop)| {
+        let role = match i {
+            0 => "guard",
+            i if i + 1 == hops.len() => "exit",
+            _ => "middle",
+        };
+        json::Object::new()
+            .str("role", role)
+            .opt_str("fingerprint", hop.fingerprint.as_deref())
+            .opt_str("nickname", hop.nickname.as_deref())
+            .opt_str("address", hop.address.as_deref())
+            .opt_str("country", None)
+            .finish()
+    });
+    format!("{{\"hops\":{}}}", json::array(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hop(nickname: &str) -> Hop {
+        Hop {
+            fingerprint: Some("AAAA".into()),
+            nickname: Some(nickname.into()),
+            address: Some("192.0.2.1:443".into()),
+        }
+    }
+
+    #[test]
+    fn remembers_recent_circuits_once() {
+        let mut list = Vec::new();
+        for id in [1, 2, 1, 3, 4] {
+            push_recent(&mut list, id, 3);
+        }
+        assert_eq!(list, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn takes_only_the_matching_group() {
+        let mut list = vec![("a", 1), ("b", 2), ("a", 3)];
+        assert_eq!(take_matching(&mut list, &"a"), vec![1, 3]);
+        assert_eq!(list, vec![("b", 2)]);
+        assert!(take_matching(&mut list, &"c").is_empty());
+        assert!(take_isolated(IsolationToken::new()).is_empty());
+    }
+
+    #[test]
+    fn taking_with_nothing_recorded_is_harmless() {
+        clear();
+        assert!(take_used().is_empty());
+        assert!(take_used().is_empty());
+    }
+
+    #[test]
+    fn labels_guard_middle_and_exit() {
+        let json = hops_json(&[hop("g"), hop("m"), hop("e")]);
+        assert!(json.starts_with(r#"{"hops":[{"role":"guard","fingerprint":"AAAA","#));
+        assert!(json.contains(r#"{"role":"middle","#));
+        assert!(json.contains(r#"{"role":"exit","#));
+        assert!(json.ends_with(r#""address":"192.0.2.1:443","country":null}]}"#));
+    }
+}
@@ -0,0 +1,694 @@
+//! Client configuration
+//!
+//! Settings made through the FFI before `arti_start` are kept here and
+//! folded into the `TorClientConfig` each time one is built, so a running
+//! client can be reconfigured from the same inputs.
+
+use std::ffi::c_int;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+use arti_client::config::dir::NetworkConfigBuilder;
+use arti_client::config::{BridgeConfigBuilder, TorClientConfigBuilder};
+use arti_client::{TorClient, TorClientConfig};
+use tor_config::PaddingLevel;
+use tor_linkspec::HasAddrs;
+use tor_netdir::NetDir;
+use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_rtcompat::PreferredRuntime;
+
+/// Requested number of primary entry guards (0 = use the consensus default)
+pub static NUM_GUARDS: AtomicI32 = AtomicI32::new(0);
+/// Upper bound accepted by `arti_set_num_guards`
+pub const MAX_NUM_GUARDS: c_int = 10;
+
+/// Times a failed bootstrap is retried before giving up
+pub static BOOTSTRAP_RETRIES: AtomicI32 = AtomicI32::new(DEFAULT_BOOTSTRAP_RETRIES);
+pub const DEFAULT_BOOTSTRAP_RETRIES: c_int = 2;
+/// Upper bound accepted by `arti_set_bootstrap_retries`
+pub const MAX_BOOTSTRAP_RETRIES: c_int = 10;
+/// Times the whole bootstrap is attempted, each with a new client
+pub static BOOTSTRAP_ATTEMPTS: AtomicI32 = AtomicI32::new(1);
+/// Upper bound accepted by `arti_set_bootstrap_attempts`
+pub const MAX_BOOTSTRAP_ATTEMPTS: c_int = 10;
+
+/// Fixed circuit build timeout in milliseconds (0 = learned adaptively)
+pub static CIRCUIT_BUILD_TIMEOUT_MS: AtomicI32 = AtomicI32::new(0);
+/// Smallest build timeout the consensus parameter accepts
+pub const MIN_CIRCUIT_BUILD_TIMEOUT_MS: c_int = 10;
+
+/// Soft memory quota for arti in MiB (0 = arti's automatic default)
+pub static MAX_MEMORY_MB: AtomicI32 = AtomicI32::new(0);
+
+/// Channel padding level
+pub static PADDING: Mutex<PaddingLevel> = Mutex::new(PaddingLevel::Normal);
+
+/// Relay that must be used as the first hop, if any
+pub static PINNED_GUARD: Mutex<Option<RsaIdentity>> = Mutex::new(None);
+
+/// Custom directory authorities and fallback caches, replacing the public
+/// Tor network's
+pub static TOR_NETWORK: Mutex<Option<NetworkConfigBuilder>> = Mutex::new(None);
+
+/// Setting categories, as reported by `arti_apply_config`
+pub const CATEGORY_PADDING: c_int = 1 << 0;
+pub const CATEGORY_BRIDGES: c_int = 1 << 1;
+pub const CATEGORY_GUARDS: c_int = 1 << 2;
+pub const CATEGORY_MEMORY: c_int = 1 << 3;
+pub const CATEGORIES: [c_int; 4] = [
+    CATEGORY_PADDING,
+    CATEGORY_BRIDGES,
+    CATEGORY_GUARDS,
+    CATEGORY_MEMORY,
+];
+
+/// A set of settings; `None` fields are left unchanged when stored.
+///
+/// Parsed from a small TOML document:
+///
+/// ```toml
+/// [channel]
+/// padding = "reduced"          # "normal", "reduced" or "none"
+///
+/// [bridges]
+/// bridges = ["192.0.2.1:443 0123...4567"]   # [] clears
+///
+/// [guards]
+/// num_primary = 2              # 0 = consensus default
+///
+/// [system.memory]
+/// max_mb = 512                 # 0 = arti's default
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub padding: Option<PaddingLevel>,
+    pub bridges: Option<Vec<BridgeConfigBuilder>>,
+    pub num_guards: Option<i32>,
+    pub max_memory_mb: Option<i32>,
+}
+
+impl Settings {
+    /// Parse a settings document. Unknown sections and keys are rejected,
+    /// so typos don't go unnoticed.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let doc: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| e.message().to_owned())?;
+        let mut settings = Settings::default();
+        for (section, value) in &doc {
+            let table = as_table(section, value)?;
+            match section.as_str() {
+                "channel" => {
+                    only_keys(section, table, &["padding"])?;
+                    if let Some(v) = table.get("padding") {
+                        settings.padding = Some(parse_padding(v)?);
+                    }
+                }
+                "bridges" => {
+                    only_keys(section, table, &["bridges"])?;
+                    if let Some(v) = table.get("bridges") {
+                        let lines = v
+                            .as_array()
+                            .and_then(|a| a.iter().map(|l| l.as_str()).collect::<Option<Vec<_>>>())
+                            .ok_or("bridges.bridges must be an array of strings")?;
+                        let parsed = crate::bridges::parse_lines(&lines.join("\n"))
+                            .map_err(|e| format!("bridges.bridges {}", e))?;
+                        settings.bridges = Some(parsed);
+                    }
+                }
+                "guards" => {
+                    only_keys(section, table, &["num_primary"])?;
+                    if let Some(v) = table.get("num_primary") {
+                        settings.num_guards =
+                            Some(int_in(v, "guards.num_primary", MAX_NUM_GUARDS)?);
+                    }
+                }
+                "system" => {
+                    only_keys(section, table, &["memory"])?;
+                    if let Some(v) = table.get("memory") {
+                        let memory = as_table("system.memory", v)?;
+                        only_keys("system.memory", memory, &["max_mb"])?;
+                        if let Some(v) = memory.get("max_mb") {
+                            settings.max_memory_mb =
+                                Some(int_in(v, "system.memory.max_mb", i32::MAX)?);
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown section [{}]", section)),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The categories this set touches.
+    pub fn categories(&self) -> c_int {
+        [
+            (self.padding.is_some(), CATEGORY_PADDING),
+            (self.bridges.is_some(), CATEGORY_BRIDGES),
+            (self.num_guards.is_some(), CATEGORY_GUARDS),
+            (self.max_memory_mb.is_some(), CATEGORY_MEMORY),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |mask, (_, category)| mask | category)
+    }
+
+    /// Store the settings in `categories`, leaving the others alone.
+    pub fn store(&self, categories: c_int) {
+        if categories & CATEGORY_PADDING != 0 {
+            if let (Some(level), Ok(mut padding)) = (self.padding, PADDING.lock()) {
+                *padding = level;
+            }
+        }
+        if categories & CATEGORY_BRIDGES != 0 {
+            if let Some(bridges) = &self.bridges {
+                crate::bridges::set(bridges.clone());
+            }
+        }
+        if categories & CATEGORY_GUARDS != 0 {
+            if let Some(n) = self.num_guards {
+                NUM_GUARDS.store(n, Ordering::SeqCst);
+            }
+        }
+        if categories & CATEGORY_MEMORY != 0 {
+            if let Some(mb) = self.max_memory_mb {
+                MAX_MEMORY_MB.store(mb, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Every setting at its default.
+    pub fn defaults() -> Self {
+        Settings {
+            padding: Some(PaddingLevel::Normal),
+            bridges: Some(Vec::new()),
+            num_guards: Some(0),
+            max_memory_mb: Some(0),
+        }
+    }
+
+    /// Every setting as currently stored.
+    pub fn current() -> Self {
+        Settings {
+            padding: PADDING.lock().ok().map(|p| *p),
+            bridges: Some(crate::bridges::configured()),
+            num_guards: Some(NUM_GUARDS.load(Ordering::SeqCst)),
+            max_memory_mb: Some(MAX_MEMORY_MB.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+/// Restore every stored setting, bridges included, to its default.
+pub fn reset() {
+    NUM_GUARDS.store(0, Ordering::SeqCst);
+    BOOTSTRAP_RETRIES.store(DEFAULT_BOOTSTRAP_RETRIES, Ordering::SeqCst);
+    BOOTSTRAP_ATTEMPTS.store(1, Ordering::SeqCst);
+    MAX_MEMORY_MB.store(0, Ordering::SeqCst);
+    CIRCUIT_BUILD_TIMEOUT_MS.store(0, Ordering::SeqCst);
+    if let Ok(mut padding) = PADDING.lock() {
+        *padding = PaddingLevel::Normal;
+    }
+    if let Ok(mut pinned) = PINNED_GUARD.lock() {
+        *pinned = None;
+    }
+    if let Ok(mut network) = TOR_NETWORK.lock() {
+        *network = None;
+    }
+    crate::bridges::set(Vec::new());
+}
+
+fn parse_padding(value: &toml::Value) -> Result<PaddingLevel, String> {
+    match value.as_str() {
+        Some("normal") => Ok(PaddingLevel::Normal),
+        Some("reduced") => Ok(PaddingLevel::Reduced),
+        Some("none") => Ok(PaddingLevel::None),
+        _ => Err(r#"channel.padding must be "normal", "reduced" or "none""#.to_owned()),
+    }
+}
+
+fn as_table<'a>(name: &str, value: &'a toml::Value) -> Result<&'a toml::Table, String> {
+    value
+        .as_table()
+        .ok_or_else(|| format!("{} must be a table", name))
+}
+
+fn only_keys(name: &str, table: &toml::Table, allowed: &[&str]) -> Result<(), String> {
+    match table.keys().find(|k| !allowed.contains(&k.as_str())) {
+        Some(key) => Err(format!("unknown key {}.{}", name, key)),
+        None => Ok(()),
+    }
+}
+
+fn int_in(value: &toml::Value, name: &str, max: i32) -> Result<i32, String> {
+    value
+        .as_integer()
+        .filter(|n| (0..=max as i64).contains(n))
+        .map(|n| n as i32)
+        .ok_or_else(|| format!("{} must be an integer from 0 to {}", name, max))
+}
+
+/// Parse a `[tor_network]` section as arti's own configuration file spells
+/// it (`fallback_caches` and `authorities`), e.g. the one chutney writes.
+///
+/// The section is checked as a whole: custom authorities need custom
+/// fallback caches too, since the default ones serve the public network.
+pub fn parse_tor_network(text: &str) -> Result<NetworkConfigBuilder, String> {
+    let doc: toml::Table = text
+        .parse()
+        .map_err(|e: toml::de::Error| e.message().to_owned())?;
+    if let Some(section) = doc.keys().find(|k| *k != "tor_network") {
+        return Err(format!("unknown section [{}]", section));
+    }
+    let section = match doc.get("tor_network") {
+        Some(v) => as_table("tor_network", v)?.clone(),
+        None => return Err("missing [tor_network] section".to_owned()),
+    };
+    only_keys("tor_network", &section, &["authorities", "fallback_caches"])?;
+    let network: NetworkConfigBuilder = section
+        .try_into()
+        .map_err(|e: toml::de::Error| format!("tor_network: {}", e.message()))?;
+    network
+        .build()
+        .map_err(|e| format!("tor_network: {}", e))?;
+    Ok(network)
+}
+
+/// Where a client keeps its state and cache.
+#[derive(Clone, Debug)]
+pub struct Dirs {
+    pub state_dir: PathBuf,
+    pub cache_dir: PathBuf,
+}
+
+impl Dirs {
+    /// The default layout: `state` and `cache` subdirectories of `data_dir`.
+    pub fn under(data_dir: &Path) -> Self {
+        Self {
+            state_dir: data_dir.join("state"),
+            cache_dir: data_dir.join("cache"),
+        }
+    }
+}
+
+/// Parse a relay fingerprint: 40 hex digits, optionally prefixed with `$`
+/// and optionally split into space-separated groups.
+pub fn parse_fingerprint(s: &str) -> Option<RsaIdentity> {
+    let hex: String = s
+        .trim()
+        .trim_start_matches('$')
+        .chars()
+        .filter(|c| *c != ' ')
+        .collect();
+    if hex.len() != 40 {
+        return None;
+    }
+    RsaIdentity::from_hex(&hex)
+}
+
+/// Format a relay identity as 40 uppercase hex digits.
+pub fn fingerprint_hex(id: &RsaIdentity) -> String {
+    id.to_string().trim_start_matches('$').to_uppercase()
+}
+
+/// Build the client configuration from the current settings.
+///
+/// `client` is consulted for directory information when a setting needs
+/// it (e.g. the pinned guard's address); pass `None` before bootstrap.
+pub fn build(
+    dirs: &Dirs,
+    client: Option<&TorClient<PreferredRuntime>>,
+) -> Result<TorClientConfig, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(builder(dirs, client).build()?)
+}
+
+/// `build` for a client yet to be created, with the pinned guard (if
+/// any) taken from `netdir`, e.g. a cached consensus.
+pub fn build_with_netdir(
+    dirs: &Dirs,
+    netdir: &NetDir,
+) -> Result<TorClientConfig, Box<dyn std::error::Error + Send + Sync>> {
+    let pinned = pinned_guard().and_then(|id| pinned_guard_bridge(netdir, &id));
+    Ok(builder_with_first_hop(dirs, pinned).build()?)
+}
+
+/// The current settings as an arti config document: everything `build`
+/// sets, as TOML. Options left at arti's defaults are omitted.
+pub fn to_toml(
+    dirs: &Dirs,
+    client: Option<&TorClient<PreferredRuntime>>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    // TOML has no null or unit, which unset options and some placeholder
+    // fields serialize as, so go through JSON and drop them, along with the
+    // sections they leave empty
+    let mut value = serde_json::to_value(builder(dirs, client))?;
+    prune(&mut value);
+    Ok(toml::to_string(&value)?)
+}
+
+fn prune(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.values_mut().for_each(prune);
+            map.retain(|_, v| !v.is_null() && v.as_object().is_none_or(|m| !m.is_empty()));
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(prune),
+        _ => {}
+    }
+}
+
+fn builder(dirs: &Dirs, client: Option<&TorClient<PreferredRuntime>>) -> TorClientConfigBuilder {
+    let pinned = pinned_guard().zip(client).and_then(|(id, client)| {
+        let netdir = client.dirmgr().timely_netdir().ok()?;
+        pinned_guard_bridge(&netdir, &id)
+    });
+    builder_with_first_hop(dirs, pinned)
+}
+
+/// The configuration `build` makes, with `pinned` (if any) as the only
+/// bridge in place of the configured ones.
+fn builder_with_first_hop(
+    dirs: &Dirs,
+    pinned: Option<BridgeConfigBuilder>,
+) -> TorClientConfigBuilder {
+    // Use from_directories which sets up storage correctly
+    let mut builder = TorClientConfigBuilder::from_directories(&dirs.state_dir, &dirs.cache_dir);
+
+    let num_guards = NUM_GUARDS.load(Ordering::SeqCst);
+    if num_guards > 0 {
+        builder
+            .override_net_params()
+            .insert("guard-n-primary-guards".to_owned(), num_guards);
+    }
+
+    // A fixed timeout is arti's fallback for when learning is disabled
+    let build_timeout_ms = CIRCUIT_BUILD_TIMEOUT_MS.load(Ordering::SeqCst);
+    if build_timeout_ms > 0 {
+        let params = builder.override_net_params();
+        params.insert("cbtdisabled".to_owned(), 1);
+        params.insert("cbtinitialtimeout".to_owned(), build_timeout_ms);
+    }
+
+    let max_memory_mb = MAX_MEMORY_MB.load(Ordering::SeqCst);
+    if max_memory_mb > 0 {
+        builder
+            .system()
+            .memory()
+            .max(max_memory_mb as usize * 1024 * 1024);
+    }
+
+    if let Ok(padding) = PADDING.lock() {
+        builder.channel().padding(*padding);
+    }
+
+    match pinned {
+        // Any other bridge would be a first hop too
+        Some(bridge) => builder.bridges().bridges().push(bridge),
+        None => {
+            for bridge in crate::bridges::configured() {
+                builder.bridges().bridges().push(bridge);
+            }
+        }
+    }
+
+    if let Some(network) = TOR_NETWORK.lock().ok().and_then(|n| n.clone()) {
+        *builder.tor_network() = network;
+    }
+
+    builder
+}
+
+/// The currently pinned guard, if any.
+pub fn pinned_guard() -> Option<RsaIdentity> {
+    PINNED_GUARD.lock().ok().and_then(|g| *g)
+}
+
+/// Whether `id` is a usable relay in the client's current consensus.
+pub fn relay_in_consensus(client: &TorClient<PreferredRuntime>, id: &RsaIdentity) -> bool {
+    client
+        .dirmgr()
+        .timely_netdir()
+        .map(|netdir| netdir.by_id(id).is_some())
+        .unwrap_or(false)
+}
+
+/// Whether `s` is a valid relay nickname: 1 to 19 ASCII letters and digits.
+pub fn is_nickname(s: &str) -> bool {
+    (1..=19).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// Whether `relay`, a fingerprint or nickname, names a relay in `netdir`
+/// that allows exit traffic.
+pub fn is_listed_exit(netdir: &NetDir, relay: &str) -> bool {
+    let exit = |r: &tor_netdir::Relay<'_>| r.low_level_details().policies_allow_some_port();
+    match parse_fingerprint(relay) {
+        Some(id) => netdir.by_id(&id).is_some_and(|r| exit(&r)),
+        None => netdir.relays().any(|r| r.rs().nickname().eq_ignore_ascii_case(relay) && exit(&r)),
+    }
+}
+
+/// Describe a consensus relay as a bridge, which is how the first hop is
+/// pinned: with a single bridge configured, every circuit starts there.
+fn pinned_guard_bridge(netdir: &NetDir, id: &RsaIdentity) -> Option<BridgeConfigBuilder> {
+    let relay = netdir.by_id(id)?;
+    let addr = relay.addrs().find(|a| a.is_ipv4())?;
+    format!("{} {}", addr, fingerprint_hex(id)).parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tor_guardmgr::GuardUsageKind;
+    use tor_linkspec::HasRelayIds;
+
+    /// Held by tests that change the stored settings `build` reads
+    static SETTINGS_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn settings_serialize_as_toml() {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+
+        NUM_GUARDS.store(3, Ordering::SeqCst);
+        let text = to_toml(&dirs, None);
+        NUM_GUARDS.store(0, Ordering::SeqCst);
+
+        let text = text.unwrap();
+        let doc: toml::Table = text.parse().unwrap();
+        assert_eq!(
+            doc["override_net_params"]["guard-n-primary-guards"].as_integer(),
+            Some(3),
+            "{}",
+            text
+        );
+        assert_eq!(
+            doc["storage"]["state_dir"]["literal"].as_str(),
+            Some("/tmp/arti-config-test/state")
+        );
+        assert!(!doc.contains_key("vanguards"), "{}", text);
+    }
+
+    #[test]
+    fn padding_propagates_into_built_config() {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+
+        *PADDING.lock().unwrap() = PaddingLevel::Reduced;
+        let built = build(&dirs, None).unwrap();
+        *PADDING.lock().unwrap() = PaddingLevel::Normal;
+
+        let mut expected =
+            TorClientConfigBuilder::from_directories(&dirs.state_dir, &dirs.cache_dir);
+        expected.channel().padding(PaddingLevel::Reduced);
+        assert_eq!(built, expected.build().unwrap());
+        assert_ne!(built, build(&dirs, None).unwrap());
+    }
+
+    #[test]
+    fn num_guards_propagates_into_built_config() {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+
+        NUM_GUARDS.store(4, Ordering::SeqCst);
+        let built = build(&dirs, None).unwrap();
+        NUM_GUARDS.store(0, Ordering::SeqCst);
+
+        let mut expected =
+            TorClientConfigBuilder::from_directories(&dirs.state_dir, &dirs.cache_dir);
+        expected
+            .override_net_params()
+            .insert("guard-n-primary-guards".to_owned(), 4);
+        assert_eq!(built, expected.build().unwrap());
+        assert_ne!(built, build(&dirs, None).unwrap());
+    }
+
+    #[test]
+    fn pinned_guard_is_the_only_first_hop() {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+        let netdir = tor_netdir::testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let relay = netdir.relays().next().unwrap();
+        let id = *relay.rsa_id();
+        let pinned = pinned_guard_bridge(&netdir, &id).unwrap();
+        assert!(pinned_guard_bridge(&netdir, &RsaIdentity::from([0xAB; 20])).is_none());
+
+        let spare = "192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567";
+        crate::bridges::set(vec![spare.parse().unwrap()]);
+        let built = builder_with_first_hop(&dirs, Some(pinned.clone())).build();
+        crate::bridges::set(Vec::new());
+
+        let mut expected =
+            TorClientConfigBuilder::from_directories(&dirs.state_dir, &dirs.cache_dir);
+        expected.bridges().bridges().push(pinned);
+        assert_eq!(built.unwrap(), expected.build().unwrap());
+    }
+
+    #[tokio::test]
+    async fn pinned_guard_is_the_first_hop_from_the_first_client() {
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+        let netdir = tor_netdir::testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let id = *netdir.relays().nth(3).unwrap().rsa_id();
+        let built = {
+            let _guard = SETTINGS_LOCK.lock().unwrap();
+            *PINNED_GUARD.lock().unwrap() = Some(id);
+            let built = build_with_netdir(&dirs, &netdir);
+            *PINNED_GUARD.lock().unwrap() = None;
+            built.unwrap()
+        };
+
+        // What a client built from this config picks as the first hop
+        let state = tor_persist::TestingStateMgr::new();
+        assert!(tor_persist::StateMgr::try_lock(&state).unwrap().held());
+        let guards =
+            tor_guardmgr::GuardMgr::new(PreferredRuntime::current().unwrap(), state, &built)
+                .unwrap();
+        // Bootstrap's directory fetches go through it rather than another
+        // guard; data circuits get no first hop until its descriptor arrives
+        let usage = |kind| tor_guardmgr::GuardUsageBuilder::new().kind(kind).build().unwrap();
+        for _ in 0..5 {
+            let (first_hop, _, _) =
+                guards.select_guard(usage(GuardUsageKind::OneHopDirectory)).unwrap();
+            assert_eq!(first_hop.rsa_identity(), Some(&id));
+            if let Ok((first_hop, _, _)) = guards.select_guard(usage(GuardUsageKind::Data)) {
+                assert_eq!(first_hop.rsa_identity(), Some(&id));
+            }
+        }
+    }
+
+    #[test]
+    fn only_listed_exits_are_found() {
+        let netdir = tor_netdir::testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let is_exit = |r: &tor_netdir::Relay<'_>| r.low_level_details().policies_allow_some_port();
+        let exit = netdir.relays().find(is_exit).unwrap();
+        let other = netdir.relays().find(|r| !is_exit(r)).unwrap();
+
+        assert!(is_listed_exit(&netdir, &fingerprint_hex(exit.rsa_id())));
+        assert!(is_listed_exit(&netdir, exit.rs().nickname()));
+        assert!(!is_listed_exit(&netdir, &fingerprint_hex(other.rsa_id())));
+        assert!(!is_listed_exit(&netdir, &"AB".repeat(20)));
+        assert!(!is_listed_exit(&netdir, "nosuchrelay"));
+        assert!(is_nickname("moria1") && !is_nickname("") && !is_nickname("not-a-nickname"));
+    }
+
+    #[test]
+    fn fixed_build_timeout_disables_learning() {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+
+        CIRCUIT_BUILD_TIMEOUT_MS.store(90_000, Ordering::SeqCst);
+        let built = build(&dirs, None).unwrap();
+        CIRCUIT_BUILD_TIMEOUT_MS.store(0, Ordering::SeqCst);
+
+        let mut expected =
+            TorClientConfigBuilder::from_directories(&dirs.state_dir, &dirs.cache_dir);
+        let params = expected.override_net_params();
+        params.insert("cbtdisabled".to_owned(), 1);
+        params.insert("cbtinitialtimeout".to_owned(), 90_000);
+        assert_eq!(built, expected.build().unwrap());
+        assert_ne!(built, build(&dirs, None).unwrap());
+    }
+
+    const TEST_NETWORK: &str = r#"
+        [tor_network.authorities]
+        v3idents = ["0586DE53A4B1A4B5DC8E692D2A1D4AC1E8D4A2E8"]
+
+        [[tor_network.fallback_caches]]
+        rsa_identity = "E2A2AF570166665D6B43B4A4BA1DBB8A526B3D55"
+        ed_identity = "dT9uGd3TamwNJOmGpIerxVn1Uhqa1hEfX++yvWgwdKA"
+        orports = ["127.0.0.1:5000"]
+    "#;
+
+    #[test]
+    fn custom_tor_network_propagates_into_built_config() {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let dirs = Dirs::under(Path::new("/tmp/arti-config-test"));
+        let network = parse_tor_network(TEST_NETWORK).unwrap();
+
+        *TOR_NETWORK.lock().unwrap() = Some(network.clone());
+        let built = build(&dirs, None).unwrap();
+        *TOR_NETWORK.lock().unwrap() = None;
+
+        let mut expected =
+            TorClientConfigBuilder::from_directories(&dirs.state_dir, &dirs.cache_dir);
+        *expected.tor_network() = network;
+        assert_eq!(built, expected.build().unwrap());
+        assert_ne!(built, build(&dirs, None).unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_tor_network() {
+        // Custom authorities without custom fallbacks would bootstrap from
+        // the public network and then reject its consensus
+        let authorities_only = TEST_NETWORK.split("[[").next().unwrap();
+        for doc in [
+            authorities_only,
+            "[tor_network.authorities]\nv3idents = [\"nothex\"]",
+            "[tor_network]\nfallbacks = []",
+            "[channel]\npadding = \"none\"",
+            "",
+        ] {
+            assert!(parse_tor_network(doc).is_err(), "{}", doc);
+        }
+    }
+
+    #[test]
+    fn parses_settings_document() {
+        let settings = Settings::parse(
+            r#"
+            [channel]
+            padding = "reduced"
+
+            [bridges]
+            bridges = ["192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567"]
+
+            [system.memory]
+            max_mb = 256
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.padding, Some(PaddingLevel::Reduced));
+        assert_eq!(settings.bridges.as_ref().map(Vec::len), Some(1));
+        assert_eq!(settings.num_guards, None);
+        assert_eq!(settings.max_memory_mb, Some(256));
+        assert_eq!(
+            settings.categories(),
+            CATEGORY_PADDING | CATEGORY_BRIDGES | CATEGORY_MEMORY
+        );
+        assert_eq!(Settings::parse("").unwrap().categories(), 0);
+    }
+
+    #[test]
+    fn rejects_unknown_or_invalid_settings() {
+        for doc in [
+            "[chanel]\npadding = \"none\"",
+            "[channel]\npaddding = \"none\"",
+            "[channel]\npadding = \"some\"",
+            "[guards]\nnum_primary = 11",
+            "[bridges]\nbridges = [\"not-a-bridge\"]",
+            "[channel",
+        ] {
+            assert!(Settings::parse(doc).is_err(), "{}", doc);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Outbound stream abstraction
+//!
+//! The SOCKS handler opens its outbound streams through [`StreamConnector`]
+//! rather than calling `TorClient` directly, which keeps the protocol logic
+//! independent of Tor and lets tests substitute an in-memory connector.
+
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use arti_client::{StreamPrefs, TorAddr, TorClient};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tor_rtcompat::PreferredRuntime;
+
+/// A bidirectional byte stream returned by a connector.
+pub trait RelayStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> RelayStream for T {}
+
+/// Boxed future returned by [`StreamConnector::connect`].
+pub type ConnectFuture<'a> =
+    Pin<Box<dyn Future<Output = io::Result<Box<dyn RelayStream>>> + Send + 'a>>;
+
+/// Boxed future returned by [`StreamConnector::resolve`].
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<IpAddr>>> + Send + 'a>>;
+
+/// Boxed future returned by [`StreamConnector::resolve_ptr`].
+pub type ResolvePtrFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<String>>> + Send + 'a>>;
+
+/// Something that can open a stream to a target address.
+pub trait StreamConnector: Send + Sync {
+    /// Open a stream to `target`, honoring `prefs` where applicable.
+    fn connect<'a>(&'a self, target: TorAddr, prefs: &'a StreamPrefs) -> ConnectFuture<'a>;
+
+    /// Look up the addresses of `hostname`. Unsupported by default.
+    fn resolve<'a>(&'a self, _hostname: &'a str, _prefs: &'a StreamPrefs) -> ResolveFuture<'a> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+
+    /// Look up the hostnames for `addr`. Unsupported by default.
+    fn resolve_ptr<'a>(&'a self, _addr: IpAddr, _prefs: &'a StreamPrefs) -> ResolvePtrFuture<'a> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+}
+
+/// Map an arti error into the `io::Error` the SOCKS layer works with.
+fn io_error(kind: io::ErrorKind, e: arti_client::Error) -> io::Error {
+    io::Error::new(kind, e.to_string())
+}
+
+impl StreamConnector for TorClient<PreferredRuntime> {
+    fn connect<'a>(&'a self, target: TorAddr, prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+        Box::pin(async move {
+            let stream = self
+                .connect_with_prefs(target, prefs)
+                .await
+                .map_err(|e| io_error(io::ErrorKind::ConnectionRefused, e))?;
+            crate::circuit::record(self, &stream);
+            Ok(Box::new(stream) as Box<dyn RelayStream>)
+        })
+    }
+
+    fn resolve<'a>(&'a self, hostname: &'a str, prefs: &'a StreamPrefs) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            self.resolve_with_prefs(hostname, prefs)
+                .await
+                .map_err(|e| io_error(io::ErrorKind::NotFound, e))
+        })
+    }
+
+    fn resolve_ptr<'a>(&'a self, addr: IpAddr, prefs: &'a StreamPrefs) -> ResolvePtrFuture<'a> {
+        Box::pin(async move {
+            self.resolve_ptr_with_prefs(addr, prefs)
+                .await
+                .map_err(|e| io_error(io::ErrorKind::NotFound, e))
+        })
+    }
+}
+
+/// Connector for offline mode: every stream fails as network-unreachable.
+pub struct Offline;
+
+impl Offline {
+    fn error() -> io::Error {
+        io::Error::new(io::ErrorKind::NetworkUnreachable, "Arti is in offline mode")
+    }
+}
+
+impl StreamConnector for Offline {
+    fn connect<'a>(&'a self, _target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+        Box::pin(async { Err(Self::error()) })
+    }
+
+    fn resolve<'a>(&'a self, _hostname: &'a str, _prefs: &'a StreamPrefs) -> ResolveFuture<'a> {
+        Box::pin(async { Err(Self::error()) })
+    }
+
+    fn resolve_ptr<'a>(&'a self, _addr: IpAddr, _prefs: &'a StreamPrefs) -> ResolvePtrFuture<'a> {
+        Box::pin(async { Err(Self::error()) })
+    }
+}
+
+/// What [`Pending`] does with a stream requested before it is ready.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PendingMode {
+    /// Hold the request until the client is ready (bounded by the connect
+    /// timeout, if one is set)
+    Queue,
+    /// Fail it as a general SOCKS failure
+    Reject,
+    /// Fail it as network-unreachable, so apps can tell "not ready yet"
+    /// apart from other failures
+    FailFast,
+}
+
+#[derive(Clone)]
+enum PendingState {
+    Waiting,
+    Ready(Arc<dyn StreamConnector>),
+    Failed,
+}
+
+/// Connector for a listener opened before bootstrap: requests are handled
+/// per its [`PendingMode`] until [`Pending::ready`] hands over the real
+/// connector, and then go straight through to it.
+pub struct Pending {
+    mode: PendingMode,
+    state: watch::Sender<PendingState>,
+}
+
+impl Pending {
+    pub fn new(mode: PendingMode) -> Self {
+        Pending {
+            mode,
+            state: watch::Sender::new(PendingState::Waiting),
+        }
+    }
+
+    /// Send every request from now on, including queued ones, to `connector`.
+    pub fn ready(&self, connector: Arc<dyn StreamConnector>) {
+        self.state.send_replace(PendingState::Ready(connector));
+    }
+
+    /// Give up on becoming ready: queued and later requests fail.
+    pub fn fail(&self) {
+        self.state.send_replace(PendingState::Failed);
+    }
+
+    async fn connector(&self) -> io::Result<Arc<dyn StreamConnector>> {
+        let mut state = self.state.subscribe();
+        loop {
+            let current = state.borrow_and_update().clone();
+            match (current, self.mode) {
+                (PendingState::Ready(connector), _) => return Ok(connector),
+                (PendingState::Failed, _) => {
+                    return Err(io::Error::new(io::ErrorKind::NotConnected, "Tor failed to start"))
+                }
+                (PendingState::Waiting, PendingMode::Queue) => {}
+                (PendingState::Waiting, PendingMode::Reject) => {
+                    return Err(io::Error::new(io::ErrorKind::NotConnected, "Tor is not ready"))
+                }
+                (PendingState::Waiting, PendingMode::FailFast) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NetworkUnreachable,
+                        "Tor is not ready",
+                    ))
+                }
+            }
+            // The sender lives in `self`, so this only waits for a change
+            let _ = state.changed().await;
+        }
+    }
+}
+
+impl StreamConnector for Pending {
+    fn connect<'a>(&'a self, target: TorAddr, prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+        Box::pin(async move { self.connector().await?.connect(target, prefs).await })
+    }
+
+    fn resolve<'a>(&'a self, hostname: &'a str, prefs: &'a StreamPrefs) -> ResolveFuture<'a> {
+        Box::pin(async move { self.connector().await?.resolve(hostname, prefs).await })
+    }
+
+    fn resolve_ptr<'a>(&'a self, addr: IpAddr, prefs: &'a StreamPrefs) -> ResolvePtrFuture<'a> {
+        Box::pin(async move { self.connector().await?.resolve_ptr(addr, prefs).await })
+    }
+}
+
+/// Connectors shared by the unit tests.
+#[cfg(test)]
+pub mod testing {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Connector whose streams echo back whatever is written to them,
+    /// recording the targets and lookups asked for.
+    #[derive(Default)]
+    pub struct EchoConnector {
+        pub targets: Mutex<Vec<String>>,
+        pub lookups: Mutex<Vec<String>>,
+    }
+
+    impl StreamConnector for EchoConnector {
+        fn connect<'a>(&'a self, target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+            self.targets.lock().unwrap().push(target.to_string());
+            Box::pin(async move {
+                let (near, far) = tokio::io::duplex(1024);
+                tokio::spawn(async move {
+                    let (mut r, mut w) = tokio::io::split(far);
+                    let _ = tokio::io::copy(&mut r, &mut w).await;
+                });
+                Ok(Box::new(near) as Box<dyn RelayStream>)
+            })
+        }
+
+        fn resolve<'a>(&'a self, hostname: &'a str, _prefs: &'a StreamPrefs) -> ResolveFuture<'a> {
+            self.lookups.lock().unwrap().push(hostname.to_owned());
+            Box::pin(async { Ok(Vec::new()) })
+        }
+    }
+}
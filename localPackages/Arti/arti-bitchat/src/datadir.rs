@@ -0,0 +1,91 @@
+//! Data directory checks
+//!
+//! Lets the app find out before `arti_start` whether a directory will do,
+//! rather than from a failure deep inside bootstrap.
+
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Free space wanted for the directory cache and state: a consensus and
+/// microdescriptors come to roughly 10 MiB, with room to replace them
+pub const MIN_FREE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Why a directory can't be used.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DirProblem {
+    /// Relative, not a directory, or not creatable under its parents
+    InvalidPath,
+    /// A test file could not be written
+    NotWritable,
+    /// Less than `MIN_FREE_BYTES` available
+    NotEnoughSpace,
+}
+
+/// Check that `path` exists as a writable directory with enough space, or
+/// could be created as one. Nothing is created; the write test happens in
+/// the directory, or in its nearest existing ancestor, and is removed.
+pub fn validate(path: &Path) -> Result<(), DirProblem> {
+    if !path.is_absolute() {
+        return Err(DirProblem::InvalidPath);
+    }
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .ok_or(DirProblem::InvalidPath)?;
+    if !existing.is_dir() {
+        return Err(DirProblem::InvalidPath);
+    }
+    test_write(existing).map_err(|_| DirProblem::NotWritable)?;
+    match free_bytes(existing) {
+        Ok(free) if free < MIN_FREE_BYTES => Err(DirProblem::NotEnoughSpace),
+        // Not knowing is no reason to refuse
+        _ => Ok(()),
+    }
+}
+
+fn test_write(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".arti-write-test-{}", std::process::id()));
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .and_then(|mut file| io::Write::write_all(&mut file, b"ok"));
+    let _ = std::fs::remove_file(&probe);
+    written
+}
+
+fn free_bytes(dir: &Path) -> io::Result<u64> {
+    let c_path = CString::new(dir.as_os_str().as_bytes())?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_existing_and_creatable_dirs_without_creating_them() {
+        let dir = std::env::temp_dir().join(format!("arti-datadir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(validate(&dir), Ok(()));
+
+        let missing = dir.join("not/yet");
+        assert_eq!(validate(&missing), Ok(()));
+        assert!(!dir.join("not").exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let file = dir.join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(validate(&file), Err(DirProblem::InvalidPath));
+        assert_eq!(validate(&file.join("below")), Err(DirProblem::InvalidPath));
+        assert_eq!(validate(Path::new("relative/dir")), Err(DirProblem::InvalidPath));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,25 @@
+//! Last-error reporting
+//!
+//! FFI calls that can fail for reasons worth showing to a user record a
+//! description here, retrievable with `arti_last_error`.
+
+use std::sync::Mutex;
+
+static LAST_ERROR: Mutex<String> = Mutex::new(String::new());
+
+/// Record `msg` as the most recent error.
+pub fn set_last_error(msg: &str) {
+    if let Ok(mut last) = LAST_ERROR.lock() {
+        msg.clone_into(&mut last);
+    }
+}
+
+/// Forget the most recent error.
+pub fn clear_last_error() {
+    set_last_error("");
+}
+
+/// The most recent error, or an empty string if none was recorded.
+pub fn last_error() -> String {
+    LAST_ERROR.lock().map(|e| e.clone()).unwrap_or_default()
+}
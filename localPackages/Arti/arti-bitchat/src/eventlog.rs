@@ -0,0 +1,125 @@
+//! Newline-delimited JSON event log
+//!
+//! For field diagnostics: bootstrap updates, stream opens and closes and
+//! network errors are appended to a file, one JSON object per line:
+//! `{"ts_ms":…,"type":"stream","data":{…}}`, where `data` is the same
+//! object the matching callback receives. The file is capped in size;
+//! when a line would push it past the cap it is renamed to `<path>.1`,
+//! replacing any older one, and a fresh file is started.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Size at which the log is rotated
+pub const MAX_BYTES: u64 = 1024 * 1024;
+
+static LOG: Mutex<Option<EventLog>> = Mutex::new(None);
+
+struct EventLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl EventLog {
+    fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(EventLog {
+            path: path.to_owned(),
+            file,
+            size,
+            max_bytes,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, rotated)?;
+            *self = EventLog::open(&self.path, self.max_bytes)?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Start logging to `path`, appending if it exists; `None` stops logging.
+pub fn set_path(path: Option<&Path>) -> io::Result<()> {
+    let Some(path) = path else {
+        stop();
+        return Ok(());
+    };
+    let log = EventLog::open(path, MAX_BYTES)?;
+    if let Ok(mut current) = LOG.lock() {
+        *current = Some(log);
+    }
+    Ok(())
+}
+
+/// Stop logging.
+pub fn stop() {
+    if let Ok(mut current) = LOG.lock() {
+        *current = None;
+    }
+}
+
+/// Append an event of `kind` whose details are the JSON object `data`.
+pub fn record(kind: &str, data: &str) {
+    let Ok(mut guard) = LOG.lock() else {
+        return;
+    };
+    let Some(log) = guard.as_mut() else {
+        return;
+    };
+    let ts_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let line = format!(
+        "{{\"ts_ms\":{},\"type\":{},\"data\":{}}}\n",
+        ts_ms,
+        crate::json::string(kind),
+        data
+    );
+    if let Err(e) = log.write(&line) {
+        // Stop rather than fail on every event; tracing may still reach
+        // the app
+        tracing::warn!("Event log {} disabled: {}", log.path.display(), e);
+        *guard = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_past_the_cap() {
+        let dir = std::env::temp_dir().join(format!("arti-eventlog-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.ndjson");
+        let _ = std::fs::remove_file(&path);
+
+        let mut log = EventLog::open(&path, 25).unwrap();
+        log.write("{\"n\":1}\n").unwrap();
+        log.write("{\"n\":2}\n").unwrap();
+        log.write("{\"n\":3}\n").unwrap();
+        log.write("{\"n\":4}\n").unwrap();
+
+        let rotated = std::fs::read_to_string(dir.join("events.ndjson.1")).unwrap();
+        assert_eq!(rotated, "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"n\":4}\n");
+
+        // Reopening appends and counts what is already there
+        drop(log);
+        let log = EventLog::open(&path, 25).unwrap();
+        assert_eq!(log.size, 8);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
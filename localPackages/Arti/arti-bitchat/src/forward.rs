@@ -0,0 +1,142 @@
+//! Local port forwards over Tor
+//!
+//! A forward listens on a loopback port and tunnels every connection it
+//! accepts to one fixed target, clearnet or onion. It is for apps that can
+//! only be pointed at a local address: there is no SOCKS handshake, so the
+//! bytes an app writes go straight to the target. Each stream still goes
+//! through the policy a SOCKS CONNECT does (see `socks::check_connect`).
+
+use std::io;
+use std::sync::Arc;
+
+use arti_client::TorAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+
+use crate::connector::StreamConnector;
+use crate::{circuit, isolation, socks};
+
+/// Where a forward's connections go
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    pub addr: TorAddr,
+}
+
+/// Accept connections on `listener` and tunnel each one to `target`
+/// until the listener fails.
+pub async fn serve(
+    listener: &TcpListener,
+    connector: Arc<dyn StreamConnector>,
+    target: Target,
+) -> io::Error {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer_addr)) => {
+                crate::wake_on_connection();
+                let connector = connector.clone();
+                let target = target.clone();
+                tokio::spawn(async move {
+                    let options = socks::HandlerOptions::current();
+                    match tunnel(stream, connector.as_ref(), &target, &options).await {
+                        Ok((sent, received)) => tracing::debug!(
+                            "Forward from {} closed: {} bytes sent, {} received",
+                            peer_addr,
+                            sent,
+                            received
+                        ),
+                        Err(e) => tracing::debug!("Forward from {} failed: {}", peer_addr, e),
+                    }
+                });
+            }
+            Err(e) if socks::is_transient_accept_error(&e) => {
+                tracing::warn!("Accept error: {}", e);
+                if socks::is_resource_exhaustion(&e) {
+                    tokio::time::sleep(socks::ACCEPT_RETRY_DELAY).await;
+                }
+            }
+            Err(e) => {
+                tracing::error!("Forward listener failed: {}", e);
+                return e;
+            }
+        }
+    }
+}
+
+/// Open a stream to `target`, if `options` allow it, and relay `stream`
+/// over it, returning the bytes sent and received. A refused stream just
+/// closes the connection, as there is no handshake to answer.
+async fn tunnel<S>(
+    mut stream: S,
+    connector: &dyn StreamConnector,
+    target: &Target,
+    options: &socks::HandlerOptions,
+) -> io::Result<(u64, u64)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    socks::check_connect(&target.host, target.port, options)
+        .await
+        .map_err(|refusal| refusal.error)?;
+    let isolation = isolation::default_token();
+    let prefs = socks::stream_prefs(isolation, &target.host, target.port, None);
+    let connect = connector.connect(target.addr.clone(), &prefs);
+    let mut tor_stream = circuit::STREAM_ISOLATION.scope(isolation, connect).await?;
+    let buffer_size = options.relay_buffer_size();
+    tokio::io::copy_bidirectional_with_sizes(&mut stream, &mut tor_stream, buffer_size, buffer_size)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::testing::EchoConnector;
+    use arti_client::IntoTorAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn target(host: &str, port: u16) -> Target {
+        let addr = (host, port).into_tor_addr().unwrap();
+        Target { host: host.to_owned(), port, addr }
+    }
+
+    #[tokio::test]
+    async fn forwards_every_connection_to_the_fixed_target() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connector = Arc::new(EchoConnector::default());
+        let target = target("example.onion", 80);
+        let server = tokio::spawn({
+            let connector: Arc<dyn StreamConnector> = connector.clone();
+            async move { serve(&listener, connector, target).await }
+        });
+
+        for message in [&b"first"[..], b"second"] {
+            let mut app = tokio::net::TcpStream::connect(addr).await.unwrap();
+            app.write_all(message).await.unwrap();
+            let mut echoed = vec![0u8; message.len()];
+            app.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(echoed, message);
+        }
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["example.onion:80"; 2]);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn refused_targets_are_never_connected() {
+        let connector = EchoConnector::default();
+        let refusing = [
+            socks::HandlerOptions { allowed_ports: vec![443].into(), ..Default::default() },
+            socks::HandlerOptions {
+                tor_only_domains: vec!["example.com".to_owned()].into(),
+                ..Default::default()
+            },
+        ];
+        for options in &refusing {
+            let (app, _far) = tokio::io::duplex(64);
+            let result = tunnel(app, &connector, &target("example.net", 80), options).await;
+            assert!(result.is_err(), "{:?}", options);
+        }
+        assert!(connector.targets.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+//! Moving guard state between devices
+//!
+//! Arti keeps its guard selection in `state/guards.json` under the state
+//! directory. Exporting hands that file over as-is; importing stages a
+//! copy that is written into place at the next start, before arti opens
+//! its state, so a new device carries on with the old one's guards.
+
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::json;
+
+/// Guard state waiting to be installed at the next start
+static STAGED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Arti's guard state file within a state directory.
+fn guards_file(state_dir: &Path) -> PathBuf {
+    state_dir.join("state").join("guards.json")
+}
+
+/// Read the guard state from `state_dir`.
+pub fn export(state_dir: &Path) -> io::Result<String> {
+    std::fs::read_to_string(guards_file(state_dir))
+}
+
+/// Stage `blob` for installation at the next start, or forget a staged
+/// one when `None`. Only checks that it is a JSON object; arti itself
+/// falls back to choosing new guards if it can't use the contents.
+pub fn stage(blob: Option<&str>) -> Result<(), String> {
+    if let Some(blob) = blob {
+        match serde_json::from_str::<serde_json::Value>(blob) {
+            Ok(value) if value.is_object() => {}
+            Ok(_) => return Err("guard state is not a JSON object".to_owned()),
+            Err(e) => return Err(format!("guard state is not valid JSON: {}", e)),
+        }
+    }
+    if let Ok(mut staged) = STAGED.lock() {
+        *staged = blob.map(str::to_owned);
+    }
+    Ok(())
+}
+
+/// Forget any staged guard state.
+pub fn reset() {
+    if let Ok(mut staged) = STAGED.lock() {
+        *staged = None;
+    }
+}
+
+/// Write any staged guard state into `state_dir`, replacing what is there.
+/// The state is unstaged only once written.
+pub fn install_staged(state_dir: &Path) -> io::Result<()> {
+    let Ok(mut staged) = STAGED.lock() else {
+        return Ok(());
+    };
+    let Some(blob) = staged.as_deref() else {
+        return Ok(());
+    };
+    let path = guards_file(state_dir);
+    let dir = path.parent().expect("guards file has a parent");
+    // Arti refuses state that others can read or write
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(blob.as_bytes())?;
+    *staged = None;
+    tracing::warn!("Installed imported guard state; this device now shares the old one's guards");
+    Ok(())
+}
+
+/// Guard samples in the state file, by the name they are reported under
+const SAMPLES: [&str; 3] = ["default", "restricted", "bridges"];
+
+/// Describe the guards in `blob` (the state file's contents) that arti has
+/// not ruled out, i.e. neither disabled nor missing from the consensus, as
+/// `{"guards":[{"fingerprint":..., "sample":..., "added_at":...,
+/// "confirmed_at":..., "in_use":...}, ...]}`. `confirmed_at` is when the
+/// guard was first used successfully (null if never) and `in_use` says
+/// whether it is `current`, the guard of the latest circuit.
+pub fn usable_json(blob: &str, current: Option<&str>) -> Result<String, String> {
+    let state: Value = serde_json::from_str(blob).map_err(|e| e.to_string())?;
+    let mut items = Vec::new();
+    for sample in SAMPLES {
+        let guards = state[sample]["guards"].as_array().map(Vec::as_slice).unwrap_or_default();
+        for guard in guards {
+            if !guard["disabled"].is_null() || !guard["unlisted_since"].is_null() {
+                continue;
+            }
+            let fingerprint = guard["id"]["rsa"].as_str().map(str::to_ascii_uppercase);
+            let in_use = fingerprint.is_some() && fingerprint.as_deref() == current;
+            items.push(
+                json::Object::new()
+                    .opt_str("fingerprint", fingerprint.as_deref())
+                    .str("sample", sample)
+                    .opt_str("added_at", guard["added_at"].as_str())
+                    .opt_str("confirmed_at", guard["confirmed_at"].as_str())
+                    .bool("in_use", in_use)
+                    .finish(),
+            );
+        }
+    }
+    Ok(format!("{{\"guards\":{}}}", json::array(items)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staged_state_is_installed_once() {
+        let dir = std::env::temp_dir().join(format!("arti-guardstate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(stage(Some("[]")).is_err());
+        assert!(stage(Some("{not json")).is_err());
+        install_staged(&dir).unwrap();
+        assert!(export(&dir).is_err());
+
+        let blob = r#"{"default":{"guards":[]}}"#;
+        stage(Some(blob)).unwrap();
+        install_staged(&dir).unwrap();
+        assert_eq!(export(&dir).unwrap(), blob);
+
+        std::fs::write(guards_file(&dir), "{}").unwrap();
+        install_staged(&dir).unwrap();
+        assert_eq!(export(&dir).unwrap(), "{}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lists_guards_not_ruled_out() {
+        let blob = r#"{"default":{"guards":[
+            {"id":{"rsa":"aa01"},"added_at":"2026-01-01T00:00:00Z","confirmed_at":null},
+            {"id":{"rsa":"bb02"},"added_at":"2026-01-02T00:00:00Z",
+             "confirmed_at":"2026-01-03T00:00:00Z","unlisted_since":null},
+            {"id":{"rsa":"cc03"},"unlisted_since":"2026-02-01T00:00:00Z"},
+            {"id":{"rsa":"dd04"},"disabled":{"type":"TooManyIndeterminateFailures"}}
+        ]},"bridges":{"guards":[]}}"#;
+        let listed = usable_json(blob, Some("BB02")).unwrap();
+        assert_eq!(
+            listed,
+            r#"{"guards":[{"fingerprint":"AA01","sample":"default","#.to_owned()
+                + r#""added_at":"2026-01-01T00:00:00Z","confirmed_at":null,"in_use":false},"#
+                + r#"{"fingerprint":"BB02","sample":"default","#
+                + r#""added_at":"2026-01-02T00:00:00Z","#
+                + r#""confirmed_at":"2026-01-03T00:00:00Z","in_use":true}]}"#
+        );
+        assert_eq!(usable_json("{}", None).unwrap(), r#"{"guards":[]}"#);
+        assert!(usable_json("{not json", None).is_err());
+    }
+}
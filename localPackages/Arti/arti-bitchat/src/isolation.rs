@@ -0,0 +1,192 @@
+//! Stream isolation bookkeeping
+//!
+//! The FFI identifies isolation groups with plain `u64` ids. Each id is mapped
+//! to its own arti `IsolationToken` the first time it is seen, so streams that
+//! share an id may share circuits and streams with different ids never do.
+//!
+//! On top of that, [`set_flags`] can split SOCKS streams further by
+//! destination and SOCKS credentials, via a [`StreamKey`] that carries the
+//! listener's token together with the selected fields.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use arti_client::isolation::IsolationHelper;
+use arti_client::IsolationToken;
+use once_cell::sync::Lazy;
+
+static TOKENS: Lazy<Mutex<HashMap<u64, IsolationToken>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Isolation id for SOCKS connections on listeners without their own
+/// (0 = arti's default isolation)
+static DEFAULT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Streams to different destination hosts never share a circuit
+pub const ISOLATE_DEST_ADDR: i32 = 1 << 0;
+/// Streams to different destination ports never share a circuit
+pub const ISOLATE_DEST_PORT: i32 = 1 << 1;
+/// Streams with different SOCKS username/password never share a circuit
+pub const ISOLATE_CREDENTIALS: i32 = 1 << 2;
+/// Every defined flag
+pub const ALL_FLAGS: i32 = ISOLATE_DEST_ADDR | ISOLATE_DEST_PORT | ISOLATE_CREDENTIALS;
+
+/// Extra isolation applied to SOCKS streams (`ISOLATE_*` bits)
+static FLAGS: AtomicI32 = AtomicI32::new(0);
+
+/// Return the isolation token for `id`, creating it on first use.
+pub fn token_for(id: u64) -> IsolationToken {
+    match TOKENS.lock() {
+        Ok(mut tokens) => *tokens.entry(id).or_insert_with(IsolationToken::new),
+        // A fresh token is the safe fallback: it never shares circuits
+        Err(_) => IsolationToken::new(),
+    }
+}
+
+/// The token already created for `id`, if any.
+pub fn existing_token(id: u64) -> Option<IsolationToken> {
+    TOKENS.lock().ok()?.get(&id).copied()
+}
+
+/// The isolation id used by the default SOCKS path.
+pub fn default_id() -> u64 {
+    DEFAULT_ID.load(Ordering::SeqCst)
+}
+
+/// Set the isolation id used by the default SOCKS path.
+pub fn set_default_id(id: u64) {
+    DEFAULT_ID.store(id, Ordering::SeqCst);
+}
+
+/// The token for the default SOCKS path, if one has been set.
+pub fn default_token() -> Option<IsolationToken> {
+    match default_id() {
+        0 => None,
+        id => Some(token_for(id)),
+    }
+}
+
+/// The extra isolation flags applied to SOCKS streams.
+pub fn flags() -> i32 {
+    FLAGS.load(Ordering::SeqCst)
+}
+
+/// Set the extra isolation flags; `false` if `flags` has unknown bits.
+pub fn set_flags(flags: i32) -> bool {
+    if flags & !ALL_FLAGS != 0 {
+        return false;
+    }
+    FLAGS.store(flags, Ordering::SeqCst);
+    true
+}
+
+/// Forget every isolation id and clear the default id and flags.
+pub fn reset() {
+    if let Ok(mut tokens) = TOKENS.lock() {
+        tokens.clear();
+    }
+    DEFAULT_ID.store(0, Ordering::SeqCst);
+    FLAGS.store(0, Ordering::SeqCst);
+}
+
+/// Isolation for one SOCKS stream: two streams may share a circuit only if
+/// every field matches. Fields not selected by the flags are left `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamKey {
+    token: Option<IsolationToken>,
+    host: Option<String>,
+    port: Option<u16>,
+    credentials: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl StreamKey {
+    /// Build the key for a stream from the fields selected by `flags`.
+    pub fn new(
+        flags: i32,
+        token: Option<IsolationToken>,
+        host: &str,
+        port: u16,
+        credentials: Option<(Vec<u8>, Vec<u8>)>,
+    ) -> Self {
+        StreamKey {
+            token,
+            host: (flags & ISOLATE_DEST_ADDR != 0).then(|| host.to_ascii_lowercase()),
+            port: (flags & ISOLATE_DEST_PORT != 0).then_some(port),
+            credentials: if flags & ISOLATE_CREDENTIALS != 0 {
+                Some(credentials.unwrap_or_default())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl IsolationHelper for StreamKey {
+    fn compatible_same_type(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn join_same_type(&self, other: &Self) -> Option<Self> {
+        (self == other).then(|| self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arti_client::isolation::Isolation;
+
+    fn shares(a: &StreamKey, b: &StreamKey) -> bool {
+        let a: Box<dyn Isolation> = Box::new(a.clone());
+        let b: Box<dyn Isolation> = Box::new(b.clone());
+        a.compatible(b.as_ref())
+    }
+
+    fn creds(user: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+        Some((user.as_bytes().to_vec(), b"x".to_vec()))
+    }
+
+    #[test]
+    fn no_flags_shares_across_destinations_and_credentials() {
+        let a = StreamKey::new(0, None, "a.example", 443, creds("alice"));
+        let b = StreamKey::new(0, None, "b.example", 80, creds("bob"));
+        assert!(shares(&a, &b));
+    }
+
+    #[test]
+    fn dest_addr_flag_splits_by_host_only() {
+        let a = StreamKey::new(ISOLATE_DEST_ADDR, None, "a.example", 443, None);
+        assert!(!shares(&a, &StreamKey::new(ISOLATE_DEST_ADDR, None, "b.example", 443, None)));
+        assert!(shares(&a, &StreamKey::new(ISOLATE_DEST_ADDR, None, "A.example", 80, None)));
+    }
+
+    #[test]
+    fn dest_port_flag_splits_by_port_only() {
+        let a = StreamKey::new(ISOLATE_DEST_PORT, None, "a.example", 443, None);
+        assert!(!shares(&a, &StreamKey::new(ISOLATE_DEST_PORT, None, "a.example", 80, None)));
+        assert!(shares(&a, &StreamKey::new(ISOLATE_DEST_PORT, None, "b.example", 443, None)));
+    }
+
+    #[test]
+    fn credentials_flag_splits_by_credentials_only() {
+        let f = ISOLATE_CREDENTIALS;
+        let a = StreamKey::new(f, None, "a.example", 443, creds("alice"));
+        assert!(!shares(&a, &StreamKey::new(f, None, "a.example", 443, creds("bob"))));
+        assert!(!shares(&a, &StreamKey::new(f, None, "a.example", 443, None)));
+        assert!(shares(&a, &StreamKey::new(f, None, "b.example", 80, creds("alice"))));
+    }
+
+    #[test]
+    fn token_still_separates_listeners() {
+        let a = StreamKey::new(ALL_FLAGS, Some(token_for(1001)), "a.example", 443, None);
+        let b = StreamKey::new(ALL_FLAGS, Some(token_for(1002)), "a.example", 443, None);
+        assert!(!shares(&a, &b));
+        assert!(shares(&a, &a.clone()));
+    }
+
+    #[test]
+    fn rejects_unknown_flag_bits() {
+        assert!(!set_flags(1 << 3));
+        assert!(!set_flags(-1));
+    }
+}
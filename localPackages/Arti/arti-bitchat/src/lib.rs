@@ -3,20 +3,64 @@
 //! Provides a C-compatible interface for embedding Arti (Rust Tor) in iOS/macOS apps.
 //! Exposes a SOCKS5 proxy on localhost that Swift code can route traffic through.
 
-use std::ffi::{c_char, c_int, CStr};
-use std::net::SocketAddr;
+// FFI entry points necessarily take raw pointers from C callers; each one
+// checks for null before dereferencing.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU16, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use arti_client::TorClient;
+use arti_client::config::Reconfigure;
+use arti_client::status::{BlockageKind, BootstrapStatus};
+use arti_client::{IntoTorAddr, TorClient, TorClientConfig};
+use futures::StreamExt;
 use once_cell::sync::OnceCell;
 use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
 use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tor_config::PaddingLevel;
 use tor_rtcompat::PreferredRuntime;
 
+mod bandwidth;
+mod bridges;
+mod bundle;
+mod callbacks;
+mod censorship;
+mod circuit;
+mod config;
+pub mod connector;
+mod datadir;
+mod error;
+mod eventlog;
+mod forward;
+mod guardstate;
+mod isolation;
+mod json;
+mod onion;
+mod pipestream;
+mod profiles;
+mod proxy_protocol;
+mod retry;
+mod sessions;
 mod socks;
+mod status;
+mod statuspage;
+mod threads;
+mod watchdog;
+
+use callbacks::{
+    ArtiBootstrapJsonCallback, ArtiBridgeProviderCallback, ArtiBudgetExpiringCallback,
+    ArtiCensorshipCallback, ArtiConnectionFilterCallback, ArtiConnectivityCallback,
+    ArtiConsensusCallback, ArtiNetworkErrorCallback, ArtiOfflineCallback, ArtiRuntimeStallCallback,
+    ArtiStreamEventCallback,
+};
+use connector::StreamConnector;
 
 /// Global state for the Arti instance
 struct ArtiState {
@@ -26,21 +70,118 @@ struct ArtiState {
     shutdown_tx: Option<oneshot::Sender<()>>,
     /// TorClient handle for status queries
     client: Option<Arc<TorClient<PreferredRuntime>>>,
+    /// Directories of the running client, for rebuilding its configuration
+    dirs: Option<config::Dirs>,
+    /// The main SOCKS listener(s)
+    socks: Option<SocksServer>,
+    /// Additional SOCKS listeners added with `arti_add_socks_port`
+    extra_listeners: Vec<ExtraListener>,
+    /// Port forwards added with `arti_forward_port`
+    forwards: Vec<PortForward>,
+    /// The page started with `arti_start_status_server`, and its port
+    status_server: Option<(u16, JoinHandle<()>)>,
+    /// Timer for the budget set with `arti_set_background_budget`
+    budget_timer: Option<JoinHandle<()>>,
+    /// Main SOCKS port closed by `arti_go_dormant`, with its connector, to
+    /// reopen on wake
+    dormant_socks: Option<(u16, Arc<dyn StreamConnector>)>,
+    /// When the latest `arti_start` was called
+    started_at: Option<Instant>,
+    /// How long the latest start took to bootstrap, once it has
+    bootstrap_duration: Option<Duration>,
+}
+
+/// The task serving the main SOCKS port
+struct SocksServer {
+    port: u16,
+    /// Every address bound (IPv4, plus IPv6 in dual-stack mode)
+    addrs: Vec<SocketAddr>,
+    task: JoinHandle<()>,
+    /// Where its streams go, for reopening the port
+    connector: Arc<dyn StreamConnector>,
+    /// Serving an app-provided socket, which can't be reopened once closed
+    adopted: bool,
+}
+
+/// Where the main SOCKS listener comes from
+enum SocksListen {
+    /// Bind 127.0.0.1 on this port (0 = any free port)
+    Port(u16),
+    /// Serve a listening socket handed over by the app
+    Adopted(std::net::TcpListener),
+}
+
+/// An additional SOCKS port whose connections share one isolation token
+struct ExtraListener {
+    port: u16,
+    isolation_id: u64,
+    task: JoinHandle<()>,
+}
+
+/// A loopback port tunnelled to one fixed Tor target
+struct PortForward {
+    port: u16,
+    target: String,
+    task: JoinHandle<()>,
 }
 
 static ARTI_STATE: OnceCell<Mutex<ArtiState>> = OnceCell::new();
 static BOOTSTRAP_PROGRESS: AtomicI32 = AtomicI32::new(0);
+/// Bootstrap attempts made since the last start, retries and new clients
+/// alike
+static BOOTSTRAP_ATTEMPT: AtomicI32 = AtomicI32::new(0);
 static IS_RUNNING: AtomicBool = AtomicBool::new(false);
-static BOOTSTRAP_SUMMARY: Mutex<String> = Mutex::new(String::new());
+/// Counts starts, so a run's task can tell whether a later start took over
+static RUN_ID: AtomicU64 = AtomicU64::new(0);
+static DUAL_STACK: AtomicBool = AtomicBool::new(false);
+/// Port the main SOCKS listener is bound to (0 when not listening)
+static SOCKS_PORT: AtomicU16 = AtomicU16::new(0);
+/// Latest clock skew estimate in seconds (positive = our clock is fast)
+static CLOCK_SKEW_SECS: AtomicI64 = AtomicI64::new(0);
+/// Whether the latest estimate is significant enough to tell the user
+static CLOCK_SKEWED: AtomicBool = AtomicBool::new(false);
+/// Set by `arti_go_dormant`; cleared by `arti_wake` or a SOCKS connection
+static DORMANT: AtomicBool = AtomicBool::new(false);
+
+/// Rough in-memory size of one relay in the directory (routerstatus plus
+/// microdescriptor), for `arti_memory_usage_bytes`
+const ESTIMATED_BYTES_PER_RELAY: u64 = 2 * 1024;
+/// Rough buffering cost of one relayed SOCKS connection
+const ESTIMATED_BYTES_PER_CONNECTION: u64 = 64 * 1024;
+
+/// Connector substituted for Tor by `set_test_connector`
+static TEST_CONNECTOR: Mutex<Option<Arc<dyn StreamConnector>>> = Mutex::new(None);
+/// Progress at which a test-connector start stalls (-1 = it doesn't)
+static TEST_BOOTSTRAP_STALL: AtomicI32 = AtomicI32::new(-1);
+
+/// How long before a background budget runs out Arti goes dormant and
+/// warns the app
+const BUDGET_WARNING_MARGIN: Duration = Duration::from_secs(5);
+
+/// How long `arti_cleanup` waits for runtime tasks to finish
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long `arti_start` waits for the spawned task to confirm it is alive
+const STARTUP_HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
 
 /// Initialize the global state with a new runtime
 fn init_state() -> Result<(), &'static str> {
     ARTI_STATE.get_or_try_init(|| -> Result<Mutex<ArtiState>, &'static str> {
-        let runtime = Runtime::new().map_err(|_| "Failed to create tokio runtime")?;
+        let runtime = threads::build_runtime(&threads::current())
+            .map_err(|_| "Failed to create tokio runtime")?;
         Ok(Mutex::new(ArtiState {
             runtime,
             shutdown_tx: None,
             client: None,
+            dirs: None,
+            socks: None,
+            extra_listeners: Vec::new(),
+            forwards: Vec::new(),
+            status_server: None,
+            budget_timer: None,
+            dormant_socks: None,
+            started_at: None,
+            bootstrap_duration: None,
         }))
     })?;
     Ok(())
@@ -56,7 +197,7 @@ fn init_state() -> Result<(), &'static str> {
 /// * 0 on success
 /// * -1 if already running
 /// * -2 if data_dir is invalid
-/// * -3 if runtime initialization failed
+/// * -3 if runtime initialization failed or the task could not be started
 /// * -4 if bootstrap failed
 #[no_mangle]
 pub extern "C" fn arti_start(data_dir: *const c_char, socks_port: u16) -> c_int {
@@ -66,13 +207,265 @@ pub extern "C" fn arti_start(data_dir: *const c_char, socks_port: u16) -> c_int
     }
 
     // Parse data directory
-    let data_path = match unsafe { CStr::from_ptr(data_dir) }.to_str() {
-        Ok(s) => PathBuf::from(s),
-        Err(_) => return -2,
+    let data_path = match c_path(data_dir) {
+        Some(p) => p,
+        None => return -2,
+    };
+
+    start(config::Dirs::under(&data_path), SocksListen::Port(socks_port), None)
+}
+
+/// Check that a directory will do as a data directory, without starting.
+///
+/// The directory must be an absolute path that either exists and is a
+/// directory, or could be created under an existing one, and the
+/// directory (or that ancestor) must take a test write and have at least
+/// 32 MiB free. Nothing is created; the test file is removed again.
+///
+/// # Arguments
+/// * `path` - Proposed data directory (C string)
+///
+/// # Returns
+/// * 0 if usable
+/// * -1 if the path is NULL, relative, not a directory or not creatable
+/// * -2 if it is not writable
+/// * -3 if there is not enough free space
+#[no_mangle]
+pub extern "C" fn arti_validate_data_dir(path: *const c_char) -> c_int {
+    let Some(path) = c_path(path) else {
+        return -1;
+    };
+    match datadir::validate(&path) {
+        Ok(()) => 0,
+        Err(datadir::DirProblem::InvalidPath) => -1,
+        Err(datadir::DirProblem::NotWritable) => -2,
+        Err(datadir::DirProblem::NotEnoughSpace) => -3,
+    }
+}
+
+/// Start Arti with explicit state and cache directories.
+///
+/// Like `arti_start`, but rather than deriving both from one data
+/// directory, persistent state (guards, keys) goes in `state_dir` and
+/// re-downloadable directory data in `cache_dir`. On iOS this lets the
+/// cache live under `Caches/`, which the OS may purge, while state stays
+/// in a backed-up location. Each directory is created if missing.
+///
+/// # Arguments
+/// * `state_dir` - Path for persistent Tor state (C string)
+/// * `cache_dir` - Path for the directory cache (C string)
+/// * `socks_port` - Port for SOCKS5 proxy (e.g., 39050)
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if already running
+/// * -2 if state_dir is invalid or cannot be created
+/// * -3 if runtime initialization failed or the task could not be started
+/// * -5 if cache_dir is invalid or cannot be created
+#[no_mangle]
+pub extern "C" fn arti_start_with_dirs(
+    state_dir: *const c_char,
+    cache_dir: *const c_char,
+    socks_port: u16,
+) -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+
+    let state_dir = match c_path(state_dir) {
+        Some(p) if std::fs::create_dir_all(&p).is_ok() => p,
+        _ => return -2,
+    };
+    let cache_dir = match c_path(cache_dir) {
+        Some(p) if std::fs::create_dir_all(&p).is_ok() => p,
+        _ => return -5,
+    };
+
+    start(config::Dirs { state_dir, cache_dir }, SocksListen::Port(socks_port), None)
+}
+
+/// Prefix of the scratch directories used by `arti_start_ephemeral`
+const EPHEMERAL_DIR_PREFIX: &str = "arti-ephemeral-";
+
+/// Start Arti without keeping any state between runs.
+///
+/// arti-client cannot run without a state and cache directory, so this
+/// runs against a fresh scratch directory under the system temporary
+/// directory and deletes it when Arti stops. Scratch directories left
+/// behind by a crash are deleted on the next ephemeral start. The cost is
+/// that every launch bootstraps from scratch (a full consensus download)
+/// and picks new entry guards, which is slower and gives up the protection
+/// long-lived guards offer against being profiled over time.
+///
+/// # Arguments
+/// * `socks_port` - Port for SOCKS5 proxy (e.g., 39050)
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if already running
+/// * -2 if the scratch directory could not be created
+/// * -3 if runtime initialization failed or the task could not be started
+#[no_mangle]
+pub extern "C" fn arti_start_ephemeral(socks_port: u16) -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+
+    let tmp = std::env::temp_dir();
+    remove_stale_ephemeral_dirs(&tmp);
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let root = tmp.join(format!("{}{}-{}", EPHEMERAL_DIR_PREFIX, std::process::id(), nanos));
+    if std::fs::create_dir_all(&root).is_err() {
+        return -2;
+    }
+
+    start(config::Dirs::under(&root), SocksListen::Port(socks_port), Some(root))
+}
+
+/// Delete scratch directories from earlier ephemeral runs.
+fn remove_stale_ephemeral_dirs(tmp: &std::path::Path) {
+    let entries = match std::fs::read_dir(tmp) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(EPHEMERAL_DIR_PREFIX) {
+            let _ = std::fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+/// Parse a non-empty UTF-8 path from a C string.
+fn c_path(ptr: *const c_char) -> Option<PathBuf> {
+    if ptr.is_null() {
+        return None;
+    }
+    match unsafe { CStr::from_ptr(ptr) }.to_str() {
+        Ok(s) if !s.is_empty() => Some(PathBuf::from(s)),
+        _ => None,
+    }
+}
+
+/// Borrow a UTF-8 string from a C string, or `None` if it is NULL or not
+/// UTF-8.
+fn c_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().ok()
+}
+
+/// Start Arti serving SOCKS on a listening socket created by the app.
+///
+/// For a Network Extension that owns socket creation: `listener_fd` must be
+/// a TCP socket that is already bound and listening. Arti takes ownership
+/// of it and closes it on `arti_stop`. Dual-stack mode does not apply, and
+/// if the socket fails it is not rebound (the network error callback
+/// reports `ARTI_NETWORK_ERROR_LISTENER`); `arti_rebind_socks` replaces it
+/// with a port Arti binds itself.
+///
+/// # Arguments
+/// * `data_dir` - Path to data directory for Tor state (C string)
+/// * `listener_fd` - Bound, listening TCP socket
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if already running, or `listener_fd` is not a listening TCP socket
+///   (see `arti_last_error`); the caller keeps the fd
+/// * -2 if data_dir is invalid; the caller keeps the fd
+/// * -3 if runtime initialization failed or the task could not be started
+#[no_mangle]
+pub extern "C" fn arti_start_on_fd(data_dir: *const c_char, listener_fd: c_int) -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    let data_path = match c_path(data_dir) {
+        Some(p) => p,
+        None => return -2,
+    };
+    let listener = match adopt_listener(listener_fd) {
+        Ok(l) => l,
+        Err(e) => {
+            let msg = format!("Cannot use fd {} as SOCKS listener: {}", listener_fd, e);
+            error::set_last_error(&msg);
+            return -1;
+        }
+    };
+    start(config::Dirs::under(&data_path), SocksListen::Adopted(listener), None)
+}
+
+/// Take ownership of `fd` if it is a listening TCP socket; otherwise leave
+/// it open for the caller.
+fn adopt_listener(fd: RawFd) -> std::io::Result<std::net::TcpListener> {
+    if fd < 0 {
+        return Err(std::io::Error::from_raw_os_error(libc::EBADF));
+    }
+    let mut listening: c_int = 0;
+    let mut len = std::mem::size_of::<c_int>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut listening as *mut c_int as *mut c_void,
+            &mut len,
+        )
     };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if listening == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "socket is not listening",
+        ));
+    }
+
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    // A Unix-domain socket passes the checks above but has no IP address
+    match listener.local_addr() {
+        Ok(_) => Ok(listener),
+        Err(e) => {
+            let _ = listener.into_raw_fd();
+            Err(e)
+        }
+    }
+}
+
+/// Serve SOCKS through `connector` instead of Tor from the next start.
+///
+/// For tests that drive the FFI lifecycle and SOCKS path without network
+/// access: the client is neither created nor bootstrapped, and the start
+/// reports ready at once. `None` restores Tor. Not part of the C API.
+#[doc(hidden)]
+pub fn set_test_connector(connector: Option<Arc<dyn StreamConnector>>) {
+    if let Ok(mut current) = TEST_CONNECTOR.lock() {
+        *current = connector;
+    }
+}
+
+fn test_connector() -> Option<Arc<dyn StreamConnector>> {
+    TEST_CONNECTOR.lock().ok()?.clone()
+}
+
+/// Have starts with a test connector stall at `percent` bootstrapped until
+/// stopped, instead of reporting ready at once; `None` lets them finish.
+/// For tests of stopping mid-bootstrap. Not part of the C API.
+#[doc(hidden)]
+pub fn set_test_bootstrap_stall(percent: Option<c_int>) {
+    let percent = percent.map_or(-1, |p| p.clamp(0, 99));
+    TEST_BOOTSTRAP_STALL.store(percent, Ordering::SeqCst);
+}
 
+/// Shared tail of the `arti_start*` functions: spawn the main task for a
+/// client using `dirs`. `scratch`, if given, is deleted once the task ends.
+fn start(dirs: config::Dirs, socks: SocksListen, scratch: Option<PathBuf>) -> c_int {
     // Initialize runtime if needed
-    if let Err(_) = init_state() {
+    if init_state().is_err() {
         return -3;
     }
 
@@ -86,18 +479,36 @@ pub extern "C" fn arti_start(data_dir: *const c_char, socks_port: u16) -> c_int
         Err(_) => return -3,
     };
 
+    // The callers' own checks are only a fast path: exactly one of several
+    // concurrent starts gets the claim
+    let Some(claim) = RunningClaim::take() else {
+        if let SocksListen::Adopted(listener) = socks {
+            // Hand the fd back to the caller rather than closing it
+            let _ = listener.into_raw_fd();
+        }
+        return -1;
+    };
+
     // Create shutdown channel
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
     guard.shutdown_tx = Some(shutdown_tx);
+    guard.started_at = Some(Instant::now());
+    guard.bootstrap_duration = None;
 
-    let socks_addr: SocketAddr = format!("127.0.0.1:{}", socks_port)
-        .parse()
-        .expect("valid addr");
+    BOOTSTRAP_PROGRESS.store(0, Ordering::SeqCst);
+    BOOTSTRAP_ATTEMPT.store(0, Ordering::SeqCst);
+    callbacks::reset_milestones();
+    update_summary("Starting...");
 
-    // Spawn the main Arti task
-    let data_path_clone = data_path.clone();
+    // Spawn the main Arti task. The task reports back as soon as it is
+    // polled, so a dead or shutting-down runtime is detected here rather
+    // than leaving us "running" with nothing behind it.
+    let (started_tx, started_rx) = mpsc::sync_channel::<()>(1);
+    let task_scratch = scratch.clone();
+    let run = claim.run;
     guard.runtime.spawn(async move {
-        match run_arti(data_path_clone, socks_addr, shutdown_rx).await {
+        let _ = started_tx.send(());
+        match run_arti(dirs, socks, shutdown_rx).await {
             Ok(_) => {
                 tracing::info!("Arti shutdown cleanly");
             }
@@ -106,19 +517,183 @@ pub extern "C" fn arti_start(data_dir: *const c_char, socks_port: u16) -> c_int
                 update_summary(&format!("Error: {}", e));
             }
         }
+        if let Some(dir) = task_scratch {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+        // After `arti_stop` a new start may already be running; its flags
+        // are not this task's to clear. The check waits for the state lock
+        // off the worker threads, since a start holding it waits in turn
+        // for its own task to be polled.
+        tokio::task::spawn_blocking(move || {
+            let _guard = ARTI_STATE.get().map(|state| state.lock());
+            if RUN_ID.load(Ordering::SeqCst) == run {
+                IS_RUNNING.store(false, Ordering::SeqCst);
+                BOOTSTRAP_PROGRESS.store(0, Ordering::SeqCst);
+                callbacks::set_online(false);
+                callbacks::set_device_offline(false);
+                socks::set_ready(false);
+            }
+        });
+    });
+
+    if started_rx.recv_timeout(STARTUP_HANDSHAKE_TIMEOUT).is_err() {
+        tracing::error!("Arti task did not start; runtime unavailable");
+        if let Some(dir) = scratch {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+        guard.shutdown_tx = None;
+        update_summary("");
+        return -3;
+    }
+
+    // Watch the runtime for as long as this run lasts
+    let handle = guard.runtime.handle().clone();
+    let spawned = std::thread::Builder::new()
+        .name("arti-watchdog".to_owned())
+        .spawn(move || {
+            watchdog::watch(
+                handle,
+                watchdog::PROBE_INTERVAL,
+                watchdog::PROBE_DEADLINE,
+                || IS_RUNNING.load(Ordering::SeqCst) && RUN_ID.load(Ordering::SeqCst) == run,
+                || callbacks::RUNTIME_STALL.get().is_some(),
+                |stalled| callbacks::notify_runtime_stall(stalled.as_millis() as c_int),
+            )
+        });
+    if let Err(e) = spawned {
+        tracing::warn!("Could not start the runtime watchdog: {}", e);
+    }
+
+    claim.keep();
+    0
+}
+
+/// `IS_RUNNING`, taken by a start in progress. Dropping the claim gives
+/// it back, so a start that fails partway leaves Arti stopped; only
+/// [`RunningClaim::keep`] leaves it set. Taken and kept with the state
+/// lock held, which `arti_stop` checks the flag under too.
+struct RunningClaim {
+    /// Number of this run, from `RUN_ID`
+    run: u64,
+}
+
+impl RunningClaim {
+    /// Set `IS_RUNNING`, unless it already is, starting a new run.
+    fn take() -> Option<Self> {
+        IS_RUNNING
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| RunningClaim {
+                run: RUN_ID.fetch_add(1, Ordering::SeqCst) + 1,
+            })
+    }
+
+    /// The start succeeded: leave Arti running.
+    fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for RunningClaim {
+    fn drop(&mut self) {
         IS_RUNNING.store(false, Ordering::SeqCst);
-        BOOTSTRAP_PROGRESS.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Start Arti in offline mode from cached directory information.
+///
+/// No network connections are made. The cached consensus under `data_dir`
+/// (from an earlier `arti_start`) is loaded read-only so status queries can
+/// describe the last-known network. A SOCKS listener is bound on a free
+/// loopback port (see `arti_socks_port`) that answers every request with
+/// "network unreachable". Leave offline mode with `arti_stop`.
+///
+/// # Arguments
+/// * `data_dir` - Path to data directory for Tor state (C string)
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if already running
+/// * -2 if data_dir is invalid
+/// * -3 if runtime initialization failed or the listener could not be bound
+/// * -5 if no usable (complete and current) cached directory exists
+#[no_mangle]
+pub extern "C" fn arti_start_offline(data_dir: *const c_char) -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+
+    let data_path = match c_path(data_dir) {
+        Some(p) => p,
+        None => return -2,
+    };
+
+    if init_state().is_err() {
+        return -3;
+    }
+
+    let state = match ARTI_STATE.get() {
+        Some(s) => s,
+        None => return -3,
+    };
+
+    let mut guard = match state.lock() {
+        Ok(g) => g,
+        Err(_) => return -3,
+    };
+    let Some(claim) = RunningClaim::take() else {
+        return -1;
+    };
+
+    let dirs = config::Dirs::under(&data_path);
+    let dirmgr_config = match config::build(&dirs, None).map(|c| c.dir_mgr_config()) {
+        Ok(Ok(c)) => c,
+        _ => return -2,
+    };
+
+    let netdir = guard.runtime.block_on(async {
+        let runtime = PreferredRuntime::current().ok()?;
+        match tor_dirmgr::DirMgr::load_once(runtime, dirmgr_config).await {
+            Ok(netdir) => Some(netdir),
+            Err(e) => {
+                tracing::info!("No usable cached directory: {}", e);
+                None
+            }
+        }
     });
+    let netdir = match netdir {
+        Some(n) => n,
+        None => return -5,
+    };
+
+    let server = {
+        let _enter = guard.runtime.enter();
+        match spawn_socks_server(0, Arc::new(connector::Offline)) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!("Failed to bind offline SOCKS listener: {}", e);
+                return -3;
+            }
+        }
+    };
+    SOCKS_PORT.store(server.port, Ordering::SeqCst);
+    guard.socks = Some(server);
 
-    IS_RUNNING.store(true, Ordering::SeqCst);
     BOOTSTRAP_PROGRESS.store(0, Ordering::SeqCst);
-    update_summary("Starting...");
+    update_summary(&format!(
+        "Offline: {} relays in cached consensus",
+        netdir.relays().count()
+    ));
 
+    claim.keep();
     0
 }
 
 /// Stop Arti gracefully.
 ///
+/// May be called while bootstrap is still in progress, in which case the
+/// bootstrap is aborted.
+///
 /// # Returns
 /// * 0 on success
 /// * -1 if not running
@@ -137,14 +712,45 @@ pub extern "C" fn arti_stop() -> c_int {
         Ok(g) => g,
         Err(_) => return -1,
     };
+    // Another stop may have finished while this one waited for the lock
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
 
     // Send shutdown signal
     if let Some(tx) = guard.shutdown_tx.take() {
         let _ = tx.send(());
     }
 
+    // Answer handshakes still in progress, then close the SOCKS listeners
+    socks::begin_shutdown();
+    if let Some(server) = guard.socks.take() {
+        server.task.abort();
+    }
+    SOCKS_PORT.store(0, Ordering::SeqCst);
+    for extra in guard.extra_listeners.drain(..) {
+        extra.task.abort();
+    }
+    for forward in guard.forwards.drain(..) {
+        forward.task.abort();
+    }
+    if let Some((_, task)) = guard.status_server.take() {
+        task.abort();
+    }
+    if let Some(timer) = guard.budget_timer.take() {
+        timer.abort();
+    }
+    guard.dormant_socks = None;
+    DORMANT.store(false, Ordering::SeqCst);
+
     // Clear client reference
     guard.client = None;
+    circuit::clear();
+    guard.dirs = None;
+    status::update(|status| {
+        status.client = None;
+        status.blockage = None;
+    });
 
     // Give async tasks time to complete
     std::thread::sleep(std::time::Duration::from_millis(200));
@@ -152,10 +758,128 @@ pub extern "C" fn arti_stop() -> c_int {
     IS_RUNNING.store(false, Ordering::SeqCst);
     BOOTSTRAP_PROGRESS.store(0, Ordering::SeqCst);
     update_summary("");
+    callbacks::set_online(false);
+    callbacks::set_device_offline(false);
+    socks::set_ready(false);
+
+    0
+}
 
+/// Name the async runtime's threads and optionally lower their priority,
+/// e.g. to pick them out in Instruments and keep Tor's background work
+/// from competing with the UI.
+///
+/// Applies when the runtime is built: by the first `arti_start`, and again
+/// by `arti_cleanup` (which then resets this setting like the others).
+/// Threads are named `<prefix>-<n>`; Linux and Android show only the first
+/// 15 bytes of a name. Low priority means the utility QoS class on Apple
+/// platforms, which the scheduler runs behind user-initiated work and
+/// throttles under thermal or battery pressure, and a nice value of 10 on
+/// Linux and Android. Other platforms ignore it.
+///
+/// # Arguments
+/// * `name_prefix` - Thread name prefix, or NULL for tokio's default names
+/// * `low_priority` - Nonzero to run the threads at background priority
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `name_prefix` is empty, longer than 32 bytes or not UTF-8
+#[no_mangle]
+pub extern "C" fn arti_set_thread_config(name_prefix: *const c_char, low_priority: c_int) -> c_int {
+    let name_prefix = match c_str(name_prefix) {
+        Some(p) if !p.is_empty() && p.len() <= threads::MAX_PREFIX_LEN => Some(p.to_owned()),
+        None if name_prefix.is_null() => None,
+        _ => return -1,
+    };
+    threads::set(threads::ThreadConfig {
+        name_prefix,
+        low_priority: low_priority != 0,
+    });
     0
 }
 
+/// Tear everything down and return the library to its freshly loaded state.
+///
+/// The counterpart to the implicit first-time initialization done by the
+/// first `arti_start`: stops Arti if it is running, shuts the async runtime
+/// down (waiting up to 2 seconds for its tasks, then abandoning them) and
+/// replaces it with a new one, unregisters every callback, closes the event
+/// log, and resets every setting made through this API (bridges, guards,
+/// isolation, listener options and so on) to its default. Afterwards `arti_start` behaves as it
+/// did the first time. Meant for test harnesses and apps that fully reset
+/// networking, e.g. after logging out or in a forked child.
+///
+/// Blocks until done. Must not be called from one of this library's
+/// callbacks, which run on the runtime being shut down.
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if a new runtime could not be created; the old one is left in
+///   place, but everything else is still reset
+#[no_mangle]
+pub extern "C" fn arti_cleanup() -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        arti_stop();
+    }
+
+    let mut result = 0;
+    let new_runtime = |state| (state, threads::build_runtime(&threads::current()));
+    let old_runtime = match ARTI_STATE.get().map(new_runtime) {
+        Some((state, Ok(runtime))) => {
+            let mut guard = match state.lock() {
+                Ok(g) => g,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if let Some(timer) = guard.budget_timer.take() {
+                timer.abort();
+            }
+            guard.shutdown_tx = None;
+            guard.client = None;
+            guard.dirs = None;
+            guard.socks = None;
+            guard.extra_listeners.clear();
+            guard.forwards.clear();
+            guard.status_server = None;
+            guard.dormant_socks = None;
+            Some(std::mem::replace(&mut guard.runtime, runtime))
+        }
+        Some((_, Err(e))) => {
+            tracing::error!("Failed to create tokio runtime: {}", e);
+            result = -1;
+            None
+        }
+        None => None,
+    };
+    // Outside the lock, since tasks winding down may still take it
+    if let Some(runtime) = old_runtime {
+        runtime.shutdown_timeout(CLEANUP_TIMEOUT);
+    }
+
+    IS_RUNNING.store(false, Ordering::SeqCst);
+    BOOTSTRAP_PROGRESS.store(0, Ordering::SeqCst);
+    SOCKS_PORT.store(0, Ordering::SeqCst);
+    DUAL_STACK.store(false, Ordering::SeqCst);
+    DORMANT.store(false, Ordering::SeqCst);
+    CLOCK_SKEW_SECS.store(0, Ordering::SeqCst);
+    CLOCK_SKEWED.store(false, Ordering::SeqCst);
+    set_test_connector(None);
+    set_test_bootstrap_stall(None);
+    bandwidth::reset();
+    callbacks::clear_all();
+    censorship::reset();
+    circuit::clear();
+    config::reset();
+    error::clear_last_error();
+    eventlog::stop();
+    guardstate::reset();
+    isolation::reset();
+    socks::reset();
+    status::reset();
+    threads::reset();
+
+    result
+}
+
 /// Check if Arti is currently running.
 ///
 /// # Returns
@@ -176,8 +900,47 @@ pub extern "C" fn arti_bootstrap_progress() -> c_int {
     BOOTSTRAP_PROGRESS.load(Ordering::SeqCst)
 }
 
+/// Get the number of the bootstrap attempt in progress.
+///
+/// Counts every try since the latest `arti_start`: the first is 1, each
+/// retry (see `arti_set_bootstrap_retries`) adds one, and so does each
+/// fresh client's first try (see `arti_set_bootstrap_attempts`). During
+/// the wait before a retry it is still the failed attempt's number. Once
+/// bootstrapped it stays at the attempt that succeeded, until the next
+/// start. `arti_stop` cancels a bootstrap at once, waits included.
+///
+/// # Returns
+/// * The attempt number, 1 or more
+/// * 0 if no attempt has been made since the latest start (or ever)
+#[no_mangle]
+pub extern "C" fn arti_bootstrap_attempt() -> c_int {
+    BOOTSTRAP_ATTEMPT.load(Ordering::SeqCst)
+}
+
+/// Get how long the latest `arti_start` took to bootstrap, from the call
+/// until the client was ready for traffic.
+///
+/// The value stays available after `arti_stop`, until the next start.
+///
+/// # Returns
+/// * Duration in milliseconds (saturating at `INT32_MAX`)
+/// * -1 if no start has finished bootstrapping yet
+#[no_mangle]
+pub extern "C" fn arti_last_bootstrap_duration_ms() -> c_int {
+    ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| guard.bootstrap_duration)
+        .map_or(-1, |d| d.as_millis().min(c_int::MAX as u128) as c_int)
+}
+
 /// Get the current bootstrap summary string.
 ///
+/// When Arti is not running the buffer is still filled (with the last
+/// summary, e.g. the error that stopped it, or an empty string if it was
+/// never started) but -2 is returned, so a blank summary is never mistaken
+/// for a running client.
+///
 /// # Arguments
 /// * `buf` - Buffer to write the summary into
 /// * `len` - Length of the buffer
@@ -185,131 +948,4224 @@ pub extern "C" fn arti_bootstrap_progress() -> c_int {
 /// # Returns
 /// * Number of bytes written (not including null terminator)
 /// * -1 if buffer is null or too small
+/// * -2 if Arti is not running
 #[no_mangle]
 pub extern "C" fn arti_bootstrap_summary(buf: *mut c_char, len: c_int) -> c_int {
     if buf.is_null() || len <= 0 {
         return -1;
     }
 
-    let summary = match BOOTSTRAP_SUMMARY.lock() {
-        Ok(s) => s.clone(),
-        Err(_) => return -1,
-    };
-
-    let bytes = summary.as_bytes();
-    let copy_len = std::cmp::min(bytes.len(), (len - 1) as usize);
-
-    unsafe {
-        std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf as *mut u8, copy_len);
-        *buf.add(copy_len) = 0; // null terminator
+    let written = write_c_string(buf, len, &status::load().summary);
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -2;
     }
-
-    copy_len as c_int
+    written
 }
 
-/// Signal Arti to go dormant (reduce resource usage).
-/// This is a hint; Arti may not fully support dormant mode yet.
+/// Brand the bootstrap summary with the app's name.
+///
+/// Every summary set from now on starts with `prefix` and ": ", e.g.
+/// "MyApp: Bootstrapping...", for apps that show it to users as is. An
+/// empty summary stays empty. Kept until changed or `arti_cleanup`.
+///
+/// # Arguments
+/// * `prefix` - Prefix (C string), at most 32 bytes; NULL or "" for none
 ///
 /// # Returns
 /// * 0 on success
-/// * -1 if not running
+/// * -1 if the prefix is not valid UTF-8 or too long
 #[no_mangle]
-pub extern "C" fn arti_go_dormant() -> c_int {
-    if !IS_RUNNING.load(Ordering::SeqCst) {
+pub extern "C" fn arti_set_summary_prefix(prefix: *const c_char) -> c_int {
+    let prefix = if prefix.is_null() {
+        ""
+    } else {
+        match unsafe { CStr::from_ptr(prefix) }.to_str() {
+            Ok(s) if s.len() <= status::MAX_SUMMARY_PREFIX_LEN => s,
+            _ => return -1,
+        }
+    };
+    status::set_summary_prefix(prefix);
+    0
+}
+
+/// Get the prefix set with `arti_set_summary_prefix`.
+///
+/// # Arguments
+/// * `buf` - Buffer to write the prefix into
+/// * `len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator); 0 if there
+///   is no prefix. A prefix too long for the buffer is truncated.
+/// * -1 if buffer is null or empty
+#[no_mangle]
+pub extern "C" fn arti_summary_prefix(buf: *mut c_char, len: c_int) -> c_int {
+    write_c_string(buf, len, &status::summary_prefix())
+}
+
+/// Get the reason bootstrap is stuck, if arti thinks it is.
+///
+/// While bootstrap is blocked this is arti's description of the blockage,
+/// e.g. "We seem to be offline (...)" or "Can't bootstrap a Tor directory.
+/// (...)"; it is empty while bootstrap is progressing normally, once it is
+/// complete, and when Arti is not running. Useful next to a progress bar
+/// that has stopped moving. A reason too long for the buffer is truncated.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the reason into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator); 0 if not
+///   blocked
+/// * -1 if the buffer is null or empty
+#[no_mangle]
+pub extern "C" fn arti_bootstrap_blockage(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let snapshot = status::load();
+    write_c_string(out_buf, out_len, snapshot.blockage.as_deref().unwrap_or_default())
+}
+
+/// Signal Arti to go dormant (reduce resource usage).
+/// This is a hint; Arti may not fully support dormant mode yet.
+///
+/// `keep_listener` decides what happens to the main SOCKS port meanwhile:
+/// * Non-zero: it keeps accepting, and the first connection wakes Arti as
+///   `arti_wake` would. Apps can connect at any time, but that first
+///   connection pays for waking up before its request is served.
+/// * Zero: it is closed (`arti_socks_port` reports -1), so connections are
+///   refused outright instead of waiting. `arti_wake` reopens it on the
+///   same port, which adds a bind to wake-up and can fail if another
+///   process took the port in the meantime.
+///
+/// Additional ports from `arti_add_socks_port` keep accepting either way.
+/// A listener adopted with `arti_start_on_fd` can't be reopened, so it
+/// can't be closed either.
+///
+/// # Arguments
+/// * `keep_listener` - Non-zero to keep the SOCKS port open
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if not running
+/// * -2 if `keep_listener` is 0 but the listener was adopted (nothing changes)
+#[no_mangle]
+pub extern "C" fn arti_go_dormant(keep_listener: c_int) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    if keep_listener == 0 {
+        let Some(mut guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) else {
+            return -1;
+        };
+        if guard.socks.as_ref().is_some_and(|s| s.adopted) {
+            return -2;
+        }
+        if let Some(server) = guard.socks.take() {
+            server.task.abort();
+            SOCKS_PORT.store(0, Ordering::SeqCst);
+            tracing::info!("Closed SOCKS port {} while dormant", server.port);
+            guard.dormant_socks = Some((server.port, server.connector));
+        }
+    }
+    enter_dormant();
+    0
+}
+
+fn enter_dormant() {
+    // Arti doesn't have explicit dormant mode yet, but we can note the intent
+    DORMANT.store(true, Ordering::SeqCst);
+    update_summary("Dormant");
+}
+
+/// A SOCKS connection arrived: leave dormancy, as `arti_wake` would (a
+/// port closed for dormancy can't see connections, so there's none to
+/// reopen).
+pub(crate) fn wake_on_connection() {
+    if DORMANT.swap(false, Ordering::SeqCst) {
+        tracing::info!("SOCKS connection while dormant; waking");
+        update_summary("Active");
+    }
+}
+
+/// Signal Arti to wake from dormant mode.
+///
+/// Also cancels any budget set with `arti_set_background_budget`, and
+/// reopens the SOCKS port if `arti_go_dormant` closed it.
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if not running
+/// * -2 if the SOCKS port could not be reopened (Arti stays dormant; try
+///   again, or move to a new port with `arti_rebind_socks` after waking)
+#[no_mangle]
+pub extern "C" fn arti_wake() -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    if let Some(mut guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) {
+        if let Some(timer) = guard.budget_timer.take() {
+            timer.abort();
+        }
+        if let Some((port, connector)) = guard.dormant_socks.take() {
+            let reopened = {
+                let _enter = guard.runtime.enter();
+                spawn_socks_server(port, connector.clone())
+            };
+            match reopened {
+                Ok(server) => {
+                    SOCKS_PORT.store(server.port, Ordering::SeqCst);
+                    guard.socks = Some(server);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to reopen SOCKS port {}: {}", port, e);
+                    guard.dormant_socks = Some((port, connector));
+                    return -2;
+                }
+            }
+        }
+    }
+    DORMANT.store(false, Ordering::SeqCst);
+    update_summary("Active");
+    0
+}
+
+/// Recover after the OS has torn down the network (e.g. iOS closing every
+/// socket on a network transition), without a full stop/start.
+///
+/// Retires the circuits that recent SOCKS streams were using, so new
+/// streams build fresh circuits instead of waiting for the dead ones to
+/// time out. Config, guards and the data dir are untouched: the new
+/// circuits go through the same guards, re-dialing any channel whose socket
+/// was lost, and existing streams keep whatever circuit they have. This is
+/// not a new identity; paths beyond the guard are simply rebuilt. Safe to
+/// call while healthy, where it only costs a few circuit builds.
+///
+/// # Returns
+/// * Number of circuits retired
+/// * -1 if not running or not yet bootstrapped
+#[no_mangle]
+pub extern "C" fn arti_reconnect() -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    let Some(client) = status::load().client.clone() else {
+        return -1;
+    };
+    let retired = circuit::take_used();
+    for id in &retired {
+        client.circmgr().retire_circ(id);
+    }
+    tracing::info!("Reconnect requested; retired {} circuits", retired.len());
+    retired.len() as c_int
+}
+
+/// Retire the circuits one isolation group has been using, e.g. when one
+/// account of a multi-account app logs out.
+///
+/// Only circuits that streams with this id were attached to are retired
+/// (the most recent few hundred are remembered across all ids); other
+/// groups keep theirs, and guards and settings are untouched. Streams
+/// already open carry on, and the group's next streams build fresh
+/// circuits.
+///
+/// # Arguments
+/// * `id` - Isolation id, as used with `arti_add_socks_port` or
+///   `arti_set_default_isolation`
+///
+/// # Returns
+/// * Number of circuits retired (0 for an id with none, or for 0)
+/// * -1 if not running or not yet bootstrapped
+#[no_mangle]
+pub extern "C" fn arti_drop_isolation(id: u64) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    let Some(client) = status::load().client.clone() else {
+        return -1;
+    };
+    let Some(token) = isolation::existing_token(id) else {
+        return 0;
+    };
+    let retired = circuit::take_isolated(token);
+    for id in &retired {
+        client.circmgr().retire_circ(id);
+    }
+    tracing::info!("Dropped isolation {}; retired {} circuits", id, retired.len());
+    retired.len() as c_int
+}
+
+/// Tell Arti how long the app may keep running in the background.
+///
+/// Advisory: iOS grants limited background time, and this lets Arti wind
+/// down before it is suspended rather than mid-operation. When the budget
+/// is within a few seconds of running out (immediately, for very short
+/// budgets), Arti goes dormant as with `arti_go_dormant` and fires the
+/// callback from `arti_set_budget_expiring_callback`, giving the app a
+/// chance to request more time or suspend cleanly. Setting a new budget
+/// replaces the old one; `arti_wake`, on returning to the foreground,
+/// cancels it.
+///
+/// # Arguments
+/// * `secs` - Remaining background time in seconds, or 0 to cancel
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if not running
+#[no_mangle]
+pub extern "C" fn arti_set_background_budget(secs: c_int) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    let Some(mut guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) else {
+        return -1;
+    };
+    if let Some(timer) = guard.budget_timer.take() {
+        timer.abort();
+    }
+    if secs <= 0 {
+        return 0;
+    }
+
+    let budget = Duration::from_secs(secs as u64);
+    let warn_after = budget.saturating_sub(BUDGET_WARNING_MARGIN);
+    let timer = guard.runtime.spawn(async move {
+        tokio::time::sleep(warn_after).await;
+        tracing::info!("Background budget nearly exhausted; going dormant");
+        enter_dormant();
+        callbacks::notify_budget_expiring((budget - warn_after).as_secs() as c_int);
+    });
+    guard.budget_timer = Some(timer);
+    0
+}
+
+/// Register a callback fired when a background budget is nearly used up.
+///
+/// See `arti_set_background_budget`. The callback receives the seconds
+/// left in the budget.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the remaining seconds and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_budget_expiring_callback(
+    cb: Option<ArtiBudgetExpiringCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::BUDGET_EXPIRING.set(cb, ctx);
+    0
+}
+
+/// Enable or disable dual-stack SOCKS listening.
+///
+/// When enabled, the next `arti_start` binds the SOCKS port on both
+/// `127.0.0.1` and `[::1]`, so clients resolving `localhost` to either
+/// family can connect. If IPv6 loopback is unavailable, only IPv4 is bound.
+///
+/// # Arguments
+/// * `enabled` - Non-zero to bind both loopback addresses
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_dual_stack(enabled: c_int) -> c_int {
+    DUAL_STACK.store(enabled != 0, Ordering::SeqCst);
+    0
+}
+
+/// Set the number of primary entry guards used by the next `arti_start`.
+///
+/// Tor's default (taken from the consensus, currently 3) is chosen to balance
+/// exposure against reliability. Raising it means more relays get to observe
+/// your entry traffic over time, which weakens protection against a hostile
+/// guard; lowering it makes you more dependent on fewer relays staying up.
+/// Only change this if you understand your threat model.
+///
+/// # Arguments
+/// * `n` - Number of guards, clamped to 1..=10; 0 or negative restores the default
+///
+/// # Returns
+/// * The effective value (0 means the consensus default)
+#[no_mangle]
+pub extern "C" fn arti_set_num_guards(n: c_int) -> c_int {
+    let n = if n <= 0 { 0 } else { n.min(config::MAX_NUM_GUARDS) };
+    config::NUM_GUARDS.store(n, Ordering::SeqCst);
+    n
+}
+
+/// Get the circuit build timeout currently in use.
+///
+/// Arti learns this from how long its circuits have taken to build, so it
+/// changes over time; until it has enough samples it uses the consensus
+/// default (60 seconds). With a timeout set by
+/// `arti_set_circuit_build_timeout`, that value is returned.
+///
+/// # Returns
+/// * The timeout for building a 3-hop circuit, in milliseconds
+/// * -1 if Arti is not running and bootstrapped
+#[no_mangle]
+pub extern "C" fn arti_circuit_build_timeout_ms() -> c_int {
+    let Some(client) = status::load().client.clone() else {
+        return -1;
+    };
+    let action = tor_circmgr::timeouts::Action::BuildCircuit { length: 3 };
+    let timeout = client.circmgr().estimate_timeout(&action);
+    timeout.as_millis().min(c_int::MAX as u128) as c_int
+}
+
+/// Fix the circuit build timeout instead of letting Arti learn it.
+///
+/// Circuits taking longer than this to build are given up on. On
+/// high-latency mobile links a learned timeout can end up too tight and
+/// discard circuits that would have finished; a fixed, generous one avoids
+/// that. Overriding disables adaptive learning entirely: the timeout stays
+/// fixed until it is cleared, even if the network gets faster. Applies to
+/// the running client immediately and to later starts.
+///
+/// # Arguments
+/// * `ms` - Timeout in milliseconds, at least 10; 0 restores adaptive learning
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if Arti is not running and bootstrapped, or `ms` is out of range
+/// * -2 if the setting could not be applied (see `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_set_circuit_build_timeout(ms: c_int) -> c_int {
+    if ms < 0 || (ms > 0 && ms < config::MIN_CIRCUIT_BUILD_TIMEOUT_MS) {
+        return -1;
+    }
+    let Some((client, dirs)) = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| Some((guard.client.clone()?, guard.dirs.clone()?)))
+    else {
+        return -1;
+    };
+
+    let previous = config::CIRCUIT_BUILD_TIMEOUT_MS.swap(ms, Ordering::SeqCst);
+    let result = config::build(&dirs, Some(&client)).and_then(|cfg| {
+        client.reconfigure(&cfg, Reconfigure::AllOrNothing)?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        config::CIRCUIT_BUILD_TIMEOUT_MS.store(previous, Ordering::SeqCst);
+        error::set_last_error(&format!("Failed to set circuit build timeout: {}", e));
+        return -2;
+    }
+    0
+}
+
+/// Set resource limits to keep Arti within mobile memory budgets.
+///
+/// `max_memory_mb` feeds arti's memory quota: when queued data exceeds it,
+/// arti reclaims memory by tearing down the largest consumers. It applies at
+/// the next `arti_start`.
+///
+/// arti does not expose a cap on circuits, so `max_circuits` instead limits
+/// the number of concurrently relayed SOCKS connections, which bounds the
+/// circuit and stream state they can pull in. Requests over the limit get a
+/// SOCKS general-failure reply and a warning is logged. It takes effect
+/// immediately.
+///
+/// # Arguments
+/// * `max_circuits` - Maximum concurrent connections; 0 or negative for no limit
+/// * `max_memory_mb` - Memory quota in MiB; 0 or negative for arti's default
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_resource_limits(max_circuits: c_int, max_memory_mb: c_int) -> c_int {
+    socks::MAX_CONNECTIONS.store(max_circuits.max(0) as usize, Ordering::SeqCst);
+    config::MAX_MEMORY_MB.store(max_memory_mb.max(0), Ordering::SeqCst);
+    0
+}
+
+/// Pin the first hop of every circuit to a specific relay.
+///
+/// The relay is identified by its RSA fingerprint (40 hex digits, with an
+/// optional `$` prefix). Pinning works by using the relay as the sole
+/// bridge, so it needs the consensus to learn the relay's address: if set
+/// while running it is checked and applied immediately. If set before
+/// start, a cached consensus naming the relay makes it the first hop from
+/// the start, bootstrap included; without one, bootstrap goes through
+/// the usual guards and the pin is applied once it completes, retiring
+/// the circuits built until then. Either way the start fails (the reason
+/// is in `arti_bootstrap_summary` and goes to the network error callback)
+/// if the relay is not in the fresh consensus. While a guard is
+/// pinned, configured bridges are set aside, so the pinned relay is the
+/// only first hop. Intended for debugging and specialised deployments; a
+/// single fixed entry relay is easy to fingerprint.
+///
+/// # Arguments
+/// * `fingerprint` - Relay fingerprint (C string), or NULL to clear
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if running and the relay isn't in the current consensus
+/// * -2 if the fingerprint is malformed
+/// * -3 if the configuration could not be applied to the running client
+///   (the previous pin stays in place; reason in `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_set_entry_guard(fingerprint: *const c_char) -> c_int {
+    let id = if fingerprint.is_null() {
+        None
+    } else {
+        let s = match unsafe { CStr::from_ptr(fingerprint) }.to_str() {
+            Ok(s) => s,
+            Err(_) => return -2,
+        };
+        match config::parse_fingerprint(s) {
+            Some(id) => Some(id),
+            None => return -2,
+        }
+    };
+
+    // If running, validate against the consensus before committing
+    let running = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| Some((guard.client.clone()?, guard.dirs.clone()?)));
+    if let (Some((client, _)), Some(id)) = (&running, &id) {
+        if !config::relay_in_consensus(client, id) {
+            return -1;
+        }
+    }
+
+    let previous = config::PINNED_GUARD
+        .lock()
+        .map(|mut pinned| std::mem::replace(&mut *pinned, id));
+
+    let Some((client, dirs)) = running else {
+        return 0;
+    };
+    let result = match &id {
+        Some(id) => apply_pinned_guard(&client, &dirs, id),
+        None => config::build(&dirs, None).and_then(|cfg| {
+            client.reconfigure(&cfg, Reconfigure::AllOrNothing)?;
+            Ok(())
+        }),
+    };
+    if let Err(e) = result {
+        // The next start shouldn't pick up a pin that never took effect
+        if let (Ok(previous), Ok(mut pinned)) = (previous, config::PINNED_GUARD.lock()) {
+            *pinned = previous;
+        }
+        tracing::warn!("Failed to apply entry guard setting: {}", e);
+        error::set_last_error(&format!("Failed to apply entry guard setting: {}", e));
+        return -3;
+    }
+    0
+}
+
+/// Configure an upstream HTTP or SOCKS5 proxy for reaching the Tor network.
+///
+/// The URL must be of the form `http://host:port` or `socks5://host:port`.
+/// arti-client does not yet expose a way to route its own relay connections
+/// through an outbound proxy, so a valid URL is rejected with -1 rather than
+/// silently ignored. Passing NULL clears any previous setting.
+///
+/// # Arguments
+/// * `url` - Proxy URL (C string), or NULL to clear
+///
+/// # Returns
+/// * 0 if cleared
+/// * -1 if upstream proxies are unsupported by this build
+/// * -2 if the URL is invalid or uses an unsupported scheme
+#[no_mangle]
+pub extern "C" fn arti_set_upstream_proxy(url: *const c_char) -> c_int {
+    if url.is_null() {
+        return 0;
+    }
+    let url = match unsafe { CStr::from_ptr(url) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -2,
+    };
+    if parse_proxy_url(url).is_none() {
+        return -2;
+    }
+    tracing::warn!("Upstream proxy {} requested, but not supported by arti-client", url);
+    -1
+}
+
+/// Restrict path selection to relays advertising certain subprotocols.
+///
+/// `proto` uses the consensus `proto` syntax, e.g. `"Relay=4 Link=5"`.
+/// arti chooses relays against its own built-in protocol requirements and
+/// exposes no predicate for adding more, so a well-formed constraint is
+/// rejected with -1 rather than silently ignored. Passing NULL clears any
+/// previous setting.
+///
+/// # Arguments
+/// * `proto` - Required subprotocol versions (C string), or NULL to clear
+///
+/// # Returns
+/// * 0 if cleared
+/// * -1 if protocol constraints are unsupported by this build
+/// * -2 if `proto` is malformed
+#[no_mangle]
+pub extern "C" fn arti_set_min_relay_protocol(proto: *const c_char) -> c_int {
+    if proto.is_null() {
+        return 0;
+    }
+    let proto = match unsafe { CStr::from_ptr(proto) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -2,
+    };
+    if proto.trim().is_empty() || proto.parse::<tor_protover::Protocols>().is_err() {
+        return -2;
+    }
+    tracing::warn!(
+        "Relay protocol constraint {} requested, but not supported by arti-client",
+        proto
+    );
+    -1
+}
+
+/// Split a `scheme://host:port` proxy URL, accepting only http and socks5.
+fn parse_proxy_url(url: &str) -> Option<(&str, &str, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    if !matches!(scheme, "http" | "socks5") {
+        return None;
+    }
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    let (host, port) = rest.rsplit_once(':')?;
+    let port: u16 = port.parse().ok()?;
+    if host.is_empty() || port == 0 {
+        return None;
+    }
+    Some((scheme, host, port))
+}
+
+/// Get the port the main SOCKS listener is bound to.
+///
+/// # Returns
+/// * The port number
+/// * -1 if the SOCKS listener is not up (not running or still bootstrapping)
+#[no_mangle]
+pub extern "C" fn arti_socks_port() -> c_int {
+    match SOCKS_PORT.load(Ordering::SeqCst) {
+        0 => -1,
+        port => port as c_int,
+    }
+}
+
+/// Report clock skew between this device and the Tor network.
+///
+/// A wrong device clock is one of the most common reasons bootstrap fails:
+/// the consensus looks expired or not yet valid. Arti estimates the skew
+/// from the time relays report during handshakes. When it is significant,
+/// bootstrap failures are reported with `ARTI_NETWORK_ERROR_CLOCK_SKEW`, so
+/// the app can ask the user to fix their clock. The estimate survives a
+/// failed bootstrap and is reset by the next start.
+///
+/// # Arguments
+/// * `out_skew` - Receives the estimated skew in seconds: positive if this
+///   device's clock is fast, negative if slow, 0 if there is no estimate
+///
+/// # Returns
+/// * 1 if significant skew was detected
+/// * 0 if not (or nothing is known yet)
+/// * -1 if `out_skew` is NULL
+#[no_mangle]
+pub extern "C" fn arti_clock_skew_secs(out_skew: *mut c_int) -> c_int {
+    if out_skew.is_null() {
+        return -1;
+    }
+    let secs = CLOCK_SKEW_SECS.load(Ordering::SeqCst);
+    unsafe { *out_skew = secs.clamp(c_int::MIN as i64, c_int::MAX as i64) as c_int };
+    CLOCK_SKEWED.load(Ordering::SeqCst) as c_int
+}
+
+/// Move the main SOCKS listener to a different port.
+///
+/// The new port is bound before the old one is closed, and the `TorClient`
+/// is untouched, so circuits survive and no re-bootstrap is needed.
+/// Connections already accepted on the old port keep running. Requires a
+/// running, bootstrapped client.
+///
+/// # Arguments
+/// * `new_port` - Port to listen on (0 picks a free port)
+///
+/// # Returns
+/// * The port now bound
+/// * -1 if not running, not bootstrapped, or the new port could not be bound
+#[no_mangle]
+pub extern "C" fn arti_rebind_socks(new_port: u16) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+
+    let state = match ARTI_STATE.get() {
+        Some(s) => s,
+        None => return -1,
+    };
+
+    let mut guard = match state.lock() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let connector = match &guard.socks {
+        Some(server) => server.connector.clone(),
+        None => return -1,
+    };
+
+    let server = {
+        let _enter = guard.runtime.enter();
+        match spawn_socks_server(new_port, connector) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("Failed to rebind SOCKS port {}: {}", new_port, e);
+                return -1;
+            }
+        }
+    };
+
+    let port = server.port;
+    if let Some(old) = guard.socks.replace(server) {
+        old.task.abort();
+        tracing::info!("SOCKS5 proxy moved from port {} to {}", old.port, port);
+    }
+    SOCKS_PORT.store(port, Ordering::SeqCst);
+
+    port as c_int
+}
+
+/// Bind an additional SOCKS port whose connections are all isolated together.
+///
+/// Every connection accepted on this port uses the isolation token for
+/// `isolation_id`, so it shares circuits only with other connections using
+/// the same id (on this or any other port), similar to running several Tor
+/// `SocksPort` lines. Requires a running, bootstrapped client; the listener
+/// is closed by `arti_stop`.
+///
+/// # Arguments
+/// * `port` - Loopback port to listen on
+/// * `isolation_id` - Isolation group for all connections on this port
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if not running or not yet bootstrapped
+/// * -2 if the port could not be bound
+/// * -3 if the port is already an additional SOCKS port
+#[no_mangle]
+pub extern "C" fn arti_add_socks_port(port: u16, isolation_id: u64) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+
+    let state = match ARTI_STATE.get() {
+        Some(s) => s,
+        None => return -1,
+    };
+
+    let mut guard = match state.lock() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let client = match &guard.client {
+        Some(c) => c.clone(),
+        None => return -1,
+    };
+
+    if guard.extra_listeners.iter().any(|l| l.port == port) {
+        return -3;
+    }
+
+    let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+    let listener = {
+        let _enter = guard.runtime.enter();
+        match bind_listener(addr) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::warn!("Failed to bind extra SOCKS port {}: {}", addr, e);
+                return -2;
+            }
+        }
+    };
+
+    let token = isolation::token_for(isolation_id);
+    let task = guard.runtime.spawn(async move {
+        let connector: Arc<dyn StreamConnector> = client;
+        let err = socks::supervise(
+            listener,
+            move || bind_listener(addr),
+            |listener: TcpListener| {
+                let connector = connector.clone();
+                async move { socks::serve(&listener, None, connector, Some(token)).await }
+            },
+            socks::REBIND_POLICY,
+        )
+        .await;
+        callbacks::notify_network_error(
+            callbacks::NETWORK_ERROR_LISTENER,
+            &format!("SOCKS listener on {} failed and could not be rebound: {}", addr, err),
+        );
+    });
+    guard.extra_listeners.push(ExtraListener {
+        port,
+        isolation_id,
+        task,
+    });
+
+    tracing::info!("SOCKS5 proxy listening on {} (isolation {})", addr, isolation_id);
+    0
+}
+
+/// Forward a loopback port to a fixed Tor target.
+///
+/// Every connection accepted on `127.0.0.1:local_port` is tunnelled over
+/// Tor to `target_host:target_port`, a clearnet host or an onion service,
+/// with no SOCKS handshake: for apps that can only be pointed at a local
+/// address. Streams use the isolation set with
+/// `arti_set_default_isolation` and are subject to the same policy as
+/// SOCKS connections (allowed ports, Tor-only domains, the connection
+/// filter and fail closed); a refused one is simply closed. The target is
+/// checked per connection, as the policy can change while the forward
+/// runs. Requires a running, bootstrapped client;
+/// forwards are removed by `arti_forward_stop` and `arti_stop`.
+///
+/// # Arguments
+/// * `local_port` - Loopback port to listen on
+/// * `target_host` - Hostname, IP address or onion address (C string)
+/// * `target_port` - Port to reach on the target
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if not running or not yet bootstrapped
+/// * -2 if the port could not be bound
+/// * -3 if the port is already forwarded
+/// * -4 if the target is not a valid address (see `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_forward_port(
+    local_port: u16,
+    target_host: *const c_char,
+    target_port: u16,
+) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    if target_host.is_null() {
+        error::set_last_error("target host is NULL");
+        return -4;
+    }
+    let Ok(host) = unsafe { CStr::from_ptr(target_host) }.to_str() else {
+        error::set_last_error("target host is not valid UTF-8");
+        return -4;
+    };
+    let target_name = format!("{}:{}", host, target_port);
+    let target = match target_name.as_str().into_tor_addr() {
+        Ok(addr) => forward::Target { host: host.to_owned(), port: target_port, addr },
+        Err(e) => {
+            error::set_last_error(&format!("invalid forward target {}: {}", target_name, e));
+            return -4;
+        }
+    };
+
+    let Some(mut guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) else {
+        return -1;
+    };
+    let client = match &guard.client {
+        Some(c) => c.clone(),
+        None => return -1,
+    };
+    if guard.forwards.iter().any(|f| f.port == local_port) {
+        return -3;
+    }
+
+    let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), local_port);
+    let listener = {
+        let _enter = guard.runtime.enter();
+        match bind_listener(addr) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::warn!("Failed to bind forward port {}: {}", addr, e);
+                return -2;
+            }
+        }
+    };
+
+    let task = guard.runtime.spawn(async move {
+        let connector: Arc<dyn StreamConnector> = client;
+        let err = socks::supervise(
+            listener,
+            move || bind_listener(addr),
+            |listener: TcpListener| {
+                let connector = connector.clone();
+                let target = target.clone();
+                async move { forward::serve(&listener, connector, target).await }
+            },
+            socks::REBIND_POLICY,
+        )
+        .await;
+        callbacks::notify_network_error(
+            callbacks::NETWORK_ERROR_LISTENER,
+            &format!("Forward listener on {} failed and could not be rebound: {}", addr, err),
+        );
+    });
+    tracing::info!("Forwarding {} to {} over Tor", addr, target_name);
+    guard.forwards.push(PortForward {
+        port: local_port,
+        target: target_name,
+        task,
+    });
+    0
+}
+
+/// Remove a port forward added with `arti_forward_port`.
+///
+/// The listener is closed; connections already being forwarded carry on
+/// until either side closes them.
+///
+/// # Arguments
+/// * `local_port` - The forwarded loopback port
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if no forward uses that port
+#[no_mangle]
+pub extern "C" fn arti_forward_stop(local_port: u16) -> c_int {
+    let Some(mut guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) else {
+        return -1;
+    };
+    let Some(index) = guard.forwards.iter().position(|f| f.port == local_port) else {
+        return -1;
+    };
+    let forward = guard.forwards.remove(index);
+    forward.task.abort();
+    tracing::info!("Stopped forwarding port {} to {}", forward.port, forward.target);
+    0
+}
+
+/// Open a stream over Tor and hand it over as two pipe file descriptors,
+/// one per direction.
+///
+/// Blocks until the stream is open. Read what the destination sends from
+/// `*out_read_fd` and write to it through `*out_write_fd`. The directions
+/// end independently: closing the write fd tells the destination that no
+/// more data is coming while the read fd keeps delivering its reply, and
+/// the read fd returns end-of-file once the destination has finished.
+///
+/// Both fds belong to the caller, who must `close` each of them; the
+/// stream is torn down once both are closed, and can also be closed early
+/// with `arti_close_connection` (it is listed with the label `pipes`).
+/// The fds are blocking and close-on-exec. Streams use the isolation set
+/// with `arti_set_default_isolation` and get the same checks as a SOCKS
+/// CONNECT: the Tor-only domains, allowed ports, connection filter, fail
+/// closed, connection limit and connect timeouts.
+///
+/// # Arguments
+/// * `host` - Hostname, IP address or onion address (C string)
+/// * `port` - Destination port
+/// * `out_read_fd` - Receives the fd to read from
+/// * `out_write_fd` - Receives the fd to write to
+///
+/// # Returns
+/// * 0 on success
+/// * -1 on failure: not running or bootstrapped, bad arguments, refused by
+///   one of those checks, or the stream could not be opened (see
+///   `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_open_stream_split(
+    host: *const c_char,
+    port: u16,
+    out_read_fd: *mut c_int,
+    out_write_fd: *mut c_int,
+) -> c_int {
+    if out_read_fd.is_null() || out_write_fd.is_null() {
+        error::set_last_error("fd out pointer is NULL");
+        return -1;
+    }
+    if host.is_null() {
+        error::set_last_error("host is NULL");
+        return -1;
+    }
+    let Ok(host) = unsafe { CStr::from_ptr(host) }.to_str() else {
+        error::set_last_error("host is not valid UTF-8");
+        return -1;
+    };
+    let target_name = format!("{}:{}", host, port);
+    let target = match target_name.as_str().into_tor_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error::set_last_error(&format!("invalid target {}: {}", target_name, e));
+            return -1;
+        }
+    };
+
+    // Don't hold the state lock while the stream opens
+    let running = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .filter(|_| IS_RUNNING.load(Ordering::SeqCst))
+        .and_then(|guard| Some((guard.client.clone()?, guard.runtime.handle().clone())));
+    let Some((client, handle)) = running else {
+        error::set_last_error("Arti is not running or not yet bootstrapped");
+        return -1;
+    };
+
+    let options = socks::HandlerOptions::current();
+    let opened = handle.block_on(open_split(client.as_ref(), host, port, target, &options));
+    match opened {
+        Ok(app) => {
+            unsafe {
+                *out_read_fd = app.read.into_raw_fd();
+                *out_write_fd = app.write.into_raw_fd();
+            }
+            0
+        }
+        Err(e) => {
+            error::set_last_error(&format!("Cannot open stream to {}: {}", target_name, e));
+            -1
+        }
+    }
+}
+
+/// Open the stream for `arti_open_stream_split` through `connector`,
+/// under the policy and limits a SOCKS CONNECT gets, and start relaying
+/// it to the pipes.
+async fn open_split(
+    connector: &dyn StreamConnector,
+    host: &str,
+    port: u16,
+    target: arti_client::TorAddr,
+    options: &socks::HandlerOptions,
+) -> std::io::Result<pipestream::AppEnds> {
+    #[cfg(not(feature = "onion"))]
+    if socks::is_onion(host) {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, onion::NOT_COMPILED_IN));
+    }
+    socks::check_connect(host, port, options).await.map_err(|refusal| refusal.error)?;
+    let Some(slot) = socks::ConnectionSlot::acquire() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "Connection limit reached",
+        ));
+    };
+
+    let isolation = isolation::default_token();
+    let prefs = socks::stream_prefs(isolation, host, port, None);
+    let connect = circuit::STREAM_ISOLATION.scope(isolation, connector.connect(target, &prefs));
+    let stream = match options.connect_timeout_for(host) {
+        Some(limit) => tokio::time::timeout(limit, connect).await.map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("Connect timed out after {:?}", limit),
+            )
+        })??,
+        None => connect.await?,
+    };
+    let (app, relay_ends) = pipestream::open()?;
+    let target = format!("{}:{}", host, port);
+    tokio::spawn(async move {
+        let _slot = slot;
+        match pipestream::relay(stream, relay_ends, target.clone()).await {
+            Ok((sent, received)) => tracing::debug!(
+                "Pipe stream to {} closed: {} bytes sent, {} received",
+                target,
+                sent,
+                received
+            ),
+            Err(e) => tracing::debug!("Pipe stream to {} failed: {}", target, e),
+        }
+    });
+    Ok(app)
+}
+
+/// Serve a small status page on loopback, for running as a daemon.
+///
+/// `http://127.0.0.1:<port>/` shows the bootstrap state, traffic and
+/// circuits, refreshing every two seconds; `/status.json` serves the raw
+/// document:
+/// `{"running":true,"progress":100,"summary":"Ready","bootstrap":{...},
+/// "socks_port":39050,"bytes_to_tor":1024,"bytes_from_tor":4096,
+/// "active_connections":1,"connections":[...],"last_circuit":{"hops":[...]}}`,
+/// where `bootstrap` is the document `arti_set_bootstrap_json_callback`
+/// delivers (null until bootstrapped), `connections` is the list from
+/// `arti_connection_info` and `last_circuit` the path from
+/// `arti_last_circuit_path` (null before the first connection). The page
+/// only answers requests addressed to 127.0.0.1, localhost or [::1], and
+/// is closed by `arti_stop`.
+///
+/// # Arguments
+/// * `port` - Loopback port to listen on; 0 for any free port
+///
+/// # Returns
+/// * The port the page is served on
+/// * -1 if Arti is not running
+/// * -2 if the port could not be bound
+/// * -3 if the status page is already being served
+#[no_mangle]
+pub extern "C" fn arti_start_status_server(port: u16) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    let Some(mut guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) else {
+        return -1;
+    };
+    if guard.status_server.as_ref().is_some_and(|(_, task)| !task.is_finished()) {
+        return -3;
+    }
+
+    let listener = {
+        let _enter = guard.runtime.enter();
+        match bind_listener(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::warn!("Failed to bind status page port {}: {}", port, e);
+                return -2;
+            }
+        }
+    };
+    let port = match listener.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(_) => return -2,
+    };
+    let task = guard.runtime.spawn(async move {
+        statuspage::serve(&listener, status_json).await;
+    });
+    guard.status_server = Some((port, task));
+    tracing::info!("Status page at http://127.0.0.1:{}/", port);
+    port as c_int
+}
+
+/// The document the status page serves.
+fn status_json() -> String {
+    let snapshot = status::load();
+    let bootstrap = snapshot.client.as_ref().map(|c| bootstrap_status_json(&c.bootstrap_status()));
+    let (to_tor, from_tor) = socks::traffic_totals();
+    json::Object::new()
+        .bool("running", IS_RUNNING.load(Ordering::SeqCst))
+        .num("progress", BOOTSTRAP_PROGRESS.load(Ordering::SeqCst))
+        .str("summary", &snapshot.summary)
+        .raw("bootstrap", bootstrap.as_deref().unwrap_or("null"))
+        .num("socks_port", SOCKS_PORT.load(Ordering::SeqCst))
+        .num("bytes_to_tor", to_tor)
+        .num("bytes_from_tor", from_tor)
+        .num("active_connections", socks::active_connections())
+        .raw("connections", &sessions::list_json())
+        .raw("last_circuit", circuit::last_json().as_deref().unwrap_or("null"))
+        .finish()
+}
+
+/// Register a callback for failures reaching the Tor network itself.
+///
+/// Fires when arti reports that bootstrap is blocked (offline, filtered,
+/// guards unreachable, clock skew, directory fetch failing) and when
+/// bootstrap fails outright. It does not fire for individual SOCKS stream
+/// failures. Each distinct blockage is reported once until it clears. It
+/// also fires, with `NETWORK_ERROR_LISTENER`, when a SOCKS listener breaks
+/// and every attempt to rebind it has failed.
+///
+/// # Arguments
+/// * `cb` - Callback receiving an error category, message, and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_network_error_callback(
+    cb: Option<ArtiNetworkErrorCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::NETWORK_ERROR.set(cb, ctx);
+    0
+}
+
+/// Register a callback suggesting that the app offer bridges.
+///
+/// Fires when bootstrap has been blocked on reaching the Tor network
+/// (connections filtered, or guards unreachable) and has made no progress
+/// for the threshold set with `arti_set_censorship_threshold` (30 seconds
+/// by default). It fires once per stall; if bootstrap progresses and then
+/// stalls again, it fires again. Being offline or having a wrong clock
+/// does not count.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the blockage category, stall length in
+///   seconds, and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_censorship_callback(
+    cb: Option<ArtiCensorshipCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::CENSORSHIP.set(cb, ctx);
+    0
+}
+
+/// Set how long bootstrap must stall before the censorship callback fires.
+///
+/// Applies from the next bootstrap status update, including to a stall
+/// already in progress.
+///
+/// # Arguments
+/// * `secs` - Seconds without progress; 30 by default
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `secs` is not positive
+#[no_mangle]
+pub extern "C" fn arti_set_censorship_threshold(secs: c_int) -> c_int {
+    if secs <= 0 {
+        return -1;
+    }
+    censorship::set_threshold_secs(secs as u32);
+    0
+}
+
+/// Register a callback fired when the async runtime appears stuck.
+///
+/// While Arti is running, a watchdog thread checks every 10 seconds that
+/// the runtime can still run a trivial task, and fires the callback if
+/// the task has not run within 5 seconds: a sign that every worker thread
+/// is blocked, for instance in a blocking call made from a callback. It
+/// fires once per stall, from the watchdog thread, and the check is
+/// skipped entirely while no callback is registered.
+///
+/// # Arguments
+/// * `cb` - Callback receiving how long the probe went unanswered, in
+///   milliseconds, and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_runtime_stall_callback(
+    cb: Option<ArtiRuntimeStallCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::RUNTIME_STALL.set(cb, ctx);
+    0
+}
+
+/// Register a callback that fetches fresh bridges when bootstrap appears
+/// blocked.
+///
+/// Called right after the censorship callback fires (see
+/// `arti_set_censorship_callback`), on a background thread where it may
+/// block while it fetches. It returns newline-separated bridge lines, read
+/// like `arti_load_bridges_file` reads them, or NULL to offer none. The
+/// bridges that parse replace the configured set and the bootstrapping
+/// client retries with them straight away.
+///
+/// The library copies the returned string as soon as the callback returns
+/// and never frees it: return a static string, or keep the last one alive
+/// and free it on the next call.
+///
+/// # Arguments
+/// * `cb` - Callback receiving `ctx` and returning bridge lines; NULL to
+///   clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_bridge_provider_callback(
+    cb: Option<ArtiBridgeProviderCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::BRIDGE_PROVIDER.set(cb, ctx);
+    0
+}
+
+/// Get pluggable transport statistics as JSON.
+///
+/// Reports the active transport name plus any byte/connection counts the
+/// transport exposes, e.g. `{"transport":"obfs4","connections":2}`; fields
+/// other than `transport` are optional. This build does not configure
+/// pluggable transports, so there is never an active one to report.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if no pluggable transport is active
+#[no_mangle]
+pub extern "C" fn arti_transport_stats(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let _ = (out_buf, out_len);
+    -1
+}
+
+/// Copy `s` into a caller-provided C buffer, truncating to fit and always
+/// null-terminating. Truncation never splits a character, so the buffer
+/// always holds valid UTF-8 (Swift's `String(cString:)` expects it).
+/// Returns the number of bytes written, or -1 if the buffer is null or
+/// empty.
+fn write_c_string(buf: *mut c_char, len: c_int, s: &str) -> c_int {
+    if buf.is_null() || len <= 0 {
+        return -1;
+    }
+
+    let bytes = truncate_on_char_boundary(s, (len - 1) as usize).as_bytes();
+    let copy_len = bytes.len();
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf as *mut u8, copy_len);
+        *buf.add(copy_len) = 0; // null terminator
+    }
+
+    copy_len as c_int
+}
+
+/// The longest prefix of `s` that fits in `max` bytes without splitting a
+/// character.
+fn truncate_on_char_boundary(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Register a callback receiving every bootstrap status update as JSON.
+///
+/// Each update is an object such as
+/// `{"percentage":40,"phase":"blocked","ready":false,"blockage":"...",
+/// "blockage_category":1,"detail":"..."}`. See `ArtiBootstrapJsonCallback`
+/// for string lifetime rules. Updates may be coalesced if they arrive faster
+/// than they are delivered.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the JSON and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_bootstrap_json_callback(
+    cb: Option<ArtiBootstrapJsonCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::BOOTSTRAP_JSON.set(cb, ctx);
+    0
+}
+
+/// Register a callback receiving SOCKS stream events as JSON.
+///
+/// An event fires when a relayed CONNECT stream closes, e.g.
+/// `{"event":"closed","target":"example.com:443","bytes_read":5120,
+/// "bytes_written":310,"duration_ms":840,"success":true}`. `bytes_read` is
+/// what came back over Tor and `bytes_written` what was sent, counted as the
+/// data moves, so the totals are right even when one direction closes early
+/// or the relay fails. `success` is false if the relay ended with an error.
+/// Streams that never connect don't produce an event. See
+/// `ArtiStreamEventCallback` for string lifetime rules.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the JSON and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_stream_event_callback(
+    cb: Option<ArtiStreamEventCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::STREAM_EVENT.set(cb, ctx);
+    0
+}
+
+/// Register a callback that can veto SOCKS connections, e.g. to block
+/// known-malicious hosts.
+///
+/// Called with the requested host and port after the SOCKS request is
+/// read and the port checked against `arti_set_allowed_ports`, before
+/// anything is sent over Tor. Returning nonzero refuses the connection
+/// with SOCKS reply 0x02 ("connection not allowed by ruleset"). The
+/// callback runs on a thread set aside for blocking work, not on the
+/// async reactor, and is called concurrently for concurrent connections;
+/// each connection waits for its answer, so keep it quick. See
+/// `ArtiConnectionFilterCallback` for string lifetime rules.
+///
+/// # Arguments
+/// * `cb` - Filter callback; NULL to allow every connection
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_connection_filter_callback(
+    cb: Option<ArtiConnectionFilterCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::CONNECTION_FILTER.set(cb, ctx);
+    0
+}
+
+/// Register a callback for the device itself going offline and back.
+///
+/// Unlike the network error and censorship callbacks, which also cover
+/// the Tor network being unreachable from a working connection, this fires
+/// only when arti finds it can't reach anything at all: dialing guards
+/// fails locally, as with no route or an unreachable network, so the app
+/// can show "no internet" rather than "Tor is blocked". It fires 1 then,
+/// and 0 once that clears (or Arti stops). On recovery Arti also rebuilds
+/// recent circuits, as `arti_reconnect` does, so traffic resumes without
+/// waiting for dead circuits to time out.
+///
+/// # Arguments
+/// * `cb` - Callback receiving 1 (offline) or 0 (back) and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_offline_callback(
+    cb: Option<ArtiOfflineCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::OFFLINE.set(cb, ctx);
+    0
+}
+
+/// Register a callback for transitions to and from full connectivity.
+///
+/// "Online" means arti reports itself ready for traffic: bootstrapped, with
+/// a usable directory and guards reachable. The callback fires on every
+/// change (e.g. when guards become unreachable after a network loss, and
+/// again once they recover) and goes offline when Arti stops. The current
+/// state is reported once, synchronously, on registration.
+///
+/// # Arguments
+/// * `cb` - Callback receiving 1 (online) or 0 (offline) and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_connectivity_callback(
+    cb: Option<ArtiConnectivityCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::set_connectivity_callback(cb, ctx);
+    0
+}
+
+/// Get a coarse estimate of the memory held by the Arti client, in bytes.
+///
+/// arti-client does not expose its memory tracker, so this is derived from
+/// what is visible here: the size of the current directory and the number
+/// of connections being relayed. Treat it as an order of magnitude for
+/// correlating with memory pressure, not an exact figure.
+///
+/// # Returns
+/// * Estimated bytes in use
+/// * 0 if not running or not yet bootstrapped
+#[no_mangle]
+pub extern "C" fn arti_memory_usage_bytes() -> u64 {
+    if !IS_RUNNING.load(Ordering::SeqCst) {
+        return 0;
+    }
+
+    let client = match status::load().client.clone() {
+        Some(c) => c,
+        None => return 0,
+    };
+
+    let relays = client
+        .dirmgr()
+        .timely_netdir()
+        .map(|netdir| netdir.relays().count() as u64)
+        .unwrap_or(0);
+    let connections = socks::active_connections() as u64;
+
+    relays * ESTIMATED_BYTES_PER_RELAY + connections * ESTIMATED_BYTES_PER_CONNECTION
+}
+
+/// Enable or disable PROXY protocol headers on the SOCKS listeners.
+///
+/// When enabled, every SOCKS connection must begin with an HAProxy PROXY
+/// protocol header (v1 or v2), as sent by a local forwarder; the original
+/// client address it carries is used in logs. Connections without a valid
+/// header are dropped. Applies to connections accepted after the call.
+///
+/// # Arguments
+/// * `enabled` - Non-zero to require the header
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_proxy_protocol(enabled: c_int) -> c_int {
+    socks::PROXY_PROTOCOL.store(enabled != 0, Ordering::SeqCst);
+    0
+}
+
+/// Limit how long a SOCKS CONNECT to a clearnet target may take.
+///
+/// A CONNECT that hasn't completed in time gets SOCKS reply 0x06 ("TTL
+/// expired"). `.onion` targets are governed by
+/// `arti_set_onion_connect_timeout` instead. Applies to connections
+/// accepted afterwards.
+///
+/// # Arguments
+/// * `timeout_ms` - Milliseconds; 0 (the default) leaves only arti's own timeouts
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `timeout_ms` is negative
+#[no_mangle]
+pub extern "C" fn arti_set_connect_timeout(timeout_ms: c_int) -> c_int {
+    if timeout_ms < 0 {
+        return -1;
+    }
+    socks::CONNECT_TIMEOUT_MS.store(timeout_ms as u32, Ordering::SeqCst);
+    0
+}
+
+/// Limit how long a SOCKS CONNECT to a `.onion` target may take.
+///
+/// Reaching an onion service takes a descriptor fetch, an introduction and
+/// a rendezvous, so it legitimately takes much longer than a clearnet
+/// connection; keeping this separate lets the clearnet timeout stay tight.
+/// Behaves like `arti_set_connect_timeout` otherwise.
+///
+/// # Arguments
+/// * `timeout_ms` - Milliseconds; 0 (the default) leaves only arti's own timeouts
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `timeout_ms` is negative
+#[no_mangle]
+pub extern "C" fn arti_set_onion_connect_timeout(timeout_ms: c_int) -> c_int {
+    if timeout_ms < 0 {
+        return -1;
+    }
+    socks::ONION_CONNECT_TIMEOUT_MS.store(timeout_ms as u32, Ordering::SeqCst);
+    0
+}
+
+/// Check whether an onion service's descriptor is currently published.
+///
+/// Lets an app show whether a contact's service is online before
+/// connecting. arti has no descriptor-only lookup, so this runs its onion
+/// service connector as far as a rendezvous circuit, without opening a
+/// stream; the circuit is then discarded. A service whose descriptor is
+/// found but which doesn't answer still counts as found. Blocks the calling
+/// thread for at most `timeout_ms`; running out of time counts as not
+/// found.
+///
+/// # Arguments
+/// * `onion_addr` - Onion address (C string), e.g. `"<56 chars>.onion"`; no port
+/// * `timeout_ms` - Upper bound on the check, in milliseconds
+///
+/// # Returns
+/// * 1 if the descriptor was found
+/// * 0 if it was not found within the timeout
+/// * -1 on a bad argument or address, or if Arti is not running and
+///   bootstrapped (see `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_onion_reachable(onion_addr: *const c_char, timeout_ms: c_int) -> c_int {
+    if onion_addr.is_null() || timeout_ms <= 0 {
+        return -1;
+    }
+    let Ok(addr) = unsafe { CStr::from_ptr(onion_addr) }.to_str() else {
+        error::set_last_error("onion address is not valid UTF-8");
+        return -1;
+    };
+    let Some(client) = status::load().client.clone() else {
+        error::set_last_error("Arti is not bootstrapped");
+        return -1;
+    };
+    let Some((handle, dirs)) = ARTI_STATE.get().and_then(|state| {
+        let guard = state.lock().ok()?;
+        Some((guard.runtime.handle().clone(), guard.dirs.clone()?))
+    }) else {
+        return -1;
+    };
+    let config = match config::build(&dirs, Some(&client)) {
+        Ok(c) => c,
+        Err(e) => {
+            error::set_last_error(&format!("Failed to build configuration: {}", e));
+            return -1;
+        }
+    };
+
+    let limit = Duration::from_millis(timeout_ms as u64);
+    let check = onion::descriptor_found(&client, &config, addr);
+    match handle.block_on(tokio::time::timeout(limit, check)) {
+        Ok(Ok(found)) => found as c_int,
+        Ok(Err(e)) => {
+            error::set_last_error(&e);
+            -1
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Build the circuits to an onion service ahead of the first stream.
+///
+/// For when the app knows it will soon connect to a contact's service:
+/// fetches the descriptor and builds the rendezvous circuit now, so the
+/// first real stream only has to open on it. arti builds a rendezvous
+/// circuit only as part of opening a stream, so the warm-up opens one to
+/// the given port and closes it straight away; the service sees a
+/// connection that is closed without data. arti then keeps the circuit
+/// for reuse while it is fresh (and drops it once unused for a while).
+///
+/// The warm-up is isolated like a SOCKS stream on the main port without a
+/// username and password; streams isolated any other way build their own
+/// circuit. Blocks until the circuit is built or building it fails.
+///
+/// # Arguments
+/// * `onion_addr` - Onion service and port (C string), e.g.
+///   `"<56 chars>.onion:80"`
+///
+/// # Returns
+/// * 0 once the circuit is ready
+/// * -1 on a bad address, if Arti is not running and bootstrapped, or if
+///   the service could not be reached (see `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_onion_prewarm(onion_addr: *const c_char) -> c_int {
+    let Some(addr) = c_str(onion_addr) else {
+        error::set_last_error("onion address is missing or not valid UTF-8");
+        return -1;
+    };
+    let Some((host, port)) = addr.trim().rsplit_once(':').and_then(|(host, port)| {
+        Some((host, port.parse::<u16>().ok().filter(|&p| p != 0)?))
+    }) else {
+        error::set_last_error("onion address must include a port, e.g. <addr>.onion:80");
+        return -1;
+    };
+    let Some(client) = status::load().client.clone() else {
+        error::set_last_error("Arti is not bootstrapped");
+        return -1;
+    };
+    let Some(handle) = ARTI_STATE
+        .get()
+        .and_then(|state| Some(state.lock().ok()?.runtime.handle().clone()))
+    else {
+        return -1;
+    };
+    match handle.block_on(onion::prewarm(&client, host, port)) {
+        Ok(()) => 0,
+        Err(e) => {
+            error::set_last_error(&e);
+            -1
+        }
+    }
+}
+
+/// Get the running onion service's introduction and rendezvous counts as
+/// JSON, e.g. `{"introduction_points":3,"rendezvous_circuits":1,
+/// "inbound_connections":4}`, to confirm it is published and reachable.
+///
+/// This build includes only arti's onion service client, so it never
+/// hosts a service and there is nothing to report.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if no onion service is running
+#[no_mangle]
+pub extern "C" fn arti_onion_stats(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let _ = (out_buf, out_len);
+    -1
+}
+
+/// List the onion services this library is hosting, as JSON:
+/// `[{"nickname":"alice","address":"<56 chars>.onion"}, ...]`.
+///
+/// This build includes only arti's onion service client, so it never
+/// hosts a service and the list is always empty.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if the buffer is null or too small
+#[no_mangle]
+pub extern "C" fn arti_onion_list(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let list = json::array(std::iter::empty());
+    if out_len <= 0 || list.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &list)
+}
+
+/// Shut down the hosted onion service called `nickname`, leaving any
+/// others running.
+///
+/// This build hosts no onion services (see `arti_onion_list`), so there
+/// is never one to shut down.
+///
+/// # Arguments
+/// * `nickname` - Service nickname (C string)
+///
+/// # Returns
+/// * 0 once the service is down
+/// * -1 if there is no service called `nickname`
+#[no_mangle]
+pub extern "C" fn arti_onion_destroy_by_name(nickname: *const c_char) -> c_int {
+    let _ = nickname;
+    -1
+}
+
+/// Report congestion-control and flow-control state, e.g. each circuit's
+/// congestion window, to tell congestion from a slow exit.
+///
+/// Arti 0.38 keeps this state inside its circuit reactors and offers no
+/// way to read it, so there is nothing to report; the reason is in
+/// `arti_last_error`. The stream event callback's byte counts and
+/// durations give throughput per stream instead.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if Arti is not running or the stats are unavailable
+#[no_mangle]
+pub extern "C" fn arti_congestion_stats(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let _ = (out_buf, out_len);
+    if status::load().client.is_some() {
+        error::set_last_error("arti does not expose congestion-control state");
+    }
+    -1
+}
+
+/// Estimate the exit's bandwidth by downloading a small test file over
+/// plain HTTP through Tor.
+///
+/// The stream goes through the same policy (allowed ports, Tor-only
+/// domains, the connection filter and fail closed) and isolation as a
+/// SOCKS stream on the main port without credentials, so it normally
+/// rides the circuit that traffic is using. At most 256 KiB of the body
+/// is read, and only its arrival is timed, not circuit setup or the
+/// request round trip. The exit and the endpoint see the request, which
+/// carries nothing about the app. Blocks the calling thread; must not be
+/// called from one of this library's callbacks.
+///
+/// Unless another endpoint is set with `arti_set_bandwidth_probe_url`,
+/// the file comes from a third party: 256 KiB from speed.cloudflare.com.
+///
+/// # Arguments
+/// * `timeout_ms` - Longest the whole measurement may take, in milliseconds
+///
+/// # Returns
+/// * Estimated kilobytes (1000 bytes) per second; a download cut short
+///   by the timeout still counts if at least 16 KiB arrived
+/// * -1 if Arti is not running, `timeout_ms` is invalid, the policy
+///   refuses the endpoint, or the download failed (reason in
+///   `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_measure_exit_bandwidth(timeout_ms: c_int) -> c_int {
+    if timeout_ms <= 0 {
+        error::set_last_error("timeout must be positive");
+        return -1;
+    }
+    let endpoint = bandwidth::endpoint();
+    let running = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .filter(|_| IS_RUNNING.load(Ordering::SeqCst))
+        .and_then(|guard| Some((guard.client.clone()?, guard.runtime.handle().clone())));
+    let Some((client, handle)) = running else {
+        error::set_last_error("Arti is not running or not yet bootstrapped");
+        return -1;
+    };
+
+    let measured = handle.block_on(async {
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout_ms as u64);
+        let options = socks::HandlerOptions::current();
+        socks::check_connect(&endpoint.host, endpoint.port, &options)
+            .await
+            .map_err(|refusal| refusal.error)?;
+        let target = (endpoint.host.as_str(), endpoint.port)
+            .into_tor_addr()
+            .map_err(std::io::Error::other)?;
+        let isolation = isolation::default_token();
+        let prefs = socks::stream_prefs(isolation, &endpoint.host, endpoint.port, None);
+        let connect = circuit::STREAM_ISOLATION
+            .scope(isolation, StreamConnector::connect(client.as_ref(), target, &prefs));
+        let stream = tokio::time::timeout_at(deadline, connect).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "bandwidth probe timed out")
+        })??;
+        bandwidth::measure(stream, &endpoint, deadline).await
+    });
+    match measured {
+        Ok(rate) => rate.min(c_int::MAX as u64) as c_int,
+        Err(e) => {
+            error::set_last_error(&format!("bandwidth probe failed: {}", e));
+            -1
+        }
+    }
+}
+
+/// Choose where `arti_measure_exit_bandwidth` downloads its test file.
+///
+/// The default is a 256 KiB file from speed.cloudflare.com, which sees
+/// each probe's request arrive from the exit; apps that would rather not
+/// involve a third party can serve a file themselves. Reset to the default
+/// by `arti_cleanup`.
+///
+/// # Arguments
+/// * `url` - `http://host[:port][/path]` (C string); NULL for the default
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `url` is not an `http://` URL (reason in `arti_last_error`)
+#[no_mangle]
+pub extern "C" fn arti_set_bandwidth_probe_url(url: *const c_char) -> c_int {
+    if url.is_null() {
+        bandwidth::set_endpoint(None);
+        return 0;
+    }
+    let Some(url) = c_str(url) else {
+        error::set_last_error("bandwidth probe URL is not valid UTF-8");
+        return -1;
+    };
+    match bandwidth::Endpoint::parse(url) {
+        Ok(endpoint) => {
+            bandwidth::set_endpoint(Some(endpoint));
+            0
+        }
+        Err(e) => {
+            error::set_last_error(&format!("invalid bandwidth probe URL: {}", e));
+            -1
+        }
+    }
+}
+
+/// Longest a single `arti_wait_socks_ready` probe may take to connect
+const SOCKS_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Pause between `arti_wait_socks_ready` checks while the port is down
+const SOCKS_READY_POLL: Duration = Duration::from_millis(10);
+
+/// Wait until the main SOCKS listener is bound and accepting connections.
+///
+/// Confirms it by connecting to the port over loopback; the listener
+/// recognises the probe and closes it without treating it as a client.
+/// A probe that gets no answer is retried until the timeout runs out.
+/// This only says the port is live, not that Tor is bootstrapped: with a
+/// pre-bootstrap behavior other than closed (see
+/// `arti_set_prebootstrap_behavior`) the port comes up first. Must not be
+/// called from one of this library's callbacks.
+///
+/// # Arguments
+/// * `timeout_ms` - Longest to wait, in milliseconds; 0 checks once,
+///   without waiting on a connection that isn't answered at once
+///
+/// # Returns
+/// * 0 once the port accepts connections
+/// * -1 if Arti is not running (or stops while waiting) or `timeout_ms`
+///   is negative
+/// * -2 on timeout
+#[no_mangle]
+pub extern "C" fn arti_wait_socks_ready(timeout_ms: c_int) -> c_int {
+    if timeout_ms < 0 {
+        return -1;
+    }
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms as u64);
+    wait_until_listening(deadline, || {
+        if !IS_RUNNING.load(Ordering::SeqCst) {
+            return None;
+        }
+        ARTI_STATE.get().and_then(|s| s.lock().ok()).map(|guard| {
+            let addr = guard.socks.as_ref().and_then(|server| server.addrs.first().copied());
+            (addr, guard.runtime.handle().clone())
+        })
+    })
+}
+
+/// Probe the address `listening` reports until a probe connects or
+/// `deadline` passes, as `arti_wait_socks_ready` returns. `listening` gives
+/// None once Arti has stopped, and no address while the port is down.
+fn wait_until_listening(
+    deadline: Instant,
+    mut listening: impl FnMut() -> Option<(Option<SocketAddr>, tokio::runtime::Handle)>,
+) -> c_int {
+    loop {
+        let Some((addr, handle)) = listening() else {
+            return -1;
+        };
+        if let Some(addr) = addr {
+            let limit = deadline.saturating_duration_since(Instant::now()).min(SOCKS_PROBE_TIMEOUT);
+            match handle.block_on(socks::probe(addr, limit)) {
+                Ok(()) => return 0,
+                Err(e) => tracing::debug!("SOCKS port {} not accepting yet: {}", addr, e),
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return -2;
+        }
+        std::thread::sleep(SOCKS_READY_POLL.min(remaining));
+    }
+}
+
+/// Check whether traffic is flowing, for a live activity indicator.
+///
+/// # Returns
+/// * 1 if bytes moved on any SOCKS stream within the activity window
+///   (see `arti_set_activity_window`)
+/// * 0 otherwise
+#[no_mangle]
+pub extern "C" fn arti_is_active() -> c_int {
+    socks::is_active() as c_int
+}
+
+/// Time since traffic last flowed, for telling a quiet connection from a
+/// dead one.
+///
+/// Counts from the last byte relayed on any SOCKS stream, the same moment
+/// `arti_is_active` measures from, so an app can decide for itself how
+/// long a silence warrants a reconnect. Circuit builds with no traffic on
+/// them do not count.
+///
+/// # Returns
+/// * Whole seconds since bytes last moved
+/// * -1 if none have moved since the library was loaded or reset
+#[no_mangle]
+pub extern "C" fn arti_seconds_since_activity() -> c_int {
+    match socks::since_activity() {
+        Some(elapsed) => elapsed.as_secs().min(c_int::MAX as u64) as c_int,
+        None => -1,
+    }
+}
+
+/// Set how recent traffic must be for `arti_is_active` to report it.
+///
+/// # Arguments
+/// * `ms` - Window in milliseconds; 1000 by default
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `ms` is negative
+#[no_mangle]
+pub extern "C" fn arti_set_activity_window(ms: c_int) -> c_int {
+    if ms < 0 {
+        return -1;
+    }
+    socks::ACTIVITY_WINDOW_MS.store(ms as u32, Ordering::SeqCst);
+    0
+}
+
+/// Seed circuit path selection so tests against a known network pick the
+/// same paths every run. Testing only: predictable paths reduce anonymity.
+///
+/// Arti 0.38 draws paths from its thread-local RNG and offers no way to
+/// seed it, so this build always refuses; the reason is available from
+/// `arti_last_error`.
+///
+/// # Arguments
+/// * `seed` - RNG seed
+///
+/// # Returns
+/// * -1 (not supported by the compiled arti)
+#[no_mangle]
+pub extern "C" fn arti_set_deterministic_paths(seed: u64) -> c_int {
+    tracing::warn!(
+        "Deterministic paths (seed {}) requested; these reduce anonymity and must \
+         never be used outside tests",
+        seed
+    );
+    error::set_last_error("deterministic path selection is not supported by this arti build");
+    -1
+}
+
+/// Standard number of hops on an exit circuit
+const DEFAULT_CIRCUIT_LENGTH: c_int = 3;
+
+/// Choose how many hops exit circuits use. Experimental.
+///
+/// Three hops is what every Tor client uses. A different length makes this
+/// client's circuits stand out from everyone else's, which can be enough
+/// to single its traffic out; longer paths are also slower, and a shorter
+/// one lets a single relay see both ends. Only researchers who know why
+/// they need it should change this.
+///
+/// Arti 0.38 builds its exit circuits with exactly three hops and has no
+/// setting for the length (its vanguards only lengthen onion service
+/// circuits), so only the standard length is accepted; other values are
+/// refused with the reason in `arti_last_error`.
+///
+/// # Arguments
+/// * `hops` - Hops per circuit; 3 by default
+///
+/// # Returns
+/// * 0 if `hops` is 3
+/// * -1 otherwise (not supported by the compiled arti)
+#[no_mangle]
+pub extern "C" fn arti_set_circuit_length(hops: c_int) -> c_int {
+    if hops == DEFAULT_CIRCUIT_LENGTH {
+        return 0;
+    }
+    tracing::warn!(
+        "Circuit length {} requested; nonstandard lengths make circuits stand out \
+         and weaken anonymity",
+        hops
+    );
+    error::set_last_error("this arti build only builds 3-hop exit circuits");
+    -1
+}
+
+/// Keep relays in the given countries out of every position (guard,
+/// middle and exit) of the circuits this client builds.
+///
+/// Placing relays in countries needs a GeoIP database, which this build
+/// leaves out (see `arti_last_circuit_path`), so a non-empty list is
+/// refused with the reason in `arti_last_error` rather than silently
+/// building circuits through the countries the caller wanted to avoid.
+///
+/// # Arguments
+/// * `exclude_countries` - Array of ISO 3166-1 alpha-2 country codes (C
+///   strings; may be NULL when `count` is 0)
+/// * `count` - Number of codes; 0 excludes no countries
+///
+/// # Returns
+/// * 0 if `count` is 0
+/// * -1 otherwise (GeoIP not available in this build)
+#[no_mangle]
+pub extern "C" fn arti_set_country_constraints(
+    exclude_countries: *const *const c_char,
+    count: c_int,
+) -> c_int {
+    if count == 0 {
+        return 0;
+    }
+    let _ = exclude_countries;
+    error::set_last_error("country constraints need GeoIP, which this build does not include");
+    -1
+}
+
+/// Prefer or require a particular exit relay for subsequent connections.
+///
+/// The relay is checked first: it must be a fingerprint or a nickname
+/// and, while running, a relay in the current consensus that allows exit
+/// traffic. Arti 0.38 picks exits itself and offers no way to steer the
+/// choice towards one relay, so a valid preference is accepted without
+/// changing the exit used (a warning is logged), and requiring a relay is
+/// refused rather than appearing to succeed while connections leave
+/// through other exits. Failures leave the reason in `arti_last_error`.
+///
+/// # Arguments
+/// * `fingerprint_or_nickname` - Relay fingerprint (40 hex digits,
+///   optionally `$`-prefixed) or nickname (C string); NULL or empty for
+///   no preference
+/// * `require` - Nonzero to fail connections that can't use the relay,
+///   0 to only prefer it
+///
+/// # Returns
+/// * 0 if no relay is given, or a valid relay is only preferred
+/// * -1 if the relay is malformed or not a listed exit, or is required
+///   (not supported by the compiled arti)
+#[no_mangle]
+pub extern "C" fn arti_set_exit_relay(
+    fingerprint_or_nickname: *const c_char,
+    require: c_int,
+) -> c_int {
+    if fingerprint_or_nickname.is_null() {
+        return 0;
+    }
+    let Some(relay) = c_str(fingerprint_or_nickname).map(str::trim) else {
+        error::set_last_error("exit relay is not valid UTF-8");
+        return -1;
+    };
+    if relay.is_empty() {
+        return 0;
+    }
+    if config::parse_fingerprint(relay).is_none() && !config::is_nickname(relay) {
+        error::set_last_error(&format!("{:?} is not a relay fingerprint or nickname", relay));
+        return -1;
+    }
+
+    let client = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| guard.client.clone());
+    if let Some(client) = client {
+        let listed = client.dirmgr().timely_netdir().ok();
+        if !listed.is_some_and(|netdir| config::is_listed_exit(&netdir, relay)) {
+            error::set_last_error(&format!("{} is not an exit relay in the consensus", relay));
+            return -1;
+        }
+    }
+
+    if require != 0 {
+        error::set_last_error("this arti build cannot require an exit relay");
+        return -1;
+    }
+    tracing::warn!("Exit relay {} preferred, but this arti build chooses exits itself", relay);
+    0
+}
+
+/// Allow or forbid IPv6 addresses when connecting to relays.
+///
+/// Arti 0.38 has no setting for this. It does already dial a relay's
+/// addresses in parallel, each 150 ms after the last, so a relay whose
+/// IPv6 address is unreachable is still reached over IPv4 without waiting
+/// for a timeout. Only leaving IPv6 enabled is accepted; disabling it is
+/// refused with the reason in `arti_last_error`.
+///
+/// # Arguments
+/// * `enabled` - Nonzero to allow IPv6 relay addresses (the default)
+///
+/// # Returns
+/// * 0 if `enabled` is nonzero
+/// * -1 otherwise (not supported by the compiled arti)
+#[no_mangle]
+pub extern "C" fn arti_set_relay_ipv6(enabled: c_int) -> c_int {
+    if enabled != 0 {
+        return 0;
+    }
+    error::set_last_error("this arti build cannot be limited to IPv4 relay addresses");
+    -1
+}
+
+/// Report the hit, miss and entry counts of the DNS cache.
+///
+/// There is no DNS cache to report on: every SOCKS RESOLVE and
+/// RESOLVE_PTR goes to an exit relay, and neither this library nor arti
+/// keeps the answers. The outputs are left untouched and the reason is in
+/// `arti_last_error`.
+///
+/// # Arguments
+/// * `out_hits` - Receives the number of lookups answered from the cache
+/// * `out_misses` - Receives the number of lookups sent to an exit
+/// * `out_entries` - Receives the number of names cached
+///
+/// # Returns
+/// * -1 always (no DNS cache in this build)
+#[no_mangle]
+pub extern "C" fn arti_dns_cache_stats(
+    out_hits: *mut u64,
+    out_misses: *mut u64,
+    out_entries: *mut u64,
+) -> c_int {
+    let _ = (out_hits, out_misses, out_entries);
+    error::set_last_error("this build does not cache DNS lookups");
+    -1
+}
+
+/// Drop every cached DNS answer, e.g. after a network change.
+///
+/// Nothing is cached (see `arti_dns_cache_stats`), so lookups can never
+/// return a stale answer from before the change and there is nothing to
+/// drop.
+///
+/// # Returns
+/// * 0 always
+#[no_mangle]
+pub extern "C" fn arti_dns_cache_clear() -> c_int {
+    0
+}
+
+/// Set the per-direction buffer used to relay each SOCKS connection
+/// (applies to connections accepted afterwards).
+///
+/// Each direction reads at most one buffer ahead of what the other side
+/// has taken, so memory use is twice this per connection. Larger buffers
+/// mean fewer reads and writes per byte: relaying between loopback
+/// sockets, 256 KiB buffers moved about 2.6 GB/s against 2.1 GB/s for the
+/// default 16 KiB (see the `relay_throughput_by_buffer_size` test). A Tor
+/// circuit is far slower than either, so the gain is mainly CPU time
+/// during bulk downloads rather than speed.
+///
+/// # Arguments
+/// * `bytes` - Buffer size, 4 KiB to 1 MiB; 16 KiB by default
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `bytes` is out of range
+#[no_mangle]
+pub extern "C" fn arti_set_relay_buffer_size(bytes: c_int) -> c_int {
+    let range = socks::MIN_RELAY_BUFFER_SIZE..=socks::MAX_RELAY_BUFFER_SIZE;
+    match usize::try_from(bytes) {
+        Ok(bytes) if range.contains(&bytes) => {
+            socks::RELAY_BUFFER_SIZE.store(bytes, Ordering::SeqCst);
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Keep idle SOCKS streams' circuits in use, so that NAT and firewall
+/// mappings along the way don't expire under a long-lived, mostly silent
+/// connection such as a subscription (applies to connections accepted
+/// afterwards).
+///
+/// SOCKS has no keepalive of its own and the app's data can't be padded,
+/// so whenever a stream has been idle for `secs`, a DNS lookup of its
+/// destination (a reverse lookup for an address) is sent with the stream's
+/// isolation; it normally travels the stream's own circuit. Each costs a
+/// few cells each way per interval per idle stream, trading some bandwidth
+/// for connection longevity. Onion service streams are left alone.
+///
+/// # Arguments
+/// * `secs` - Idle seconds between keepalives; 0 (the default) turns them off
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `secs` is negative
+#[no_mangle]
+pub extern "C" fn arti_set_stream_keepalive(secs: c_int) -> c_int {
+    if secs < 0 {
+        return -1;
+    }
+    socks::STREAM_KEEPALIVE_SECS.store(secs as u32, Ordering::SeqCst);
+    0
+}
+
+/// Refuse SOCKS CONNECTs outright whenever Tor is not ready for traffic,
+/// so an app never mistakes a proxy that is still bootstrapping (or has
+/// lost its connection to the network) for a working one.
+///
+/// While Tor is not ready, every CONNECT gets SOCKS reply 0x03 ("network
+/// unreachable") at once, even with the pre-bootstrap behavior set to
+/// queue (see `arti_set_prebootstrap_behavior`), and nothing is sent over
+/// Tor. Ready means bootstrapped and not since reported unready by arti.
+/// Applies to connections accepted afterwards.
+///
+/// # Arguments
+/// * `enabled` - Nonzero to fail closed; off by default
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_fail_closed(enabled: c_int) -> c_int {
+    socks::FAIL_CLOSED.store(enabled != 0, Ordering::SeqCst);
+    0
+}
+
+/// Set whether listeners are bound with SO_REUSEADDR (applies to ports
+/// bound afterwards).
+///
+/// With it, a SOCKS port can be bound again straight after a stop even
+/// while connections from the last run linger in TIME_WAIT, instead of
+/// failing with "address already in use" for a minute or two. It also
+/// applies to the extra SOCKS ports, port forwards and the status page.
+/// It never lets two listeners share a port on Linux or Apple platforms;
+/// on Windows it would, so it is off there by default.
+///
+/// # Arguments
+/// * `enabled` - Nonzero to set SO_REUSEADDR; on by default except on
+///   Windows
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_reuse_addr(enabled: c_int) -> c_int {
+    socks::REUSE_ADDR.store(enabled != 0, Ordering::SeqCst);
+    0
+}
+
+/// Turn the per-connection debug logging of the SOCKS listeners on or off.
+///
+/// Each SOCKS connection logs its request, its outcome and any error at
+/// debug level. With thousands of short-lived connections and a debug
+/// subscriber installed, formatting those messages adds up: in the
+/// `connection_logging_overhead` test, refused connections went through
+/// at about 145,000/s with it on against 230,000/s off. Turning it off
+/// skips the work entirely, whatever the tracing filter says; other
+/// logging is unaffected.
+///
+/// # Arguments
+/// * `enabled` - Nonzero to log each connection (the default)
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_connection_logging(enabled: c_int) -> c_int {
+    socks::CONNECTION_LOGGING.store(enabled != 0, Ordering::SeqCst);
+    0
+}
+
+/// Choose what the SOCKS port does before bootstrap finishes (applies at
+/// next start).
+///
+/// By default the port only opens once Tor is ready, so early connections
+/// are refused by the OS. The other modes open it as soon as Tor starts:
+/// queued requests wait for bootstrap (and for the connect timeout, if
+/// one is set), rejected ones get a SOCKS general failure, and fail-fast
+/// ones get "network unreachable" so they can be told apart from other
+/// errors. If Tor fails to start, waiting requests get a general failure.
+///
+/// # Arguments
+/// * `mode` - 0 = closed until ready, 1 = queue, 2 = reject, 3 = fail fast
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `mode` is unknown
+#[no_mangle]
+pub extern "C" fn arti_set_prebootstrap_behavior(mode: c_int) -> c_int {
+    if !(socks::PREBOOTSTRAP_CLOSED..=socks::PREBOOTSTRAP_FAIL_FAST).contains(&mode) {
+        return -1;
+    }
+    socks::PREBOOTSTRAP_BEHAVIOR.store(mode, Ordering::SeqCst);
+    0
+}
+
+/// Set how many times a failed bootstrap is retried (applies at next start).
+///
+/// Right after a network change the first guard connection often fails
+/// for reasons that are gone a moment later. Retries wait 1s, 2s, 4s, ...
+/// (capped at 30s), each randomly stretched or shrunk by up to half, and
+/// the bootstrap summary shows the attempt, e.g. "Bootstrapping (attempt 2
+/// of 3)...". Failures caused by clock skew are not retried.
+/// `arti_stop` interrupts the waits.
+///
+/// # Arguments
+/// * `count` - Retries after the first attempt, 0 to 10; 2 by default
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `count` is out of range
+#[no_mangle]
+pub extern "C" fn arti_set_bootstrap_retries(count: c_int) -> c_int {
+    if !(0..=config::MAX_BOOTSTRAP_RETRIES).contains(&count) {
+        return -1;
+    }
+    config::BOOTSTRAP_RETRIES.store(count, Ordering::SeqCst);
+    0
+}
+
+/// Set how many times the whole bootstrap is attempted (applies at next
+/// start).
+///
+/// Where `arti_set_bootstrap_retries` retries on the same client, an
+/// attempt starts over with a new one, for failures a client doesn't
+/// recover from. Each attempt gets its own retries. Attempts are spaced
+/// like retries, and the bootstrap summary shows the attempt, e.g.
+/// "Retrying bootstrap 2/3...". Only once every attempt has failed does
+/// the start fail. Failures caused by clock skew are not retried.
+/// `arti_stop` interrupts the waits.
+///
+/// # Arguments
+/// * `count` - Attempts in all, 1 to 10; 1 by default
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `count` is out of range
+#[no_mangle]
+pub extern "C" fn arti_set_bootstrap_attempts(count: c_int) -> c_int {
+    if !(1..=config::MAX_BOOTSTRAP_ATTEMPTS).contains(&count) {
+        return -1;
+    }
+    config::BOOTSTRAP_ATTEMPTS.store(count, Ordering::SeqCst);
+    0
+}
+
+/// Restrict which destination ports SOCKS CONNECT requests may reach.
+///
+/// Requests to any other port are refused with SOCKS reply 0x02
+/// ("connection not allowed by ruleset") before anything is sent over Tor.
+/// RESOLVE requests are unaffected. Applies to connections accepted
+/// afterwards, on every listener.
+///
+/// # Arguments
+/// * `ports` - Array of allowed ports (may be NULL when `count` is 0)
+/// * `count` - Number of ports; 0 allows every port
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `ports` is NULL with a non-zero `count`, or `count` is negative
+#[no_mangle]
+pub extern "C" fn arti_set_allowed_ports(ports: *const u16, count: c_int) -> c_int {
+    let ports = match count {
+        0 => Vec::new(),
+        n if n < 0 || ports.is_null() => return -1,
+        n => unsafe { std::slice::from_raw_parts(ports, n as usize) }.to_vec(),
+    };
+    socks::set_allowed_ports(ports);
+    0
+}
+
+/// Send only the listed domains through Tor, for apps that use Tor for
+/// some endpoints and connect to the rest directly.
+///
+/// A SOCKS CONNECT or RESOLVE naming any other host, including an address
+/// literal, is refused with SOCKS reply 0x02 ("connection not allowed by
+/// ruleset") before anything is sent over Tor, so a misrouted request
+/// fails visibly instead of going out through Tor. Each domain also
+/// covers its subdomains; matching ignores case and a trailing dot.
+/// RESOLVE_PTR requests are unaffected. Applies to connections accepted
+/// afterwards, on every listener.
+///
+/// # Arguments
+/// * `domains` - Array of domain names (may be NULL when `count` is 0)
+/// * `count` - Number of domains; 0 sends everything through Tor
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `domains` or an entry is NULL, empty or not UTF-8, or `count`
+///   is negative
+#[no_mangle]
+pub extern "C" fn arti_set_tor_only_domains(
+    domains: *const *const c_char,
+    count: c_int,
+) -> c_int {
+    let entries = match count {
+        0 => &[][..],
+        n if n < 0 || domains.is_null() => return -1,
+        n => unsafe { std::slice::from_raw_parts(domains, n as usize) },
+    };
+    let mut listed = Vec::with_capacity(entries.len());
+    for &entry in entries {
+        match c_str(entry) {
+            Some(domain) if !domain.is_empty() => listed.push(domain.to_owned()),
+            _ => return -1,
+        }
+    }
+    socks::set_tor_only_domains(listed);
+    0
+}
+
+/// Set the isolation id for connections on the main SOCKS port.
+///
+/// Changing the id moves future connections on the main port onto a fresh
+/// set of circuits, much like a new identity for that port alone; streams
+/// already open are unaffected. Ids share the namespace used by
+/// `arti_add_socks_port`, so reusing one of those ids shares its circuits.
+///
+/// # Arguments
+/// * `id` - Isolation group, or 0 for arti's default isolation
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_default_isolation(id: u64) -> c_int {
+    isolation::set_default_id(id);
+    0
+}
+
+/// Get the isolation id used for connections on the main SOCKS port.
+///
+/// # Returns
+/// * The id last set with `arti_set_default_isolation`, or 0 if none
+#[no_mangle]
+pub extern "C" fn arti_default_isolation() -> u64 {
+    isolation::default_id()
+}
+
+/// Set extra isolation for SOCKS connections, on every listener.
+///
+/// Each set bit stops streams that differ in that respect from sharing a
+/// circuit, on top of any listener or default isolation id:
+/// * 1 (`ARTI_ISOLATE_DEST_ADDR`) - destination host
+/// * 2 (`ARTI_ISOLATE_DEST_PORT`) - destination port
+/// * 4 (`ARTI_ISOLATE_CREDENTIALS`) - SOCKS username/password; clients
+///   without credentials form one group
+///
+/// Applies to connections accepted afterwards.
+///
+/// # Arguments
+/// * `flags` - Bitwise OR of the flags above, or 0 for none
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `flags` contains unknown bits (nothing is changed)
+#[no_mangle]
+pub extern "C" fn arti_set_isolation_flags(flags: c_int) -> c_int {
+    if isolation::set_flags(flags) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Configure channel padding, which hides traffic patterns between this
+/// device and its guard behind dummy cells.
+///
+/// Normal padding costs roughly a few hundred bytes per second per open
+/// channel (more with several guards), which adds up on metered mobile data
+/// and keeps the radio awake. Reduced padding, as offered by Tor Browser
+/// for Android, sends far less while still padding idle periods; disabling
+/// padding removes this defense entirely. Takes effect at the next
+/// `arti_start`, or immediately on a running client.
+///
+/// # Arguments
+/// * `enabled` - Zero to disable padding
+/// * `reduced` - Non-zero (with `enabled`) for reduced padding
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if the setting could not be applied to the running client
+#[no_mangle]
+pub extern "C" fn arti_set_padding(enabled: c_int, reduced: c_int) -> c_int {
+    let level = match (enabled != 0, reduced != 0) {
+        (false, _) => PaddingLevel::None,
+        (true, true) => PaddingLevel::Reduced,
+        (true, false) => PaddingLevel::Normal,
+    };
+    if let Ok(mut padding) = config::PADDING.lock() {
+        *padding = level;
+    }
+
+    let running = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| Some((guard.client.clone()?, guard.dirs.clone()?)));
+    if let Some((client, dirs)) = running {
+        let result = config::build(&dirs, Some(&client)).and_then(|cfg| {
+            client.reconfigure(&cfg, Reconfigure::AllOrNothing)?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            tracing::warn!("Failed to apply padding setting: {}", e);
+            return -1;
+        }
+    }
+    0
+}
+
+/// Apply a settings document, live where the running client allows it.
+///
+/// Avoids a stop/start (and dropping every circuit) for changes arti can
+/// make in place. The document is a small TOML subset; see
+/// `config::Settings` for the full list:
+///
+/// ```toml
+/// [channel]
+/// padding = "reduced"
+/// [bridges]
+/// bridges = ["192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567"]
+/// [guards]
+/// num_primary = 2
+/// [system.memory]
+/// max_mb = 512
+/// ```
+///
+/// Only the sections present are changed. When not running, everything is
+/// stored for the next start and reported as applied. When running, each
+/// category is checked against the client separately: those arti can change
+/// live are applied together, and those it can't are left unchanged and
+/// reported as needing a restart (call again after `arti_stop` to store
+/// them).
+///
+/// Categories: 1 = padding, 2 = bridges, 4 = guards, 8 = memory.
+///
+/// # Arguments
+/// * `config_toml` - Settings document (C string)
+///
+/// # Returns
+/// * Applied categories in bits 0-7, categories needing a restart in
+///   bits 8-15
+/// * -1 if the document is NULL or invalid (see `arti_last_error`)
+/// * -2 if the client rejected the change (nothing is changed)
+#[no_mangle]
+pub extern "C" fn arti_apply_config(config_toml: *const c_char) -> c_int {
+    if config_toml.is_null() {
+        error::set_last_error("config document is missing");
+        return -1;
+    }
+    let parsed = unsafe { CStr::from_ptr(config_toml) }
+        .to_str()
+        .map_err(|_| "config document is not valid UTF-8".to_owned())
+        .and_then(config::Settings::parse);
+    let settings = match parsed {
+        Ok(s) => s,
+        Err(e) => {
+            error::set_last_error(&format!("Invalid config: {}", e));
+            return -1;
+        }
+    };
+    let changed = settings.categories();
+
+    let running = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| Some((guard.client.clone()?, guard.dirs.clone()?)));
+    let Some((client, dirs)) = running else {
+        settings.store(changed);
+        return changed;
+    };
+
+    let original = config::Settings::current();
+    let check = |client: &TorClient<PreferredRuntime>, how| {
+        config::build(&dirs, Some(client)).and_then(|cfg| Ok(client.reconfigure(&cfg, how)?))
+    };
+
+    let mut live = 0;
+    let mut restart = 0;
+    for category in config::CATEGORIES.into_iter().filter(|c| changed & c != 0) {
+        settings.store(category);
+        if check(&client, Reconfigure::CheckAllOrNothing).is_ok() {
+            live |= category;
+        } else {
+            original.store(category);
+            restart |= category;
+        }
+    }
+
+    if live != 0 {
+        if let Err(e) = check(&client, Reconfigure::AllOrNothing) {
+            original.store(live);
+            error::set_last_error(&format!("Failed to apply config: {}", e));
+            return -2;
+        }
+    }
+    if restart != 0 {
+        tracing::info!("Config categories {:#x} need a restart", restart);
+    }
+    live | (restart << 8)
+}
+
+/// Save a settings document as a named profile, for `arti_start_profile`.
+///
+/// The document is the one `arti_apply_config` takes, and is checked the
+/// same way before it is saved as `<data_dir>/profiles/<name>.toml`,
+/// replacing any profile of that name.
+///
+/// # Arguments
+/// * `data_dir` - Data directory the profile belongs to (C string)
+/// * `name` - Profile name: 1-64 ASCII letters, digits, `-` or `_`
+/// * `config_toml` - Settings document (C string)
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if `data_dir` or `name` is missing or invalid
+/// * -2 if the document is missing or invalid (see `arti_last_error`)
+/// * -3 if the profile could not be written
+#[no_mangle]
+pub extern "C" fn arti_save_profile(
+    data_dir: *const c_char,
+    name: *const c_char,
+    config_toml: *const c_char,
+) -> c_int {
+    let Some(data_path) = c_path(data_dir) else {
+        return -1;
+    };
+    let Some(name) = c_str(name) else {
+        return -1;
+    };
+    let Some(text) = c_str(config_toml) else {
+        error::set_last_error("config document is missing or not valid UTF-8");
+        return -2;
+    };
+    match profiles::save(&data_path, name, text) {
+        Ok(()) => 0,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot save profile {}: {}", name, e));
+            match e {
+                profiles::ProfileError::InvalidName => -1,
+                profiles::ProfileError::Invalid(_) => -2,
+                profiles::ProfileError::Io(_) => -3,
+            }
+        }
+    }
+}
+
+/// Start Arti with the settings of a saved profile.
+///
+/// Every setting a profile document can hold is first returned to its
+/// default and then set from the profile, so nothing carries over from a
+/// previously started profile. If the start fails, the settings are put
+/// back as they were. Otherwise like `arti_start`.
+///
+/// # Arguments
+/// * `data_dir` - Data directory holding the profile (C string)
+/// * `name` - Profile saved with `arti_save_profile`
+/// * `socks_port` - Port for SOCKS5 proxy (e.g., 39050)
+///
+/// # Returns
+/// * As `arti_start`, plus
+/// * -5 if the profile is missing or unreadable (see `arti_last_error`);
+///   no settings are changed
+#[no_mangle]
+pub extern "C" fn arti_start_profile(
+    data_dir: *const c_char,
+    name: *const c_char,
+    socks_port: u16,
+) -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        return -1;
+    }
+    let Some(data_path) = c_path(data_dir) else {
+        return -2;
+    };
+    let Some(name) = c_str(name) else {
+        error::set_last_error("profile name is missing or not valid UTF-8");
+        return -5;
+    };
+    let settings = match profiles::load(&data_path, name) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot load profile {}: {}", name, e));
+            return -5;
+        }
+    };
+    let all = config::CATEGORIES.iter().fold(0, |all, c| all | c);
+    let previous = config::Settings::current();
+    config::Settings::defaults().store(all);
+    settings.store(settings.categories());
+    tracing::info!("Starting with profile {}", name);
+    let result = start(config::Dirs::under(&data_path), SocksListen::Port(socks_port), None);
+    if result != 0 {
+        previous.store(all);
+    }
+    result
+}
+
+/// List the profiles saved under a data directory, as a JSON array of
+/// names sorted alphabetically, e.g. `["home","work"]`.
+///
+/// # Arguments
+/// * `data_dir` - Data directory (C string)
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if `data_dir` is invalid, the profiles can't be read, or the
+///   buffer is null or too small
+#[no_mangle]
+pub extern "C" fn arti_list_profiles(
+    data_dir: *const c_char,
+    out_buf: *mut c_char,
+    out_len: c_int,
+) -> c_int {
+    let Some(data_path) = c_path(data_dir) else {
+        return -1;
+    };
+    let names = match profiles::list(&data_path) {
+        Ok(names) => names,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot list profiles: {}", e));
+            return -1;
+        }
+    };
+    let list = json::array(names.iter().map(|name| json::string(name)));
+    if out_len <= 0 || list.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &list)
+}
+
+/// List the SOCKS listeners and port forwards that are currently open, as
+/// JSON.
+///
+/// Writes an array with one object per bound address, e.g.
+/// `[{"type":"socks","address":"127.0.0.1:39050","isolation_id":null},
+/// {"type":"socks","address":"127.0.0.1:39051","isolation_id":7},
+/// {"type":"forward","address":"127.0.0.1:8080","target":"example.com:80"}]`.
+/// The main port reports the id set by `arti_set_default_isolation` (null
+/// when unset); ports from `arti_add_socks_port` report their own id.
+/// Listeners closed by `arti_stop` or `arti_rebind_socks` are not listed.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if the buffer is null or too small for the whole list
+#[no_mangle]
+pub extern "C" fn arti_list_listeners(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let mut entries = Vec::new();
+    let listener = |addr: SocketAddr, isolation_id: Option<u64>| {
+        let obj = json::Object::new()
+            .str("type", "socks")
+            .str("address", &addr.to_string());
+        match isolation_id {
+            Some(id) => obj.num("isolation_id", id),
+            None => obj.opt_str("isolation_id", None),
+        }
+        .finish()
+    };
+
+    if let Some(guard) = ARTI_STATE.get().and_then(|state| state.lock().ok()) {
+        if let Some(server) = guard.socks.as_ref().filter(|s| !s.task.is_finished()) {
+            let default_id = Some(isolation::default_id()).filter(|id| *id != 0);
+            entries.extend(server.addrs.iter().map(|addr| listener(*addr, default_id)));
+        }
+        for extra in guard.extra_listeners.iter().filter(|l| !l.task.is_finished()) {
+            let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), extra.port);
+            entries.push(listener(addr, Some(extra.isolation_id)));
+        }
+        for forward in guard.forwards.iter().filter(|f| !f.task.is_finished()) {
+            let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), forward.port);
+            entries.push(
+                json::Object::new()
+                    .str("type", "forward")
+                    .str("address", &addr.to_string())
+                    .str("target", &forward.target)
+                    .finish(),
+            );
+        }
+    }
+
+    let list = json::array(entries);
+    if out_len <= 0 || list.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &list)
+}
+
+/// Write diagnostic events to a newline-delimited JSON file, e.g. under the
+/// data directory, for support to read after the fact.
+///
+/// Each line is `{"ts_ms":…,"type":…,"data":{…}}` with `type` one of
+/// `bootstrap` (the object the bootstrap JSON callback receives),
+/// `stream` (an `opened` event, then what the stream event callback
+/// receives) or `error` (`{"category":…,"message":…}`, as passed to the
+/// network error callback). An existing file is appended to. Past 1 MiB
+/// the file is renamed to `<path>.1`, replacing the previous one, and a
+/// new file begun. Logging stops if a write fails.
+///
+/// # Arguments
+/// * `path` - Log file path (C string); NULL stops logging
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if the path is not valid UTF-8 or the file can't be opened
+#[no_mangle]
+pub extern "C" fn arti_set_event_log_file(path: *const c_char) -> c_int {
+    let path = if path.is_null() {
+        None
+    } else {
+        match c_path(path) {
+            Some(path) => Some(path),
+            None => {
+                error::set_last_error("event log path is empty or not valid UTF-8");
+                return -1;
+            }
+        }
+    };
+    match eventlog::set_path(path.as_deref()) {
+        Ok(()) => 0,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot open event log: {}", e));
+            -1
+        }
+    }
+}
+
+/// Get the configuration Arti is running with, as an arti TOML document.
+///
+/// Rebuilt from the current settings the way `arti_start` and the
+/// setters that reconfigure a running client build it, so it shows every
+/// override in effect (data directories, guards, bridges, padding,
+/// network parameters and so on). Options left at arti's defaults are
+/// omitted. A setting that only applies at the next start is shown
+/// already.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the TOML into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if Arti is not running, or the buffer is null or too small
+#[no_mangle]
+pub extern "C" fn arti_effective_config(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let running = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| Some((guard.client.clone(), guard.dirs.clone()?)));
+    let Some((client, dirs)) = running.filter(|_| IS_RUNNING.load(Ordering::SeqCst)) else {
+        return -1;
+    };
+    let text = match config::to_toml(&dirs, client.as_deref()) {
+        Ok(text) => text,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot serialize config: {}", e));
+            return -1;
+        }
+    };
+    if out_len <= 0 || text.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &text)
+}
+
+/// List the SOCKS sessions currently being relayed, as JSON.
+///
+/// Writes an array, oldest first, e.g. `[{"index":3,"target":
+/// "example.com:443","label":"feed","age_ms":5120}]`. `label` is the one
+/// set by `arti_set_next_connection_label` (null if none). Pass `index` to
+/// `arti_close_connection` to close a session.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if the buffer is null or too small for the whole list
+#[no_mangle]
+pub extern "C" fn arti_connection_info(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let list = sessions::list_json();
+    if out_len <= 0 || list.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &list)
+}
+
+/// Close one SOCKS session, e.g. a request that is stuck.
+///
+/// The app's connection is shut down and the session's Tor stream ended;
+/// the circuit and other streams sharing it are not disturbed. The stream
+/// event callback reports the session as closed without success.
+///
+/// # Arguments
+/// * `index` - Session index from `arti_connection_info`
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if Arti is not running or no session has that index
+#[no_mangle]
+pub extern "C" fn arti_close_connection(index: c_int) -> c_int {
+    if !IS_RUNNING.load(Ordering::SeqCst) || !sessions::close(index) {
+        return -1;
+    }
+    0
+}
+
+/// Get the circuit path of the most recent connection, as JSON.
+///
+/// Writes the relays of the circuit the last SOCKS CONNECT was attached
+/// to, guard first, e.g. `{"hops":[{"role":"guard","fingerprint":"9695…",
+/// "nickname":"relay1","address":"192.0.2.1:443","country":null}, …]}`.
+/// Only the latest connection is remembered. `nickname` is null for relays
+/// missing from the consensus (such as bridges); `country` is always null
+/// because this build has no GeoIP database.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * 0 if no connection has been made since start
+/// * -1 if the buffer is null or too small for the whole path
+#[no_mangle]
+pub extern "C" fn arti_last_circuit_path(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let Some(path) = circuit::last_json() else {
+        return 0;
+    };
+    if out_len <= 0 || path.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &path)
+}
+
+/// Export the guard selection, to carry over to a new device with
+/// `arti_import_guards`.
+///
+/// Writes arti's guard state file (JSON) as it currently stands. Moving
+/// guard state to another device makes the two indistinguishable to
+/// their guards, and anyone holding the blob learns which relays this
+/// user enters the Tor network through: treat it as sensitive, move it
+/// only between devices of the same user, and don't keep copies.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if Arti has not bootstrapped, the state can't be read, or the
+///   buffer is null or too small
+#[no_mangle]
+pub extern "C" fn arti_export_guards(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let dirs = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| guard.dirs.clone());
+    let Some(dirs) = dirs.filter(|_| IS_RUNNING.load(Ordering::SeqCst)) else {
+        return -1;
+    };
+    let blob = match guardstate::export(&dirs.state_dir) {
+        Ok(blob) => blob,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot read guard state: {}", e));
+            return -1;
+        }
+    };
+    if out_len <= 0 || blob.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &blob)
+}
+
+/// List the entry guards in arti's saved guard state, for working out why
+/// connections route the way they do.
+///
+/// Writes `{"guards":[{"fingerprint":"ABCD...","sample":"default",
+/// "added_at":"2026-01-01T00:00:00Z","confirmed_at":"2026-01-02T00:00:00Z",
+/// "in_use":true}, ...]}`. `sample` is the set the guard belongs to
+/// (`default`, `restricted` when a strict filter is in force, or `bridges`);
+/// `confirmed_at` is when it was first used successfully, null if never;
+/// `in_use` marks the guard of the latest circuit a stream used. Read from
+/// arti's guard state file, which arti rewrites as its guards change, so it
+/// leaves out guards that are disabled or no longer listed in the
+/// consensus.
+///
+/// This is not a reachability report. Arti 0.38 keeps whether each guard
+/// is reachable, and when it was last contacted, inside its guard manager
+/// and offers no way to read them, so neither is included and a guard
+/// that just failed is still listed.
+///
+/// # Arguments
+/// * `out_buf` - Buffer to write the JSON into
+/// * `out_len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator)
+/// * -1 if Arti is not running, the state can't be read, or the buffer is
+///   null or too small
+#[no_mangle]
+pub extern "C" fn arti_saved_guards(out_buf: *mut c_char, out_len: c_int) -> c_int {
+    let dirs = ARTI_STATE
+        .get()
+        .and_then(|state| state.lock().ok())
+        .and_then(|guard| guard.dirs.clone());
+    let Some(dirs) = dirs.filter(|_| IS_RUNNING.load(Ordering::SeqCst)) else {
+        return -1;
+    };
+    let listed = guardstate::export(&dirs.state_dir)
+        .map_err(|e| e.to_string())
+        .and_then(|blob| guardstate::usable_json(&blob, circuit::last_guard().as_deref()));
+    let listed = match listed {
+        Ok(listed) => listed,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot read guard state: {}", e));
+            return -1;
+        }
+    };
+    if out_len <= 0 || listed.len() >= out_len as usize {
+        return -1;
+    }
+    write_c_string(out_buf, out_len, &listed)
+}
+
+/// Import a guard selection exported with `arti_export_guards`.
+///
+/// The state replaces this device's guard state when Arti next starts, so
+/// it skips choosing fresh guards. See `arti_export_guards` for the
+/// privacy cost. Only checked for being a JSON object here; if arti can't
+/// use the contents it chooses new guards as usual.
+///
+/// # Arguments
+/// * `blob` - Exported guard state (C string); NULL cancels a pending import
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if Arti is running, or the blob is not a JSON object
+#[no_mangle]
+pub extern "C" fn arti_import_guards(blob: *const c_char) -> c_int {
+    if IS_RUNNING.load(Ordering::SeqCst) {
+        error::set_last_error("guard state can only be imported while stopped");
+        return -1;
+    }
+    let blob = if blob.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(blob) }.to_str() {
+            Ok(s) => Some(s),
+            Err(_) => {
+                error::set_last_error("guard state is not valid UTF-8");
+                return -1;
+            }
+        }
+    };
+    match guardstate::stage(blob) {
+        Ok(()) => {
+            if blob.is_some() {
+                tracing::warn!("Guard state imported from another device; applies at next start");
+            }
+            0
+        }
+        Err(e) => {
+            error::set_last_error(&e);
+            -1
+        }
+    }
+}
+
+/// Set the bridges to use from a block of newline-separated bridge lines.
+///
+/// Friendlier than an array of strings for bindings that read bridges from
+/// a text field or QR code. Blank lines and `#` comments are ignored and a
+/// torrc-style `Bridge` prefix is accepted. The whole blob is rejected if
+/// any line fails to parse; the error, naming the line, is available from
+/// `arti_last_error`. This build has no pluggable transports, so only
+/// plain `IP:ORPort FINGERPRINT` lines are accepted. Applies at the next
+/// `arti_start`.
+///
+/// # Arguments
+/// * `lines` - Bridge lines (C string); NULL or empty clears the bridges
+///
+/// # Returns
+/// * Number of bridges stored
+/// * -1 on a parse error or invalid UTF-8
+#[no_mangle]
+pub extern "C" fn arti_set_bridges_blob(lines: *const c_char) -> c_int {
+    let blob = if lines.is_null() {
+        ""
+    } else {
+        match unsafe { CStr::from_ptr(lines) }.to_str() {
+            Ok(s) => s,
+            Err(_) => {
+                error::set_last_error("bridge lines are not valid UTF-8");
+                return -1;
+            }
+        }
+    };
+
+    match bridges::parse_lines(blob) {
+        Ok(parsed) => {
+            let count = parsed.len() as c_int;
+            bridges::set(parsed);
+            count
+        }
+        Err(e) => {
+            error::set_last_error(&format!("Invalid bridge {}", e));
+            -1
+        }
+    }
+}
+
+/// Load bridges from a Tor-style `bridges` file, such as one kept for an
+/// existing Tor setup.
+///
+/// Lines are read like `arti_set_bridges_blob` reads them, except that a
+/// line which fails to parse (another torrc option, or a transport this
+/// build can't use) is skipped with a warning in the log rather than
+/// rejecting the file. The bridges that parsed replace the current set and
+/// apply at the next `arti_start`; if none did, the current set is left
+/// alone.
+///
+/// # Arguments
+/// * `path` - Path to the file (C string)
+///
+/// # Returns
+/// * Number of bridges loaded
+/// * -1 if `path` is NULL/invalid or the file can't be read
+#[no_mangle]
+pub extern "C" fn arti_load_bridges_file(path: *const c_char) -> c_int {
+    let Some(path) = c_path(path) else {
+        error::set_last_error("bridges file path is missing or not valid UTF-8");
+        return -1;
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot read bridges {}: {}", path.display(), e));
+            return -1;
+        }
+    };
+
+    let (parsed, skipped) = bridges::parse_usable_lines(&text);
+    for e in &skipped {
+        tracing::warn!("Skipping {} of {}", e, path.display());
+    }
+    let count = parsed.len() as c_int;
+    if count > 0 {
+        bridges::set(parsed);
+    }
+    count
+}
+
+/// Use custom directory authorities and fallback caches, e.g. to join a
+/// private or chutney test network instead of the public Tor network.
+///
+/// Takes the `[tor_network]` section of an arti configuration file:
+/// `[tor_network.authorities]` with `v3idents` (plus optional `uploads`,
+/// `downloads` and `votes`) and one `[[tor_network.fallback_caches]]` per
+/// cache with `rsa_identity`, `ed_identity` and `orports`. Overriding the
+/// authorities requires overriding the fallback caches too. The section is
+/// validated as a whole; on error nothing changes and the reason is
+/// available from `arti_last_error`. Applies at the next `arti_start`.
+///
+/// # Arguments
+/// * `config_toml` - TOML document (C string); NULL restores the public network
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if the document is invalid or not valid UTF-8
+#[no_mangle]
+pub extern "C" fn arti_set_directory_authorities(config_toml: *const c_char) -> c_int {
+    if config_toml.is_null() {
+        if let Ok(mut network) = config::TOR_NETWORK.lock() {
+            *network = None;
+        }
+        return 0;
+    }
+    let Ok(text) = unsafe { CStr::from_ptr(config_toml) }.to_str() else {
+        error::set_last_error("directory authority config is not valid UTF-8");
+        return -1;
+    };
+    match config::parse_tor_network(text) {
+        Ok(parsed) => {
+            if let Ok(mut network) = config::TOR_NETWORK.lock() {
+                *network = Some(parsed);
+            }
+            0
+        }
+        Err(e) => {
+            error::set_last_error(&format!("Invalid directory authority config: {}", e));
+            -1
+        }
+    }
+}
+
+/// Load bridges from a passphrase-encrypted config bundle.
+///
+/// The bundle holds bridge lines in the format `arti_set_bridges_blob`
+/// takes, encrypted with AES-256-CTR and authenticated with HMAC-SHA256
+/// under keys derived from the passphrase by PBKDF2; the exact layout is
+/// documented in `bundle.rs`. On success the bridges replace the current
+/// set and apply at the next `arti_start`. Every failure records a
+/// description for `arti_last_error`.
+///
+/// # Arguments
+/// * `path` - Path to the bundle file (C string)
+/// * `passphrase` - Passphrase (C string)
+///
+/// # Returns
+/// * Number of bridges loaded
+/// * -1 if an argument is NULL/invalid or the file can't be read
+/// * -2 if the file is not a valid bundle
+/// * -3 if the passphrase is wrong (or the bundle was tampered with)
+/// * -4 if the decrypted bridge lines fail to parse
+#[no_mangle]
+pub extern "C" fn arti_load_config_bundle(
+    path: *const c_char,
+    passphrase: *const c_char,
+) -> c_int {
+    let Some(path) = c_path(path) else {
+        error::set_last_error("bundle path is missing or not valid UTF-8");
+        return -1;
+    };
+    if passphrase.is_null() {
+        error::set_last_error("bundle passphrase is missing");
         return -1;
     }
-    // Arti doesn't have explicit dormant mode yet, but we can note the intent
-    update_summary("Dormant");
-    0
+    let passphrase = unsafe { CStr::from_ptr(passphrase) }.to_bytes();
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            error::set_last_error(&format!("Cannot read bundle {}: {}", path.display(), e));
+            return -1;
+        }
+    };
+    let lines = match bundle::open(&data, passphrase) {
+        Ok(lines) => lines,
+        Err(bundle::BundleError::Malformed(why)) => {
+            error::set_last_error(&format!("Invalid config bundle: {}", why));
+            return -2;
+        }
+        Err(bundle::BundleError::WrongPassphrase) => {
+            error::set_last_error("Wrong passphrase for config bundle");
+            return -3;
+        }
+    };
+
+    match bridges::parse_lines(&lines) {
+        Ok(parsed) => {
+            let count = parsed.len() as c_int;
+            bridges::set(parsed);
+            count
+        }
+        Err(e) => {
+            error::set_last_error(&format!("Invalid bridge in config bundle {}", e));
+            -4
+        }
+    }
 }
 
-/// Signal Arti to wake from dormant mode.
+/// Normalize a bridge from a QR code or link into canonical bridge lines.
+///
+/// Accepts `bridge://` URIs (a percent-encoded bridge line, as shared by
+/// Tor Browser for Android and Orbot), the `['line', 'line']` list carried
+/// by bridges.torproject.org QR codes, and plain bridge lines. The output
+/// is one line per bridge, newline-separated, ready for
+/// `arti_set_bridges_blob`. Nothing is stored, so the UI can validate an
+/// import before committing it.
+///
+/// # Arguments
+/// * `uri` - The scanned or pasted text (C string)
+/// * `out_line_buf` - Buffer to write the bridge lines into
+/// * `out_len` - Length of the buffer
 ///
 /// # Returns
-/// * 0 on success
-/// * -1 if not running
+/// * Number of bytes written (not including null terminator)
+/// * -1 if the format is not recognized
+/// * -2 if recognized but not usable in this build (see `arti_last_error`)
+/// * -3 if the buffer is null or too small
 #[no_mangle]
-pub extern "C" fn arti_wake() -> c_int {
-    if !IS_RUNNING.load(Ordering::SeqCst) {
+pub extern "C" fn arti_parse_bridge_uri(
+    uri: *const c_char,
+    out_line_buf: *mut c_char,
+    out_len: c_int,
+) -> c_int {
+    if uri.is_null() {
         return -1;
     }
-    update_summary("Active");
+    let uri = match unsafe { CStr::from_ptr(uri) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    let lines = match bridges::normalize_uri(uri) {
+        Some(lines) => lines.join("\n"),
+        None => return -1,
+    };
+
+    if let Err(e) = bridges::parse_lines(&lines) {
+        error::set_last_error(&format!("Unusable bridge {}", e));
+        return -2;
+    }
+
+    if out_line_buf.is_null() || out_len <= 0 || lines.len() >= out_len as usize {
+        return -3;
+    }
+    write_c_string(out_line_buf, out_len, &lines)
+}
+
+/// Get a description of the most recent error reported by a call that
+/// documents it (e.g. `arti_set_bridges_blob`).
+///
+/// # Arguments
+/// * `buf` - Buffer to write the message into
+/// * `len` - Length of the buffer
+///
+/// # Returns
+/// * Number of bytes written (not including null terminator); 0 if none
+/// * -1 if buffer is null or too small
+#[no_mangle]
+pub extern "C" fn arti_last_error(buf: *mut c_char, len: c_int) -> c_int {
+    write_c_string(buf, len, &error::last_error())
+}
+
+/// Register a callback fired as bootstrap first reaches 25, 50, 75 and 100
+/// percent.
+///
+/// A lighter alternative to `arti_set_bootstrap_json_callback` for a
+/// staged UI: it fires at most four times per start, once per milestone,
+/// and a jump past several milestones reports each in order. 100 means
+/// ready for traffic, as with `arti_bootstrap_progress`.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the milestone percentage and `ctx`; NULL to
+///   clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_milestone_callback(
+    cb: Option<callbacks::ArtiMilestoneCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::MILESTONE.set(cb, ctx);
+    0
+}
+
+/// Register a callback fired whenever arti installs a new consensus.
+///
+/// The callback receives the consensus valid-after time (Unix seconds). It
+/// fires once for the consensus in use when bootstrap completes, then for
+/// each replacement (roughly hourly), so relay lists can be refreshed
+/// without polling.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the valid-after time and `ctx`; NULL to clear
+/// * `ctx` - Opaque pointer passed back to `cb`
+///
+/// # Returns
+/// * 0 on success
+#[no_mangle]
+pub extern "C" fn arti_set_consensus_callback(
+    cb: Option<ArtiConsensusCallback>,
+    ctx: *mut c_void,
+) -> c_int {
+    callbacks::CONSENSUS.set(cb, ctx);
+    0
+}
+
+/// Tag the next accepted SOCKS connection with a label for logging.
+///
+/// Every log line for that connection then carries `label`, so it can be
+/// matched to an app-side request id. SOCKS has no field for this, so the
+/// label attaches to whichever connection is accepted next, on any
+/// listener: set it immediately before opening the connection, and only
+/// rely on it when requests are issued one at a time. With concurrent
+/// requests the label may land on a different connection. A label that is
+/// never used is replaced by the next call.
+///
+/// # Arguments
+/// * `label` - Label text (C string), or NULL to clear
+///
+/// # Returns
+/// * 0 on success
+/// * -1 if the label is not valid UTF-8
+#[no_mangle]
+pub extern "C" fn arti_set_next_connection_label(label: *const c_char) -> c_int {
+    let label = if label.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(label) }.to_str() {
+            Ok(s) => Some(s.to_owned()),
+            Err(_) => return -1,
+        }
+    };
+    socks::set_next_label(label);
     0
 }
 
 fn update_summary(s: &str) {
-    if let Ok(mut guard) = BOOTSTRAP_SUMMARY.lock() {
-        guard.clear();
-        guard.push_str(s);
-    }
+    let summary = status::prefixed_summary(s);
+    status::update(|status| status.summary = summary);
 }
 
 /// Main async entry point for Arti
 async fn run_arti(
-    data_dir: PathBuf,
-    socks_addr: SocketAddr,
+    dirs: config::Dirs,
+    socks: SocksListen,
     mut shutdown_rx: oneshot::Receiver<()>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Ensure data directory exists
-    std::fs::create_dir_all(&data_dir)?;
+    // Ensure the state and cache directories exist
+    std::fs::create_dir_all(&dirs.state_dir)?;
+    std::fs::create_dir_all(&dirs.cache_dir)?;
+    if let Err(e) = guardstate::install_staged(&dirs.state_dir) {
+        tracing::warn!("Could not install imported guard state: {}", e);
+    }
+
+    if let Some(connector) = test_connector() {
+        tracing::info!("Serving SOCKS through the test connector; Tor is not used");
+        if let Some(mut guard) = ARTI_STATE.get().and_then(|s| s.lock().ok()) {
+            guard.dirs = Some(dirs.clone());
+        }
+        let stall = TEST_BOOTSTRAP_STALL.load(Ordering::SeqCst);
+        if stall >= 0 {
+            BOOTSTRAP_PROGRESS.store(stall, Ordering::SeqCst);
+            update_summary("Bootstrapping...");
+            until_shutdown(std::future::pending::<()>(), &mut shutdown_rx).await;
+            bootstrap_cancelled();
+            return Ok(());
+        }
+        return serve_until_shutdown(Some(socks), connector, shutdown_rx).await;
+    }
+
+    // Optionally open the SOCKS port now, handing requests made during
+    // bootstrap to a placeholder connector
+    let (socks, early_socks) = match socks::prebootstrap_mode() {
+        Some(mode) => {
+            let pending = Arc::new(connector::Pending::new(mode));
+            open_socks_server(socks, pending.clone())?;
+            (None, Some(EarlySocks(pending)))
+        }
+        None => (Some(socks), None),
+    };
 
     update_summary("Configuring...");
 
     // Build Arti configuration with custom directories
-    let cache_dir = data_dir.join("cache");
-    let state_dir = data_dir.join("state");
-
-    // Use from_directories which sets up storage correctly
-    use arti_client::config::TorClientConfigBuilder;
-    let config = TorClientConfigBuilder::from_directories(state_dir, cache_dir)
-        .build()?;
+    let config = match config::pinned_guard() {
+        Some(id) => pinned_from_cache(&dirs, &id).await?,
+        None => config::build(&dirs, None)?,
+    };
 
     update_summary("Bootstrapping...");
 
-    // Create and bootstrap the Tor client
-    let client = TorClient::create_bootstrapped(config).await?;
-    let client = Arc::new(client);
+    // Bootstrap, starting over with a new client if one fails outright,
+    // racing against shutdown so that `arti_stop` can abort a bootstrap
+    // that is still in progress
+    let attempts = config::BOOTSTRAP_ATTEMPTS.load(Ordering::SeqCst).max(1) as u32;
+    let bootstrap = with_bootstrap_attempts(attempts, retry::BOOTSTRAP_BACKOFF, |_| {
+        bootstrap_client(&config, &dirs)
+    });
+    let (client, _monitors) = match until_shutdown(bootstrap, &mut shutdown_rx).await {
+        Some(Ok(bootstrapped)) => bootstrapped,
+        Some(Err(failure)) => {
+            callbacks::notify_network_error(
+                failure.category,
+                &format!("Bootstrap failed: {}", failure.error),
+            );
+            return Err(failure.error);
+        }
+        None => {
+            bootstrap_cancelled();
+            return Ok(());
+        }
+    };
+
+    // Checked again against the fresh consensus, and applied now if the
+    // cache couldn't place the relay; arti retires the circuits built so
+    // far when the bridges change. Rather than run with a first hop that
+    // wasn't asked for, the start fails if the pinned relay is unusable.
+    if let Some(id) = config::pinned_guard() {
+        if let Err(e) = apply_pinned_guard(&client, &dirs, &id) {
+            let message = format!("Pinned entry guard unavailable: {}", e);
+            callbacks::notify_network_error(callbacks::NETWORK_ERROR_OTHER, &message);
+            return Err(message.into());
+        }
+    }
 
     // Store client reference for status queries
     if let Some(state) = ARTI_STATE.get() {
         if let Ok(mut guard) = state.lock() {
             guard.client = Some(client.clone());
+            guard.dirs = Some(dirs.clone());
         }
     }
+    status::update(|status| status.client = Some(client.clone()));
+    let _consensus_monitor = AbortOnDrop(tokio::spawn(monitor_consensus(client.clone())));
+
+    if let Some(early) = &early_socks {
+        early.0.ready(client.clone());
+    }
+    serve_until_shutdown(socks, client, shutdown_rx).await
+}
+
+/// Run `work` until it finishes or the shutdown signal arrives, whichever
+/// is first; `None` means shutdown. `work` is dropped on shutdown, so a
+/// backoff wait in it ends at once rather than being waited out.
+async fn until_shutdown<T>(
+    work: impl std::future::Future<Output = T>,
+    shutdown_rx: &mut oneshot::Receiver<()>,
+) -> Option<T> {
+    tokio::select! {
+        output = work => Some(output),
+        _ = shutdown_rx => None,
+    }
+}
+
+/// Note that `arti_stop` ended a bootstrap still in progress.
+fn bootstrap_cancelled() {
+    tracing::info!("Shutdown signal received during bootstrap");
+    update_summary("Shutting down...");
+}
+
+/// Why a full bootstrap attempt failed
+struct BootstrapFailure {
+    error: Box<dyn std::error::Error + Send + Sync>,
+    /// Network error category reported if no attempt succeeds
+    category: c_int,
+    /// Whether starting over with a new client might get further
+    retryable: bool,
+}
+
+/// Make up to `attempts` full bootstrap `attempt`s (given the 1-based
+/// attempt number), showing "Retrying bootstrap 2/3..." before each retry.
+async fn with_bootstrap_attempts<T, F, Fut>(
+    attempts: u32,
+    backoff: retry::Backoff,
+    mut attempt: F,
+) -> Result<T, BootstrapFailure>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = Result<T, BootstrapFailure>>,
+{
+    retry::with_retries(
+        attempts.saturating_sub(1),
+        backoff,
+        |number| {
+            if number > 1 {
+                tracing::info!("Retrying bootstrap with a new client ({} of {})", number, attempts);
+                update_summary(&format!("Retrying bootstrap {}/{}...", number, attempts));
+            }
+            attempt(number)
+        },
+        |failure| failure.retryable,
+    )
+    .await
+}
+
+/// Create a Tor client and bootstrap it, retrying transient failures,
+/// while watching its status. Returns the client and its monitors.
+async fn bootstrap_client(
+    config: &TorClientConfig,
+    dirs: &config::Dirs,
+) -> Result<(Arc<TorClient<PreferredRuntime>>, [AbortOnDrop<()>; 2]), BootstrapFailure> {
+    let created = TorClient::builder().config(config.clone()).create_unbootstrapped();
+    let client = Arc::new(created.map_err(|e| BootstrapFailure {
+        error: e.into(),
+        category: callbacks::NETWORK_ERROR_OTHER,
+        // A client that can't be created won't be on the next attempt
+        retryable: false,
+    })?);
+    let monitor = AbortOnDrop(tokio::spawn(monitor_bootstrap(client.clone(), dirs.clone())));
+    CLOCK_SKEW_SECS.store(0, Ordering::SeqCst);
+    CLOCK_SKEWED.store(false, Ordering::SeqCst);
+    let skew_monitor = AbortOnDrop(tokio::spawn(monitor_clock_skew(client.clone())));
+
+    let retries = config::BOOTSTRAP_RETRIES.load(Ordering::SeqCst).max(0) as u32;
+    let bootstrapped = retry::with_retries(
+        retries,
+        retry::BOOTSTRAP_BACKOFF,
+        |attempt| {
+            BOOTSTRAP_ATTEMPT.fetch_add(1, Ordering::SeqCst);
+            if attempt > 1 {
+                tracing::info!("Bootstrap attempt {} of {}", attempt, retries + 1);
+                update_summary(&format!(
+                    "Bootstrapping (attempt {} of {})...",
+                    attempt,
+                    retries + 1
+                ));
+            }
+            client.bootstrap()
+        },
+        // A wrong clock won't fix itself between attempts
+        |_| !CLOCK_SKEWED.load(Ordering::SeqCst),
+    )
+    .await;
+    match bootstrapped {
+        Ok(()) => Ok((client, [monitor, skew_monitor])),
+        Err(e) => Err(BootstrapFailure {
+            category: bootstrap_error_category(&client),
+            retryable: !CLOCK_SKEWED.load(Ordering::SeqCst),
+            error: e.into(),
+        }),
+    }
+}
 
+/// Mark bootstrap complete, serve SOCKS through `connector` (unless the
+/// port was already opened before bootstrap) and wait for the shutdown
+/// signal.
+async fn serve_until_shutdown(
+    socks: Option<SocksListen>,
+    connector: Arc<dyn StreamConnector>,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Mark bootstrap complete
     BOOTSTRAP_PROGRESS.store(100, Ordering::SeqCst);
+    callbacks::report_progress(100);
+    socks::set_ready(true);
     update_summary("Ready");
+    if let Some(mut guard) = ARTI_STATE.get().and_then(|s| s.lock().ok()) {
+        guard.bootstrap_duration = guard.started_at.map(|at| at.elapsed());
+    }
+
+    if let Some(socks) = socks {
+        open_socks_server(socks, connector)?;
+    }
+
+    // Run until shutdown
+    let _ = (&mut shutdown_rx).await;
+    tracing::info!("Shutdown signal received");
+
+    close_socks_server();
+    update_summary("Shutting down...");
+    Ok(())
+}
+
+/// Bind SOCKS listener(s) and serve them on their own task, so the port
+/// can be rebound without disturbing the client. Must be called within
+/// the tokio runtime.
+fn open_socks_server(
+    socks: SocksListen,
+    connector: Arc<dyn StreamConnector>,
+) -> std::io::Result<()> {
+    let server = match socks {
+        SocksListen::Port(port) => spawn_socks_server(port, connector)?,
+        SocksListen::Adopted(listener) => spawn_adopted_socks_server(listener, connector)?,
+    };
+    SOCKS_PORT.store(server.port, Ordering::SeqCst);
+    if let Some(state) = ARTI_STATE.get() {
+        if let Ok(mut guard) = state.lock() {
+            guard.socks = Some(server);
+        }
+    }
+    Ok(())
+}
+
+/// Stop serving SOCKS.
+fn close_socks_server() {
+    if let Some(state) = ARTI_STATE.get() {
+        if let Ok(mut guard) = state.lock() {
+            if let Some(server) = guard.socks.take() {
+                server.task.abort();
+            }
+        }
+    }
+    SOCKS_PORT.store(0, Ordering::SeqCst);
+}
+
+/// A SOCKS port opened before bootstrap. However `run_arti` returns, the
+/// port is closed and requests still waiting on bootstrap are failed.
+struct EarlySocks(Arc<connector::Pending>);
+
+impl Drop for EarlySocks {
+    fn drop(&mut self) {
+        self.0.fail();
+        close_socks_server();
+    }
+}
+
+/// Bind a loopback TCP listener. Must be called within the tokio runtime.
+fn bind_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    socks::bind(addr)
+}
+
+/// Bind the main SOCKS port (plus `[::1]` in dual-stack mode) and spawn
+/// the task serving it. Must be called within the tokio runtime.
+fn spawn_socks_server(
+    port: u16,
+    connector: Arc<dyn StreamConnector>,
+) -> std::io::Result<SocksServer> {
+    let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+    let listener = bind_listener(addr)?;
+    // Report the port actually bound, in case 0 was requested
+    let port = listener.local_addr()?.port();
+    tracing::info!("SOCKS5 proxy listening on 127.0.0.1:{}", port);
+
+    let mut addrs = vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)];
+
+    // Optionally bind the IPv6 loopback on the same port
+    let listener_v6 = if DUAL_STACK.load(Ordering::SeqCst) {
+        let addr_v6 = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port);
+        match bind_listener(addr_v6) {
+            Ok(l) => {
+                tracing::info!("SOCKS5 proxy listening on {}", addr_v6);
+                addrs.push(addr_v6);
+                Some(l)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to bind {}: {}", addr_v6, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // If the listener breaks, rebind the same addresses (IPv6 stays
+    // best-effort) and carry on
+    let rebind_addrs = addrs.clone();
+    let rebind = move || {
+        let v4 = bind_listener(rebind_addrs[0])?;
+        let v6 = rebind_addrs.get(1).and_then(|addr| bind_listener(*addr).ok());
+        Ok((v4, v6))
+    };
+    let server_connector = connector.clone();
+    let task = tokio::spawn(async move {
+        let err = socks::supervise(
+            (listener, listener_v6),
+            rebind,
+            |(v4, v6): (TcpListener, Option<TcpListener>)| {
+                let connector = connector.clone();
+                async move { socks::serve(&v4, v6.as_ref(), connector, None).await }
+            },
+            socks::REBIND_POLICY,
+        )
+        .await;
+        let _ = SOCKS_PORT.compare_exchange(port, 0, Ordering::SeqCst, Ordering::SeqCst);
+        callbacks::notify_network_error(
+            callbacks::NETWORK_ERROR_LISTENER,
+            &format!("SOCKS listener on port {} failed and could not be rebound: {}", port, err),
+        );
+    });
+    Ok(SocksServer {
+        port,
+        addrs,
+        task,
+        connector: server_connector,
+        adopted: false,
+    })
+}
+
+/// Spawn the task serving an app-provided listener. Unlike
+/// [`spawn_socks_server`], the socket cannot be recreated, so a fatal error
+/// ends the task. Must be called within the tokio runtime.
+fn spawn_adopted_socks_server(
+    listener: std::net::TcpListener,
+    connector: Arc<dyn StreamConnector>,
+) -> std::io::Result<SocksServer> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let addr = listener.local_addr()?;
+    tracing::info!("SOCKS5 proxy listening on adopted socket {}", addr);
+
+    let port = addr.port();
+    let server_connector = connector.clone();
+    let task = tokio::spawn(async move {
+        let err = socks::serve(&listener, None, connector, None).await;
+        let _ = SOCKS_PORT.compare_exchange(port, 0, Ordering::SeqCst, Ordering::SeqCst);
+        callbacks::notify_network_error(
+            callbacks::NETWORK_ERROR_LISTENER,
+            &format!("Adopted SOCKS listener on {} failed: {}", addr, err),
+        );
+    });
+    Ok(SocksServer {
+        port,
+        addrs: vec![addr],
+        task,
+        connector: server_connector,
+        adopted: true,
+    })
+}
+
+/// The configuration for a first client with guard `id` pinned, so that
+/// bootstrap itself only goes through it, when a cached consensus gives
+/// the relay's address. Without one it is left unpinned, to be pinned once
+/// bootstrapped.
+async fn pinned_from_cache(
+    dirs: &config::Dirs,
+    id: &tor_llcrypto::pk::rsa::RsaIdentity,
+) -> Result<TorClientConfig, Box<dyn std::error::Error + Send + Sync>> {
+    let unpinned = config::build(dirs, None)?;
+    let runtime = PreferredRuntime::current()?;
+    match tor_dirmgr::DirMgr::load_once(runtime, unpinned.dir_mgr_config()?).await {
+        Ok(netdir) if netdir.by_id(id).is_some() => {
+            tracing::info!("Bootstrapping through pinned guard {}", config::fingerprint_hex(id));
+            config::build_with_netdir(dirs, &netdir)
+        }
+        Ok(_) => {
+            tracing::warn!("Pinned guard is not in the cached consensus; pinning after bootstrap");
+            Ok(unpinned)
+        }
+        Err(e) => {
+            tracing::info!(
+                "No cached consensus to pin the guard from ({}); pinning after bootstrap",
+                e
+            );
+            Ok(unpinned)
+        }
+    }
+}
+
+/// Reconfigure `client` so that `id` is its only first hop.
+fn apply_pinned_guard(
+    client: &TorClient<PreferredRuntime>,
+    dirs: &config::Dirs,
+    id: &tor_llcrypto::pk::rsa::RsaIdentity,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !config::relay_in_consensus(client, id) {
+        return Err(format!("relay {} not in consensus", config::fingerprint_hex(id)).into());
+    }
+    let cfg = config::build(dirs, Some(client))?;
+    client.reconfigure(&cfg, Reconfigure::AllOrNothing)?;
+    tracing::info!("Entry guard pinned to {}", config::fingerprint_hex(id));
+    Ok(())
+}
 
-    // Bind SOCKS listener
-    let listener = TcpListener::bind(socks_addr).await?;
-    tracing::info!("SOCKS5 proxy listening on {}", socks_addr);
+/// Aborts the wrapped task when dropped, tying its lifetime to a scope.
+struct AbortOnDrop<T>(JoinHandle<T>);
 
-    // Accept connections until shutdown
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Track bootstrap status events: keep the progress value current, report
+/// each new blockage to the network error callback, and suggest bridges
+/// once a censorship-like stall lasts past the threshold.
+async fn monitor_bootstrap(client: Arc<TorClient<PreferredRuntime>>, dirs: config::Dirs) {
+    let mut events = client.bootstrap_events();
+    let mut last_blockage: Option<String> = None;
+    let mut last_category = callbacks::NETWORK_ERROR_OTHER;
+    let mut detector = censorship::Detector::default();
     loop {
-        tokio::select! {
-            accept_result = listener.accept() => {
-                match accept_result {
-                    Ok((stream, peer_addr)) => {
-                        let client = client.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = socks::handle_socks_connection(stream, peer_addr, client).await {
-                                tracing::debug!("SOCKS connection error from {}: {}", peer_addr, e);
-                            }
-                        });
-                    }
-                    Err(e) => {
-                        tracing::warn!("Accept error: {}", e);
-                    }
+        let next = match detector.deadline(censorship::threshold()) {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), events.next()).await,
+            None => Ok(events.next().await),
+        };
+        let status = match next {
+            Ok(Some(status)) => status,
+            Ok(None) => break,
+            Err(_) => {
+                // No update before the deadline: the stall is still going
+                if report_censorship(&mut detector, last_category) {
+                    retry_with_provided_bridges(&client, &dirs).await;
                 }
+                continue;
+            }
+        };
+
+        if !status.ready_for_traffic() {
+            // Hold 100 back for "Ready", which `run_arti` sets itself
+            let percent = (status.as_frac() * 100.0).round() as c_int;
+            BOOTSTRAP_PROGRESS.store(percent.clamp(0, 99), Ordering::SeqCst);
+            callbacks::report_progress(percent.clamp(0, 99));
+        }
+
+        callbacks::notify_bootstrap_json(&bootstrap_status_json(&status));
+        callbacks::set_online(status.ready_for_traffic());
+        socks::set_ready(status.ready_for_traffic());
+
+        let blockage = status.blocked();
+        let censored = blockage
+            .as_ref()
+            .is_some_and(|b| censorship::suggests_censorship(&b.kind()));
+        detector.observe(status.as_frac(), censored, std::time::Instant::now());
+
+        // Arti calls it offline when dialing guards fails locally (no
+        // route, network unreachable) rather than somewhere on the way
+        let offline = blockage.as_ref().is_some_and(|b| matches!(b.kind(), BlockageKind::Offline));
+        if callbacks::set_device_offline(offline) {
+            // Connectivity is back: don't wait for dead circuits to time out
+            tracing::info!("Device back online; rebuilding circuits");
+            arti_reconnect();
+        }
+
+        let description = blockage.as_ref().map(|b| b.to_string());
+        if description != last_blockage {
+            if let (Some(b), Some(d)) = (&blockage, &description) {
+                tracing::warn!("Bootstrap blocked: {}", d);
+                last_category = callbacks::blockage_category(&b.kind());
+                callbacks::notify_network_error(last_category, d);
             }
-            _ = &mut shutdown_rx => {
-                tracing::info!("Shutdown signal received");
-                break;
+            status::update(|s| s.blockage.clone_from(&description));
+            last_blockage = description;
+        }
+        if report_censorship(&mut detector, last_category) {
+            retry_with_provided_bridges(&client, &dirs).await;
+        }
+    }
+}
+
+/// Fire the censorship callback if the detector's stall has crossed the
+/// threshold, returning whether it did.
+fn report_censorship(detector: &mut censorship::Detector, category: c_int) -> bool {
+    let now = std::time::Instant::now();
+    let Some(stalled) = detector.check(censorship::threshold(), now) else {
+        return false;
+    };
+    tracing::warn!("No bootstrap progress for {:?}; Tor may be blocked", stalled);
+    callbacks::notify_censorship(category, stalled.as_secs() as c_int);
+    true
+}
+
+/// Ask the bridge provider callback for bridges and, if it offers any that
+/// parse, switch the bootstrapping client over to them.
+async fn retry_with_provided_bridges(client: &TorClient<PreferredRuntime>, dirs: &config::Dirs) {
+    let Some(lines) = callbacks::provide_bridges().await else {
+        return;
+    };
+    let (provided, skipped) = bridges::parse_usable_lines(&lines);
+    for e in &skipped {
+        tracing::warn!("Skipping provided bridge {}", e);
+    }
+    if provided.is_empty() {
+        tracing::warn!("Bridge provider offered no usable bridges");
+        return;
+    }
+
+    let count = provided.len();
+    let previous = bridges::configured();
+    bridges::set(provided);
+    let applied = config::build(dirs, Some(client))
+        .and_then(|cfg| Ok(client.reconfigure(&cfg, Reconfigure::AllOrNothing)?));
+    match applied {
+        Ok(()) => tracing::info!("Retrying bootstrap with {} provided bridge(s)", count),
+        Err(e) => {
+            tracing::warn!("Could not apply provided bridges: {}", e);
+            bridges::set(previous);
+        }
+    }
+}
+
+/// Report each newly installed consensus (starting with the current one) to
+/// the consensus callback.
+async fn monitor_consensus(client: Arc<TorClient<PreferredRuntime>>) {
+    let dirmgr = client.dirmgr();
+    let mut events = dirmgr.events();
+    let mut last_valid_after = None;
+    loop {
+        // Events also fire for new descriptors and parameter changes, so
+        // only a change of valid-after counts as a new consensus
+        if let Ok(netdir) = dirmgr.timely_netdir() {
+            let valid_after = netdir
+                .lifetime()
+                .valid_after()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            if last_valid_after != Some(valid_after) {
+                last_valid_after = Some(valid_after);
+                callbacks::notify_consensus(valid_after);
             }
         }
+        if events.next().await.is_none() {
+            break;
+        }
     }
+}
 
-    update_summary("Shutting down...");
-    Ok(())
+/// Keep the clock skew estimate (from guard and fallback handshakes)
+/// current.
+async fn monitor_clock_skew(client: Arc<TorClient<PreferredRuntime>>) {
+    let mut events = client.circmgr().skew_events();
+    while let Some(estimate) = events.next().await {
+        let (secs, noteworthy) = match estimate {
+            Some(e) => (e.skew().as_secs_f64().round() as i64, e.noteworthy()),
+            None => (0, false),
+        };
+        CLOCK_SKEW_SECS.store(secs, Ordering::SeqCst);
+        let was_skewed = CLOCK_SKEWED.swap(noteworthy, Ordering::SeqCst);
+        if noteworthy && !was_skewed {
+            tracing::warn!("Clock skew detected: about {} seconds", secs);
+        }
+    }
+}
+
+/// Encode a bootstrap status update for the JSON callback.
+///
+/// Fields: `percentage` (0-100), `phase` (`starting`, `bootstrapping`,
+/// `blocked` or `ready`), `ready`, `blockage` (message or null),
+/// `blockage_category` (an `ARTI_NETWORK_ERROR_*` value or null) and
+/// `detail` (arti's human-readable description).
+fn bootstrap_status_json(status: &BootstrapStatus) -> String {
+    let percent = (status.as_frac() * 100.0).round().clamp(0.0, 100.0) as u32;
+    let ready = status.ready_for_traffic();
+    let blockage = status.blocked();
+    let phase = if ready {
+        "ready"
+    } else if blockage.is_some() {
+        "blocked"
+    } else if percent == 0 {
+        "starting"
+    } else {
+        "bootstrapping"
+    };
+
+    let message = blockage.as_ref().map(|b| b.message().to_string());
+    let obj = json::Object::new()
+        .num("percentage", percent)
+        .str("phase", phase)
+        .bool("ready", ready)
+        .opt_str("blockage", message.as_deref());
+    let obj = match &blockage {
+        Some(b) => obj.num("blockage_category", callbacks::blockage_category(&b.kind())),
+        None => obj.opt_str("blockage_category", None),
+    };
+    obj.str("detail", &status.to_string()).finish()
+}
+
+/// Best-effort category for a bootstrap failure, based on the last status.
+/// Detected clock skew wins, since it breaks everything else.
+fn bootstrap_error_category(client: &TorClient<PreferredRuntime>) -> c_int {
+    if CLOCK_SKEWED.load(Ordering::SeqCst) {
+        return callbacks::NETWORK_ERROR_CLOCK_SKEW;
+    }
+    client
+        .bootstrap_status()
+        .blocked()
+        .map(|b| callbacks::blockage_category(&b.kind()))
+        .unwrap_or(callbacks::NETWORK_ERROR_OTHER)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fill a `len`-byte buffer through `write` and return what a C caller
+    /// would read back.
+    fn read_back(
+        len: usize,
+        write: impl FnOnce(*mut c_char, c_int) -> c_int,
+    ) -> (c_int, Vec<u8>) {
+        let mut buf = vec![0x7f as c_char; len];
+        let written = write(buf.as_mut_ptr(), len as c_int);
+        let text = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_bytes().to_vec();
+        (written, text)
+    }
+
+    #[test]
+    fn truncation_keeps_whole_characters() {
+        // "é" is 2 bytes, "€" 3 and "🧅" 4
+        let s = "aé€🧅";
+        for len in 1..=s.len() + 1 {
+            let (written, text) = read_back(len, |buf, len| write_c_string(buf, len, s));
+            let text = std::str::from_utf8(&text).expect("valid UTF-8");
+            assert_eq!(written as usize, text.len());
+            assert!(s.starts_with(text));
+            assert!(text.len() >= (len - 1).saturating_sub(3), "{} bytes for {}", text.len(), len);
+        }
+        assert_eq!(read_back(3, |buf, len| write_c_string(buf, len, s)).1, b"a");
+        assert_eq!(read_back(4, |buf, len| write_c_string(buf, len, s)).1, "aé".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn refused_split_streams_are_never_opened() {
+        let _ready = socks::READY_LOCK.lock().await;
+        socks::set_ready(false);
+        let connector = connector::testing::EchoConnector::default();
+        let target = ("example.net", 80).into_tor_addr().unwrap();
+        let refusing = [
+            (
+                socks::HandlerOptions { allowed_ports: vec![443].into(), ..Default::default() },
+                std::io::ErrorKind::PermissionDenied,
+            ),
+            (
+                socks::HandlerOptions {
+                    tor_only_domains: vec!["example.com".to_owned()].into(),
+                    ..Default::default()
+                },
+                std::io::ErrorKind::PermissionDenied,
+            ),
+            (
+                socks::HandlerOptions { fail_closed: true, ..Default::default() },
+                std::io::ErrorKind::NetworkUnreachable,
+            ),
+        ];
+        for (options, kind) in &refusing {
+            let refused = open_split(&connector, "example.net", 80, target.clone(), options).await;
+            assert_eq!(refused.err().map(|e| e.kind()), Some(*kind), "{:?}", options);
+        }
+        assert!(connector.targets.lock().unwrap().is_empty());
+
+        let allowed = socks::HandlerOptions::default();
+        assert!(open_split(&connector, "example.net", 80, target, &allowed).await.is_ok());
+        assert_eq!(connector.targets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn socks_ready_probes_retry_until_the_deadline() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        // A full accept queue: further connections are never answered
+        let listener = {
+            let _enter = runtime.enter();
+            let socket = tokio::net::TcpSocket::new_v4().unwrap();
+            socket.bind((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+            socket.listen(0).unwrap()
+        };
+        let addr = listener.local_addr().unwrap();
+        let queued: Vec<_> = (0..8)
+            .map_while(|_| {
+                std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(200)).ok()
+            })
+            .collect();
+        assert!(queued.len() < 8, "accept queue never filled");
+
+        let probes = AtomicI32::new(0);
+        let listening = || {
+            probes.fetch_add(1, Ordering::SeqCst);
+            Some((Some(addr), runtime.handle().clone()))
+        };
+        // Timeout 0 checks once and doesn't wait out a whole probe
+        let started = Instant::now();
+        assert_eq!(wait_until_listening(started, listening), -2);
+        assert!(started.elapsed() < SOCKS_PROBE_TIMEOUT / 2);
+        assert_eq!(probes.swap(0, Ordering::SeqCst), 1);
+
+        // A probe that times out is retried while time is left
+        let started = Instant::now();
+        let wait = SOCKS_PROBE_TIMEOUT + Duration::from_millis(300);
+        assert_eq!(wait_until_listening(started + wait, listening), -2);
+        assert!(started.elapsed() >= wait);
+        assert!(started.elapsed() < wait + SOCKS_PROBE_TIMEOUT / 2);
+        assert!(probes.load(Ordering::SeqCst) >= 2);
+
+        drop((queued, listener));
+        assert_eq!(wait_until_listening(Instant::now(), || None), -1);
+    }
+
+    #[test]
+    fn summary_and_prefix_truncate_on_a_character_boundary() {
+        update_summary("Загрузка 🧅");
+        for len in 1..32 {
+            let (written, text) = read_back(len, |buf, len| arti_bootstrap_summary(buf, len));
+            assert_eq!(written, -2);
+            let text = std::str::from_utf8(&text).expect("valid UTF-8");
+            assert!("Загрузка 🧅".starts_with(text));
+        }
+        update_summary("");
+
+        status::set_summary_prefix("Мост");
+        let (written, text) = read_back(6, |buf, len| arti_summary_prefix(buf, len));
+        assert_eq!((written, &text[..]), (4, "Мо".as_bytes()));
+        let (written, text) = read_back(9, |buf, len| arti_summary_prefix(buf, len));
+        assert_eq!((written, &text[..]), (8, "Мост".as_bytes()));
+        status::set_summary_prefix("");
+        assert_eq!(read_back(1, |buf, len| arti_summary_prefix(buf, len)).0, 0);
+    }
+
+    #[test]
+    fn bootstrap_blockage_truncates_to_fit() {
+        let reason = "We seem to be offline (no route)";
+        status::update(|s| s.blockage = Some(reason.to_owned()));
+        let (written, text) = read_back(12, |buf, len| arti_bootstrap_blockage(buf, len));
+        assert_eq!((written, &text[..]), (11, &reason.as_bytes()[..11]));
+        let (written, text) = read_back(64, |buf, len| arti_bootstrap_blockage(buf, len));
+        assert_eq!((written, &text[..]), (reason.len() as c_int, reason.as_bytes()));
+        assert_eq!(arti_bootstrap_blockage(std::ptr::null_mut(), 64), -1);
+        status::update(|s| s.blockage = None);
+        assert_eq!(read_back(1, |buf, len| arti_bootstrap_blockage(buf, len)).0, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_bootstrap_starts_over_with_a_new_client() {
+        const FAST: retry::Backoff = retry::Backoff {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+        };
+        let failure = |retryable| BootstrapFailure {
+            error: "no guard reachable".into(),
+            category: callbacks::NETWORK_ERROR_OTHER,
+            retryable,
+        };
+
+        // A client that fails once, then one that bootstraps
+        let mut clients = 0;
+        let result = with_bootstrap_attempts(3, FAST, |attempt| {
+            clients += 1;
+            let result = if attempt == 1 { Err(failure(true)) } else { Ok(attempt) };
+            async move { result }
+        })
+        .await;
+        assert_eq!((result.ok(), clients), (Some(2), 2));
+
+        let mut clients = 0;
+        let result: Result<(), _> = with_bootstrap_attempts(3, FAST, |_| {
+            clients += 1;
+            let result = Err(failure(false));
+            async move { result }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(clients, 1);
+    }
+
+    #[tokio::test]
+    async fn stop_cuts_a_retry_wait_short() {
+        const SLOW: retry::Backoff = retry::Backoff {
+            initial_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+        };
+        let (stop, mut shutdown_rx) = oneshot::channel();
+        let (failed_tx, failed_rx) = oneshot::channel();
+        let mut failed_tx = Some(failed_tx);
+        let bootstrap = with_bootstrap_attempts(3, SLOW, |_| {
+            if let Some(tx) = failed_tx.take() {
+                let _ = tx.send(());
+            }
+            async {
+                Err::<(), _>(BootstrapFailure {
+                    error: "no guard reachable".into(),
+                    category: callbacks::NETWORK_ERROR_OTHER,
+                    retryable: true,
+                })
+            }
+        });
+        let stopper = tokio::spawn(async move {
+            failed_rx.await.unwrap();
+            // Well into the wait before the second attempt
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let _ = stop.send(());
+        });
+
+        let started = Instant::now();
+        assert!(until_shutdown(bootstrap, &mut shutdown_rx).await.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        stopper.await.unwrap();
+    }
 }
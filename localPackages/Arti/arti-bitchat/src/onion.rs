@@ -0,0 +1,127 @@
+//! Onion service reachability checks
+//!
+//! arti looks up an onion service's descriptor only as the first step of
+//! connecting to it; there is no descriptor-only fetch. The check therefore
+//! runs arti's HS connector up to the point where a stream would be
+//! opened: it fetches the descriptor and then tries to build a rendezvous
+//! circuit, which is discarded. A failure after the descriptor was found
+//! still means the service is published.
+//!
+//! Prewarming goes the other way: it has the client's own connector, the
+//! one SOCKS streams use, build the rendezvous circuit, so that arti keeps
+//! it for the next stream to the service.
+//!
+//! Without the `onion` feature both report that onion support is missing.
+
+#[cfg(feature = "onion")]
+use std::sync::Arc;
+
+use arti_client::{IntoTorAddr, StreamPrefs, TorClient, TorClientConfig};
+#[cfg(feature = "onion")]
+use futures::StreamExt;
+#[cfg(feature = "onion")]
+use tor_circmgr::isolation::StreamIsolation;
+#[cfg(feature = "onion")]
+use tor_hsclient::{ConnError, HsClientConnector, HsClientSecretKeys};
+#[cfg(feature = "onion")]
+use tor_hscrypto::pk::HsId;
+use tor_rtcompat::PreferredRuntime;
+
+use crate::isolation;
+
+/// Why onion requests fail in a build without the `onion` feature
+pub const NOT_COMPILED_IN: &str = "onion support not compiled in";
+
+/// Whether the descriptor for `onion_addr` (`<56 chars>.onion`) can be
+/// found. `Err` means the question couldn't be asked: a bad address, or no
+/// usable directory.
+#[cfg(feature = "onion")]
+pub async fn descriptor_found(
+    client: &TorClient<PreferredRuntime>,
+    config: &TorClientConfig,
+    onion_addr: &str,
+) -> Result<bool, String> {
+    let hsid: HsId = onion_addr
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid onion address: {}", e))?;
+    let netdir = client
+        .dirmgr()
+        .timely_netdir()
+        .map_err(|e| format!("No usable directory: {}", e))?;
+    // A connector of our own, so nothing it learns is reused for streams;
+    // its housekeeping task ends with the empty prompt stream
+    let connector = HsClientConnector::new(
+        client.runtime().clone(),
+        Arc::clone(client.hs_circ_pool()),
+        config,
+        futures::stream::empty().boxed(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    let result = connector
+        .get_or_launch_tunnel(
+            &netdir,
+            hsid,
+            HsClientSecretKeys::default(),
+            StreamIsolation::no_isolation(),
+        )
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        // The descriptor was fetched, but the service didn't answer
+        Err(ConnError::Failed(_) | ConnError::NoUsableIntroPoints) => Ok(true),
+        Err(e @ ConnError::DescriptorDownload(_)) => {
+            tracing::debug!("Onion service descriptor not found: {}", e);
+            Ok(false)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(not(feature = "onion"))]
+pub async fn descriptor_found(
+    client: &TorClient<PreferredRuntime>,
+    config: &TorClientConfig,
+    onion_addr: &str,
+) -> Result<bool, String> {
+    let _ = (client, config, onion_addr);
+    Err(NOT_COMPILED_IN.to_owned())
+}
+
+/// Open a stream to `host`:`port` and drop it at once, leaving arti with
+/// a rendezvous circuit to the service that it reuses for the next stream
+/// there. The stream is isolated like a SOCKS stream on the main port
+/// without credentials, so that stream is the one that gets the circuit.
+pub async fn prewarm(
+    client: &TorClient<PreferredRuntime>,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    if !host.to_ascii_lowercase().ends_with(".onion") {
+        return Err(format!("{} is not an onion address", host));
+    }
+    if cfg!(not(feature = "onion")) {
+        return Err(NOT_COMPILED_IN.to_owned());
+    }
+    let target = (host, port)
+        .into_tor_addr()
+        .map_err(|e| format!("Invalid onion address: {}", e))?;
+    let mut prefs = StreamPrefs::new();
+    let token = isolation::default_token();
+    match isolation::flags() {
+        0 => {
+            if let Some(token) = token {
+                prefs.set_isolation(token);
+            }
+        }
+        flags => {
+            prefs.set_isolation(isolation::StreamKey::new(flags, token, host, port, None));
+        }
+    }
+    client
+        .connect_with_prefs(target, &prefs)
+        .await
+        .map(drop)
+        .map_err(|e| format!("Cannot reach {}: {}", host, e))
+}
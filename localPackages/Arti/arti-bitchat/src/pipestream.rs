@@ -0,0 +1,138 @@
+//! Tor streams handed to the app as a pair of pipes
+//!
+//! For integrations that want one file descriptor per direction: the app
+//! reads what the destination sends from one pipe and writes what it
+//! sends to the other. Each direction ends on its own, so closing the
+//! write end half-closes the stream while replies keep arriving.
+
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::unix::pipe;
+
+use crate::sessions;
+
+/// The app's ends: it reads from `read` and writes to `write`.
+pub struct AppEnds {
+    pub read: OwnedFd,
+    pub write: OwnedFd,
+}
+
+/// The relay's ends of the same two pipes.
+pub struct RelayEnds {
+    to_app: pipe::Sender,
+    from_app: pipe::Receiver,
+}
+
+/// Create the two pipes. Must be called within the tokio runtime.
+pub fn open() -> io::Result<(AppEnds, RelayEnds)> {
+    let (app_read, to_app) = raw_pipe()?;
+    let (from_app, app_write) = raw_pipe()?;
+    #[cfg(target_vendor = "apple")]
+    {
+        // Writing to a pipe the app has closed must fail with EPIPE, not
+        // kill the app with SIGPIPE
+        use std::os::fd::AsRawFd;
+        if unsafe { libc::fcntl(to_app.as_raw_fd(), libc::F_SETNOSIGPIPE, 1) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let relay = RelayEnds {
+        to_app: pipe::Sender::from_owned_fd(to_app)?,
+        from_app: pipe::Receiver::from_owned_fd(from_app)?,
+    };
+    Ok((
+        AppEnds {
+            read: app_read,
+            write: app_write,
+        },
+        relay,
+    ))
+}
+
+/// A close-on-exec pipe, as (read end, write end).
+fn raw_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for fd in [fds[0], fds[1]] {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((read, write))
+}
+
+/// Relay `stream` to and from the app's pipes until both directions have
+/// ended or the app closes the session, returning the bytes sent and
+/// received.
+pub async fn relay<S>(stream: S, ends: RelayEnds, target: String) -> io::Result<(u64, u64)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let session = sessions::register(target, Some("pipes".to_owned()));
+    let RelayEnds {
+        mut to_app,
+        mut from_app,
+    } = ends;
+    let (mut tor_read, mut tor_write) = tokio::io::split(stream);
+    let upstream = async {
+        let sent = tokio::io::copy(&mut from_app, &mut tor_write).await?;
+        tor_write.shutdown().await?;
+        Ok::<_, io::Error>(sent)
+    };
+    let downstream = async move {
+        let received = tokio::io::copy(&mut tor_read, &mut to_app).await?;
+        // Dropping the write end is what gives the app its EOF
+        drop(to_app);
+        Ok::<_, io::Error>(received)
+    };
+    tokio::select! {
+        result = async { tokio::try_join!(upstream, downstream) } => result,
+        _ = session.closed() => {
+            Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Closed by the app"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[tokio::test]
+    async fn each_direction_closes_on_its_own() {
+        let (near, far) = tokio::io::duplex(1024);
+        let (app, ends) = open().unwrap();
+        let relay = tokio::spawn(relay(near, ends, "example.com:80".to_owned()));
+
+        // The far side answers, then waits for the app to finish writing
+        let peer = tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+            let (mut r, mut w) = tokio::io::split(far);
+            w.write_all(b"hello").await.unwrap();
+            w.shutdown().await.unwrap();
+            let mut request = Vec::new();
+            r.read_to_end(&mut request).await.unwrap();
+            request
+        });
+
+        let (mut read, mut write) = (std::fs::File::from(app.read), std::fs::File::from(app.write));
+        let reply = tokio::task::spawn_blocking(move || {
+            let mut reply = Vec::new();
+            read.read_to_end(&mut reply).unwrap();
+            // Still writable after the reply ended
+            write.write_all(b"bye").unwrap();
+            reply
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(reply, b"hello");
+        assert_eq!(peer.await.unwrap(), b"bye");
+        assert_eq!(relay.await.unwrap().unwrap(), (3, 5));
+    }
+}
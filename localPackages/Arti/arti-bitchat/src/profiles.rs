@@ -0,0 +1,128 @@
+//! Named connection profiles
+//!
+//! A profile is a settings document (see `config::Settings`) saved under
+//! `<data dir>/profiles/<name>.toml`, so an app can keep several setups,
+//! say bridges at work and a direct connection at home, and start with
+//! one by name. Documents are validated when saved, so starting a profile
+//! only fails if its file was damaged or edited by hand since.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Settings;
+
+/// Longest profile name accepted
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Why a profile could not be saved or loaded.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// Empty, too long, or not made of letters, digits, `-` and `_`
+    InvalidName,
+    /// The settings document does not parse
+    Invalid(String),
+    /// Reading or writing the file failed
+    Io(io::Error),
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::InvalidName => write!(
+                f,
+                "profile names are 1-{} letters, digits, '-' or '_'",
+                MAX_NAME_LEN
+            ),
+            ProfileError::Invalid(e) => write!(f, "invalid profile: {}", e),
+            ProfileError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("profiles")
+}
+
+/// Whether `name` can be used as a profile name. The restriction keeps
+/// names usable as file names everywhere, with no path separators.
+pub fn valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LEN).contains(&name.len())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn path(data_dir: &Path, name: &str) -> Result<PathBuf, ProfileError> {
+    if !valid_name(name) {
+        return Err(ProfileError::InvalidName);
+    }
+    Ok(dir(data_dir).join(format!("{}.toml", name)))
+}
+
+/// Validate `text` and save it as profile `name`, replacing any profile
+/// of that name. The file is replaced in one rename, so a crash midway
+/// leaves the old profile intact.
+pub fn save(data_dir: &Path, name: &str, text: &str) -> Result<(), ProfileError> {
+    let path = path(data_dir, name)?;
+    Settings::parse(text).map_err(ProfileError::Invalid)?;
+    fs::create_dir_all(dir(data_dir)).map_err(ProfileError::Io)?;
+    let staging = path.with_extension("toml.tmp");
+    let written = fs::File::create(&staging)
+        .and_then(|mut file| file.write_all(text.as_bytes()).and_then(|()| file.sync_all()))
+        .and_then(|()| fs::rename(&staging, &path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&staging);
+        return Err(ProfileError::Io(e));
+    }
+    Ok(())
+}
+
+/// Read and parse profile `name`.
+pub fn load(data_dir: &Path, name: &str) -> Result<Settings, ProfileError> {
+    let text = fs::read_to_string(path(data_dir, name)?).map_err(ProfileError::Io)?;
+    Settings::parse(&text).map_err(ProfileError::Invalid)
+}
+
+/// Names of the saved profiles, sorted. No profile directory means no
+/// profiles.
+pub fn list(data_dir: &Path) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(dir(data_dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let file_name = entry?.file_name();
+        let name = file_name.to_str().and_then(|n| n.strip_suffix(".toml"));
+        if let Some(name) = name.filter(|n| valid_name(n)) {
+            names.push(name.to_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_validated_saved_and_listed() {
+        let data_dir = std::env::temp_dir().join(format!("arti-profiles-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        assert!(list(&data_dir).unwrap().is_empty());
+
+        save(&data_dir, "work", "[guards]\nnum_primary = 2\n").unwrap();
+        save(&data_dir, "home", "[channel]\npadding = \"reduced\"\n").unwrap();
+        assert!(matches!(
+            save(&data_dir, "broken", "[guards]\nnum_primry = 2\n"),
+            Err(ProfileError::Invalid(_))
+        ));
+        assert!(matches!(save(&data_dir, "../escape", ""), Err(ProfileError::InvalidName)));
+        assert_eq!(list(&data_dir).unwrap(), vec!["home", "work"]);
+
+        assert_eq!(load(&data_dir, "work").unwrap().num_guards, Some(2));
+        assert!(matches!(load(&data_dir, "missing"), Err(ProfileError::Io(_))));
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+}
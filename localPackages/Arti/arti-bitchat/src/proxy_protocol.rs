@@ -0,0 +1,160 @@
+//! HAProxy PROXY protocol header parsing
+//!
+//! A local forwarder in front of the SOCKS port can prepend a PROXY protocol
+//! header (v1 text or v2 binary) carrying the original client address. When
+//! enabled, the header is consumed before SOCKS negotiation begins.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The 12-byte signature that starts every v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest possible v1 header, including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+
+const V2_CMD_LOCAL: u8 = 0x0;
+const V2_CMD_PROXY: u8 = 0x1;
+const V2_FAM_TCP4: u8 = 0x11;
+const V2_FAM_TCP6: u8 = 0x21;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("PROXY protocol: {}", msg))
+}
+
+/// Read a PROXY protocol header from the start of `stream`.
+///
+/// Returns the original source address, or `None` when the header doesn't
+/// carry one (v1 `UNKNOWN`, v2 `LOCAL` or a non-TCP family). Fails if the
+/// stream doesn't start with a well-formed header.
+pub async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut first = [0u8; 1];
+    stream.read_exact(&mut first).await?;
+    match first[0] {
+        b'P' => read_v1(stream).await,
+        b'\r' => read_v2(stream).await,
+        _ => Err(invalid("missing header")),
+    }
+}
+
+/// Read the rest of a v1 header (after its leading `P`).
+async fn read_v1<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = vec![b'P'];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("v1 not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src, _dst, sport, _dport] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("v1 bad source address"))?;
+            if ip.is_ipv4() != (*proto == "TCP4") {
+                return Err(invalid("v1 address family mismatch"));
+            }
+            let port: u16 = sport.parse().map_err(|_| invalid("v1 bad source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+/// Read the rest of a v2 header (after the first signature byte).
+async fn read_v2<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 15];
+    stream.read_exact(&mut header).await?;
+    if header[..11] != V2_SIGNATURE[1..] {
+        return Err(invalid("bad v2 signature"));
+    }
+
+    let ver_cmd = header[11];
+    let family = header[12];
+    let len = u16::from_be_bytes([header[13], header[14]]) as usize;
+    if ver_cmd >> 4 != 0x2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+
+    // Always consume the whole address block, including any TLVs
+    let mut addrs = vec![0u8; len];
+    stream.read_exact(&mut addrs).await?;
+
+    match ver_cmd & 0x0F {
+        V2_CMD_LOCAL => return Ok(None),
+        V2_CMD_PROXY => {}
+        _ => return Err(invalid("unsupported v2 command")),
+    }
+
+    match family {
+        V2_FAM_TCP4 if len >= 12 => {
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        V2_FAM_TCP6 if len >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addrs[..16]);
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        V2_FAM_TCP4 | V2_FAM_TCP6 => Err(invalid("v2 address block too short")),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(bytes: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let mut input = bytes;
+        read_header(&mut input).await
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let addr = parse(b"PROXY TCP4 192.0.2.1 127.0.0.1 5555 9050\r\n").await.unwrap();
+        assert_eq!(addr, Some("192.0.2.1:5555".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6_and_unknown() {
+        let addr = parse(b"PROXY TCP6 2001:db8::1 ::1 5555 9050\r\n").await.unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:5555".parse().unwrap()));
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v1_rejects_malformed() {
+        assert!(parse(b"PROXY TCP4 192.0.2.1\r\n").await.is_err());
+        assert!(parse(b"PROXY TCP4 2001:db8::1 ::1 1 2\r\n").await.is_err());
+        assert!(parse(&[b'P'; 200]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn v2_local_has_no_address() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(parse(&header).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_header() {
+        assert!(parse(&[0x05, 0x01, 0x00]).await.is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Retrying with jittered exponential backoff
+//!
+//! Used around bootstrap: right after a network change the first guard
+//! connection often fails for reasons that are gone a moment later. The
+//! jitter keeps many devices that lost the network together from retrying
+//! in lockstep.
+
+use std::future::Future;
+use std::time::Duration;
+
+/// How long to wait between attempts.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    /// Delay before the second attempt; doubled for each one after
+    pub initial_delay: Duration,
+    /// Upper bound on any one delay, before jitter
+    pub max_delay: Duration,
+}
+
+/// Bootstrap retries: 1s, 2s, 4s, ... capped at 30s, each ±50%.
+pub const BOOTSTRAP_BACKOFF: Backoff = Backoff {
+    initial_delay: Duration::from_secs(1),
+    max_delay: Duration::from_secs(30),
+};
+
+impl Backoff {
+    /// The delay after failed attempt `attempt` (1-based), scaled by
+    /// `jitter` (expected in `0.5..1.5`).
+    pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(16);
+        let base = self
+            .initial_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay);
+        base.mul_f64(jitter)
+    }
+}
+
+/// Run `attempt` (given the 1-based attempt number) until it succeeds,
+/// `retry` says an error isn't worth retrying, or `retries` retries have
+/// failed. Returns the last result.
+pub async fn with_retries<T, E, F, Fut>(
+    retries: u32,
+    backoff: Backoff,
+    mut attempt: F,
+    retry: impl Fn(&E) -> bool,
+) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut number = 1;
+    loop {
+        match attempt(number).await {
+            Err(e) if number <= retries && retry(&e) => {
+                let delay = backoff.delay(number, rand::random_range(0.5..1.5));
+                tracing::debug!("Attempt {} failed; retrying in {:?}", number, delay);
+                tokio::time::sleep(delay).await;
+                number += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Backoff = Backoff {
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(4),
+    };
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let delays: Vec<_> = (1..=7).map(|n| BOOTSTRAP_BACKOFF.delay(n, 1.0).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(BOOTSTRAP_BACKOFF.delay(2, 0.5), Duration::from_secs(1));
+        assert_eq!(BOOTSTRAP_BACKOFF.delay(u32::MAX, 1.0), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn honors_the_retry_count() {
+        for retries in [0, 1, 3] {
+            let mut calls = 0;
+            let result: Result<(), u32> = with_retries(
+                retries,
+                FAST,
+                |n| {
+                    calls += 1;
+                    async move { Err(n) }
+                },
+                |_| true,
+            )
+            .await;
+            assert_eq!(calls, retries + 1);
+            assert_eq!(result, Err(retries + 1));
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_on_success_or_unretryable_error() {
+        let mut calls = 0;
+        let result = with_retries(
+            5,
+            FAST,
+            |n| {
+                calls += 1;
+                async move { if n == 2 { Ok(n) } else { Err(n) } }
+            },
+            |_| true,
+        )
+        .await;
+        assert_eq!((result, calls), (Ok(2), 2));
+
+        let mut calls = 0;
+        let result: Result<(), &str> = with_retries(
+            5,
+            FAST,
+            |_| {
+                calls += 1;
+                async { Err("fatal") }
+            },
+            |e| *e != "fatal",
+        )
+        .await;
+        assert_eq!((result, calls), (Err("fatal"), 1));
+    }
+}
@@ -0,0 +1,137 @@
+//! Registry of SOCKS sessions being relayed
+//!
+//! Each relayed connection registers itself under a small index that the
+//! app can list and use to close that one connection. Entries remove
+//! themselves when the relay ends, however it ends.
+
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tokio::sync::Notify;
+
+use crate::json;
+
+static SESSIONS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+static NEXT_INDEX: AtomicI32 = AtomicI32::new(1);
+
+struct Entry {
+    index: c_int,
+    target: String,
+    label: Option<String>,
+    started: Instant,
+    close: Arc<Notify>,
+}
+
+/// A registered session; unregisters on drop.
+pub struct Session {
+    index: c_int,
+    close: Arc<Notify>,
+}
+
+impl Session {
+    /// The index listed for this session.
+    pub fn index(&self) -> c_int {
+        self.index
+    }
+
+    /// Resolves once the app has asked for this session to be closed.
+    pub async fn closed(&self) {
+        self.close.notified().await
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            sessions.retain(|entry| entry.index != self.index);
+        }
+    }
+}
+
+/// Register a session relaying to `target`.
+pub fn register(target: String, label: Option<String>) -> Session {
+    let index = NEXT_INDEX
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            Some(if n == c_int::MAX { 1 } else { n + 1 })
+        })
+        .unwrap_or(1);
+    let close = Arc::new(Notify::new());
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.push(Entry {
+            index,
+            target,
+            label,
+            started: Instant::now(),
+            close: close.clone(),
+        });
+    }
+    Session { index, close }
+}
+
+/// Ask the session with `index` to close; false if there is none.
+///
+/// Only that session's relay stops: its Tor stream ends on its own, and
+/// the circuit and any other streams on it carry on.
+pub fn close(index: c_int) -> bool {
+    let Ok(sessions) = SESSIONS.lock() else {
+        return false;
+    };
+    match sessions.iter().find(|entry| entry.index == index) {
+        Some(entry) => {
+            // Stores a permit, so a relay that hasn't started waiting yet
+            // still sees it
+            entry.close.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+/// The current sessions as a JSON array, oldest first.
+pub fn list_json() -> String {
+    let entries: Vec<String> = SESSIONS
+        .lock()
+        .map(|sessions| {
+            sessions
+                .iter()
+                .map(|entry| {
+                    json::Object::new()
+                        .num("index", entry.index)
+                        .str("target", &entry.target)
+                        .opt_str("label", entry.label.as_deref())
+                        .num("age_ms", entry.started.elapsed().as_millis())
+                        .finish()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    json::array(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn close_reaches_only_its_session() {
+        let first = register("example.com:443".into(), Some("sessions-test".into()));
+        let second = register("example.org:80".into(), None);
+        assert_ne!(first.index(), second.index());
+        assert!(list_json().contains(&format!(
+            r#"{{"index":{},"target":"example.com:443","label":"sessions-test","#,
+            first.index()
+        )));
+
+        assert!(close(first.index()));
+        first.closed().await;
+        let pending = tokio::time::timeout(std::time::Duration::from_millis(20), second.closed());
+        assert!(pending.await.is_err());
+
+        let index = first.index();
+        drop(first);
+        assert!(!close(index));
+        assert!(!list_json().contains(&format!(r#""index":{},"#, index)));
+    }
+}
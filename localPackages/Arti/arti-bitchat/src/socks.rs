@@ -1,33 +1,799 @@
 //! SOCKS5 protocol handler for Arti
 //!
 //! Implements a minimal SOCKS5 server that forwards connections through Tor.
+//! Besides CONNECT it supports Tor's RESOLVE and RESOLVE_PTR extensions, and
+//! username/password auth with any credentials (used only for isolation).
 
 use std::io;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{
+    AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use arti_client::{TorClient, IntoTorAddr};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tor_rtcompat::PreferredRuntime;
+use arti_client::{IntoTorAddr, IsolationToken, StreamPrefs};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::watch;
+use tracing::Instrument;
+
+use crate::connector::{PendingMode, StreamConnector};
+use crate::{callbacks, circuit, isolation, json, proxy_protocol, sessions};
+
+/// Log per-connection detail at debug level unless `CONNECTION_LOGGING`
+/// is off. The flag is checked before the message is formatted, or even
+/// offered to the subscriber.
+macro_rules! connection_debug {
+    ($($arg:tt)*) => {
+        if CONNECTION_LOGGING.load(Ordering::Relaxed) {
+            tracing::debug!($($arg)*);
+        }
+    };
+}
 
 // SOCKS5 constants
 const SOCKS5_VERSION: u8 = 0x05;
 const SOCKS5_AUTH_NONE: u8 = 0x00;
+const SOCKS5_AUTH_USERPASS: u8 = 0x02;
+const USERPASS_VERSION: u8 = 0x01;
 const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_CMD_RESOLVE: u8 = 0xF0;
+const SOCKS5_CMD_RESOLVE_PTR: u8 = 0xF1;
 const SOCKS5_ATYP_IPV4: u8 = 0x01;
 const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
 const SOCKS5_ATYP_IPV6: u8 = 0x04;
 const SOCKS5_REP_SUCCESS: u8 = 0x00;
 const SOCKS5_REP_FAILURE: u8 = 0x01;
+const SOCKS5_REP_NOT_ALLOWED: u8 = 0x02;
+const SOCKS5_REP_NETWORK_UNREACHABLE: u8 = 0x03;
+const SOCKS5_REP_HOST_UNREACHABLE: u8 = 0x04;
 const SOCKS5_REP_CONN_REFUSED: u8 = 0x05;
+const SOCKS5_REP_TTL_EXPIRED: u8 = 0x06;
+#[cfg(not(feature = "onion"))]
+const SOCKS5_REP_ADDR_NOT_SUPPORTED: u8 = 0x08;
+
+/// Per-direction buffer used when relaying a connection, in bytes
+pub static RELAY_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_RELAY_BUFFER_SIZE);
+pub const DEFAULT_RELAY_BUFFER_SIZE: usize = 16 * 1024;
+/// Bounds accepted by `arti_set_relay_buffer_size`
+pub const MIN_RELAY_BUFFER_SIZE: usize = 4 * 1024;
+pub const MAX_RELAY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Maximum number of concurrently relayed connections (0 = unlimited)
+pub static MAX_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Bumped by `begin_shutdown`; handlers watch it for changes
+static SHUTDOWN: OnceLock<watch::Sender<u64>> = OnceLock::new();
+/// Time a handshake in progress at shutdown gets to finish before it is
+/// answered anyway
+pub const SHUTDOWN_GRACE: Duration = Duration::from_millis(100);
+
+fn shutdown_sender() -> &'static watch::Sender<u64> {
+    SHUTDOWN.get_or_init(|| watch::channel(0).0)
+}
+
+/// Tell connections accepted so far that Arti is stopping: handshakes
+/// still in progress are answered with a failure rather than opened, and
+/// connections already relaying are left alone.
+pub fn begin_shutdown() {
+    shutdown_sender().send_modify(|generation| *generation += 1);
+}
+
+/// Log each SOCKS connection's progress and errors at debug level
+pub static CONNECTION_LOGGING: AtomicBool = AtomicBool::new(true);
+
+/// Label for the next accepted connection, consumed on accept
+static NEXT_LABEL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Tag the next connection accepted on any SOCKS listener with `label`.
+pub fn set_next_label(label: Option<String>) {
+    if let Ok(mut next) = NEXT_LABEL.lock() {
+        *next = label;
+    }
+}
+
+fn take_next_label() -> Option<String> {
+    NEXT_LABEL.lock().ok()?.take()
+}
+
+/// Expect a PROXY protocol header before the SOCKS greeting
+pub static PROXY_PROTOCOL: AtomicBool = AtomicBool::new(false);
+
+/// Destination ports CONNECT may reach (empty = any)
+static ALLOWED_PORTS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+/// Restrict CONNECT to `ports`; an empty list allows every port.
+pub fn set_allowed_ports(mut ports: Vec<u16>) {
+    ports.sort_unstable();
+    ports.dedup();
+    if let Ok(mut allowed) = ALLOWED_PORTS.lock() {
+        *allowed = ports;
+    }
+}
+
+/// Domains that requests may name (empty = any), lowercase without a
+/// trailing dot
+static TOR_ONLY_DOMAINS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Restrict CONNECT and RESOLVE to `domains` and their subdomains; an
+/// empty list allows every destination.
+pub fn set_tor_only_domains(domains: Vec<String>) {
+    let mut domains: Vec<String> = domains.iter().map(|d| normalize_host(d)).collect();
+    domains.sort_unstable();
+    domains.dedup();
+    if let Ok(mut listed) = TOR_ONLY_DOMAINS.lock() {
+        *listed = domains;
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase()
+}
+
+/// Time allowed for a CONNECT to a clearnet target, in milliseconds
+/// (0 = only arti's own timeouts)
+pub static CONNECT_TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
+/// Time allowed for a CONNECT to a `.onion` target, in milliseconds
+/// (0 = only arti's own timeouts)
+pub static ONION_CONNECT_TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Idle time after which a relayed stream's circuit is sent a keepalive,
+/// in seconds (0 = never)
+pub static STREAM_KEEPALIVE_SECS: AtomicU32 = AtomicU32::new(0);
+
+/// Refuse CONNECT at once whenever Tor is not ready for traffic
+pub static FAIL_CLOSED: AtomicBool = AtomicBool::new(false);
+/// Whether Tor is bootstrapped and ready for traffic, as last reported
+static TOR_READY: AtomicBool = AtomicBool::new(false);
+/// Held by tests that change `TOR_READY`
+#[cfg(test)]
+pub static READY_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Set SO_REUSEADDR on listeners before binding them
+pub static REUSE_ADDR: AtomicBool = AtomicBool::new(DEFAULT_REUSE_ADDR);
+/// On Windows SO_REUSEADDR lets another socket take over a bound port,
+/// so there it stays off unless asked for
+pub const DEFAULT_REUSE_ADDR: bool = cfg!(not(windows));
+/// Pending connections a listener queues before refusing more
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// Record whether Tor is ready for traffic, for [`FAIL_CLOSED`].
+pub fn set_ready(ready: bool) {
+    TOR_READY.store(ready, Ordering::SeqCst);
+}
+
+/// What the SOCKS port does before bootstrap finishes
+pub static PREBOOTSTRAP_BEHAVIOR: AtomicI32 = AtomicI32::new(PREBOOTSTRAP_CLOSED);
+/// Not listening until bootstrapped
+pub const PREBOOTSTRAP_CLOSED: i32 = 0;
+/// Listening; requests wait for bootstrap
+pub const PREBOOTSTRAP_QUEUE: i32 = 1;
+/// Listening; requests fail with a general failure
+pub const PREBOOTSTRAP_REJECT: i32 = 2;
+/// Listening; requests fail with network unreachable
+pub const PREBOOTSTRAP_FAIL_FAST: i32 = 3;
+
+/// The early-listener mode for the configured pre-bootstrap behavior, or
+/// `None` if the port should only open once bootstrapped.
+pub fn prebootstrap_mode() -> Option<PendingMode> {
+    match PREBOOTSTRAP_BEHAVIOR.load(Ordering::SeqCst) {
+        PREBOOTSTRAP_QUEUE => Some(PendingMode::Queue),
+        PREBOOTSTRAP_REJECT => Some(PendingMode::Reject),
+        PREBOOTSTRAP_FAIL_FAST => Some(PendingMode::FailFast),
+        _ => None,
+    }
+}
+
+/// How recent relayed traffic must be to count as active, in milliseconds
+pub static ACTIVITY_WINDOW_MS: AtomicU32 = AtomicU32::new(DEFAULT_ACTIVITY_WINDOW_MS);
+pub const DEFAULT_ACTIVITY_WINDOW_MS: u32 = 1000;
+
+/// When bytes last moved on any stream, as milliseconds since
+/// `ACTIVITY_EPOCH` plus one (0 = never)
+static LAST_ACTIVITY: AtomicU64 = AtomicU64::new(0);
+static ACTIVITY_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+fn activity_clock() -> u64 {
+    let epoch = ACTIVITY_EPOCH.get_or_init(Instant::now);
+    epoch.elapsed().as_millis() as u64 + 1
+}
+
+/// Record that bytes just moved on a relayed stream.
+fn note_activity() {
+    LAST_ACTIVITY.store(activity_clock(), Ordering::Relaxed);
+}
+
+/// Bytes relayed to and from Tor over every SOCKS stream since the last
+/// reset
+static BYTES_TO_TOR: AtomicU64 = AtomicU64::new(0);
+static BYTES_FROM_TOR: AtomicU64 = AtomicU64::new(0);
+
+/// Total bytes relayed (to Tor, from Tor).
+pub fn traffic_totals() -> (u64, u64) {
+    (BYTES_TO_TOR.load(Ordering::Relaxed), BYTES_FROM_TOR.load(Ordering::Relaxed))
+}
+
+/// Whether bytes moved on any relayed stream within the activity window.
+pub fn is_active() -> bool {
+    let window = ACTIVITY_WINDOW_MS.load(Ordering::Relaxed).into();
+    within_window(LAST_ACTIVITY.load(Ordering::Relaxed), activity_clock(), window)
+}
+
+/// Time since bytes last moved on any relayed stream, or `None` if none
+/// have since the last reset.
+pub fn since_activity() -> Option<Duration> {
+    match LAST_ACTIVITY.load(Ordering::Relaxed) {
+        0 => None,
+        last => Some(Duration::from_millis(activity_clock().saturating_sub(last))),
+    }
+}
+
+fn within_window(last: u64, now: u64, window: u64) -> bool {
+    last != 0 && now.saturating_sub(last) <= window
+}
+
+/// Restore the listener settings to their defaults.
+pub fn reset() {
+    MAX_CONNECTIONS.store(0, Ordering::SeqCst);
+    PROXY_PROTOCOL.store(false, Ordering::SeqCst);
+    CONNECT_TIMEOUT_MS.store(0, Ordering::SeqCst);
+    ONION_CONNECT_TIMEOUT_MS.store(0, Ordering::SeqCst);
+    ACTIVITY_WINDOW_MS.store(DEFAULT_ACTIVITY_WINDOW_MS, Ordering::SeqCst);
+    RELAY_BUFFER_SIZE.store(DEFAULT_RELAY_BUFFER_SIZE, Ordering::SeqCst);
+    PREBOOTSTRAP_BEHAVIOR.store(PREBOOTSTRAP_CLOSED, Ordering::SeqCst);
+    STREAM_KEEPALIVE_SECS.store(0, Ordering::SeqCst);
+    FAIL_CLOSED.store(false, Ordering::SeqCst);
+    CONNECTION_LOGGING.store(true, Ordering::SeqCst);
+    TOR_READY.store(false, Ordering::SeqCst);
+    REUSE_ADDR.store(DEFAULT_REUSE_ADDR, Ordering::SeqCst);
+    LAST_ACTIVITY.store(0, Ordering::SeqCst);
+    BYTES_TO_TOR.store(0, Ordering::SeqCst);
+    BYTES_FROM_TOR.store(0, Ordering::SeqCst);
+    set_next_label(None);
+    set_allowed_ports(Vec::new());
+    set_tor_only_domains(Vec::new());
+    if let Ok(mut probes) = PROBES.lock() {
+        probes.clear();
+    }
+}
+
+/// Per-connection settings, captured when the connection is accepted.
+#[derive(Clone, Debug, Default)]
+pub struct HandlerOptions {
+    /// Expect a PROXY protocol header before the SOCKS greeting
+    pub proxy_protocol: bool,
+    /// Destination ports CONNECT may reach (empty = any)
+    pub allowed_ports: Arc<[u16]>,
+    /// Domains CONNECT and RESOLVE may name, with their subdomains
+    /// (empty = any)
+    pub tor_only_domains: Arc<[String]>,
+    /// Time allowed for a CONNECT to a clearnet target
+    pub connect_timeout: Option<Duration>,
+    /// Time allowed for a CONNECT to a `.onion` target
+    pub onion_connect_timeout: Option<Duration>,
+    /// Label from `set_next_label`, if this connection took one
+    pub label: Option<String>,
+    /// Per-direction relay buffer in bytes (0 = the default)
+    pub relay_buffer_size: usize,
+    /// Changes when Arti starts shutting down (`None` = never)
+    pub shutdown: Option<watch::Receiver<u64>>,
+    /// Idle time after which the stream's circuit gets a keepalive
+    pub keepalive: Option<Duration>,
+    /// Refuse CONNECT while Tor is not ready rather than waiting for it
+    pub fail_closed: bool,
+}
+
+impl HandlerOptions {
+    /// The options currently configured through the FFI.
+    pub fn current() -> Self {
+        HandlerOptions {
+            proxy_protocol: PROXY_PROTOCOL.load(Ordering::SeqCst),
+            allowed_ports: ALLOWED_PORTS.lock().map(|p| p.as_slice().into()).unwrap_or_default(),
+            tor_only_domains: TOR_ONLY_DOMAINS
+                .lock()
+                .map(|d| d.as_slice().into())
+                .unwrap_or_default(),
+            connect_timeout: timeout_from_ms(&CONNECT_TIMEOUT_MS),
+            onion_connect_timeout: timeout_from_ms(&ONION_CONNECT_TIMEOUT_MS),
+            label: None,
+            relay_buffer_size: RELAY_BUFFER_SIZE.load(Ordering::SeqCst),
+            shutdown: Some(shutdown_sender().subscribe()),
+            keepalive: match STREAM_KEEPALIVE_SECS.load(Ordering::SeqCst) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs.into())),
+            },
+            fail_closed: FAIL_CLOSED.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn relay_buffer_size(&self) -> usize {
+        match self.relay_buffer_size {
+            0 => DEFAULT_RELAY_BUFFER_SIZE,
+            size => size,
+        }
+    }
+
+    /// The CONNECT timeout for `host`: onion services take several extra
+    /// round trips (descriptor fetch, introduction, rendezvous) to reach.
+    pub fn connect_timeout_for(&self, host: &str) -> Option<Duration> {
+        if is_onion(host) {
+            self.onion_connect_timeout
+        } else {
+            self.connect_timeout
+        }
+    }
+
+    fn port_allowed(&self, port: u16) -> bool {
+        self.allowed_ports.is_empty() || self.allowed_ports.contains(&port)
+    }
+
+    fn host_allowed(&self, host: &str) -> bool {
+        if self.tor_only_domains.is_empty() {
+            return true;
+        }
+        let host = normalize_host(host);
+        self.tor_only_domains.iter().any(|domain| {
+            host.strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+        })
+    }
+}
+
+/// A stream refused by policy: the SOCKS reply it gets and the error it
+/// ends with.
+#[derive(Debug)]
+pub struct Refusal {
+    pub reply: u8,
+    pub error: io::Error,
+}
+
+impl Refusal {
+    fn new(reply: u8, kind: io::ErrorKind, message: impl Into<String>) -> Self {
+        Refusal { reply, error: io::Error::new(kind, message.into()) }
+    }
+}
+
+/// Check a stream to `host:port` against the policy every outbound stream
+/// gets, SOCKS CONNECT or not: the Tor-only domains, the allowed ports,
+/// the connection filter and fail closed, in that order.
+pub async fn check_connect(host: &str, port: u16, options: &HandlerOptions) -> Result<(), Refusal> {
+    if !options.host_allowed(host) {
+        tracing::info!("Rejecting request for a host outside the Tor-only domains");
+        return Err(Refusal::new(
+            SOCKS5_REP_NOT_ALLOWED,
+            io::ErrorKind::PermissionDenied,
+            "Host is not one of the Tor-only domains",
+        ));
+    }
+    if !options.port_allowed(port) {
+        tracing::info!("Rejecting CONNECT to disallowed port {}", port);
+        return Err(Refusal::new(
+            SOCKS5_REP_NOT_ALLOWED,
+            io::ErrorKind::PermissionDenied,
+            format!("Port {} is not allowed", port),
+        ));
+    }
+    if callbacks::connection_blocked(host, port).await {
+        tracing::info!("Connection filter refused CONNECT to port {}", port);
+        return Err(Refusal::new(
+            SOCKS5_REP_NOT_ALLOWED,
+            io::ErrorKind::PermissionDenied,
+            "Refused by the connection filter",
+        ));
+    }
+    if options.fail_closed && !TOR_READY.load(Ordering::SeqCst) {
+        tracing::info!("Refusing CONNECT while Tor is not ready (fail closed)");
+        return Err(Refusal::new(
+            SOCKS5_REP_NETWORK_UNREACHABLE,
+            io::ErrorKind::NetworkUnreachable,
+            "Tor is not ready",
+        ));
+    }
+    Ok(())
+}
+
+/// Preferences isolating a stream to `host:port` the way every outbound
+/// stream is: by `isolation` alone, or by the key the flags from
+/// `isolation::flags` make of it, the target and `credentials`.
+pub fn stream_prefs(
+    isolation: Option<IsolationToken>,
+    host: &str,
+    port: u16,
+    credentials: Option<(Vec<u8>, Vec<u8>)>,
+) -> StreamPrefs {
+    let mut prefs = StreamPrefs::new();
+    match isolation::flags() {
+        0 => {
+            if let Some(token) = isolation {
+                prefs.set_isolation(token);
+            }
+        }
+        flags => {
+            let key = isolation::StreamKey::new(flags, isolation, host, port, credentials);
+            prefs.set_isolation(key);
+        }
+    }
+    prefs
+}
+
+fn timeout_from_ms(ms: &AtomicU32) -> Option<Duration> {
+    match ms.load(Ordering::SeqCst) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms.into())),
+    }
+}
+
+/// Whether `host` names an onion service.
+pub fn is_onion(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    host.len() > ".onion".len()
+        && host[host.len() - ".onion".len()..].eq_ignore_ascii_case(".onion")
+}
+
+/// Number of connections currently being relayed.
+pub fn active_connections() -> usize {
+    ACTIVE_CONNECTIONS.load(Ordering::SeqCst)
+}
+
+/// A reserved slot under `MAX_CONNECTIONS`, released on drop.
+pub struct ConnectionSlot;
+
+impl ConnectionSlot {
+    pub fn acquire() -> Option<Self> {
+        let max = MAX_CONNECTIONS.load(Ordering::SeqCst);
+        let reserved = ACTIVE_CONNECTIONS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            (max == 0 || n < max).then_some(n + 1)
+        });
+        reserved.ok().map(|_| ConnectionSlot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Bind a listener on `addr`, with SO_REUSEADDR per `REUSE_ADDR` so that
+/// a port whose last connections linger in TIME_WAIT can be bound again
+/// at once. Must be called within the tokio runtime.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(REUSE_ADDR.load(Ordering::SeqCst))?;
+    socket.bind(addr)?;
+    socket.listen(LISTEN_BACKLOG)
+}
+
+/// Local addresses of `probe` connections not yet accepted
+static PROBES: Mutex<Vec<SocketAddr>> = Mutex::new(Vec::new());
+
+/// Check that the listener on `addr` accepts connections by connecting to
+/// it, giving up after `limit`. The accept loop in [`serve`] recognises the
+/// connection and closes it unhandled, so the probe takes no label and
+/// wakes nothing up.
+pub async fn probe(addr: SocketAddr, limit: Duration) -> io::Result<()> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let socket = if ip.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    // Bound first, so the accept loop knows it before it can see it
+    socket.bind(SocketAddr::new(ip, 0))?;
+    let local = socket.local_addr()?;
+    if let Ok(mut probes) = PROBES.lock() {
+        probes.push(local);
+    }
+    let connected = tokio::time::timeout(limit, socket.connect(SocketAddr::new(ip, addr.port())))
+        .await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "probe timed out")));
+    if connected.is_err() {
+        take_probe(local);
+    }
+    connected.map(drop)
+}
+
+/// Whether `peer` is a probe, forgetting it if so.
+fn take_probe(peer: SocketAddr) -> bool {
+    let Ok(mut probes) = PROBES.lock() else {
+        return false;
+    };
+    match probes.iter().position(|&probe| probe == peer) {
+        Some(i) => {
+            probes.swap_remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Accept SOCKS connections on `primary` (and `secondary`, if given) until
+/// the future is dropped, handling each connection on its own task.
+/// Without an `isolation` token, each connection uses the default token
+/// current when it is accepted.
+///
+/// Transient accept errors (an aborted handshake, running out of file
+/// descriptors) are logged and retried. Only returns, with the error, when
+/// accepting fails in a way that means the listener itself is broken.
+pub async fn serve(
+    primary: &TcpListener,
+    secondary: Option<&TcpListener>,
+    connector: Arc<dyn StreamConnector>,
+    isolation: Option<IsolationToken>,
+) -> io::Error {
+    loop {
+        match accept_either(primary, secondary).await {
+            Ok((_, peer_addr)) if take_probe(peer_addr) => {}
+            Ok((stream, peer_addr)) => {
+                crate::wake_on_connection();
+                let connector = connector.clone();
+                let isolation = isolation.or_else(isolation::default_token);
+                let options = HandlerOptions {
+                    label: take_next_label(),
+                    ..HandlerOptions::current()
+                };
+                // Everything logged for this connection carries its label
+                let span = match &options.label {
+                    Some(label) => tracing::info_span!("socks", label = %label),
+                    None => tracing::Span::none(),
+                };
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = handle_socks_connection(
+                            stream,
+                            peer_addr,
+                            connector,
+                            isolation,
+                            &options,
+                        )
+                        .await
+                        {
+                            connection_debug!("SOCKS connection error from {}: {}", peer_addr, e);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) if is_transient_accept_error(&e) => {
+                tracing::warn!("Accept error: {}", e);
+                if is_resource_exhaustion(&e) {
+                    // Give connections a chance to close rather than spinning
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                }
+            }
+            Err(e) => {
+                tracing::error!("SOCKS listener failed: {}", e);
+                return e;
+            }
+        }
+    }
+}
+
+/// Pause after an accept fails for lack of resources
+pub const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Errors from `accept` that concern one incoming connection, or a passing
+/// shortage, rather than the listening socket.
+pub fn is_transient_accept_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    ) || is_resource_exhaustion(e)
+}
+
+pub fn is_resource_exhaustion(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    )
+}
+
+/// How `supervise` retries binding a failed listener.
+#[derive(Clone, Copy, Debug)]
+pub struct RebindPolicy {
+    /// Rebind attempts before giving up
+    pub attempts: u32,
+    /// Delay before the first attempt; doubled for each one after
+    pub initial_delay: Duration,
+}
+
+/// Default policy: 5 attempts over about 3 seconds.
+pub const REBIND_POLICY: RebindPolicy = RebindPolicy {
+    attempts: 5,
+    initial_delay: Duration::from_millis(100),
+};
+
+/// Keep a listener served, rebinding it if it fails.
+///
+/// `serve_once` runs on `listeners` until it returns a fatal error; then
+/// `bind` is retried per `policy` and serving resumes on the new
+/// listeners. Returns the last bind error once every attempt has failed.
+pub async fn supervise<L, B, S, F>(
+    mut listeners: L,
+    mut bind: B,
+    mut serve_once: S,
+    policy: RebindPolicy,
+) -> io::Error
+where
+    B: FnMut() -> io::Result<L>,
+    S: FnMut(L) -> F,
+    F: std::future::Future<Output = io::Error>,
+{
+    'serving: loop {
+        let err = serve_once(listeners).await;
+        let mut last_err = err;
+        let mut delay = policy.initial_delay;
+        for attempt in 1..=policy.attempts {
+            tokio::time::sleep(delay).await;
+            match bind() {
+                Ok(rebound) => {
+                    tracing::info!("SOCKS listener rebound after attempt {}", attempt);
+                    listeners = rebound;
+                    continue 'serving;
+                }
+                Err(e) => {
+                    tracing::warn!("SOCKS rebind attempt {} failed: {}", attempt, e);
+                    last_err = e;
+                }
+            }
+            delay *= 2;
+        }
+        return last_err;
+    }
+}
+
+/// Accept from the primary listener or, if present, the secondary one.
+async fn accept_either(
+    primary: &TcpListener,
+    secondary: Option<&TcpListener>,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    match secondary {
+        Some(secondary) => tokio::select! {
+            r = primary.accept() => r,
+            r = secondary.accept() => r,
+        },
+        None => primary.accept().await,
+    }
+}
+
+/// How far a connection's handshake has got, so that a shutdown can
+/// answer it at the right point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Reading the greeting; no method chosen yet
+    Greeting = 0,
+    /// Username/password chosen, reading the credentials
+    Auth = 1,
+    /// Reading the request
+    Request = 2,
+    /// Request read, not yet answered
+    Connecting = 3,
+    /// Answered with success and relaying
+    Relaying = 4,
+}
+
+/// A handshake's phase, plus whether a shutdown has begun, shared between
+/// the handler and the task watching for shutdown.
+#[derive(Default)]
+struct Progress {
+    phase: AtomicU8,
+    stopping: AtomicBool,
+}
+
+impl Progress {
+    fn set(&self, phase: Phase) {
+        self.phase.store(phase as u8, Ordering::SeqCst);
+    }
+
+    fn phase(&self) -> Phase {
+        match self.phase.load(Ordering::SeqCst) {
+            0 => Phase::Greeting,
+            1 => Phase::Auth,
+            2 => Phase::Request,
+            3 => Phase::Connecting,
+            _ => Phase::Relaying,
+        }
+    }
+
+    fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+fn shutting_down() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Arti is shutting down")
+}
 
 /// Handle a single SOCKS5 connection
-pub async fn handle_socks_connection(
-    mut stream: TcpStream,
+///
+/// The outbound stream is opened through `connector` (normally the
+/// `TorClient`). If `isolation` is set, the stream is isolated with that
+/// token so it only shares circuits with other streams carrying the same token.
+/// If `options.proxy_protocol` is set, the connection must start with a
+/// PROXY protocol header, whose source address replaces `peer_addr`.
+/// CONNECT to a port outside `options.allowed_ports` gets a "connection not
+/// allowed by ruleset" reply, as does a CONNECT or RESOLVE naming a host
+/// outside `options.tor_only_domains`.
+///
+/// When `options.shutdown` fires, a handshake still in progress gets
+/// `SHUTDOWN_GRACE` to finish and is then answered with a failure (the
+/// reply due at the point it reached), so the client sees a well-formed
+/// refusal rather than a dropped connection. A relay already running
+/// carries on.
+pub async fn handle_socks_connection<S>(
+    mut stream: S,
+    peer_addr: SocketAddr,
+    connector: Arc<dyn StreamConnector>,
+    isolation: Option<IsolationToken>,
+    options: &HandlerOptions,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let progress = Progress::default();
+    let handler =
+        negotiate_and_relay(&mut stream, peer_addr, connector, isolation, options, &progress);
+    let mut handler = Box::pin(handler);
+    let Some(mut shutdown) = options.shutdown.clone() else {
+        return handler.await;
+    };
+    tokio::select! {
+        result = &mut handler => return result,
+        // An error means the sender is gone, which it never is
+        _ = shutdown.changed() => {}
+    }
+
+    progress.stopping.store(true, Ordering::SeqCst);
+    match progress.phase() {
+        Phase::Relaying => return handler.await,
+        // A connect under way is abandoned at once
+        Phase::Connecting => {}
+        Phase::Greeting | Phase::Auth | Phase::Request => {
+            if let Ok(result) = tokio::time::timeout(SHUTDOWN_GRACE, &mut handler).await {
+                return result;
+            }
+        }
+    }
+    let phase = progress.phase();
+    drop(handler);
+    connection_debug!("Answering SOCKS handshake from {} at shutdown ({:?})", peer_addr, phase);
+    match phase {
+        Phase::Greeting => stream.write_all(&[SOCKS5_VERSION, 0xFF]).await?,
+        Phase::Auth => stream.write_all(&[USERPASS_VERSION, 0x01]).await?,
+        Phase::Request | Phase::Connecting => send_reply(&mut stream, SOCKS5_REP_FAILURE).await?,
+        Phase::Relaying => {}
+    }
+    let _ = stream.shutdown().await;
+    Err(shutting_down())
+}
+
+async fn negotiate_and_relay<S>(
+    mut stream: S,
     peer_addr: SocketAddr,
-    client: Arc<TorClient<PreferredRuntime>>,
-) -> io::Result<()> {
+    connector: Arc<dyn StreamConnector>,
+    isolation: Option<IsolationToken>,
+    options: &HandlerOptions,
+    progress: &Progress,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // --- PROXY protocol header ---
+    let peer_addr = if options.proxy_protocol {
+        proxy_protocol::read_header(&mut stream).await?.unwrap_or(peer_addr)
+    } else {
+        peer_addr
+    };
+
     // --- Greeting ---
     // Client sends: VER | NMETHODS | METHODS
     let mut greeting = [0u8; 2];
@@ -44,18 +810,26 @@ pub async fn handle_socks_connection(
     let mut methods = vec![0u8; nmethods];
     stream.read_exact(&mut methods).await?;
 
-    // We only support no-auth
-    if !methods.contains(&SOCKS5_AUTH_NONE) {
+    // Username/password is preferred when offered, since its credentials can
+    // feed stream isolation; any credentials are accepted.
+    let credentials = if methods.contains(&SOCKS5_AUTH_USERPASS) {
+        stream.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_USERPASS]).await?;
+        progress.set(Phase::Auth);
+        let credentials = read_credentials(&mut stream).await?;
+        progress.set(Phase::Request);
+        Some(credentials)
+    } else if methods.contains(&SOCKS5_AUTH_NONE) {
+        stream.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_NONE]).await?;
+        progress.set(Phase::Request);
+        None
+    } else {
         // Send failure: no acceptable methods
         stream.write_all(&[SOCKS5_VERSION, 0xFF]).await?;
         return Err(io::Error::new(
             io::ErrorKind::PermissionDenied,
             "No acceptable auth methods",
         ));
-    }
-
-    // Accept no-auth
-    stream.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_NONE]).await?;
+    };
 
     // --- Request ---
     // Client sends: VER | CMD | RSV | ATYP | DST.ADDR | DST.PORT
@@ -72,12 +846,12 @@ pub async fn handle_socks_connection(
     let cmd = request_header[1];
     let atyp = request_header[3];
 
-    if cmd != SOCKS5_CMD_CONNECT {
-        // We only support CONNECT
+    if !matches!(cmd, SOCKS5_CMD_CONNECT | SOCKS5_CMD_RESOLVE | SOCKS5_CMD_RESOLVE_PTR) {
+        // We only support CONNECT and Tor's resolve extensions
         send_reply(&mut stream, SOCKS5_REP_FAILURE).await?;
         return Err(io::Error::new(
             io::ErrorKind::Unsupported,
-            "Only CONNECT supported",
+            "Only CONNECT, RESOLVE and RESOLVE_PTR supported",
         ));
     }
 
@@ -127,14 +901,56 @@ pub async fn handle_socks_connection(
         }
     };
 
-    tracing::debug!("SOCKS5 CONNECT from {} to {}:{}", peer_addr, dest_host, dest_port);
+    progress.set(Phase::Connecting);
+    if progress.stopping() {
+        send_reply(&mut stream, SOCKS5_REP_FAILURE).await?;
+        return Err(shutting_down());
+    }
+
+    let prefs = stream_prefs(isolation, &dest_host, dest_port, credentials);
+
+    if cmd == SOCKS5_CMD_RESOLVE && !options.host_allowed(&dest_host) {
+        tracing::info!("Rejecting request for a host outside the Tor-only domains");
+        send_reply(&mut stream, SOCKS5_REP_NOT_ALLOWED).await?;
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Host is not one of the Tor-only domains",
+        ));
+    }
+
+    #[cfg(not(feature = "onion"))]
+    if cmd == SOCKS5_CMD_CONNECT && is_onion(&dest_host) {
+        tracing::info!("Refusing CONNECT to an onion service: onion support not compiled in");
+        crate::error::set_last_error(crate::onion::NOT_COMPILED_IN);
+        send_reply(&mut stream, SOCKS5_REP_ADDR_NOT_SUPPORTED).await?;
+        return Err(io::Error::new(io::ErrorKind::Unsupported, crate::onion::NOT_COMPILED_IN));
+    }
+
+    match cmd {
+        SOCKS5_CMD_RESOLVE => {
+            connection_debug!("SOCKS5 RESOLVE from {} for {}", peer_addr, dest_host);
+            return handle_resolve(&mut stream, &dest_host, connector.as_ref(), &prefs).await;
+        }
+        SOCKS5_CMD_RESOLVE_PTR => {
+            connection_debug!("SOCKS5 RESOLVE_PTR from {} for {}", peer_addr, dest_host);
+            return handle_resolve_ptr(&mut stream, &dest_host, connector.as_ref(), &prefs).await;
+        }
+        _ => {}
+    }
+
+    connection_debug!("SOCKS5 CONNECT from {} to {}:{}", peer_addr, dest_host, dest_port);
+
+    if let Err(refusal) = check_connect(&dest_host, dest_port, options).await {
+        send_reply(&mut stream, refusal.reply).await?;
+        return Err(refusal.error);
+    }
 
     // Connect through Tor
     let tor_addr = format!("{}:{}", dest_host, dest_port);
     let tor_addr = match tor_addr.as_str().into_tor_addr() {
         Ok(a) => a,
         Err(e) => {
-            tracing::debug!("Invalid Tor address: {}", e);
+            connection_debug!("Invalid Tor address: {}", e);
             send_reply(&mut stream, SOCKS5_REP_FAILURE).await?;
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -143,21 +959,46 @@ pub async fn handle_socks_connection(
         }
     };
 
-    let tor_stream = match client.connect(tor_addr).await {
-        Ok(s) => s,
-        Err(e) => {
-            tracing::debug!("Tor connect failed: {}", e);
-            send_reply(&mut stream, SOCKS5_REP_CONN_REFUSED).await?;
+    let _slot = match ConnectionSlot::acquire() {
+        Some(slot) => slot,
+        None => {
+            tracing::warn!("Connection limit reached; rejecting SOCKS request from {}", peer_addr);
+            send_reply(&mut stream, SOCKS5_REP_FAILURE).await?;
             return Err(io::Error::new(
                 io::ErrorKind::ConnectionRefused,
-                e.to_string(),
+                "Connection limit reached",
             ));
         }
     };
 
+    let connect = circuit::STREAM_ISOLATION.scope(isolation, connector.connect(tor_addr, &prefs));
+    let connected = match options.connect_timeout_for(&dest_host) {
+        Some(limit) => match tokio::time::timeout(limit, connect).await {
+            Ok(result) => result,
+            Err(_) => {
+                connection_debug!("Tor connect timed out after {:?}", limit);
+                send_reply(&mut stream, SOCKS5_REP_TTL_EXPIRED).await?;
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Connect timed out after {:?}", limit),
+                ));
+            }
+        },
+        None => connect.await,
+    };
+    let tor_stream = match connected {
+        Ok(s) => s,
+        Err(e) => {
+            connection_debug!("Tor connect failed: {}", e);
+            send_reply(&mut stream, error_reply(&e, SOCKS5_REP_CONN_REFUSED)).await?;
+            return Err(e);
+        }
+    };
+
     // Send success reply
     // Reply: VER | REP | RSV | ATYP | BND.ADDR | BND.PORT
     // We use 0.0.0.0:0 as the bound address since we're proxying
+    progress.set(Phase::Relaying);
     let reply = [
         SOCKS5_VERSION,
         SOCKS5_REP_SUCCESS,
@@ -168,41 +1009,1424 @@ pub async fn handle_socks_connection(
     ];
     stream.write_all(&reply).await?;
 
-    // Bidirectional copy
-    let (mut client_read, mut client_write) = stream.into_split();
-    let (mut tor_read, mut tor_write) = tor_stream.split();
-
-    let client_to_tor = async {
-        tokio::io::copy(&mut client_read, &mut tor_write).await
+    // Bidirectional copy through fixed-size buffers. Each direction only
+    // reads again once its last chunk has been written out, so a slow side
+    // throttles the fast one instead of data piling up in memory.
+    let started = Instant::now();
+    let target = format!("{}:{}", dest_host, dest_port);
+    let session = sessions::register(target.clone(), options.label.clone());
+    connection_debug!("Relaying session {} to {}", session.index(), target);
+    // Only the log hears about opens; the callback reports finished streams
+    crate::eventlog::record(
+        "stream",
+        &json::Object::new()
+            .str("event", "opened")
+            .num("index", session.index())
+            .str("target", &target)
+            .finish(),
+    );
+    let mut tor_stream = Counted::new(tor_stream);
+    let last_moved = tor_stream.last_moved.clone();
+    let keepalive = async {
+        match options.keepalive.filter(|_| !is_onion(&dest_host)) {
+            Some(every) => {
+                keep_warm(connector.as_ref(), &prefs, &dest_host, every, &last_moved).await
+            }
+            None => std::future::pending().await,
+        }
     };
-    let tor_to_client = async {
-        tokio::io::copy(&mut tor_read, &mut client_write).await
+    let result = tokio::select! {
+        result = tokio::io::copy_bidirectional_with_sizes(
+            &mut stream,
+            &mut tor_stream,
+            options.relay_buffer_size(),
+            options.relay_buffer_size(),
+        ) => result,
+        _ = session.closed() => {
+            // Dropping the Tor stream ends just this stream on its circuit
+            let _ = stream.shutdown().await;
+            Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Closed by the app"))
+        }
+        never = keepalive => match never {},
     };
+    match &result {
+        Ok((to_tor, from_tor)) => {
+            connection_debug!("Relay closed: {} bytes to Tor, {} bytes from Tor", to_tor, from_tor);
+        }
+        Err(e) => {
+            connection_debug!("Relay copy error: {}", e);
+        }
+    }
+    // The wrapper's counts include whatever moved before an error, which
+    // the copy's own totals don't report
+    callbacks::notify_stream_event(&stream_closed_json(
+        &target,
+        &tor_stream,
+        started.elapsed(),
+        result.is_ok(),
+    ));
 
-    tokio::select! {
-        result = client_to_tor => {
-            if let Err(e) = result {
-                tracing::debug!("Client to Tor copy error: {}", e);
-            }
+    Ok(())
+}
+
+/// Keep an idle stream's circuit in use: whenever `every` passes with no
+/// bytes moved, look up `host` (or, for an address, its hostname) with the
+/// stream's own preferences. The lookup goes out on a circuit with the
+/// same isolation, normally the stream's own, so the traffic travels the
+/// same guard connection and keeps NAT mappings along it from expiring.
+/// The stream itself is untouched, since SOCKS carries no keepalive of its
+/// own. Never returns.
+async fn keep_warm(
+    connector: &dyn StreamConnector,
+    prefs: &StreamPrefs,
+    host: &str,
+    every: Duration,
+    last_moved: &AtomicU64,
+) -> std::convert::Infallible {
+    let every_ms = every.as_millis() as u64;
+    loop {
+        let idle = activity_clock().saturating_sub(last_moved.load(Ordering::Relaxed));
+        if idle < every_ms {
+            tokio::time::sleep(Duration::from_millis(every_ms - idle)).await;
+            continue;
+        }
+        let looked_up = match host.parse::<IpAddr>() {
+            Ok(addr) => connector.resolve_ptr(addr, prefs).await.map(drop),
+            Err(_) => connector.resolve(host, prefs).await.map(drop),
+        };
+        if let Err(e) = looked_up {
+            connection_debug!("Keepalive lookup for {} failed: {}", host, e);
+        }
+        // Counts as movement, so the next one waits a full interval
+        last_moved.store(activity_clock(), Ordering::Relaxed);
+    }
+}
+
+/// Describe a finished relay for the stream event callback.
+fn stream_closed_json<S>(
+    target: &str,
+    stream: &Counted<S>,
+    duration: Duration,
+    ok: bool,
+) -> String {
+    json::Object::new()
+        .str("event", "closed")
+        .str("target", target)
+        .num("bytes_read", stream.read)
+        .num("bytes_written", stream.written)
+        .num("duration_ms", duration.as_millis())
+        .bool("success", ok)
+        .finish()
+}
+
+/// Counts the bytes read from and written to a stream as they pass, so the
+/// totals are right however the relay ends, and records the activity for
+/// [`is_active`] and [`traffic_totals`].
+struct Counted<S> {
+    inner: S,
+    read: u64,
+    written: u64,
+    /// When bytes last moved either way, on the `activity_clock`
+    last_moved: Arc<AtomicU64>,
+}
+
+impl<S> Counted<S> {
+    fn new(inner: S) -> Self {
+        Counted {
+            inner,
+            read: 0,
+            written: 0,
+            last_moved: Arc::new(AtomicU64::new(activity_clock())),
+        }
+    }
+
+    fn note_moved(&self) {
+        note_activity();
+        self.last_moved.store(activity_clock(), Ordering::Relaxed);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - before;
+        if n > 0 {
+            self.read += n as u64;
+            BYTES_FROM_TOR.fetch_add(n as u64, Ordering::Relaxed);
+            self.note_moved();
         }
-        result = tor_to_client => {
-            if let Err(e) = result {
-                tracing::debug!("Tor to client copy error: {}", e);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            if n > 0 {
+                self.written += n as u64;
+                BYTES_TO_TOR.fetch_add(n as u64, Ordering::Relaxed);
+                self.note_moved();
             }
         }
+        poll
     }
 
-    Ok(())
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
 }
 
-async fn send_reply(stream: &mut TcpStream, rep: u8) -> io::Result<()> {
-    let reply = [
-        SOCKS5_VERSION,
-        rep,
-        0x00, // RSV
-        SOCKS5_ATYP_IPV4,
-        0, 0, 0, 0, // BND.ADDR
-        0, 0, // BND.PORT
-    ];
-    stream.write_all(&reply).await
+/// Read an RFC 1929 username/password sub-negotiation and accept it.
+async fn read_credentials<S>(stream: &mut S) -> io::Result<(Vec<u8>, Vec<u8>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != USERPASS_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid username/password version",
+        ));
+    }
+    let mut username = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut username).await?;
+    let mut len = [0u8; 1];
+    stream.read_exact(&mut len).await?;
+    let mut password = vec![0u8; len[0] as usize];
+    stream.read_exact(&mut password).await?;
+
+    stream.write_all(&[USERPASS_VERSION, 0x00]).await?;
+    Ok((username, password))
+}
+
+/// Answer a RESOLVE request with the first address found for `host`.
+async fn handle_resolve<S>(
+    stream: &mut S,
+    host: &str,
+    connector: &dyn StreamConnector,
+    prefs: &StreamPrefs,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    // An address literal resolves to itself
+    let resolved = match parse_ip(host) {
+        Some(ip) => Ok(vec![ip]),
+        None => connector.resolve(host, prefs).await,
+    };
+    let ip = match resolved.and_then(|ips| {
+        ips.into_iter()
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No addresses"))
+    }) {
+        Ok(ip) => ip,
+        Err(e) => {
+            connection_debug!("Tor resolve failed: {}", e);
+            send_reply(stream, error_reply(&e, SOCKS5_REP_HOST_UNREACHABLE)).await?;
+            return Err(e);
+        }
+    };
+
+    let mut reply = vec![SOCKS5_VERSION, SOCKS5_REP_SUCCESS, 0x00];
+    match ip {
+        IpAddr::V4(v4) => {
+            reply.push(SOCKS5_ATYP_IPV4);
+            reply.extend_from_slice(&v4.octets());
+        }
+        IpAddr::V6(v6) => {
+            reply.push(SOCKS5_ATYP_IPV6);
+            reply.extend_from_slice(&v6.octets());
+        }
+    }
+    reply.extend_from_slice(&[0, 0]); // BND.PORT
+    stream.write_all(&reply).await
+}
+
+/// Answer a RESOLVE_PTR request with the first hostname found for `host`.
+async fn handle_resolve_ptr<S>(
+    stream: &mut S,
+    host: &str,
+    connector: &dyn StreamConnector,
+    prefs: &StreamPrefs,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let ip = match parse_ip(host) {
+        Some(ip) => ip,
+        None => {
+            send_reply(stream, SOCKS5_REP_FAILURE).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "RESOLVE_PTR needs an address",
+            ));
+        }
+    };
+
+    let name = match connector.resolve_ptr(ip, prefs).await.and_then(|names| {
+        names
+            .into_iter()
+            .find(|n| n.len() <= u8::MAX as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No hostnames"))
+    }) {
+        Ok(name) => name,
+        Err(e) => {
+            connection_debug!("Tor reverse resolve failed: {}", e);
+            send_reply(stream, error_reply(&e, SOCKS5_REP_HOST_UNREACHABLE)).await?;
+            return Err(e);
+        }
+    };
+
+    let mut reply = vec![SOCKS5_VERSION, SOCKS5_REP_SUCCESS, 0x00, SOCKS5_ATYP_DOMAIN];
+    reply.push(name.len() as u8);
+    reply.extend_from_slice(name.as_bytes());
+    reply.extend_from_slice(&[0, 0]); // BND.PORT
+    stream.write_all(&reply).await
+}
+
+/// Parse a request host as an address literal (IPv6 hosts are bracketed).
+fn parse_ip(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+/// Reply code for a failed connect or resolve; `default` unless the
+/// network itself is unavailable or Tor isn't running yet.
+fn error_reply(e: &io::Error, default: u8) -> u8 {
+    match e.kind() {
+        io::ErrorKind::NetworkUnreachable => SOCKS5_REP_NETWORK_UNREACHABLE,
+        io::ErrorKind::NotConnected => SOCKS5_REP_FAILURE,
+        _ => default,
+    }
+}
+
+async fn send_reply<S>(stream: &mut S, rep: u8) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let reply = [
+        SOCKS5_VERSION,
+        rep,
+        0x00, // RSV
+        SOCKS5_ATYP_IPV4,
+        0, 0, 0, 0, // BND.ADDR
+        0, 0, // BND.PORT
+    ];
+    stream.write_all(&reply).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::testing::EchoConnector;
+    use crate::connector::{
+        ConnectFuture, Offline, Pending, RelayStream, ResolveFuture, ResolvePtrFuture,
+    };
+    use arti_client::TorAddr;
+    use std::sync::Mutex;
+    use tokio::io::DuplexStream;
+
+    /// Connector that always fails to connect.
+    struct RefusingConnector;
+
+    impl StreamConnector for RefusingConnector {
+        fn connect<'a>(&'a self, _target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+            Box::pin(async {
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+            })
+        }
+    }
+
+    /// Connector whose connects never complete.
+    struct HangingConnector;
+
+    impl StreamConnector for HangingConnector {
+        fn connect<'a>(&'a self, _target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+            Box::pin(std::future::pending())
+        }
+    }
+
+    /// Connector whose streams send `total` bytes as fast as the relay
+    /// accepts them, counting how many have been handed over so far.
+    struct FloodConnector {
+        total: usize,
+        sent: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    const FLOOD_PIPE_SIZE: usize = 4 * 1024;
+    const FLOOD_CHUNK: usize = 1024;
+
+    impl StreamConnector for FloodConnector {
+        fn connect<'a>(&'a self, _target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+            let (total, sent) = (self.total, self.sent.clone());
+            Box::pin(async move {
+                let (near, mut far) = tokio::io::duplex(FLOOD_PIPE_SIZE);
+                tokio::spawn(async move {
+                    let chunk = [0xAB; FLOOD_CHUNK];
+                    while sent.load(Ordering::SeqCst) < total {
+                        if far.write_all(&chunk).await.is_err() {
+                            break;
+                        }
+                        sent.fetch_add(chunk.len(), Ordering::SeqCst);
+                    }
+                    // Keep the stream open so the relay doesn't wind down
+                    std::future::pending::<()>().await;
+                });
+                Ok(Box::new(near) as Box<dyn RelayStream>)
+            })
+        }
+    }
+
+    /// Connector answering lookups from fixed tables.
+    struct ResolvingConnector;
+
+    impl StreamConnector for ResolvingConnector {
+        fn connect<'a>(&'a self, _target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+            Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+        }
+
+        fn resolve<'a>(&'a self, hostname: &'a str, _prefs: &'a StreamPrefs) -> ResolveFuture<'a> {
+            Box::pin(async move {
+                match hostname {
+                    "example.com" => Ok(vec!["93.184.216.34".parse().unwrap()]),
+                    "v6.example.com" => Ok(vec!["2001:db8::1".parse().unwrap()]),
+                    _ => Err(io::ErrorKind::NotFound.into()),
+                }
+            })
+        }
+
+        fn resolve_ptr<'a>(
+            &'a self,
+            addr: IpAddr,
+            _prefs: &'a StreamPrefs,
+        ) -> ResolvePtrFuture<'a> {
+            Box::pin(async move {
+                if addr == "93.184.216.34".parse::<IpAddr>().unwrap() {
+                    Ok(vec!["example.com".to_owned()])
+                } else {
+                    Err(io::ErrorKind::NotFound.into())
+                }
+            })
+        }
+    }
+
+    /// Connector that dials a fixed local TCP address regardless of target.
+    struct LoopbackConnector {
+        addr: SocketAddr,
+    }
+
+    impl StreamConnector for LoopbackConnector {
+        fn connect<'a>(&'a self, _target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+            Box::pin(async move {
+                let stream = TcpStream::connect(self.addr).await?;
+                Ok(Box::new(stream) as Box<dyn RelayStream>)
+            })
+        }
+    }
+
+    /// Bind a local TCP echo server and return its address.
+    async fn spawn_echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut r, mut w) = stream.into_split();
+                    let _ = tokio::io::copy(&mut r, &mut w).await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn peer() -> SocketAddr {
+        "127.0.0.1:40000".parse().unwrap()
+    }
+
+    /// Start the handler on one end of an in-memory pipe and return the other.
+    fn spawn_handler(
+        connector: Arc<dyn StreamConnector>,
+    ) -> (DuplexStream, tokio::task::JoinHandle<io::Result<()>>) {
+        spawn_handler_with(connector, HandlerOptions::default())
+    }
+
+    /// Start the handler with the given options.
+    fn spawn_handler_with(
+        connector: Arc<dyn StreamConnector>,
+        options: HandlerOptions,
+    ) -> (DuplexStream, tokio::task::JoinHandle<io::Result<()>>) {
+        let (client, server) = tokio::io::duplex(1024);
+        let task = tokio::spawn(async move {
+            handle_socks_connection(server, peer(), connector, None, &options).await
+        });
+        (client, task)
+    }
+
+    /// Like `spawn_handler`, but expecting a PROXY protocol header first.
+    fn spawn_proxied_handler(
+        connector: Arc<dyn StreamConnector>,
+    ) -> (DuplexStream, tokio::task::JoinHandle<io::Result<()>>) {
+        let options = HandlerOptions {
+            proxy_protocol: true,
+            ..Default::default()
+        };
+        spawn_handler_with(connector, options)
+    }
+
+    /// Minimal SOCKS5 client: no-auth greeting, then the given request.
+    /// Returns the method-selection reply and the 10-byte request reply.
+    async fn socks_request(client: &mut DuplexStream, request: &[u8]) -> ([u8; 2], [u8; 10]) {
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        client.write_all(request).await.unwrap();
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        (method, reply)
+    }
+
+    fn connect_domain(host: &str, port: u16) -> Vec<u8> {
+        let mut req = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0x00, SOCKS5_ATYP_DOMAIN];
+        req.push(host.len() as u8);
+        req.extend_from_slice(host.as_bytes());
+        req.extend_from_slice(&port.to_be_bytes());
+        req
+    }
+
+    #[tokio::test]
+    async fn connect_domain_relays_bytes() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_handler(connector.clone());
+
+        let (method, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(method, [SOCKS5_VERSION, SOCKS5_AUTH_NONE]);
+        assert_eq!(
+            reply,
+            [SOCKS5_VERSION, SOCKS5_REP_SUCCESS, 0, SOCKS5_ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["example.com:443"]);
+
+        client.write_all(b"hello tor").await.unwrap();
+        let mut echoed = [0u8; 9];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello tor");
+    }
+
+    #[tokio::test]
+    async fn idle_stream_gets_keepalive_lookups() {
+        let connector = Arc::new(EchoConnector::default());
+        let options = HandlerOptions {
+            keepalive: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let (mut client, _task) = spawn_handler_with(connector.clone(), options);
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+
+        // Traffic holds the keepalive off...
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            client.write_all(b"x").await.unwrap();
+            client.read_exact(&mut [0u8; 1]).await.unwrap();
+        }
+        assert!(connector.lookups.lock().unwrap().is_empty());
+
+        // ...and silence brings it on, once per interval
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        let lookups = connector.lookups.lock().unwrap().clone();
+        assert!((2..=4).contains(&lookups.len()), "{:?}", lookups);
+        assert!(lookups.iter().all(|host| host == "example.com"));
+    }
+
+    #[tokio::test]
+    async fn counts_stream_bytes_when_one_direction_closes_early() {
+        let (mut app, app_far) = tokio::io::duplex(1024);
+        let (tor, mut tor_far) = tokio::io::duplex(1024);
+        let relay = tokio::spawn(async move {
+            let (mut app_far, mut tor) = (app_far, Counted::new(tor));
+            let result = tokio::io::copy_bidirectional(&mut app_far, &mut tor).await;
+            (tor, result)
+        });
+
+        // The app is done sending after its request...
+        app.write_all(b"hello").await.unwrap();
+        app.shutdown().await.unwrap();
+        let mut request = [0u8; 5];
+        tor_far.read_exact(&mut request).await.unwrap();
+
+        // ...while the response keeps coming
+        tor_far.write_all(&[0x42; 3000]).await.unwrap();
+        tor_far.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        app.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response.len(), 3000);
+
+        let (counted, result) = relay.await.unwrap();
+        assert_eq!(result.unwrap(), (5, 3000));
+        assert_eq!((counted.read, counted.written), (3000, 5));
+        assert!(is_active());
+        assert!(since_activity().unwrap() < Duration::from_secs(1));
+
+        let event = stream_closed_json("example.com:443", &counted, Duration::from_millis(7), true);
+        assert_eq!(
+            event,
+            r#"{"event":"closed","target":"example.com:443","bytes_read":3000,"#.to_owned()
+                + r#""bytes_written":5,"duration_ms":7,"success":true}"#
+        );
+    }
+
+    #[test]
+    fn activity_expires_after_the_window() {
+        assert!(!within_window(0, 5, 1000));
+        assert!(within_window(100, 100, 0));
+        assert!(within_window(100, 1100, 1000));
+        assert!(!within_window(100, 1101, 1000));
+    }
+
+    /// Index of the listed session with `label`; other tests' sessions may
+    /// be listed too.
+    fn session_index(label: &str) -> Option<i32> {
+        let listed = sessions::list_json();
+        let at = listed.find(&format!(r#""label":"{}""#, label))?;
+        let entry = &listed[listed[..at].rfind(r#"{"index":"#)? + r#"{"index":"#.len()..];
+        entry[..entry.find(',')?].parse().ok()
+    }
+
+    #[tokio::test]
+    async fn closing_a_session_ends_only_that_relay() {
+        let connector = Arc::new(EchoConnector::default());
+        let labelled = |label: &str| HandlerOptions {
+            label: Some(label.to_owned()),
+            ..Default::default()
+        };
+        let (mut doomed, task) = spawn_handler_with(connector.clone(), labelled("close-me"));
+        let (mut kept, _kept_task) = spawn_handler_with(connector, labelled("keep-me"));
+        for client in [&mut doomed, &mut kept] {
+            let (_, reply) = socks_request(client, &connect_domain("example.com", 443)).await;
+            assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        }
+
+        assert!(sessions::close(session_index("close-me").unwrap()));
+        let mut rest = Vec::new();
+        doomed.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        task.await.unwrap().unwrap();
+        assert_eq!(session_index("close-me"), None);
+
+        kept.write_all(b"still here").await.unwrap();
+        let mut echoed = [0u8; 10];
+        kept.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"still here");
+    }
+
+    extern "C" fn block_blocked_example(
+        host: *const std::ffi::c_char,
+        port: u16,
+        _ctx: *mut std::ffi::c_void,
+    ) -> std::ffi::c_int {
+        let host = unsafe { std::ffi::CStr::from_ptr(host) };
+        (host.to_bytes() == b"blocked.example" && port == 443) as std::ffi::c_int
+    }
+
+    #[tokio::test]
+    async fn connection_filter_refuses_before_connecting() {
+        // Other tests run alongside, so this only refuses its own host
+        callbacks::CONNECTION_FILTER.set(Some(block_blocked_example), std::ptr::null_mut());
+        let connector = Arc::new(EchoConnector::default());
+
+        let (mut client, task) = spawn_handler(connector.clone());
+        let (_, reply) = socks_request(&mut client, &connect_domain("blocked.example", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_NOT_ALLOWED);
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let (mut client, _task) = spawn_handler(connector.clone());
+        let (_, reply) = socks_request(&mut client, &connect_domain("blocked.example", 80)).await;
+        callbacks::CONNECTION_FILTER.set(None, std::ptr::null_mut());
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["blocked.example:80"]);
+    }
+
+    #[tokio::test]
+    async fn connect_ipv4_target() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_handler(connector.clone());
+
+        let req = [SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0, SOCKS5_ATYP_IPV4, 10, 0, 0, 1, 0, 80];
+        let (_, reply) = socks_request(&mut client, &req).await;
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["10.0.0.1:80"]);
+    }
+
+    fn allowing(ports: &[u16]) -> HandlerOptions {
+        HandlerOptions {
+            allowed_ports: ports.into(),
+            ..Default::default()
+        }
+    }
+
+    fn listing(domains: &[&str]) -> HandlerOptions {
+        let domains: Vec<String> = domains.iter().map(|d| d.to_string()).collect();
+        HandlerOptions {
+            tor_only_domains: domains.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tor_only_domains_cover_their_subdomains() {
+        let options = listing(&["example.com", "chat.example.org"]);
+        assert!(options.host_allowed("example.com"));
+        assert!(options.host_allowed("API.Example.com."));
+        assert!(options.host_allowed("chat.example.org"));
+        assert!(!options.host_allowed("example.org"));
+        assert!(!options.host_allowed("notexample.com"));
+        assert!(!options.host_allowed("93.184.216.34"));
+        assert!(listing(&[]).host_allowed("anything.test"));
+    }
+
+    #[tokio::test]
+    async fn listed_host_connects() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_handler_with(connector.clone(), listing(&["example.com"]));
+
+        let request = connect_domain("www.example.com", 443);
+        let (_, reply) = socks_request(&mut client, &request).await;
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["www.example.com:443"]);
+    }
+
+    #[tokio::test]
+    async fn unlisted_host_gets_not_allowed_reply() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, task) = spawn_handler_with(connector.clone(), listing(&["example.com"]));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.net", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_NOT_ALLOWED);
+        assert!(connector.targets.lock().unwrap().is_empty());
+
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn allowed_port_connects() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_handler_with(connector.clone(), allowing(&[443, 8443]));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["example.com:443"]);
+    }
+
+    #[tokio::test]
+    async fn disallowed_port_gets_not_allowed_reply() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, task) = spawn_handler_with(connector.clone(), allowing(&[443]));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 25)).await;
+        assert_eq!(reply[1], SOCKS5_REP_NOT_ALLOWED);
+        assert!(connector.targets.lock().unwrap().is_empty());
+
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    fn with_timeouts(clearnet_ms: u64, onion_ms: u64) -> HandlerOptions {
+        HandlerOptions {
+            connect_timeout: Some(Duration::from_millis(clearnet_ms)),
+            onion_connect_timeout: Some(Duration::from_millis(onion_ms)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn onion_targets_use_the_onion_timeout() {
+        let options = with_timeouts(100, 5000);
+        let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        assert_eq!(options.connect_timeout_for(onion), Some(Duration::from_millis(5000)));
+        assert_eq!(options.connect_timeout_for("Foo.ONION."), Some(Duration::from_millis(5000)));
+        assert_eq!(options.connect_timeout_for("example.com"), Some(Duration::from_millis(100)));
+        assert_eq!(options.connect_timeout_for("onion"), Some(Duration::from_millis(100)));
+        assert_eq!(HandlerOptions::default().connect_timeout_for(onion), None);
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_gets_ttl_expired_reply() {
+        let options = with_timeouts(50, 60_000);
+        let (mut client, task) = spawn_handler_with(Arc::new(HangingConnector), options);
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_TTL_EXPIRED);
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[cfg(feature = "onion")]
+    #[tokio::test]
+    async fn onion_connect_outlasts_the_clearnet_timeout() {
+        let options = with_timeouts(50, 60_000);
+        let (mut client, task) = spawn_handler_with(Arc::new(HangingConnector), options);
+
+        let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        client.write_all(&connect_domain(onion, 443)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!task.is_finished(), "onion connect was cut off by the clearnet timeout");
+        task.abort();
+    }
+
+    #[cfg(not(feature = "onion"))]
+    #[tokio::test]
+    async fn onion_connect_is_refused_without_onion_support() {
+        let (mut client, task) = spawn_handler(Arc::new(HangingConnector));
+        let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        let (_, reply) = socks_request(&mut client, &connect_domain(onion, 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_ADDR_NOT_SUPPORTED);
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(crate::error::last_error(), crate::onion::NOT_COMPILED_IN);
+    }
+
+    #[tokio::test]
+    async fn handshake_in_progress_at_shutdown_gets_a_failure_reply() {
+        let (stop, shutdown) = watch::channel(0);
+        let options = HandlerOptions {
+            shutdown: Some(shutdown),
+            ..Default::default()
+        };
+        let (mut client, task) = spawn_handler_with(Arc::new(EchoConnector::default()), options);
+
+        // Greet, then stall before sending the request
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [SOCKS5_VERSION, SOCKS5_AUTH_NONE]);
+        stop.send_modify(|generation| *generation += 1);
+
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[..4], [SOCKS5_VERSION, SOCKS5_REP_FAILURE, 0x00, SOCKS5_ATYP_IPV4]);
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[tokio::test]
+    async fn connect_in_progress_at_shutdown_is_answered_at_once() {
+        let (stop, shutdown) = watch::channel(0);
+        let options = HandlerOptions {
+            shutdown: Some(shutdown),
+            ..Default::default()
+        };
+        let (mut client, task) = spawn_handler_with(Arc::new(HangingConnector), options);
+
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        client.write_all(&connect_domain("example.com", 443)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send_modify(|generation| *generation += 1);
+
+        let mut replies = [0u8; 12];
+        tokio::time::timeout(SHUTDOWN_GRACE / 2, client.read_exact(&mut replies))
+            .await
+            .expect("no reply before the grace period ran out")
+            .unwrap();
+        assert_eq!(replies[..2], [SOCKS5_VERSION, SOCKS5_AUTH_NONE]);
+        assert_eq!(replies[3], SOCKS5_REP_FAILURE);
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_auth_methods() {
+        let (mut client, task) = spawn_handler(Arc::new(EchoConnector::default()));
+
+        // Offer only GSSAPI
+        client.write_all(&[SOCKS5_VERSION, 1, 0x01]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [SOCKS5_VERSION, 0xFF]);
+
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn accepts_username_password_auth() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_handler(connector.clone());
+
+        let greeting = [SOCKS5_VERSION, 2, SOCKS5_AUTH_NONE, SOCKS5_AUTH_USERPASS];
+        client.write_all(&greeting).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [SOCKS5_VERSION, SOCKS5_AUTH_USERPASS]);
+
+        client.write_all(&[USERPASS_VERSION, 5]).await.unwrap();
+        client.write_all(b"alice\x02pw").await.unwrap();
+        let mut status = [0u8; 2];
+        client.read_exact(&mut status).await.unwrap();
+        assert_eq!(status, [USERPASS_VERSION, 0x00]);
+
+        client.write_all(&connect_domain("example.com", 443)).await.unwrap();
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn rejects_non_socks5_greeting() {
+        let (mut client, task) = spawn_handler(Arc::new(EchoConnector::default()));
+
+        client.write_all(&[0x04, 1]).await.unwrap();
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn unsupported_command_gets_failure_reply() {
+        let (mut client, task) = spawn_handler(Arc::new(EchoConnector::default()));
+
+        // BIND
+        let req = [SOCKS5_VERSION, 0x02, 0, SOCKS5_ATYP_IPV4, 10, 0, 0, 1, 0, 80];
+        let (_, reply) = socks_request(&mut client, &req).await;
+        assert_eq!(reply[1], SOCKS5_REP_FAILURE);
+
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn connector_failure_gets_refused_reply() {
+        let (mut client, task) = spawn_handler(Arc::new(RefusingConnector));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_CONN_REFUSED);
+
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    fn resolve_domain(host: &str) -> Vec<u8> {
+        let mut req = connect_domain(host, 0);
+        req[1] = SOCKS5_CMD_RESOLVE;
+        req
+    }
+
+    #[tokio::test]
+    async fn resolve_replies_with_ipv4_address() {
+        let (mut client, task) = spawn_handler(Arc::new(ResolvingConnector));
+
+        let (_, reply) = socks_request(&mut client, &resolve_domain("example.com")).await;
+        assert_eq!(
+            reply,
+            [SOCKS5_VERSION, SOCKS5_REP_SUCCESS, 0, SOCKS5_ATYP_IPV4, 93, 184, 216, 34, 0, 0]
+        );
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolve_replies_with_ipv6_address() {
+        let (mut client, task) = spawn_handler(Arc::new(ResolvingConnector));
+
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        client.write_all(&resolve_domain("v6.example.com")).await.unwrap();
+        let mut reply = [0u8; 22];
+        client.read_exact(&mut reply).await.unwrap();
+
+        assert_eq!(&reply[..4], &[SOCKS5_VERSION, SOCKS5_REP_SUCCESS, 0, SOCKS5_ATYP_IPV6]);
+        let ip: std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(&reply[4..20], &ip.octets());
+        assert_eq!(&reply[20..], &[0, 0]);
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolve_failure_gets_host_unreachable_reply() {
+        let (mut client, task) = spawn_handler(Arc::new(ResolvingConnector));
+
+        let (_, reply) = socks_request(&mut client, &resolve_domain("nowhere.invalid")).await;
+        assert_eq!(reply[1], SOCKS5_REP_HOST_UNREACHABLE);
+        assert!(task.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_ptr_replies_with_hostname() {
+        let (mut client, task) = spawn_handler(Arc::new(ResolvingConnector));
+
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        let req = [
+            SOCKS5_VERSION, SOCKS5_CMD_RESOLVE_PTR, 0, SOCKS5_ATYP_IPV4, 93, 184, 216, 34, 0, 0,
+        ];
+        client.write_all(&req).await.unwrap();
+
+        let mut header = [0u8; 5];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(
+            header,
+            [SOCKS5_VERSION, SOCKS5_REP_SUCCESS, 0, SOCKS5_ATYP_DOMAIN, 11]
+        );
+        let mut rest = [0u8; 13];
+        client.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest[..11], b"example.com");
+        assert_eq!(&rest[11..], &[0, 0]);
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn proxy_v1_header_then_socks_handshake() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_proxied_handler(connector.clone());
+
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 127.0.0.1 5555 39050\r\n")
+            .await
+            .unwrap();
+        let (method, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(method, [SOCKS5_VERSION, SOCKS5_AUTH_NONE]);
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["example.com:443"]);
+    }
+
+    #[tokio::test]
+    async fn proxy_v2_header_then_socks_handshake() {
+        let connector = Arc::new(EchoConnector::default());
+        let (mut client, _task) = spawn_proxied_handler(connector.clone());
+
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]); // v2 PROXY, TCP4, 12 bytes
+        header.extend_from_slice(&[192, 0, 2, 1, 127, 0, 0, 1]);
+        header.extend_from_slice(&5555u16.to_be_bytes());
+        header.extend_from_slice(&39050u16.to_be_bytes());
+        client.write_all(&header).await.unwrap();
+
+        let (method, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(method, [SOCKS5_VERSION, SOCKS5_AUTH_NONE]);
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        assert_eq!(*connector.targets.lock().unwrap(), vec!["example.com:443"]);
+    }
+
+    #[tokio::test]
+    async fn proxy_protocol_requires_header() {
+        let (mut client, task) = spawn_proxied_handler(Arc::new(EchoConnector::default()));
+
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn slow_reader_bounds_buffering() {
+        let total = 4 * 1024 * 1024;
+        let sent = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let connector = Arc::new(FloodConnector {
+            total,
+            sent: sent.clone(),
+        });
+        let (mut client, _task) = spawn_handler(connector);
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+
+        // The client isn't reading: the sender must stall once the pipes
+        // and the relay buffer are full, far short of the total
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let stalled = sent.load(Ordering::SeqCst);
+        let bound = FLOOD_PIPE_SIZE + DEFAULT_RELAY_BUFFER_SIZE + 1024 + FLOOD_CHUNK;
+        assert!(stalled <= bound, "{} bytes buffered, bound {}", stalled, bound);
+
+        // Reading slowly lets it make progress again
+        let mut buf = vec![0u8; 64 * 1024];
+        client.read_exact(&mut buf).await.unwrap();
+        assert!(buf.iter().all(|b| *b == 0xAB));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let resumed = sent.load(Ordering::SeqCst);
+        assert!(resumed > stalled);
+        assert!(resumed <= buf.len() + bound);
+    }
+
+    /// Relay `total` bytes from a loopback TCP source to a SOCKS client
+    /// over loopback TCP; returns MB/s.
+    async fn relay_throughput(buffer_size: usize, total: usize) -> f64 {
+        let source = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = source.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = source.accept().await.unwrap();
+            let chunk = vec![0x5A; 256 * 1024];
+            let mut left = total;
+            while left > 0 {
+                let n = left.min(chunk.len());
+                stream.write_all(&chunk[..n]).await.unwrap();
+                left -= n;
+            }
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let options = HandlerOptions {
+            relay_buffer_size: buffer_size,
+            ..Default::default()
+        };
+        tokio::spawn(async move {
+            let (stream, peer) = proxy.accept().await.unwrap();
+            let connector = Arc::new(LoopbackConnector { addr });
+            handle_socks_connection(stream, peer, connector, None, &options).await
+        });
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        client.write_all(&connect_domain("example.com", 443)).await.unwrap();
+        let mut replies = [0u8; 12];
+        client.read_exact(&mut replies).await.unwrap();
+        assert_eq!(replies[3], SOCKS5_REP_SUCCESS);
+
+        let started = Instant::now();
+        let mut buf = vec![0u8; 256 * 1024];
+        let mut received = 0;
+        while received < total {
+            received += client.read(&mut buf).await.unwrap();
+        }
+        total as f64 / started.elapsed().as_secs_f64() / 1e6
+    }
+
+    /// Throughput comparison; run with `cargo test --release -- --ignored
+    /// relay_throughput --nocapture`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn relay_throughput_by_buffer_size() {
+        const TOTAL: usize = 512 * 1024 * 1024;
+        for size in [DEFAULT_RELAY_BUFFER_SIZE, 256 * 1024] {
+            let mbps = relay_throughput(size, TOTAL).await;
+            println!("{:>7} byte buffers: {:.0} MB/s", size, mbps);
+        }
+    }
+
+    /// Run `count` short CONNECTs that fail at the connector, with a
+    /// subscriber taking every debug event; returns connections per second.
+    async fn connection_churn(count: usize, logging: bool) -> f64 {
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(io::sink)
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+        CONNECTION_LOGGING.store(logging, Ordering::SeqCst);
+        let started = Instant::now();
+        for _ in 0..count {
+            let (mut client, task) = spawn_handler(Arc::new(RefusingConnector));
+            socks_request(&mut client, &connect_domain("example.com", 443)).await;
+            let _ = task.await;
+        }
+        CONNECTION_LOGGING.store(true, Ordering::SeqCst);
+        count as f64 / started.elapsed().as_secs_f64()
+    }
+
+    /// Per-connection logging cost; run with `cargo test --release --
+    /// --ignored connection_logging --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn connection_logging_overhead() {
+        const COUNT: usize = 50_000;
+        for logging in [true, false] {
+            let rate = connection_churn(COUNT, logging).await;
+            println!("logging {:>5}: {:.0} connections/s", logging, rate);
+        }
+    }
+
+    #[tokio::test]
+    async fn relays_with_a_custom_buffer_size() {
+        assert!(relay_throughput(MIN_RELAY_BUFFER_SIZE, 1024 * 1024).await > 0.0);
+    }
+
+    #[tokio::test]
+    async fn offline_connector_gets_network_unreachable_reply() {
+        let (mut client, task) = spawn_handler(Arc::new(Offline));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_NETWORK_UNREACHABLE);
+
+        let err = task.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NetworkUnreachable);
+    }
+
+    #[tokio::test]
+    async fn prebootstrap_queue_waits_for_the_client() {
+        let pending = Arc::new(Pending::new(PendingMode::Queue));
+        let (mut client, _task) = spawn_handler(pending.clone());
+
+        let request = tokio::spawn(async move {
+            let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+            (client, reply)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!request.is_finished());
+
+        pending.ready(Arc::new(EchoConnector::default()));
+        let (mut client, reply) = request.await.unwrap();
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        client.write_all(b"late").await.unwrap();
+        let mut echoed = [0u8; 4];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"late");
+    }
+
+    #[tokio::test]
+    async fn prebootstrap_queue_fails_if_tor_never_starts() {
+        let pending = Arc::new(Pending::new(PendingMode::Queue));
+        let (mut client, task) = spawn_handler(pending.clone());
+
+        let request = tokio::spawn(async move {
+            socks_request(&mut client, &connect_domain("example.com", 443)).await.1
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pending.fail();
+        assert_eq!(request.await.unwrap()[1], SOCKS5_REP_FAILURE);
+        assert_eq!(task.await.unwrap().unwrap_err().kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn prebootstrap_reject_gets_general_failure() {
+        let (mut client, task) = spawn_handler(Arc::new(Pending::new(PendingMode::Reject)));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_FAILURE);
+        assert_eq!(task.await.unwrap().unwrap_err().kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn prebootstrap_fail_fast_gets_network_unreachable() {
+        let (mut client, task) = spawn_handler(Arc::new(Pending::new(PendingMode::FailFast)));
+
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_NETWORK_UNREACHABLE);
+        assert_eq!(task.await.unwrap().unwrap_err().kind(), io::ErrorKind::NetworkUnreachable);
+    }
+
+    #[tokio::test]
+    async fn fail_closed_refuses_until_ready() {
+        let _ready = READY_LOCK.lock().await;
+        // Queued mode would hold these; failing closed refuses them at once
+        let pending = Arc::new(Pending::new(PendingMode::Queue));
+        let options = HandlerOptions {
+            fail_closed: true,
+            ..Default::default()
+        };
+        set_ready(false);
+        for _ in 0..2 {
+            let (mut client, task) = spawn_handler_with(pending.clone(), options.clone());
+            let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+            assert_eq!(reply[1], SOCKS5_REP_NETWORK_UNREACHABLE);
+            let err = task.await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NetworkUnreachable);
+        }
+
+        pending.ready(Arc::new(EchoConnector::default()));
+        set_ready(true);
+        let (mut client, _task) = spawn_handler_with(pending, options);
+        let (_, reply) = socks_request(&mut client, &connect_domain("example.com", 443)).await;
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+        set_ready(false);
+    }
+
+    #[tokio::test]
+    async fn serve_relays_over_loopback_tcp() {
+        let echo_addr = spawn_echo_server().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socks_addr = listener.local_addr().unwrap();
+        let connector: Arc<dyn StreamConnector> = Arc::new(LoopbackConnector { addr: echo_addr });
+        let server = tokio::spawn(async move {
+            serve(&listener, None, connector, None).await;
+        });
+
+        // Several sequential clients through the same accept loop
+        for i in 0..3u8 {
+            let mut client = TcpStream::connect(socks_addr).await.unwrap();
+            client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+            let mut method = [0u8; 2];
+            client.read_exact(&mut method).await.unwrap();
+            client.write_all(&connect_domain("echo.test", 7)).await.unwrap();
+            let mut reply = [0u8; 10];
+            client.read_exact(&mut reply).await.unwrap();
+            assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+
+            let payload = [i; 64];
+            client.write_all(&payload).await.unwrap();
+            let mut echoed = [0u8; 64];
+            client.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(echoed, payload);
+        }
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn probes_are_not_handled_as_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connector = Arc::new(EchoConnector::default());
+        let server = tokio::spawn({
+            let connector = connector.clone();
+            async move { serve(&listener, None, connector, None).await }
+        });
+
+        probe(addr, Duration::from_secs(1)).await.unwrap();
+        let unspecified = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port());
+        probe(unspecified, Duration::from_secs(1)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(PROBES.lock().unwrap().is_empty());
+
+        // A real client is still served
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [SOCKS5_VERSION, SOCKS5_AUTH_NONE]);
+        server.abort();
+
+        // Nothing listening: the probe fails and is forgotten
+        let _ = server.await;
+        assert!(probe(addr, Duration::from_secs(1)).await.is_err());
+        assert!(PROBES.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn classifies_accept_errors() {
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert!(is_transient_accept_error(&aborted));
+        let emfile = io::Error::from_raw_os_error(libc::EMFILE);
+        assert!(is_transient_accept_error(&emfile));
+        let ebadf = io::Error::from_raw_os_error(libc::EBADF);
+        assert!(!is_transient_accept_error(&ebadf));
+    }
+
+    #[tokio::test]
+    async fn supervise_rebinds_after_listener_failure() {
+        let policy = RebindPolicy {
+            attempts: 3,
+            initial_delay: Duration::from_millis(1),
+        };
+        let binds = Arc::new(Mutex::new(0u32));
+        let served = Arc::new(Mutex::new(Vec::new()));
+
+        // Generation 0 fails at once; the first rebind fails, the second
+        // yields generation 1, which also fails; then binding keeps failing
+        let err = {
+            let (binds, served) = (binds.clone(), served.clone());
+            supervise(
+                0u32,
+                move || {
+                    let mut n = binds.lock().unwrap();
+                    *n += 1;
+                    match *n {
+                        2 => Ok(1),
+                        _ => Err(io::Error::from(io::ErrorKind::AddrInUse)),
+                    }
+                },
+                move |generation| {
+                    served.lock().unwrap().push(generation);
+                    async { io::Error::from_raw_os_error(libc::EBADF) }
+                },
+                policy,
+            )
+            .await
+        };
+
+        assert_eq!(*served.lock().unwrap(), vec![0, 1]);
+        assert_eq!(*binds.lock().unwrap(), 2 + policy.attempts);
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[tokio::test]
+    async fn supervised_listener_serves_again_after_rebind() {
+        let echo_addr = spawn_echo_server().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socks_addr = listener.local_addr().unwrap();
+        let connector: Arc<dyn StreamConnector> = Arc::new(LoopbackConnector { addr: echo_addr });
+        let generations = Arc::new(Mutex::new(0u32));
+
+        let server = {
+            let generations = generations.clone();
+            tokio::spawn(supervise(
+                listener,
+                move || {
+                    let std_listener = std::net::TcpListener::bind(socks_addr)?;
+                    std_listener.set_nonblocking(true)?;
+                    TcpListener::from_std(std_listener)
+                },
+                move |listener| {
+                    let connector = connector.clone();
+                    let generations = generations.clone();
+                    async move {
+                        let first = {
+                            let mut n = generations.lock().unwrap();
+                            *n += 1;
+                            *n == 1
+                        };
+                        if first {
+                            // Simulate the listener dying: drop it unserved
+                            drop(listener);
+                            return io::Error::from_raw_os_error(libc::EBADF);
+                        }
+                        serve(&listener, None, connector, None).await
+                    }
+                },
+                RebindPolicy {
+                    attempts: 5,
+                    initial_delay: Duration::from_millis(5),
+                },
+            ))
+        };
+
+        // The rebound listener on the same port relays as before
+        let mut client = loop {
+            if *generations.lock().unwrap() >= 2 {
+                if let Ok(c) = TcpStream::connect(socks_addr).await {
+                    break c;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        client.write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        client.write_all(&connect_domain("echo.test", 7)).await.unwrap();
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], SOCKS5_REP_SUCCESS);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn port_with_connections_in_time_wait_binds_again() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        // Closing the server side first leaves it in TIME_WAIT
+        drop(server);
+        drop(listener);
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        drop(client);
+
+        let rebound = bind(addr).unwrap();
+        assert_eq!(rebound.local_addr().unwrap(), addr);
+    }
 }
@@ -0,0 +1,100 @@
+//! Status snapshot for read-only getters
+//!
+//! `ARTI_STATE` is locked by the FFI entry points that start, stop and
+//! reshape the client, and by the async task whenever it publishes a new
+//! client or listener. Status getters should never queue behind those, so
+//! the task also publishes an immutable [`StatusSnapshot`] here. Updates
+//! replace the whole snapshot; readers only clone the `Arc`, so the lock
+//! guarding it is held for a pointer copy and never across other work.
+//!
+//! `arti_bootstrap_summary`, `arti_bootstrap_blockage` and
+//! `arti_memory_usage_bytes` read from the snapshot. `arti_is_running`,
+//! `arti_bootstrap_progress` and `arti_socks_port` read plain atomics,
+//! which are lock-free already.
+
+use std::sync::{Arc, Mutex};
+
+use arti_client::TorClient;
+use once_cell::sync::Lazy;
+use tor_rtcompat::PreferredRuntime;
+
+/// What the getters report, as of the last update.
+#[derive(Clone, Default)]
+pub struct StatusSnapshot {
+    /// Human-readable bootstrap/lifecycle summary
+    pub summary: String,
+    /// The bootstrapped client, once there is one
+    pub client: Option<Arc<TorClient<PreferredRuntime>>>,
+    /// Why arti says bootstrap is blocked, while it is
+    pub blockage: Option<String>,
+}
+
+static CURRENT: Lazy<Mutex<Arc<StatusSnapshot>>> = Lazy::new(Default::default);
+
+/// Longest summary prefix accepted, in bytes
+pub const MAX_SUMMARY_PREFIX_LEN: usize = 32;
+
+/// Put in front of every summary, e.g. an app name
+static SUMMARY_PREFIX: Mutex<String> = Mutex::new(String::new());
+
+/// Prefix summaries published from now on with `prefix` ("" for none).
+pub fn set_summary_prefix(prefix: &str) {
+    if let Ok(mut current) = SUMMARY_PREFIX.lock() {
+        prefix.clone_into(&mut current);
+    }
+}
+
+/// The summary prefix, "" if there is none.
+pub fn summary_prefix() -> String {
+    SUMMARY_PREFIX.lock().map(|p| p.clone()).unwrap_or_default()
+}
+
+/// `summary` as published: with the prefix, unless it is empty.
+pub fn prefixed_summary(summary: &str) -> String {
+    with_prefix(&summary_prefix(), summary)
+}
+
+fn with_prefix(prefix: &str, summary: &str) -> String {
+    if prefix.is_empty() || summary.is_empty() {
+        summary.to_owned()
+    } else {
+        format!("{}: {}", prefix, summary)
+    }
+}
+
+/// The current snapshot.
+pub fn load() -> Arc<StatusSnapshot> {
+    match CURRENT.lock() {
+        Ok(current) => current.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// Go back to the empty snapshot, without a summary prefix.
+pub fn reset() {
+    set_summary_prefix("");
+    update(|status| *status = StatusSnapshot::default());
+}
+
+/// Publish a new snapshot derived from the current one.
+pub fn update(f: impl FnOnce(&mut StatusSnapshot)) {
+    let mut current = match CURRENT.lock() {
+        Ok(current) => current,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let mut next = StatusSnapshot::clone(&current);
+    f(&mut next);
+    *current = Arc::new(next);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_goes_on_non_empty_summaries() {
+        assert_eq!(with_prefix("MyApp", "Bootstrapping..."), "MyApp: Bootstrapping...");
+        assert_eq!(with_prefix("MyApp", ""), "");
+        assert_eq!(with_prefix("", "Ready"), "Ready");
+    }
+}
@@ -0,0 +1,190 @@
+//! Built-in status page
+//!
+//! A tiny HTTP server for people running this as a daemon: `/` is an HTML
+//! page that polls `/status.json`, the status document the FFI builds. The
+//! HTTP is hand-rolled and only as much as a browser needs: one GET per
+//! connection, answered and closed.
+//!
+//! The server only listens on loopback, and it also checks the `Host`
+//! header, so a web page that rebinds its own name to 127.0.0.1 still
+//! can't read the status (which names the relays in use).
+
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::socks;
+
+/// Largest request head read before giving up on a request
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+/// Time a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Arti status</title>
+<style>body{font-family:sans-serif;margin:2em}pre{background:#f4f4f4;padding:1em}</style>
+</head><body><h1>Arti status</h1><p id="summary"></p><pre id="status">Loading...</pre>
+<script>
+async function refresh() {
+  try {
+    const status = await (await fetch("/status.json")).json();
+    document.getElementById("summary").textContent =
+      status.summary + " (" + status.progress + "%)";
+    document.getElementById("status").textContent = JSON.stringify(status, null, 2);
+  } catch (e) {
+    document.getElementById("summary").textContent = "Not responding";
+  }
+}
+refresh();
+setInterval(refresh, 2000);
+</script></body></html>
+"#;
+
+/// Answer requests on `listener` with the page and `status()`, until the
+/// listener fails.
+pub async fn serve(listener: &TcpListener, status: fn() -> String) -> io::Error {
+    let port = match listener.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(e) => return e,
+    };
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, port, status).await {
+                        tracing::debug!("Status page request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) if socks::is_transient_accept_error(&e) => {
+                tracing::warn!("Status page accept error: {}", e);
+                if socks::is_resource_exhaustion(&e) {
+                    tokio::time::sleep(socks::ACCEPT_RETRY_DELAY).await;
+                }
+            }
+            Err(e) => {
+                tracing::error!("Status page listener failed: {}", e);
+                return e;
+            }
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, port: u16, status: fn() -> String) -> io::Result<()> {
+    let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await {
+        Ok(head) => head?,
+        Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+    };
+    let (code, content_type, body) = route(&head, port, status);
+    write_response(&mut stream, code, content_type, &body).await
+}
+
+/// Read up to the blank line ending the request head.
+async fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() > MAX_REQUEST_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request too long"));
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Pick the response for a request head: status line, content type, body.
+fn route(head: &str, port: u16, status: fn() -> String) -> (&'static str, &'static str, String) {
+    const TEXT: &str = "text/plain; charset=utf-8";
+    let mut lines = head.lines();
+    let mut request = lines.next().unwrap_or_default().split(' ');
+    let (method, path) = (request.next().unwrap_or_default(), request.next().unwrap_or_default());
+
+    let host = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .map(|(_, value)| value.trim());
+    if !host.is_some_and(|host| is_loopback_host(host, port)) {
+        return ("403 Forbidden", TEXT, "Forbidden\n".to_owned());
+    }
+    if method != "GET" {
+        return ("405 Method Not Allowed", TEXT, "Method not allowed\n".to_owned());
+    }
+    match path {
+        "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", PAGE.to_owned()),
+        "/status.json" => ("200 OK", "application/json", status()),
+        _ => ("404 Not Found", TEXT, "Not found\n".to_owned()),
+    }
+}
+
+/// Whether `host` (a `Host` header value) names this server on loopback.
+fn is_loopback_host(host: &str, port: u16) -> bool {
+    let port = port.to_string();
+    ["127.0.0.1", "localhost", "[::1]"].iter().any(|name| {
+        host.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .is_some_and(|p| p == port)
+    })
+}
+
+async fn write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    code: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        code,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_status() -> String {
+        r#"{"running":true}"#.to_owned()
+    }
+
+    async fn get(port: u16, path: &str, host: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_page_and_status_to_loopback_hosts_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move { serve(&listener, test_status).await });
+        let host = format!("127.0.0.1:{}", port);
+
+        let json = get(port, "/status.json", &host).await;
+        assert!(json.starts_with("HTTP/1.1 200 OK\r\n"), "{}", json);
+        assert!(json.contains("Content-Type: application/json\r\n"));
+        assert!(json.ends_with("\r\n\r\n{\"running\":true}"), "{}", json);
+
+        let page = get(port, "/", &format!("localhost:{}", port)).await;
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n") && page.contains("/status.json"));
+
+        assert!(get(port, "/nope", &host).await.starts_with("HTTP/1.1 404"));
+        let rebound = get(port, "/status.json", &format!("evil.example:{}", port)).await;
+        assert!(rebound.starts_with("HTTP/1.1 403"), "{}", rebound);
+        server.abort();
+    }
+}
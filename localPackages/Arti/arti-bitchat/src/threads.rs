@@ -0,0 +1,116 @@
+//! Names and priority for the runtime's threads
+//!
+//! Set through `arti_set_thread_config` and applied when the runtime is
+//! built. Named threads are easy to pick out in a profiler; lowering
+//! their priority keeps Tor's background work (directory parsing,
+//! circuit crypto) from competing with the app's UI thread.
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use tokio::runtime::{Builder, Runtime};
+
+/// Longest thread name prefix accepted. Linux shows only the first 15
+/// bytes of a thread name, so shorter prefixes keep the numbers visible.
+pub const MAX_PREFIX_LEN: usize = 32;
+
+/// How the runtime's threads are set up.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadConfig {
+    /// Threads are named `<prefix>-<n>` (`None` = tokio's default name)
+    pub name_prefix: Option<String>,
+    /// Run the threads at background priority
+    pub low_priority: bool,
+}
+
+static CONFIG: Mutex<Option<ThreadConfig>> = Mutex::new(None);
+
+/// Use `config` for runtimes built from now on.
+pub fn set(config: ThreadConfig) {
+    if let Ok(mut current) = CONFIG.lock() {
+        *current = Some(config);
+    }
+}
+
+/// The configuration runtimes are built with.
+pub fn current() -> ThreadConfig {
+    CONFIG.lock().ok().and_then(|c| c.clone()).unwrap_or_default()
+}
+
+/// Go back to tokio's default names and priority.
+pub fn reset() {
+    if let Ok(mut current) = CONFIG.lock() {
+        *current = None;
+    }
+}
+
+/// Build the multi-threaded runtime, its worker and blocking threads set
+/// up per `config`.
+pub fn build_runtime(config: &ThreadConfig) -> io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(prefix) = config.name_prefix.clone() {
+        let next = AtomicUsize::new(0);
+        builder.thread_name_fn(move || {
+            format!("{}-{}", prefix, next.fetch_add(1, Ordering::Relaxed))
+        });
+    }
+    if config.low_priority {
+        builder.on_thread_start(lower_priority);
+    }
+    builder.build()
+}
+
+/// Drop the calling thread to background priority. On Apple platforms
+/// that is the utility QoS class, which the scheduler runs behind
+/// user-initiated work and throttles under thermal or battery pressure.
+/// On Linux and Android the thread's nice value is set to 10. Elsewhere
+/// threads keep their priority.
+fn lower_priority() {
+    #[cfg(target_vendor = "apple")]
+    {
+        let class = libc::qos_class_t::QOS_CLASS_UTILITY;
+        if unsafe { libc::pthread_set_qos_class_self_np(class, 0) } != 0 {
+            tracing::warn!("Could not lower the QoS class of a runtime thread");
+        }
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // Linux applies a nice value set for a thread id to that thread alone
+        let tid = unsafe { libc::gettid() } as libc::id_t;
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, BACKGROUND_NICE) } != 0 {
+            tracing::warn!("Could not lower the priority of a runtime thread");
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const BACKGROUND_NICE: libc::c_int = 10;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_get_the_prefix_and_priority() {
+        let config = ThreadConfig {
+            name_prefix: Some("arti-test".to_owned()),
+            low_priority: true,
+        };
+        let runtime = build_runtime(&config).unwrap();
+        let name = || std::thread::current().name().unwrap_or_default().to_owned();
+        let worker = runtime.block_on(runtime.spawn(async move { name() }));
+        let blocking = runtime.block_on(runtime.spawn_blocking(name));
+        assert!(worker.unwrap().starts_with("arti-test-"));
+        assert!(blocking.unwrap().starts_with("arti-test-"));
+
+        #[cfg(target_os = "linux")]
+        {
+            let nice = runtime.block_on(runtime.spawn_blocking(|| unsafe {
+                libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t)
+            }));
+            assert!(nice.unwrap() >= BACKGROUND_NICE);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Runtime liveness watchdog
+//!
+//! A plain thread, outside the runtime it watches, that now and then
+//! spawns a task that does nothing but answer. If no answer comes within
+//! the deadline, every worker is presumably stuck (say, in a blocking
+//! call) and the app is told. The probe costs one task and a channel
+//! message per interval, and is skipped while nobody is listening.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+
+/// Time between probes
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+/// Time a probe gets to run before the runtime counts as stalled
+pub const PROBE_DEADLINE: Duration = Duration::from_secs(5);
+
+/// Probe `handle` every `interval` for as long as `alive()` holds, calling
+/// `on_stall` with `deadline` when a probe misses it. A stall is reported
+/// once; probing resumes after the late probe finally runs. Probes are
+/// skipped while `wanted()` is false. Returns once `alive()` is false or
+/// the runtime has shut down.
+pub fn watch(
+    handle: Handle,
+    interval: Duration,
+    deadline: Duration,
+    alive: impl Fn() -> bool,
+    wanted: impl Fn() -> bool,
+    on_stall: impl Fn(Duration),
+) {
+    loop {
+        std::thread::sleep(interval);
+        if !alive() {
+            return;
+        }
+        if !wanted() {
+            continue;
+        }
+        let (tx, rx) = mpsc::sync_channel(1);
+        handle.spawn(async move {
+            let _ = tx.send(());
+        });
+        match rx.recv_timeout(deadline) {
+            Ok(()) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                tracing::error!("Runtime ran no task within {:?}; it may be stuck", deadline);
+                on_stall(deadline);
+                // The probe is dropped unanswered if the runtime shuts down
+                if rx.recv().is_err() {
+                    return;
+                }
+                tracing::info!("Runtime is running tasks again");
+            }
+            // Dropped unrun: the runtime has shut down
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn reports_a_blocked_runtime_once() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .build()
+            .unwrap();
+        let stalls = Arc::new(AtomicUsize::new(0));
+        let probes = Arc::new(AtomicUsize::new(0));
+        let watchdog = std::thread::spawn({
+            let handle = runtime.handle().clone();
+            let (stalls, probes) = (stalls.clone(), probes.clone());
+            move || {
+                watch(
+                    handle,
+                    Duration::from_millis(10),
+                    Duration::from_millis(50),
+                    move || probes.fetch_add(1, Ordering::SeqCst) < 20,
+                    || true,
+                    move |_| {
+                        stalls.fetch_add(1, Ordering::SeqCst);
+                    },
+                )
+            }
+        });
+
+        // Hog the only worker with a blocking sleep
+        runtime.spawn(async { std::thread::sleep(Duration::from_millis(300)) });
+        watchdog.join().unwrap();
+        assert_eq!(stalls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,45 @@
+//! `arti_cleanup` returns the library to its freshly loaded state.
+
+mod common;
+
+use std::net::{Ipv4Addr, TcpStream};
+use std::sync::Arc;
+
+use arti_brindavanchat::{
+    arti_cleanup, arti_is_running, arti_set_allowed_ports, arti_socks_port, arti_start,
+    set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+#[test]
+fn cleanup_stops_arti_and_resets_settings() {
+    let (data_dir, data_dir_c) = data_dir("cleanup");
+    set_test_connector(Some(Arc::new(LoopbackConnector::to_echo_server())));
+    assert_eq!(arti_set_allowed_ports([80u16].as_ptr(), 1), 0);
+
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("the SOCKS port", || arti_socks_port() > 0);
+    let port = arti_socks_port() as u16;
+    let (_client, reply) = socks_connect(port, "example.com", 443);
+    assert_eq!(reply, 0x02, "443 is outside the allowlist");
+
+    // Cleanup while running, with a connection still open
+    assert_eq!(arti_cleanup(), 0);
+    assert_eq!(arti_is_running(), 0);
+    assert_eq!(arti_socks_port(), -1);
+    assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err());
+
+    // A fresh start on a fresh runtime, without the old allowlist (or the
+    // old test connector, which cleanup cleared too)
+    set_test_connector(Some(Arc::new(LoopbackConnector::to_echo_server())));
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("the SOCKS port", || arti_socks_port() > 0);
+    let (mut client, reply) = socks_connect(arti_socks_port() as u16, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_echoes(&mut client, b"after cleanup");
+    drop(client);
+
+    assert_eq!(arti_cleanup(), 0);
+    assert_eq!(arti_cleanup(), 0, "cleanup when already clean is a no-op");
+    let _ = std::fs::remove_dir_all(data_dir);
+}
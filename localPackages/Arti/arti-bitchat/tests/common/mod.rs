@@ -0,0 +1,96 @@
+//! Helpers shared by the integration tests.
+
+use std::ffi::CString;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arti_brindavanchat::connector::{ConnectFuture, RelayStream, StreamConnector};
+use arti_client::{StreamPrefs, TorAddr};
+
+/// Connector that opens every stream to `target`, recording what was asked for.
+pub struct LoopbackConnector {
+    target: SocketAddr,
+    pub requested: Mutex<Vec<String>>,
+}
+
+impl LoopbackConnector {
+    /// A connector in front of a fresh echo server.
+    pub fn to_echo_server() -> Self {
+        LoopbackConnector {
+            target: spawn_echo_server(),
+            requested: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl StreamConnector for LoopbackConnector {
+    fn connect<'a>(&'a self, target: TorAddr, _prefs: &'a StreamPrefs) -> ConnectFuture<'a> {
+        self.requested.lock().unwrap().push(target.to_string());
+        Box::pin(async move {
+            let stream = tokio::net::TcpStream::connect(self.target).await?;
+            Ok(Box::new(stream) as Box<dyn RelayStream>)
+        })
+    }
+}
+
+pub fn spawn_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let mut reader = stream.try_clone().unwrap();
+                let _ = io::copy(&mut reader, &mut stream);
+            });
+        }
+    });
+    addr
+}
+
+pub fn wait_for(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// A per-process data directory named after `test`, as a C string.
+pub fn data_dir(test: &str) -> (std::path::PathBuf, CString) {
+    let dir = std::env::temp_dir().join(format!("arti-{}-{}", test, std::process::id()));
+    let c = CString::new(dir.to_str().unwrap()).unwrap();
+    (dir, c)
+}
+
+/// Open a SOCKS5 connection to `port` and CONNECT to `host:dest_port`,
+/// returning the stream and the reply code.
+pub fn socks_connect(port: u16, host: &str, dest_port: u16) -> (TcpStream, u8) {
+    let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // Greeting: SOCKS5, one method, no auth
+    client.write_all(&[0x05, 0x01, 0x00]).unwrap();
+    let mut method = [0u8; 2];
+    client.read_exact(&mut method).unwrap();
+    assert_eq!(method, [0x05, 0x00]);
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&dest_port.to_be_bytes());
+    client.write_all(&request).unwrap();
+    let mut reply = [0u8; 10];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[0], 0x05);
+    (client, reply[1])
+}
+
+/// Send `payload` and check that it comes back.
+pub fn assert_echoes(client: &mut TcpStream, payload: &[u8]) {
+    client.write_all(payload).unwrap();
+    let mut echoed = vec![0u8; payload.len()];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(echoed, payload);
+}
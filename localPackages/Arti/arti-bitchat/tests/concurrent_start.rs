@@ -0,0 +1,59 @@
+//! Concurrent `arti_start` and `arti_stop` calls: exactly one of each wins.
+
+mod common;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use arti_brindavanchat::{
+    arti_is_running, arti_socks_port, arti_start, arti_stop, set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+const THREADS: usize = 16;
+const ROUNDS: usize = 5;
+
+/// Run `f` on `THREADS` threads released at once, returning their results.
+fn race(f: impl Fn() -> i32 + Send + Sync + 'static) -> Vec<i32> {
+    let f = Arc::new(f);
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (f, barrier) = (f.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                f()
+            })
+        })
+        .collect();
+    threads.into_iter().map(|t| t.join().unwrap()).collect()
+}
+
+#[test]
+fn exactly_one_concurrent_start_and_stop_wins() {
+    let (data_dir, data_dir_c) = data_dir("concurrent-start");
+    set_test_connector(Some(Arc::new(LoopbackConnector::to_echo_server())));
+
+    for round in 0..ROUNDS {
+        let dir = data_dir_c.clone();
+        let starts = race(move || arti_start(dir.as_ptr(), 0));
+        assert_eq!(starts.iter().filter(|&&r| r == 0).count(), 1, "round {}: {:?}", round, starts);
+        assert!(starts.iter().all(|&r| r == 0 || r == -1), "round {}: {:?}", round, starts);
+        assert_eq!(arti_is_running(), 1);
+
+        // The one client that started serves SOCKS
+        wait_for("the SOCKS port", || arti_socks_port() > 0);
+        let (mut client, reply) = socks_connect(arti_socks_port() as u16, "example.com", 80);
+        assert_eq!(reply, 0x00);
+        assert_echoes(&mut client, b"only one");
+        drop(client);
+
+        let stops = race(|| arti_stop());
+        assert_eq!(stops.iter().filter(|&&r| r == 0).count(), 1, "round {}: {:?}", round, stops);
+        assert_eq!(arti_is_running(), 0);
+        assert_eq!(arti_socks_port(), -1);
+    }
+
+    set_test_connector(None);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
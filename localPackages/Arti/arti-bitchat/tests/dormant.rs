@@ -0,0 +1,59 @@
+//! The SOCKS port while dormant: kept open and waking on use, or closed
+//! and reopened by `arti_wake`.
+
+mod common;
+
+use std::ffi::c_char;
+use std::net::{Ipv4Addr, TcpStream};
+use std::sync::Arc;
+
+use arti_brindavanchat::{
+    arti_bootstrap_summary, arti_go_dormant, arti_socks_port, arti_start, arti_stop, arti_wake,
+    set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+fn summary() -> String {
+    let mut buf = [0 as c_char; 64];
+    let len = arti_bootstrap_summary(buf.as_mut_ptr(), buf.len() as i32);
+    let bytes: Vec<u8> = buf[..len.max(0) as usize].iter().map(|&c| c as u8).collect();
+    String::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn dormant_listener_stays_open_or_closes_on_request() {
+    let (data_dir, data_dir_c) = data_dir("dormant");
+    set_test_connector(Some(Arc::new(LoopbackConnector::to_echo_server())));
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("the SOCKS port", || arti_socks_port() > 0);
+    let port = arti_socks_port() as u16;
+
+    // Kept open: a connection is served and wakes Arti
+    assert_eq!(arti_go_dormant(1), 0);
+    assert_eq!(summary(), "Dormant");
+    assert_eq!(arti_socks_port(), port as i32);
+    let (mut client, reply) = socks_connect(port, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_echoes(&mut client, b"while dormant");
+    assert_eq!(summary(), "Active");
+    drop(client);
+
+    // Closed: refused until woken, then back on the same port
+    assert_eq!(arti_go_dormant(0), 0);
+    assert_eq!(arti_socks_port(), -1);
+    wait_for("the SOCKS port to close", || {
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err()
+    });
+    assert_eq!(summary(), "Dormant");
+
+    assert_eq!(arti_wake(), 0);
+    assert_eq!(arti_socks_port(), port as i32);
+    let (mut client, reply) = socks_connect(port, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_echoes(&mut client, b"after waking");
+    drop(client);
+
+    assert_eq!(arti_stop(), 0);
+    set_test_connector(None);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
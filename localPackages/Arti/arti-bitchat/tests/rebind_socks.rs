@@ -0,0 +1,40 @@
+//! `arti_rebind_socks` moves the listener and keeps its streams' connector.
+
+mod common;
+
+use std::net::{Ipv4Addr, TcpStream};
+use std::sync::Arc;
+
+use arti_brindavanchat::{
+    arti_rebind_socks, arti_socks_port, arti_start, arti_stop, set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+#[test]
+fn rebind_moves_the_port_and_keeps_the_connector() {
+    let (data_dir, data_dir_c) = data_dir("rebind-socks");
+    let connector = Arc::new(LoopbackConnector::to_echo_server());
+    set_test_connector(Some(connector.clone()));
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("the SOCKS port", || arti_socks_port() > 0);
+    let old_port = arti_socks_port() as u16;
+
+    let new_port = arti_rebind_socks(0);
+    assert!(new_port > 0);
+    assert_ne!(new_port, old_port as i32);
+    assert_eq!(arti_socks_port(), new_port);
+    wait_for("the old port to close", || {
+        TcpStream::connect((Ipv4Addr::LOCALHOST, old_port)).is_err()
+    });
+
+    let (mut client, reply) = socks_connect(new_port as u16, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_echoes(&mut client, b"after the rebind");
+    drop(client);
+    assert_eq!(connector.requested.lock().unwrap().as_slice(), ["example.com:443"]);
+
+    assert_eq!(arti_stop(), 0);
+    assert_eq!(arti_rebind_socks(0), -1);
+    set_test_connector(None);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
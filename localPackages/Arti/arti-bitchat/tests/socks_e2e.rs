@@ -0,0 +1,48 @@
+//! End-to-end check of the FFI lifecycle and SOCKS path, without Tor.
+//!
+//! `arti_start` runs with a test connector that sends every stream to a
+//! local echo server, and a plain SOCKS5 client talks to the bound port.
+
+mod common;
+
+use std::net::{Ipv4Addr, TcpStream};
+use std::sync::Arc;
+
+use arti_brindavanchat::{
+    arti_bootstrap_progress, arti_is_running, arti_last_bootstrap_duration_ms, arti_socks_port,
+    arti_start, arti_stop, arti_wait_socks_ready, set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+#[test]
+fn socks_connect_round_trips_through_arti_start() {
+    let connector = Arc::new(LoopbackConnector::to_echo_server());
+    set_test_connector(Some(connector.clone()));
+
+    let (data_dir, data_dir_c) = data_dir("e2e");
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    assert_eq!(arti_wait_socks_ready(5000), 0);
+    assert!(arti_socks_port() > 0);
+    assert_eq!(arti_bootstrap_progress(), 100);
+    let bootstrap_ms = arti_last_bootstrap_duration_ms();
+    assert!(bootstrap_ms >= 0, "{}", bootstrap_ms);
+    let port = arti_socks_port() as u16;
+
+    let (mut client, reply) = socks_connect(port, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_eq!(*connector.requested.lock().unwrap(), vec!["example.com:443"]);
+    assert_echoes(&mut client, b"ping through the SOCKS port");
+    drop(client);
+
+    assert_eq!(arti_stop(), 0);
+    wait_for("the client to stop", || arti_is_running() == 0);
+    assert_eq!(arti_wait_socks_ready(0), -1);
+    assert_eq!(arti_socks_port(), -1);
+    assert_eq!(arti_last_bootstrap_duration_ms(), bootstrap_ms);
+    wait_for("the SOCKS port to close", || {
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err()
+    });
+
+    set_test_connector(None);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
@@ -0,0 +1,48 @@
+//! A profile start that fails leaves the settings as they were.
+
+mod common;
+
+use std::ffi::{c_char, CString};
+use std::sync::Arc;
+
+use arti_brindavanchat::{
+    arti_effective_config, arti_save_profile, arti_set_num_guards, arti_socks_port, arti_start,
+    arti_start_profile, arti_stop, set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+fn effective_config() -> String {
+    let mut buf = vec![0 as c_char; 64 * 1024];
+    let len = arti_effective_config(buf.as_mut_ptr(), buf.len() as i32);
+    assert!(len > 0);
+    let bytes: Vec<u8> = buf[..len as usize].iter().map(|&c| c as u8).collect();
+    String::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn start_profile_while_running_keeps_the_settings() {
+    let (data_dir, data_dir_c) = data_dir("start-profile");
+    let name = CString::new("work").unwrap();
+    let profile = CString::new("[guards]\nnum_primary = 5\n").unwrap();
+    assert_eq!(arti_save_profile(data_dir_c.as_ptr(), name.as_ptr(), profile.as_ptr()), 0);
+
+    set_test_connector(Some(Arc::new(LoopbackConnector::to_echo_server())));
+    assert_eq!(arti_set_num_guards(2), 2);
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("the SOCKS port", || arti_socks_port() > 0);
+    let before = effective_config();
+    assert!(before.contains("guard-n-primary-guards = 2"), "{}", before);
+
+    assert_eq!(arti_start_profile(data_dir_c.as_ptr(), name.as_ptr(), 0), -1);
+    assert_eq!(effective_config(), before);
+
+    // The running client is untouched too
+    let (mut client, reply) = socks_connect(arti_socks_port() as u16, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_echoes(&mut client, b"still running");
+    drop(client);
+
+    assert_eq!(arti_stop(), 0);
+    set_test_connector(None);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
@@ -0,0 +1,44 @@
+//! `arti_stop` while bootstrap is still in progress ends it cleanly.
+
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use arti_brindavanchat::{
+    arti_bootstrap_progress, arti_is_running, arti_socks_port, arti_start, arti_stop,
+    set_test_bootstrap_stall, set_test_connector,
+};
+use common::{assert_echoes, data_dir, socks_connect, wait_for, LoopbackConnector};
+
+#[test]
+fn stop_mid_bootstrap_leaves_arti_cleanly_stopped() {
+    let (data_dir, data_dir_c) = data_dir("stop-during-bootstrap");
+    set_test_connector(Some(Arc::new(LoopbackConnector::to_echo_server())));
+    set_test_bootstrap_stall(Some(40));
+
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("bootstrap to stall", || arti_bootstrap_progress() == 40);
+    assert_eq!(arti_is_running(), 1);
+    assert_eq!(arti_socks_port(), -1);
+
+    let stopping = Instant::now();
+    assert_eq!(arti_stop(), 0);
+    assert!(stopping.elapsed() < Duration::from_secs(2));
+    assert_eq!(arti_is_running(), 0);
+    assert_eq!(arti_bootstrap_progress(), 0);
+    assert_eq!(arti_stop(), -1);
+
+    // Nothing of the abandoned bootstrap is left to get in a new start's way
+    set_test_bootstrap_stall(None);
+    assert_eq!(arti_start(data_dir_c.as_ptr(), 0), 0);
+    wait_for("the SOCKS port", || arti_socks_port() > 0);
+    let (mut client, reply) = socks_connect(arti_socks_port() as u16, "example.com", 443);
+    assert_eq!(reply, 0x00);
+    assert_echoes(&mut client, b"after the stop");
+    drop(client);
+
+    assert_eq!(arti_stop(), 0);
+    set_test_connector(None);
+    let _ = std::fs::remove_dir_all(data_dir);
+}